@@ -12,6 +12,20 @@ fn main() {
         .type_attribute("netavark_proxy.MacAddress", "#[derive(serde::Serialize)]")
         .type_attribute("netavark_proxy.NvIpv4Addr", "#[derive(serde::Serialize)]")
         .type_attribute("netavark_proxy.Lease", "#[derive(serde::Deserialize)]")
+        // leases cached by older versions miss the newer fields
+        .type_attribute("netavark_proxy.Lease", "#[serde(default)]")
+        .type_attribute(
+            "netavark_proxy.ClasslessRoute",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "netavark_proxy.LeaseInfo",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "netavark_proxy.LeaseList",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
         .type_attribute(
             "netavark_proxy.DhcpV4Lease",
             "#[derive(serde::Deserialize)]",
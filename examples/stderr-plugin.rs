@@ -33,6 +33,8 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: None,
+            ntp_servers: None,
+            port_mappings: None,
         };
 
         Ok(response)
@@ -3,22 +3,23 @@
 use std::{
     collections::HashMap,
     env, fs,
-    fs::File,
+    fs::{File, OpenOptions},
     io::Write,
+    os::fd::AsFd,
     os::unix::{io::FromRawFd, net::UnixListener as stdUnixListener},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use clap::Parser;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 #[cfg(unix)]
 use tokio::net::UnixListener;
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::{
     sync::{mpsc, mpsc::Sender, oneshot, oneshot::error::TryRecvError},
-    task::AbortHandle,
+    task::{AbortHandle, JoinHandle},
     time::{timeout, Duration},
 };
 #[cfg(unix)]
@@ -33,21 +34,76 @@ use tonic::{
 use crate::{
     dhcp_proxy::{
         cache::{Clear, LeaseCache},
-        dhcp_service::{process_client_stream, DhcpV4Service},
+        dhcp_service::{
+            process_client_stream, process_client_stream_v6, DhcpService, DhcpServiceError,
+            DhcpServiceErrorKind, DhcpV4Service, DhcpV6Service, LeaseEvent, LeaseNotifier,
+            RenewalState,
+        },
         ip,
         lib::g_rpc::{
             netavark_proxy_server::{NetavarkProxy, NetavarkProxyServer},
-            Empty, Lease as NetavarkLease, NetworkConfig, OperationResponse,
+            Empty, Lease as NetavarkLease, LeaseInfo, LeaseList, LeaseRequest, NetworkConfig,
+            OperationResponse, Version,
         },
         proxy_conf::{
-            get_cache_fqname, get_proxy_sock_fqname, DEFAULT_INACTIVITY_TIMEOUT, DEFAULT_TIMEOUT,
+            get_cache_fqname, get_network_cache_fqname, get_proxy_sock_fqname,
+            DEFAULT_INACTIVITY_TIMEOUT, DEFAULT_TIMEOUT,
         },
     },
     error::{NetavarkError, NetavarkResult},
     network::core_utils,
 };
 
-type TaskData = (Arc<tokio::sync::Mutex<DhcpV4Service>>, AbortHandle);
+type TaskData = (DhcpService, TaskHandle, Arc<Mutex<RenewalState>>);
+
+/// Reply channel of a release request sent to a netns thread.
+type ReleaseReply = oneshot::Sender<Result<(), DhcpServiceError>>;
+
+/// Handle to stop the renewal task of a dhcp service.
+#[derive(Debug)]
+enum TaskHandle {
+    /// The renewal task runs on the proxy runtime.
+    Task(AbortHandle),
+    /// The renewal task runs on a thread which joined the container netns,
+    /// the lease must be released from that thread before it exits.
+    Netns(oneshot::Sender<ReleaseReply>),
+}
+
+impl TaskHandle {
+    /// Stop the renewal task and release the current lease of the service.
+    async fn release(self, service: DhcpService) -> Result<(), DhcpServiceError> {
+        match self {
+            TaskHandle::Task(handle) => {
+                handle.abort();
+                service.release_lease().await
+            }
+            TaskHandle::Netns(sender) => {
+                let (tx, rx) = oneshot::channel();
+                if sender.send(tx).is_err() {
+                    return Err(DhcpServiceError::new(
+                        DhcpServiceErrorKind::Bug,
+                        "dhcp netns thread exited".to_string(),
+                    ));
+                }
+                rx.await.unwrap_or_else(|e| {
+                    Err(DhcpServiceError::new(
+                        DhcpServiceErrorKind::Bug,
+                        format!("dhcp netns thread failed: {e}"),
+                    ))
+                })
+            }
+        }
+    }
+}
+
+/// Key of the renewal task in the task map, the ipv4 task uses the plain mac
+/// address, the ipv6 task of the same container is suffixed.
+fn task_key(mac: &str, version: Version) -> String {
+    match version {
+        Version::V4 => mac.to_string(),
+        Version::V6 => format!("{mac}-v6"),
+    }
+}
 
 #[derive(Debug)]
 /// This is the tonic netavark proxy service that is required to impl the
@@ -69,11 +125,54 @@ struct NetavarkProxyService<W: Write + Clear> {
     // channel send-side for resetting the inactivity timeout
     timeout_sender: Option<Arc<Mutex<Sender<i32>>>>,
     // All dhcp poll will be spawned on a new task, keep track of it so
-    // we can remove it on teardown. The key is the container mac.
+    // we can remove it on teardown. The key is the container mac, see task_key().
     task_map: Arc<Mutex<HashMap<String, TaskData>>>,
 }
 
 impl<W: Write + Clear> NetavarkProxyService<W> {
+    /// Add the container id and the renewal state to the cached leases.
+    fn lease_list(&self, leases: Vec<NetavarkLease>) -> LeaseList {
+        let cache = self
+            .cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        let tasks = self.task_map.lock().expect("lock tasks");
+        let leases = leases
+            .into_iter()
+            .map(|lease| {
+                let container_id = cache
+                    .get_network(&lease.mac_address, lease.is_v6)
+                    .map(|nc| nc.container_id.clone())
+                    .unwrap_or_default();
+                let version = if lease.is_v6 {
+                    Version::V6
+                } else {
+                    Version::V4
+                };
+                let state = tasks
+                    .get(&task_key(&lease.mac_address, version))
+                    .map(|(_, _, state)| state.lock().expect("lock renewal state").clone());
+                let mut info = LeaseInfo {
+                    container_id,
+                    renewing: state.is_some(),
+                    ..Default::default()
+                };
+                if let Some(state) = state {
+                    info.acquired = state.acquired;
+                    info.next_t1 = state.next(state.t1);
+                    info.next_t2 = state.next(state.t2);
+                    if let Some((time, err)) = state.last_error {
+                        info.last_error_time = time;
+                        info.last_error = err;
+                    }
+                }
+                info.lease = Some(lease);
+                info
+            })
+            .collect();
+        LeaseList { leases }
+    }
+
     fn reset_inactivity_timeout(&self) {
         if let Some(sender) = &self.timeout_sender {
             let sender_clone = sender.clone();
@@ -161,22 +260,24 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
         let cache = self.cache.clone();
         let tasks = self.task_map.clone();
 
-        let maybe_service_arc = {
+        // A dual stack container has one task per ip version, release both.
+        let services: Vec<(DhcpService, TaskHandle)> = {
             // Scope for the std::sync::MutexGuard
             let mut tasks_guard = tasks.lock().expect("lock tasks");
 
-            if let Some((service_arc, handle)) = tasks_guard.remove(&nc.container_mac_addr) {
-                handle.abort();
-                Some(service_arc)
-            } else {
-                None
-            }
+            [Version::V4, Version::V6]
+                .into_iter()
+                .filter_map(|version| {
+                    tasks_guard
+                        .remove(&task_key(&nc.container_mac_addr, version))
+                        .map(|(service, handle, _)| (service, handle))
+                })
+                .collect()
         };
-        if let Some(service_arc) = maybe_service_arc {
-            let mut service = service_arc.lock().await;
-            if let Err(e) = service.release_lease().await {
+        for (service, handle) in services {
+            if let Err(e) = handle.release(service).await {
                 warn!(
-                    "Failed to send DHCPRELEASE for {}: {}",
+                    "Failed to send DHCP release for {}: {}",
                     &nc.container_mac_addr, e
                 );
             }
@@ -202,6 +303,60 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
             .teardown()?;
         Ok(Response::new(OperationResponse { success: true }))
     }
+
+    /// List all cached leases with the state of their renewal task.
+    async fn list_leases(&self, request: Request<Empty>) -> Result<Response<LeaseList>, Status> {
+        debug!("Request from client: {:?}", request.remote_addr());
+        // notify server of activity
+        self.reset_inactivity_timeout();
+        let leases = self
+            .cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned")
+            .leases();
+        Ok(Response::new(self.lease_list(leases)))
+    }
+
+    /// Get the leases of a single container, selected by its id or mac address.
+    async fn get_lease(
+        &self,
+        request: Request<LeaseRequest>,
+    ) -> Result<Response<LeaseList>, Status> {
+        // notify server of activity
+        self.reset_inactivity_timeout();
+        let req = request.into_inner();
+        let leases = {
+            let cache = self
+                .cache
+                .lock()
+                .expect("Could not unlock cache. A thread was poisoned");
+            let mac = if !req.container_mac_addr.is_empty() {
+                Some(req.container_mac_addr.clone())
+            } else if !req.container_id.is_empty() {
+                cache.find_mac(&req.container_id)
+            } else {
+                return Err(Status::new(
+                    InvalidArgument,
+                    "container id or mac address required",
+                ));
+            };
+            let leases: Vec<NetavarkLease> = cache
+                .leases()
+                .into_iter()
+                .filter(|l| Some(&l.mac_address) == mac.as_ref())
+                .collect();
+            leases
+        };
+        if leases.is_empty() {
+            let what = if req.container_mac_addr.is_empty() {
+                format!("container {}", req.container_id)
+            } else {
+                format!("mac address {}", req.container_mac_addr)
+            };
+            return Err(Status::new(Code::NotFound, format!("no lease for {what}")));
+        }
+        Ok(Response::new(self.lease_list(leases)))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -290,12 +445,15 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
 
     let uds_stream = UnixListenerStream::new(uds);
 
-    // Create the cache file
+    // Open the cache files, leases of a previous proxy are restored
     let fq_cache_path = get_cache_fqname(optional_run_dir);
-    let file = match File::create(&fq_cache_path) {
-        Ok(file) => {
-            debug!("Successfully created leases file: {fq_cache_path:?}");
-            file
+    let fq_network_cache_path = get_network_cache_fqname(optional_run_dir);
+    let (file, network_file) = match open_cache_file(&fq_cache_path)
+        .and_then(|file| Ok((file, open_cache_file(&fq_network_cache_path)?)))
+    {
+        Ok(files) => {
+            debug!("Successfully opened leases file: {fq_cache_path:?}");
+            files
         }
         Err(e) => {
             return Err(NetavarkError::msg(format!(
@@ -304,7 +462,7 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
         }
     };
 
-    let cache = match LeaseCache::new(file) {
+    let cache = match LeaseCache::load(file, network_file) {
         Ok(c) => Arc::new(Mutex::new(c)),
         Err(e) => {
             return Err(NetavarkError::msg(format!(
@@ -313,6 +471,12 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
         }
     };
 
+    // Restore before serving, a teardown handled in the meantime would release
+    // a lease whose renewal task is only restored afterwards and never stopped.
+    // Clients wait in the socket backlog until then.
+    let task_map = Arc::new(Mutex::new(HashMap::new()));
+    restore_leases(cache.clone(), task_map.clone(), dora_timeout).await;
+
     // Create send and receive channels for activity timeout. If anything is
     // sent by the tx side, the inactivity timeout is reset
     let (activity_timeout_tx, activity_timeout_rx) = if inactivity_timeout.as_secs() > 0 {
@@ -327,7 +491,7 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
         timeout_sender: activity_timeout_tx
             .clone()
             .map(|tx| Arc::new(Mutex::new(tx))),
-        task_map: Arc::new(Mutex::new(HashMap::new())),
+        task_map,
     };
 
     let server = Server::builder()
@@ -351,6 +515,16 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
     Ok(())
 }
 
+/// Open a cache file for reading and writing without truncating it.
+fn open_cache_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
 /// manages the timeout lifecycle for the proxy server based on a defined
 /// timeout.
 ///
@@ -430,7 +604,7 @@ fn is_catch_empty<W: Write + Clear>(current_cache: Arc<Mutex<LeaseCache<W>>>) ->
 /// * `cache`: lease cache
 ///
 /// returns: Result<Lease, Status>
-async fn process_setup<W: Write + Clear>(
+async fn process_setup<W: Write + Clear + Send + 'static>(
     network_config: NetworkConfig,
     timeout: u32,
     cache: Arc<Mutex<LeaseCache<W>>>,
@@ -444,41 +618,326 @@ async fn process_setup<W: Write + Clear>(
         .map_err(|e| Status::new(InvalidArgument, format!("{e}")))?;
     let mac = &network_config.container_mac_addr.clone();
 
-    let nv_lease = match network_config.version {
-        //V4
-        0 => {
-            let mut service = DhcpV4Service::new(network_config, timeout).await?;
+    let version = Version::try_from(network_config.version)
+        .map_err(|_| Status::new(InvalidArgument, "invalid protocol version"))?;
+    let state = Arc::new(Mutex::new(RenewalState::default()));
+    let (nv_lease, service, handle) = start_service(
+        network_config.clone(),
+        timeout,
+        None,
+        lease_notifier(cache.clone(), state.clone()),
+    )
+    .await?;
+    state.lock().expect("lock renewal state").renewed(&nv_lease);
+    tasks
+        .lock()
+        .expect("lock tasks")
+        .insert(task_key(mac, version), (service, handle, state));
 
-            let lease = service.get_lease().await?;
-            let service_arc = Arc::new(tokio::sync::Mutex::new(service));
-            let service_arc_clone = service_arc.clone();
-            let task_handle = tokio::spawn(process_client_stream(service_arc_clone));
-            tasks
-                .lock()
-                .expect("lock tasks")
-                .insert(mac.to_string(), (service_arc, task_handle.abort_handle()));
-            lease
+    {
+        let mut cache = cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        if let Err(e) = cache
+            .add_lease(mac, &nv_lease)
+            .and_then(|_| cache.add_network(&network_config))
+        {
+            return Err(Status::new(
+                Internal,
+                format!("Error caching the lease: {e}"),
+            ));
+        }
+    }
+
+    ip::setup(&nv_lease, &container_network_interface, &ns_path)?;
+    Ok(nv_lease)
+}
+
+/// Update the renewal state and the cached lease after a renewal. Leases
+/// removed by a teardown in the meantime are not added back.
+fn lease_notifier<W: Write + Clear + Send + 'static>(
+    cache: Arc<Mutex<LeaseCache<W>>>,
+    state: Arc<Mutex<RenewalState>>,
+) -> LeaseNotifier {
+    Arc::new(move |event: LeaseEvent| {
+        let lease = match event {
+            LeaseEvent::Renewed(lease) => *lease,
+            LeaseEvent::Failed(err) => {
+                state.lock().expect("lock renewal state").failed(err);
+                return;
+            }
+        };
+        state.lock().expect("lock renewal state").renewed(&lease);
+        let mut cache = cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        let mac = lease.mac_address.clone();
+        if cache.get_lease(&mac, lease.is_v6).is_none() {
+            return;
+        }
+        if let Err(e) = cache.update_lease(&mac, lease) {
+            error!("Failed to update the cached lease for {mac}: {e}");
+        }
+    })
+}
+
+/// Start the dhcp client for the network config and spawn the renewal task.
+///
+/// # Arguments
+///
+/// * `network_config`: Network config
+/// * `timeout`: dora timeout
+/// * `cached`: lease from the proxy cache, when set no new lease is requested
+///   upfront and the renewal task takes over the cached lease
+/// * `notify`: called with the renewed leases
+///
+/// returns: Result<(Lease, DhcpService, TaskHandle), Status>
+async fn start_service(
+    network_config: NetworkConfig,
+    timeout: u32,
+    cached: Option<NetavarkLease>,
+    notify: LeaseNotifier,
+) -> Result<(NetavarkLease, DhcpService, TaskHandle), Status> {
+    match network_config.version() {
+        Version::V4 if !network_config.host_iface.is_empty() => {
+            let (lease, service, task_handle) =
+                start_v4_service(network_config, timeout, cached, notify).await?;
+            Ok((lease, service, TaskHandle::Task(task_handle.abort_handle())))
         }
-        //V6 TODO implement DHCPv6
-        1 => {
-            return Err(Status::new(InvalidArgument, "ipv6 not yet supported"));
+        // The DHCPv6 client always runs in the container netns, it must send
+        // from the link local address of the container interface.
+        _ => start_netns_service(network_config, timeout, cached, notify).await,
+    }
+}
+
+/// Run the dhcp client from inside the container netns. This is used for
+/// DHCPv6 and when no host interface is given because the interface itself
+/// was moved into the container, i.e. the host-device driver. The sockets used by the client are
+/// created on a dedicated thread which joined the netns so they stay bound to
+/// the container interface for the lifetime of the lease, the lease is also
+/// released from there on teardown.
+///
+/// # Arguments
+///
+/// * `network_config`: Network config
+/// * `timeout`: dora timeout
+/// * `cached`: lease from the proxy cache, see start_service()
+/// * `notify`: called with the renewed leases
+///
+/// returns: Result<(Lease, DhcpService, TaskHandle), Status>
+async fn start_netns_service(
+    network_config: NetworkConfig,
+    timeout: u32,
+    cached: Option<NetavarkLease>,
+    notify: LeaseNotifier,
+) -> Result<(NetavarkLease, DhcpService, TaskHandle), Status> {
+    let (tx, rx) = oneshot::channel::<Result<(NetavarkLease, DhcpService, TaskHandle), Status>>();
+
+    std::thread::spawn(move || {
+        let runtime = File::open(&network_config.ns_path)
+            .map_err(NetavarkError::from)
+            .and_then(|ns| core_utils::join_netns(ns.as_fd()))
+            .and_then(|_| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(NetavarkError::from)
+            });
+        let runtime = match runtime {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = tx.send(Err(Status::new(Internal, e.to_string())));
+                return;
+            }
+        };
+
+        runtime.block_on(async move {
+            let result = match network_config.version() {
+                Version::V4 => start_v4_service(network_config, timeout, cached, notify).await,
+                Version::V6 => start_v6_service(network_config, timeout, cached, notify).await,
+            };
+            let (lease, service, task_handle) = match result {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = tx.send(Err(e.into()));
+                    return;
+                }
+            };
+            let (release_tx, release_rx) = oneshot::channel::<ReleaseReply>();
+            if tx
+                .send(Ok((lease, service.clone(), TaskHandle::Netns(release_tx))))
+                .is_err()
+            {
+                // the request was dropped, nobody will ever stop this task
+                task_handle.abort();
+                return;
+            }
+            // Keep the runtime alive until teardown asks for the release, the
+            // sender is also dropped without a request when the proxy exits.
+            let release = release_rx.await;
+            task_handle.abort();
+            let _ = task_handle.await;
+            if let Ok(reply) = release {
+                let _ = reply.send(service.release_lease().await);
+            }
+        });
+    });
+
+    rx.await
+        .map_err(|e| Status::new(Internal, format!("dhcp netns thread failed: {e}")))?
+}
+
+/// Get the first lease, unless a cached one is given, and spawn the renewal task for it.
+async fn start_v4_service(
+    network_config: NetworkConfig,
+    timeout: u32,
+    cached: Option<NetavarkLease>,
+    notify: LeaseNotifier,
+) -> Result<(NetavarkLease, DhcpService, JoinHandle<()>), DhcpServiceError> {
+    let mut service = DhcpV4Service::new(network_config, timeout).await?;
+    let lease = match cached {
+        Some(lease) => {
+            service.set_previous_lease(&lease)?;
+            lease
         }
-        _ => {
-            return Err(Status::new(InvalidArgument, "invalid protocol version"));
+        None => service.get_lease().await?,
+    };
+    let service_arc = Arc::new(tokio::sync::Mutex::new(service));
+    let task_handle = tokio::spawn(process_client_stream(service_arc.clone(), notify));
+    Ok((lease, DhcpService::V4(service_arc), task_handle))
+}
+
+/// Get the first DHCPv6 lease, unless a cached one is given, and spawn the renewal task for it.
+async fn start_v6_service(
+    network_config: NetworkConfig,
+    timeout: u32,
+    cached: Option<NetavarkLease>,
+    notify: LeaseNotifier,
+) -> Result<(NetavarkLease, DhcpService, JoinHandle<()>), DhcpServiceError> {
+    let mut service = DhcpV6Service::new(network_config, timeout).await?;
+    let lease = match cached {
+        Some(lease) => {
+            service.set_previous_lease(&lease)?;
+            lease
         }
+        None => service.get_lease().await?,
     };
+    let service_arc = Arc::new(tokio::sync::Mutex::new(service));
+    let task_handle = tokio::spawn(process_client_stream_v6(service_arc.clone(), notify));
+    Ok((lease, DhcpService::V6(service_arc), task_handle))
+}
 
-    if let Err(e) = cache
+/// Recreate the renewal tasks for the leases a previous proxy left in the
+/// cache. Leases of containers whose netns is gone are released, when
+/// possible, and dropped from the cache. So are leases which cannot be
+/// restored and leases cached without their network config by older versions.
+///
+/// # Arguments
+///
+/// * `cache`: lease cache loaded from disk
+/// * `tasks`: task map of the proxy service
+/// * `timeout`: dora timeout
+async fn restore_leases<W: Write + Clear + Send + 'static>(
+    cache: Arc<Mutex<LeaseCache<W>>>,
+    tasks: Arc<Mutex<HashMap<String, TaskData>>>,
+    timeout: u32,
+) {
+    let networks = cache
         .lock()
         .expect("Could not unlock cache. A thread was poisoned")
-        .add_lease(mac, &nv_lease)
-    {
-        return Err(Status::new(
-            Internal,
-            format!("Error caching the lease: {e}"),
-        ));
+        .networks();
+    let mut stale = Vec::new();
+    for network_config in networks {
+        let mac = network_config.container_mac_addr.clone();
+        let version = network_config.version();
+        let lease = cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned")
+            .get_lease(&mac, version == Version::V6)
+            .cloned();
+        let Some(lease) = lease else {
+            warn!("No cached lease for {mac}, dropping it");
+            stale.push((mac, version));
+            continue;
+        };
+
+        if !Path::new(&network_config.ns_path).exists() {
+            info!(
+                "Netns {} for {mac} is gone, dropping its lease",
+                network_config.ns_path
+            );
+            release_stale_lease(network_config, timeout, &lease).await;
+            stale.push((mac, version));
+            continue;
+        }
+
+        let state = Arc::new(Mutex::new(RenewalState {
+            acquired: lease.acquired,
+            t1: lease.t1,
+            t2: lease.t2,
+            last_error: None,
+        }));
+        match start_service(
+            network_config,
+            timeout,
+            Some(lease),
+            lease_notifier(cache.clone(), state.clone()),
+        )
+        .await
+        {
+            Ok((_, service, handle)) => {
+                info!("Restored the renewal task for {mac}");
+                tasks
+                    .lock()
+                    .expect("lock tasks")
+                    .insert(task_key(&mac, version), (service, handle, state));
+            }
+            Err(e) => {
+                error!("Failed to restore the lease for {mac}: {}", e.message());
+                stale.push((mac, version));
+            }
+        }
     }
 
-    ip::setup(&nv_lease, &container_network_interface, &ns_path)?;
-    Ok(nv_lease)
+    let mut cache = cache
+        .lock()
+        .expect("Could not unlock cache. A thread was poisoned");
+    for lease in cache.leases() {
+        if cache.get_network(&lease.mac_address, lease.is_v6).is_none() {
+            warn!(
+                "No network config for the cached lease of {}, dropping it",
+                lease.mac_address
+            );
+            let version = if lease.is_v6 {
+                Version::V6
+            } else {
+                Version::V4
+            };
+            stale.push((lease.mac_address, version));
+        }
+    }
+    for (mac, version) in stale {
+        if let Err(e) = cache.remove_version(&mac, version == Version::V6) {
+            error!("Failed to remove the lease for {mac} from the cache: {e}");
+        }
+    }
+}
+
+/// Release the lease of a container that no longer exists. Only the proxy
+/// mode DHCPv4 client can do that, it does not need the container netns.
+async fn release_stale_lease(network_config: NetworkConfig, timeout: u32, lease: &NetavarkLease) {
+    if network_config.version() != Version::V4 || network_config.host_iface.is_empty() {
+        return;
+    }
+    let mac = network_config.container_mac_addr.clone();
+    let result = match DhcpV4Service::new(network_config, timeout).await {
+        Ok(mut service) => match service.set_previous_lease(lease) {
+            Ok(_) => service.release_lease().await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Failed to send DHCP release for {mac}: {e}");
+    }
 }
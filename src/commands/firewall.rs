@@ -0,0 +1,164 @@
+//! Render and check the firewall rules netavark creates
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::firewall;
+use crate::firewall::state::read_fw_config;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self};
+use crate::network::{constants, core_utils, host_ports, ipam};
+use crate::wrap;
+
+use clap::{Parser, Subcommand};
+use log::debug;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Firewall {
+    #[clap(subcommand)]
+    subcmd: FirewallSubCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum FirewallSubCommand {
+    /// Print the firewall changes setup would make for the given configuration without applying them.
+    Render,
+    /// Compare the firewall rules of all networks and containers with the live firewall.
+    Check {
+        /// Add the missing rules again.
+        #[clap(long)]
+        repair: bool,
+    },
+}
+
+impl Firewall {
+    pub fn exec(
+        &self,
+        input_file: Option<OsString>,
+        config_dir: Option<OsString>,
+        firewall_driver: Option<String>,
+        plugin_directories: Option<Vec<OsString>>,
+        rootless: bool,
+    ) -> NetavarkResult<()> {
+        match self.subcmd {
+            FirewallSubCommand::Render => render(
+                input_file,
+                config_dir,
+                firewall_driver,
+                plugin_directories,
+                rootless,
+            ),
+            FirewallSubCommand::Check { repair } => check(config_dir, repair),
+        }
+    }
+}
+
+/// Run the firewall part of setup with a dry run firewall driver, the nftables
+/// driver prints the rulesets as json and firewalld the dbus calls.
+fn render(
+    input_file: Option<OsString>,
+    config_dir: Option<OsString>,
+    firewall_driver: Option<String>,
+    plugin_directories: Option<Vec<OsString>>,
+    rootless: bool,
+) -> NetavarkResult<()> {
+    debug!("Rendering firewall rules...");
+    let mut network_options = network::types::NetworkOptions::load(input_file)?;
+    network::validation::validate_port_mappings(&network_options.port_mappings)?;
+
+    let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver, true)?;
+    let dns_port = core_utils::get_netavark_dns_port()?;
+    // The state is only read to pick the same host ports and addresses as setup.
+    let config_dir = config_dir.unwrap_or_else(|| constants::DEFAULT_CONFIG_DIR.into());
+
+    if let Some(ports) = network_options.port_mappings.as_mut() {
+        if rootless && ports.iter().any(|p| p.host_port == 0) {
+            return Err(NetavarkError::msg(
+                "host port allocation is not supported for rootless containers",
+            ));
+        }
+        host_ports::preview_host_ports(
+            Path::new(&config_dir),
+            &network_options.container_id,
+            ports,
+        )?;
+    }
+    for named_network_opts in network_options.networks.iter_mut() {
+        if let Some(network) = network_options.network_info.get(&named_network_opts.name) {
+            if ipam::is_host_local(network) {
+                named_network_opts.opts.static_ips = Some(ipam::preview_addresses(
+                    Path::new(&config_dir),
+                    network,
+                    &network_options.container_id,
+                    named_network_opts.opts.static_ips.as_ref(),
+                )?);
+            }
+        }
+    }
+
+    // No namespace is touched, the drivers only compute the firewall config.
+    let hostns = wrap!(File::open("/proc/self/ns/net"), "open host netns")?;
+
+    for named_network_opts in &network_options.networks {
+        let network = network_options
+            .network_info
+            .get(&named_network_opts.name)
+            .ok_or_else(|| {
+                NetavarkError::Message(format!(
+                    "network info for network {} not found",
+                    &named_network_opts.name
+                ))
+            })?;
+
+        let mut driver = get_network_driver(
+            DriverInfo {
+                firewall: firewall_driver.as_ref(),
+                container_id: &network_options.container_id,
+                container_name: &network_options.container_name,
+                container_hostname: &network_options.container_hostname,
+                container_dns_servers: &network_options.dns_servers,
+                netns_host: hostns.as_fd(),
+                netns_container: hostns.as_fd(),
+                netns_path: "",
+                network,
+                per_network_opts: &named_network_opts.opts,
+                port_mappings: &network_options.port_mappings,
+                dns_port,
+                config_dir: Path::new(&config_dir),
+                rootless,
+            },
+            &plugin_directories,
+        )?;
+
+        driver.validate()?;
+        driver.render_firewall()?;
+    }
+    Ok(())
+}
+
+/// Compare the rules for the firewall state of the config dir with the live
+/// firewall, fails when rules differ unless they were repaired.
+fn check(config_dir: Option<OsString>, repair: bool) -> NetavarkResult<()> {
+    let config_dir = Path::new(
+        config_dir
+            .as_deref()
+            .unwrap_or(OsStr::new(constants::DEFAULT_CONFIG_DIR)),
+    );
+    debug!("Checking firewall rules of {config_dir:?}");
+
+    let conf = match read_fw_config(config_dir).wrap("read firewall config")? {
+        Some(conf) => conf,
+        // no firewall state means no containers, so there is nothing to check
+        None => return Ok(()),
+    };
+    let firewall_driver =
+        firewall::get_supported_firewall_driver(Some(conf.driver.clone()), false)?;
+
+    if !firewall_driver.check(conf, repair)? && !repair {
+        return Err(NetavarkError::msg(
+            "firewall rules do not match the expected rules",
+        ));
+    }
+    Ok(())
+}
@@ -26,7 +26,7 @@ pub fn firewall_reload(config_dir: Option<OsString>) -> NetavarkResult<()> {
 }
 
 // This function is copied directly from firewalld_reload.rs.
-fn reload_rules(config_dir: &Path, conn: &Option<Connection>) -> NetavarkResult<()> {
+pub(crate) fn reload_rules(config_dir: &Path, conn: &Option<Connection>) -> NetavarkResult<()> {
     reload_rules_inner(config_dir, conn)?;
     Ok(())
 }
@@ -39,7 +39,7 @@ fn reload_rules_inner(config_dir: &Path, conn: &Option<Connection>) -> NetavarkR
     // If there are no config files, there are no running containers, so we do nothing.
     if let Some(conf) = conf {
         // Get the appropriate firewall driver
-        let fw_driver = get_supported_firewall_driver(Some(conf.driver))?;
+        let fw_driver = get_supported_firewall_driver(Some(conf.driver), false)?;
 
         // Loop through each network configuration and restore its rules.
         for net in conf.net_confs {
@@ -49,6 +49,10 @@ fn reload_rules_inner(config_dir: &Path, conn: &Option<Connection>) -> NetavarkR
         for port in &conf.port_confs {
             fw_driver.setup_port_forward(port.into(), conn)?;
         }
+        // Restore the load balanced services with all their backends.
+        for service in &conf.services {
+            fw_driver.update_service(service)?;
+        }
         log::info!("Successfully reloaded firewall rules");
     }
 
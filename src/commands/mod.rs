@@ -4,8 +4,10 @@ use crate::error::{NetavarkError, NetavarkResult};
 
 pub mod create;
 pub mod dhcp_proxy;
+pub mod firewall;
 pub mod firewall_reload;
 pub mod firewalld_reload;
+pub mod nftables_watch;
 pub mod setup;
 pub mod teardown;
 pub mod update;
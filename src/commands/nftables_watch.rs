@@ -0,0 +1,106 @@
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use zbus::blocking::Connection;
+
+use crate::{
+    commands::firewall_reload::reload_rules,
+    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    firewall::nft::is_foreign_deletion,
+    network::{
+        constants,
+        netlink::Socket,
+        netlink_netfilter::{NetlinkNftables, NftDeletion},
+    },
+};
+
+/// How long the events must stop before the rules are reapplied, a flush of the
+/// ruleset or a firewall restart comes with many events.
+const DEBOUNCE_TIME: Duration = Duration::from_millis(500);
+/// Reapply after this many debounce periods even when the events do not stop.
+const MAX_DEBOUNCE_ROUNDS: u32 = 10;
+
+pub fn watch(config_dir: Option<OsString>) -> NetavarkResult<()> {
+    let config_dir = Path::new(
+        config_dir
+            .as_deref()
+            .unwrap_or(OsStr::new(constants::DEFAULT_CONFIG_DIR)),
+    );
+    log::debug!("looking for firewall configs in {config_dir:?}");
+
+    // Subscribe before the first reload so no deletion can be missed.
+    let mut socket = Socket::<NetlinkNftables>::new().wrap("netlink nftables socket")?;
+    socket
+        .subscribe_nftables_events()
+        .wrap("subscribe to nftables events")?;
+
+    let conn = Connection::system().ok();
+
+    // The ruleset might have been flushed while we were not running.
+    reapply_rules(config_dir, &conn);
+
+    // This loops forever until the process is killed or there is some netlink error.
+    loop {
+        if !needs_reapply(socket.recv_nftables_deletions())? {
+            continue;
+        }
+        wait_for_quiet(&mut socket)?;
+        reapply_rules(config_dir, &conn);
+    }
+}
+
+/// Whether the received events deleted netavark objects.
+fn needs_reapply(deletions: NetavarkResult<Vec<NftDeletion>>) -> NetavarkResult<bool> {
+    match deletions {
+        Ok(deletions) => match deletions.iter().find(|d| is_foreign_deletion(d)) {
+            Some(deletion) => {
+                log::info!(
+                    "netavark nftables objects were deleted ({deletion:?}), reapplying firewall rules"
+                );
+                Ok(true)
+            }
+            None => Ok(false),
+        },
+        // The kernel dropped events because we did not read them fast
+        // enough, we cannot know what was deleted so reapply everything.
+        Err(NetavarkError::Io(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+            log::warn!("lost nftables events, reapplying firewall rules");
+            Ok(true)
+        }
+        Err(e) => Err(NetavarkError::wrap("receive nftables events", e)),
+    }
+}
+
+/// Wait until no more events arrive and read the queued ones, so the rest of a
+/// flush does not trigger another reapply.
+fn wait_for_quiet(socket: &mut Socket<NetlinkNftables>) -> NetavarkResult<()> {
+    for _ in 0..MAX_DEBOUNCE_ROUNDS {
+        thread::sleep(DEBOUNCE_TIME);
+        let mut quiet = true;
+        loop {
+            match socket.try_recv_nftables_deletions() {
+                Ok(Some(_)) => quiet = false,
+                Ok(None) => break,
+                // lost events do not matter, everything is reapplied anyway
+                Err(NetavarkError::Io(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    quiet = false
+                }
+                Err(e) => return Err(NetavarkError::wrap("receive nftables events", e)),
+            }
+        }
+        if quiet {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn reapply_rules(config_dir: &Path, conn: &Option<Connection>) {
+    if let Err(e) = reload_rules(config_dir, conn) {
+        log::error!("failed to reload firewall rules: {e}");
+    }
+}
@@ -4,10 +4,11 @@ use crate::dns::aardvark::Aardvark;
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
 use crate::network::driver::{get_network_driver, DriverInfo, NetworkDriver};
+use crate::network::ipam::IpamAllocations;
 use crate::network::netlink::Socket;
 use crate::network::netlink_route::{LinkID, NetlinkRoute};
 use crate::network::{self};
-use crate::network::{core_utils, types};
+use crate::network::{core_utils, host_ports, ipam, types};
 
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
@@ -49,9 +50,10 @@ impl Setup {
             }
         }
         debug!("Setting up...");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        network::validation::validate_port_mappings(&network_options.port_mappings)?;
 
-        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver)?;
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver, false)?;
 
         let mut response: HashMap<String, types::StatusBlock> = HashMap::new();
 
@@ -64,6 +66,41 @@ impl Setup {
         netns.netlink.set_up(LinkID::ID(1))?;
 
         let config_dir = get_config_dir(config_dir, "setup")?;
+
+        // Pick the host ports of the mappings without one, they are reported
+        // back in the status blocks. They stay reserved until the drivers stored
+        // them in the firewall state.
+        let _host_port_reservation = match network_options.port_mappings.as_mut() {
+            // The allocated ports are only stored in the firewall state which
+            // is not written for rootless.
+            Some(ports) if rootless && ports.iter().any(|p| p.host_port == 0) => {
+                return Err(NetavarkError::msg(
+                    "host port allocation is not supported for rootless containers",
+                ));
+            }
+            Some(ports) => host_ports::allocate_host_ports(
+                Path::new(&config_dir),
+                &network_options.container_id,
+                ports,
+            )?,
+            None => None,
+        };
+
+        // Allocate the addresses for host-local networks, static ips are stored
+        // as well so they are not handed out to other containers.
+        // On errors the guard releases the allocations again.
+        let mut ipam_allocations =
+            IpamAllocations::new(Path::new(&config_dir), &network_options.container_id);
+        for named_network_opts in network_options.networks.iter_mut() {
+            if let Some(network) = network_options.network_info.get(&named_network_opts.name) {
+                if ipam::is_host_local(network) {
+                    let ips = ipam_allocations
+                        .allocate(network, named_network_opts.opts.static_ips.as_ref())?;
+                    named_network_opts.opts.static_ips = Some(ips);
+                }
+            }
+        }
+
         let mut drivers = Vec::with_capacity(network_options.network_info.len());
 
         for named_network_opts in &network_options.networks {
@@ -161,6 +198,7 @@ impl Setup {
         }
         debug!("{response:#?}");
         let response_json = serde_json::to_string(&response)?;
+        ipam_allocations.commit();
         println!("{response_json}");
         debug!("Setup complete");
         Ok(())
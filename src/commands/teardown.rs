@@ -1,9 +1,9 @@
 use crate::commands::get_config_dir;
 use crate::dns::aardvark::{Aardvark, AardvarkEntry};
 use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
-use crate::network::constants::DRIVER_BRIDGE;
-use crate::network::core_utils;
+use crate::network::constants::{DRIVER_BRIDGE, DRIVER_VXLAN};
 use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{core_utils, host_ports, ipam};
 
 use crate::{firewall, network};
 use clap::builder::NonEmptyStringValueParser;
@@ -48,16 +48,35 @@ impl Teardown {
         let _ = unsafe { signal::signal(signal::SIGTERM, signal::SigHandler::SigIgn) };
         let _ = unsafe { signal::signal(signal::SIGINT, signal::SigHandler::SigIgn) };
 
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
 
         let mut error_list = NetavarkErrorList::new();
 
         let dns_port = core_utils::get_netavark_dns_port()?;
         let config_dir = get_config_dir(config_dir, "teardown")?;
 
+        // The host ports allocated on setup are needed to remove the port forwarding.
+        if let Some(ports) = network_options.port_mappings.as_mut() {
+            let network_ids: Vec<&str> = network_options
+                .network_info
+                .values()
+                .map(|n| n.id.as_str())
+                .collect();
+            if let Err(err) = host_ports::restore_host_ports(
+                Path::new(&config_dir),
+                &network_ids,
+                &network_options.container_id,
+                ports,
+            ) {
+                error_list.push(err);
+            }
+        }
+
         let mut aardvark_entries = Vec::new();
         for (key, network) in &network_options.network_info {
-            if network.dns_enabled && network.driver == DRIVER_BRIDGE {
+            if network.dns_enabled
+                && (network.driver == DRIVER_BRIDGE || network.driver == DRIVER_VXLAN)
+            {
                 match network_options.container_id.as_str().try_into() {
                     Ok(id) => {
                         aardvark_entries.push(AardvarkEntry {
@@ -90,7 +109,7 @@ impl Teardown {
             }
         }
 
-        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver)?;
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver, false)?;
 
         let (mut hostns, mut netns) =
             core_utils::open_netlink_sockets(&self.network_namespace_path)?;
@@ -138,9 +157,20 @@ impl Teardown {
                 Ok(_) => {}
                 Err(err) => {
                     error_list.push(err);
-                    continue;
                 }
             };
+
+            // release the addresses even when the driver teardown failed,
+            // otherwise they would be leaked forever
+            if ipam::is_host_local(network) {
+                if let Err(err) = ipam::release_addresses(
+                    Path::new(&config_dir),
+                    network,
+                    &network_options.container_id,
+                ) {
+                    error_list.push(err);
+                }
+            }
         }
 
         if !error_list.is_empty() {
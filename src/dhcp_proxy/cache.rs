@@ -1,9 +1,10 @@
-use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, Lease};
+use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, Lease, NetworkConfig, Version};
 use log::{debug, error};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::io::{Cursor, Write};
+use std::io::{Cursor, Read, Seek, Write};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -24,17 +25,21 @@ impl Clear for Cursor<Vec<u8>> {
 
 impl Clear for File {
     fn clear(&mut self) -> Result<(), ClearError> {
-        match self.set_len(0) {
+        match self.set_len(0).and_then(|_| self.rewind()) {
             Ok(_) => Ok(()),
             Err(e) => Err(ClearError { msg: e.to_string() }),
         }
     }
 }
-/// The leasing cache holds a in memory record of the leases, and a on file version
+/// The leasing cache holds a in memory record of the leases, and a on file version.
+/// The network configs the leases were requested with are kept next to them so
+/// the renewal tasks can be recreated when the proxy restarts.
 #[derive(Debug)]
 pub struct LeaseCache<W: Write + Clear> {
     mem: HashMap<String, Vec<NetavarkLease>>,
     writer: W,
+    networks: HashMap<String, Vec<NetworkConfig>>,
+    network_writer: W,
 }
 
 impl<W: Write + Clear> LeaseCache<W> {
@@ -44,17 +49,22 @@ impl<W: Write + Clear> LeaseCache<W> {
     ///
     /// * `writer`: any type that can has the Write and Clear trait implemented. In production this
     ///   is a file. In development/testing this is a Cursor of bytes
+    /// * `network_writer`: same as writer, used for the network configs
     ///
     /// returns: Result<LeaseCache<W>, Error>
     ///
-    pub fn new(writer: W) -> Result<LeaseCache<W>, io::Error> {
+    pub fn new(writer: W, network_writer: W) -> Result<LeaseCache<W>, io::Error> {
         Ok(LeaseCache {
             mem: HashMap::new(),
             writer,
+            networks: HashMap::new(),
+            network_writer,
         })
     }
 
-    /// Add a new lease to a memory and file system cache
+    /// Add a new lease to a memory and file system cache. A dual stack
+    /// container has one lease per ip version, only the lease of the same
+    /// version is replaced.
     ///
     /// # Arguments
     ///
@@ -66,12 +76,65 @@ impl<W: Write + Clear> LeaseCache<W> {
     pub fn add_lease(&mut self, mac_addr: &str, lease: &NetavarkLease) -> Result<(), io::Error> {
         debug!("add lease: {mac_addr:?}");
         // Update cache memory with new lease
-        let cache = &mut self.mem;
-        cache.insert(mac_addr.to_string(), vec![lease.clone()]);
+        insert_lease(&mut self.mem, mac_addr, lease.clone());
         // write updated memory cache to the file system
         self.save_memory_to_fs()
     }
 
+    /// Remember the network config a lease was requested with, a dual stack
+    /// container has one config per ip version.
+    ///
+    /// # Arguments
+    ///
+    /// * `network_config`: Network config of the container
+    ///
+    /// returns: Result<(), Error>
+    ///
+    pub fn add_network(&mut self, network_config: &NetworkConfig) -> Result<(), io::Error> {
+        let configs = self
+            .networks
+            .entry(network_config.container_mac_addr.clone())
+            .or_default();
+        configs.retain(|nc| nc.version != network_config.version);
+        configs.push(network_config.clone());
+        configs.sort_by_key(|nc| nc.version);
+        self.save_networks_to_fs()
+    }
+
+    /// Get the cached lease of the given ip version for the mac address.
+    pub fn get_lease(&self, mac_addr: &str, is_v6: bool) -> Option<&NetavarkLease> {
+        self.mem.get(mac_addr)?.iter().find(|l| l.is_v6 == is_v6)
+    }
+
+    /// All cached leases ordered by mac address, ipv4 first.
+    pub fn leases(&self) -> Vec<NetavarkLease> {
+        let mut leases: Vec<NetavarkLease> = self.mem.values().flatten().cloned().collect();
+        leases.sort_by(|a, b| (&a.mac_address, a.is_v6).cmp(&(&b.mac_address, b.is_v6)));
+        leases
+    }
+
+    /// All cached network configs, used to restore the renewal tasks.
+    pub fn networks(&self) -> Vec<NetworkConfig> {
+        self.networks.values().flatten().cloned().collect()
+    }
+
+    /// Get the cached network config of the given ip version for the mac address.
+    pub fn get_network(&self, mac_addr: &str, is_v6: bool) -> Option<&NetworkConfig> {
+        self.networks
+            .get(mac_addr)?
+            .iter()
+            .find(|nc| (nc.version() == Version::V6) == is_v6)
+    }
+
+    /// Find the mac address of the leases of a container.
+    pub fn find_mac(&self, container_id: &str) -> Option<String> {
+        self.networks
+            .values()
+            .flatten()
+            .find(|nc| nc.container_id == container_id)
+            .map(|nc| nc.container_mac_addr.clone())
+    }
+
     /// When a lease changes, update the lease in memory and on the writer.
     ///
     /// # Arguments
@@ -82,9 +145,8 @@ impl<W: Write + Clear> LeaseCache<W> {
     /// returns: Result<(), Error>
     ///
     pub fn update_lease(&mut self, mac_addr: &str, lease: NetavarkLease) -> Result<(), io::Error> {
-        let cache = &mut self.mem;
         // write to the memory cache
-        cache.insert(mac_addr.to_string(), vec![lease]);
+        insert_lease(&mut self.mem, mac_addr, lease);
         // write updated memory cache to the file system
         self.save_memory_to_fs()
     }
@@ -96,6 +158,9 @@ impl<W: Write + Clear> LeaseCache<W> {
     /// * `mac_addr`: Mac address of the container
     pub fn remove_lease(&mut self, mac_addr: &str) -> Result<Lease, io::Error> {
         debug!("remove lease: {mac_addr:?}");
+        if self.networks.remove(mac_addr).is_some() {
+            self.save_networks_to_fs()?;
+        }
         let mem = &mut self.mem;
         // Check and see if the lease exists, if not create an empty one
         let lease = match mem.get(mac_addr) {
@@ -116,6 +181,9 @@ impl<W: Write + Clear> LeaseCache<W> {
                 gateways: vec![],
                 ntp_servers: vec![],
                 host_name: "".to_string(),
+                classless_routes: vec![],
+                domain_search: vec![],
+                ..Default::default()
             },
             Some(l) => l[0].clone(),
         };
@@ -131,9 +199,36 @@ impl<W: Write + Clear> LeaseCache<W> {
         }
     }
 
+    /// Remove the lease and network config of one ip version, the other
+    /// version of a dual stack container is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac_addr`: Mac address of the container
+    /// * `is_v6`: ip version of the lease to remove
+    pub fn remove_version(&mut self, mac_addr: &str, is_v6: bool) -> Result<(), io::Error> {
+        let version = if is_v6 { Version::V6 } else { Version::V4 } as i32;
+        if let Some(configs) = self.networks.get_mut(mac_addr) {
+            configs.retain(|nc| nc.version != version);
+            if configs.is_empty() {
+                self.networks.remove(mac_addr);
+            }
+        }
+        if let Some(leases) = self.mem.get_mut(mac_addr) {
+            leases.retain(|l| l.is_v6 != is_v6);
+            if leases.is_empty() {
+                self.mem.remove(mac_addr);
+            }
+        }
+        self.save_networks_to_fs()?;
+        self.save_memory_to_fs()
+    }
+
     /// Clean up the memory and file system on tear down of the proxy server
     pub fn teardown(&mut self) -> Result<(), io::Error> {
         self.mem.clear();
+        self.networks.clear();
+        self.save_networks_to_fs()?;
         self.save_memory_to_fs()
     }
 
@@ -141,19 +236,12 @@ impl<W: Write + Clear> LeaseCache<W> {
     /// then write the memory map to the file. This method will be called any the lease memory cache
     /// changes (new lease, remove lease, update lease)
     fn save_memory_to_fs(&mut self) -> io::Result<()> {
-        let mem = &self.mem;
-        let writer = &mut self.writer;
-        // Clear the writer so we can add the old leases
-        match writer.clear() {
-            Ok(_) => {
-                serde_json::to_writer(writer.by_ref(), &mem)?;
-                writer.flush()
-            }
-            Err(e) => {
-                error!("Could not clear the writer. Not updating lease information: {e:?}");
-                Ok(())
-            }
-        }
+        write_map(&mut self.writer, &self.mem)
+    }
+
+    /// Same as save_memory_to_fs() for the network configs.
+    fn save_networks_to_fs(&mut self) -> io::Result<()> {
+        write_map(&mut self.network_writer, &self.networks)
     }
     // rust validators require both len and is_empty if you define one
     // of them
@@ -168,10 +256,76 @@ impl<W: Write + Clear> LeaseCache<W> {
     }
 }
 
+impl<W: Read + Write + Clear> LeaseCache<W> {
+    /// Create the cache with the leases and network configs a previous proxy
+    /// wrote. Content that cannot be parsed is logged and dropped, it will be
+    /// overwritten on the next change.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer`: the lease writer, read before it is used for writing
+    /// * `network_writer`: the network config writer, read before it is used for writing
+    ///
+    /// returns: Result<LeaseCache<W>, Error>
+    ///
+    pub fn load(mut writer: W, mut network_writer: W) -> Result<LeaseCache<W>, io::Error> {
+        let mem = read_map(&mut writer)?;
+        let networks = read_map(&mut network_writer)?;
+        debug!("loaded {} lease(s) from the cache", mem.len());
+        Ok(LeaseCache {
+            mem,
+            writer,
+            networks,
+            network_writer,
+        })
+    }
+}
+
+fn read_map<R: Read, V: DeserializeOwned>(reader: &mut R) -> io::Result<HashMap<String, V>> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    if content.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    match serde_json::from_str(&content) {
+        Ok(map) => Ok(map),
+        Err(e) => {
+            error!("Could not parse the lease cache, starting with an empty one: {e}");
+            Ok(HashMap::new())
+        }
+    }
+}
+
+fn write_map<W: Write + Clear, V: serde::Serialize>(
+    writer: &mut W,
+    map: &HashMap<String, V>,
+) -> io::Result<()> {
+    // Clear the writer so we can add the old leases
+    match writer.clear() {
+        Ok(_) => {
+            serde_json::to_writer(writer.by_ref(), map)?;
+            writer.flush()
+        }
+        Err(e) => {
+            error!("Could not clear the writer. Not updating lease information: {e:?}");
+            Ok(())
+        }
+    }
+}
+
+/// Insert the lease for the mac address replacing the lease of the same ip
+/// version, the ipv4 lease is always kept first.
+fn insert_lease(mem: &mut HashMap<String, Vec<NetavarkLease>>, mac_addr: &str, lease: Lease) {
+    let leases = mem.entry(mac_addr.to_string()).or_default();
+    leases.retain(|l| l.is_v6 != lease.is_v6);
+    leases.push(lease);
+    leases.sort_by_key(|l| l.is_v6);
+}
+
 #[cfg(test)]
 mod cache_tests {
     use super::super::cache::LeaseCache;
-    use super::super::lib::g_rpc::{Lease as NetavarkLease, Lease};
+    use super::super::lib::g_rpc::{Lease as NetavarkLease, Lease, NetworkConfig, Version};
     use crate::network::core_utils;
     use rand::{rng, Rng};
     use std::collections::HashMap;
@@ -220,6 +374,9 @@ mod cache_tests {
             ntp_servers: vec![],
             host_name: "example.host_name".to_string(),
             is_v6: false,
+            classless_routes: vec![],
+            domain_search: vec![],
+            ..Default::default()
         }
     }
     // Shared information for all tests
@@ -233,7 +390,7 @@ mod cache_tests {
         fn new() -> Self {
             // Use byte Cursor instead of file for testing
             let buff = Cursor::new(Vec::new());
-            let cache = match LeaseCache::new(buff) {
+            let cache = match LeaseCache::new(buff, Cursor::new(Vec::new())) {
                 Ok(cache) => cache,
                 Err(e) => panic!("Could not create leases cache: {e:?}"),
             };
@@ -453,4 +610,122 @@ mod cache_tests {
             assert_eq!(deserialized_updated_lease, &new_lease);
         }
     }
+
+    #[test]
+    fn dual_stack_leases() {
+        let setup = CacheTestSetup::new();
+        let mut cache = setup.cache;
+        let mac_address = random_macaddr();
+
+        let mut v6_lease = random_lease(&mac_address);
+        v6_lease.is_v6 = true;
+        v6_lease.yiaddr = "fd00::10".to_string();
+        let v4_lease = random_lease(&mac_address);
+
+        cache
+            .add_lease(&mac_address, &v6_lease)
+            .expect("could not add lease to cache");
+        cache
+            .add_lease(&mac_address, &v4_lease)
+            .expect("could not add lease to cache");
+
+        let lease_bytes = cache.writer.get_ref().as_slice();
+        let s: HashMap<String, Vec<NetavarkLease>> =
+            serde_json::from_slice(lease_bytes).expect("deserialize cache");
+        assert_eq!(s.get(&mac_address), Some(&vec![v4_lease, v6_lease.clone()]));
+
+        // updating the ipv4 lease keeps the ipv6 one
+        let new_v4_lease = random_lease(&mac_address);
+        cache
+            .update_lease(&mac_address, new_v4_lease.clone())
+            .expect("Could not update the lease");
+        let lease_bytes = cache.writer.get_ref().as_slice();
+        let s: HashMap<String, Vec<NetavarkLease>> =
+            serde_json::from_slice(lease_bytes).expect("deserialize cache");
+        assert_eq!(s.get(&mac_address), Some(&vec![new_v4_lease, v6_lease]));
+    }
+
+    #[test]
+    fn load_leases() {
+        let setup = CacheTestSetup::new();
+        let mut cache = setup.cache;
+        let mac_address = random_macaddr();
+        let lease = random_lease(&mac_address);
+        let mut v6_lease = random_lease(&mac_address);
+        v6_lease.is_v6 = true;
+        let network_config = NetworkConfig {
+            host_iface: "veth1".to_string(),
+            container_iface: "eth0".to_string(),
+            container_mac_addr: mac_address.clone(),
+            ns_path: "/run/netns/test".to_string(),
+            ..Default::default()
+        };
+        let v6_network_config = NetworkConfig {
+            version: Version::V6 as i32,
+            ..network_config.clone()
+        };
+
+        cache
+            .add_lease(&mac_address, &lease)
+            .and_then(|_| cache.add_lease(&mac_address, &v6_lease))
+            .and_then(|_| cache.add_network(&v6_network_config))
+            .and_then(|_| cache.add_network(&network_config))
+            .expect("could not add lease to cache");
+
+        // a new proxy reads what the previous one wrote
+        let mut cache = LeaseCache::load(
+            Cursor::new(cache.writer.into_inner()),
+            Cursor::new(cache.network_writer.into_inner()),
+        )
+        .expect("load cache");
+        assert_eq!(cache.get_lease(&mac_address, false), Some(&lease));
+        assert_eq!(cache.get_lease(&mac_address, true), Some(&v6_lease));
+        assert_eq!(
+            cache.networks(),
+            vec![network_config.clone(), v6_network_config]
+        );
+
+        cache
+            .remove_version(&mac_address, true)
+            .expect("remove ipv6 lease");
+        assert_eq!(cache.get_lease(&mac_address, true), None);
+        assert_eq!(cache.networks(), vec![network_config]);
+        assert_eq!(cache.len(), 1);
+
+        // an empty or corrupted cache starts empty
+        let cache = LeaseCache::load(Cursor::new(Vec::new()), Cursor::new(b"{not json".to_vec()))
+            .expect("load cache");
+        assert!(cache.is_empty());
+        assert!(cache.networks().is_empty());
+    }
+
+    #[test]
+    fn find_leases() {
+        let setup = CacheTestSetup::new();
+        let mut cache = setup.cache;
+        let mac_address = random_macaddr();
+        let lease = random_lease(&mac_address);
+        let mut v6_lease = random_lease(&mac_address);
+        v6_lease.is_v6 = true;
+        let network_config = NetworkConfig {
+            container_mac_addr: mac_address.clone(),
+            container_id: "abc123".to_string(),
+            ..Default::default()
+        };
+
+        cache
+            .add_lease(&mac_address, &v6_lease)
+            .and_then(|_| cache.add_lease(&mac_address, &lease))
+            .and_then(|_| cache.add_network(&network_config))
+            .expect("could not add lease to cache");
+
+        assert_eq!(cache.leases(), vec![lease, v6_lease]);
+        assert_eq!(cache.find_mac("abc123"), Some(mac_address.clone()));
+        assert_eq!(cache.find_mac("def456"), None);
+        assert_eq!(
+            cache.get_network(&mac_address, false),
+            Some(&network_config)
+        );
+        assert_eq!(cache.get_network(&mac_address, true), None);
+    }
 }
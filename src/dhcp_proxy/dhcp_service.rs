@@ -1,19 +1,35 @@
-use std::{net::Ipv4Addr, sync::Arc};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::network::{
     netlink,
     netlink_route::{LinkID, NetlinkRoute, Route},
 };
 use log::debug;
-use mozim::{DhcpV4Client, DhcpV4Config, DhcpV4Lease as MozimV4Lease, DhcpV4State};
-use netlink_packet_route::route::RouteType;
+use mozim::{
+    DhcpError, DhcpV4ClasslessRoute, DhcpV4Client, DhcpV4Config, DhcpV4Lease as MozimV4Lease,
+    DhcpV4State, DhcpV6Client, DhcpV6Config, DhcpV6Duid, DhcpV6DuidUuid, DhcpV6IaType,
+    DhcpV6Lease as MozimV6Lease, DhcpV6Mode, DhcpV6State,
+};
+use netlink_packet_route::{
+    address::{AddressAttribute, AddressFlags, AddressHeaderFlags},
+    route::RouteType,
+};
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 use tonic::{Code, Status};
 
 use crate::{
     dhcp_proxy::{
         dhcp_service::DhcpServiceErrorKind::{Bug, InvalidArgument, NoLease, Timeout},
-        lib::g_rpc::{Lease as NetavarkLease, NetworkConfig},
+        ip::{self, LeaseRoute},
+        lib::g_rpc::{
+            ms_classless_routes, Lease as NetavarkLease, NetworkConfig,
+            DHCPV4_OPTION_DOMAIN_SEARCH, DHCPV4_OPTION_MS_CLASSLESS_ROUTES,
+        },
     },
     error::{ErrorWrap, NetavarkError, NetavarkResult},
     network::core_utils,
@@ -21,6 +37,7 @@ use crate::{
 };
 
 /// The kind of DhcpServiceError that can be caused when finding a dhcp lease
+#[derive(Debug)]
 pub enum DhcpServiceErrorKind {
     Timeout,
     InvalidArgument,
@@ -32,6 +49,7 @@ pub enum DhcpServiceErrorKind {
 }
 
 /// A DhcpServiceError is an error caused in the process of finding a dhcp lease
+#[derive(Debug)]
 pub struct DhcpServiceError {
     kind: DhcpServiceErrorKind,
     msg: String,
@@ -54,14 +72,25 @@ pub struct DhcpV4Service {
 
 impl DhcpV4Service {
     pub async fn new(nc: NetworkConfig, timeout: u32) -> Result<Self, DhcpServiceError> {
-        let mut config = DhcpV4Config::new_proxy(&nc.host_iface, &nc.container_mac_addr)
+        // Without a host interface the service runs inside the container netns
+        // and uses the container interface directly.
+        let iface = if nc.host_iface.is_empty() {
+            nc.container_iface.clone()
+        } else {
+            nc.host_iface.clone()
+        };
+        let mut config = DhcpV4Config::new_proxy(&iface, &nc.container_mac_addr)
             .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
         config.set_timeout_sec(timeout);
+        config.request_extra_dhcp_opts(&[
+            DHCPV4_OPTION_DOMAIN_SEARCH,
+            DHCPV4_OPTION_MS_CLASSLESS_ROUTES,
+        ]);
 
         let mut socket = netlink::Socket::<NetlinkRoute>::new()
             .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
         let link = socket
-            .get_link(LinkID::Name(nc.host_iface.clone()))
+            .get_link(LinkID::Name(iface))
             .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
 
         config.set_iface_index(link.header.index);
@@ -115,10 +144,8 @@ impl DhcpV4Service {
             let state = self.client.run().await;
             match state {
                 Ok(DhcpV4State::Done(lease)) => {
-                    let mut netavark_lease =
-                        <NetavarkLease as From<MozimV4Lease>>::from((*lease).clone());
-                    netavark_lease.add_domain_name(&self.network_config.domain_name);
-                    netavark_lease.add_mac_address(&self.network_config.container_mac_addr);
+                    let lease = with_ms_classless_routes(lease);
+                    let netavark_lease = self.netavark_lease(&lease);
                     debug!(
                         "found a lease for {:?}, {:?}",
                         &self.network_config.container_mac_addr, &netavark_lease
@@ -133,25 +160,68 @@ impl DhcpV4Service {
                         self.network_config.container_mac_addr
                     );
                 }
-                Err(err) => {
-                    return Err(match err.kind() {
-                        mozim::ErrorKind::Timeout => {
-                            DhcpServiceError::new(Timeout, err.to_string())
-                        }
-                        mozim::ErrorKind::InvalidArgument => {
-                            DhcpServiceError::new(InvalidArgument, err.to_string())
-                        }
-                        mozim::ErrorKind::NoLease => {
-                            DhcpServiceError::new(NoLease, err.to_string())
-                        }
-                        mozim::ErrorKind::Bug => DhcpServiceError::new(Bug, err.to_string()),
-                        _ => DhcpServiceError::new(Bug, err.to_string()),
-                    });
-                }
+                Err(err) => return Err(err.into()),
             }
         }
     }
 
+    /// Convert the mozim lease into the lease returned by the proxy.
+    fn netavark_lease(&self, lease: &MozimV4Lease) -> NetavarkLease {
+        let mut netavark_lease = <NetavarkLease as From<MozimV4Lease>>::from(lease.clone());
+        netavark_lease.add_domain_name(&self.network_config.domain_name);
+        netavark_lease.add_mac_address(&self.network_config.container_mac_addr);
+        netavark_lease.acquired = unix_now();
+        netavark_lease
+    }
+
+    /// Use a lease from the proxy cache as the current lease. The client
+    /// continues with a renewal of the cached address once T1 of the lease
+    /// expired, the renewal task compares the next lease against it and
+    /// updates the container addresses.
+    pub fn set_previous_lease(&mut self, lease: &NetavarkLease) -> Result<(), DhcpServiceError> {
+        let mut previous = MozimV4Lease::default();
+        (previous.t1_sec, previous.t2_sec, previous.lease_time_sec) =
+            remaining_times(lease, lease.lease_time);
+        if !lease.siaddr.is_empty() {
+            previous.siaddr = parse_lease_addr(&lease.siaddr)?;
+        }
+        previous.yiaddr = parse_lease_addr(&lease.yiaddr)?;
+        previous.subnet_mask = parse_lease_addr(&lease.subnet_mask)?;
+        if !lease.srv_id.is_empty() {
+            previous.srv_id = parse_lease_addr(&lease.srv_id)?;
+        }
+        if !lease.gateways.is_empty() {
+            previous.gateways = Some(
+                lease
+                    .gateways
+                    .iter()
+                    .map(|gw| parse_lease_addr(gw))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if lease.mtu > 0 {
+            previous.mtu = u16::try_from(lease.mtu).ok();
+        }
+        if !lease.classless_routes.is_empty() {
+            previous.classless_routes = Some(
+                ip::lease_routes(lease)
+                    .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?
+                    .into_iter()
+                    .map(|(dest, router)| DhcpV4ClasslessRoute {
+                        destination: dest.network(),
+                        prefix_length: dest.prefix_len(),
+                        router,
+                    })
+                    .collect(),
+            );
+        }
+        self.client
+            .done(previous.clone())
+            .map_err(|e| DhcpServiceError::new(Bug, e.to_string()))?;
+        self.previous_lease = Some(previous);
+        Ok(())
+    }
+
     /// Sends a DHCPRELEASE message for the given lease.
     /// This is a "best effort" operation and should not block teardown.
     pub async fn release_lease(&mut self) -> Result<(), DhcpServiceError> {
@@ -178,6 +248,245 @@ impl DhcpV4Service {
     }
 }
 
+/// DHCPv6 service, unlike DHCPv4 there is no proxy mode as the client must
+/// send from the link local address of the interface. It must always be
+/// created and run on a thread that joined the container netns.
+#[derive(Debug)]
+pub struct DhcpV6Service {
+    client: DhcpV6Client,
+    network_config: NetworkConfig,
+    previous_lease: Option<MozimV6Lease>,
+}
+
+impl DhcpV6Service {
+    pub async fn new(nc: NetworkConfig, timeout: u32) -> Result<Self, DhcpServiceError> {
+        let mut socket = netlink::Socket::<NetlinkRoute>::new()
+            .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
+        let link = socket
+            .get_link(LinkID::Name(nc.container_iface.clone()))
+            .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
+        let link_local = wait_for_link_local(&mut socket, link.header.index, timeout).await?;
+
+        let mut config = DhcpV6Config::new(&nc.container_iface, DhcpV6Mode::NonTemporaryAddresses);
+        config.set_iface_index(link.header.index);
+        config.set_link_local_ip(link_local);
+        config.set_timeout_sec(timeout);
+        // Same as the DHCPv4 client id, use the container id so the container
+        // keeps its address across restarts with a new random mac.
+        if nc.container_id.is_empty() {
+            let mac = core_utils::CoreUtils::decode_address_from_hex(&nc.container_mac_addr)
+                .map_err(|e| DhcpServiceError::new(InvalidArgument, e.to_string()))?;
+            config.set_duid_by_iface_mac(&mac);
+        } else {
+            config.set_duid(container_id_duid(&nc.container_id));
+        }
+
+        let client = DhcpV6Client::init(config, None)
+            .await
+            .map_err(|err| DhcpServiceError::new(InvalidArgument, err.to_string()))?;
+        Ok(Self {
+            client,
+            network_config: nc,
+            previous_lease: None,
+        })
+    }
+
+    /// Performs the DHCPv6 solicit, advertise, request and reply exchange.
+    ///
+    /// returns: Result<Lease, DhcpSearchError>. Either finds a lease
+    /// successfully, finds no lease, or fails
+    pub async fn get_lease(&mut self) -> Result<NetavarkLease, DhcpServiceError> {
+        loop {
+            match self.client.run().await {
+                Ok(DhcpV6State::Done(lease)) => {
+                    let netavark_lease = self.netavark_lease(&lease);
+                    debug!(
+                        "found a DHCPv6 lease for {:?}, {:?}",
+                        &self.network_config.container_mac_addr, &netavark_lease
+                    );
+                    self.previous_lease = Some(*lease);
+                    return Ok(netavark_lease);
+                }
+                Ok(state) => {
+                    log::debug!(
+                        "DHCPv6 client on {} for {} enter {state} state",
+                        self.network_config.container_iface,
+                        self.network_config.container_mac_addr
+                    );
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Convert the mozim lease into the lease returned by the proxy.
+    fn netavark_lease(&self, lease: &MozimV6Lease) -> NetavarkLease {
+        let mut netavark_lease = <NetavarkLease as From<MozimV6Lease>>::from(lease.clone());
+        netavark_lease.add_domain_name(&self.network_config.domain_name);
+        netavark_lease.add_mac_address(&self.network_config.container_mac_addr);
+        netavark_lease.acquired = unix_now();
+        netavark_lease
+    }
+
+    /// Use a lease from the proxy cache as the current lease, see
+    /// DhcpV4Service::set_previous_lease(). Leases cached by older versions
+    /// miss the server DUID and cannot be renewed.
+    pub fn set_previous_lease(&mut self, lease: &NetavarkLease) -> Result<(), DhcpServiceError> {
+        if lease.server_duid.is_empty() {
+            return Err(DhcpServiceError::new(
+                InvalidArgument,
+                "cached DHCPv6 lease has no server DUID".to_string(),
+            ));
+        }
+        let mut previous = MozimV6Lease::default();
+        (previous.t1_sec, previous.t2_sec, previous.valid_time_sec) =
+            remaining_times(lease, lease.lease_time);
+        previous.preferred_time_sec = remaining_times(lease, lease.preferred_time).2;
+        previous.iaid = lease.iaid;
+        previous.ia_type = Some(DhcpV6IaType::NonTemporaryAddresses);
+        previous.address = parse_lease_addr(&lease.yiaddr)?;
+        previous.srv_duid = DhcpV6Duid::Raw(lease.server_duid.clone());
+        if !lease.srv_id.is_empty() {
+            previous.srv_ip = parse_lease_addr(&lease.srv_id)?;
+        }
+        self.client
+            .done(previous.clone())
+            .map_err(|e| DhcpServiceError::new(Bug, e.to_string()))?;
+        self.previous_lease = Some(previous);
+        Ok(())
+    }
+
+    /// Sends a DHCPv6 RELEASE message for the given lease.
+    /// This is a "best effort" operation and should not block teardown.
+    pub async fn release_lease(&mut self) -> Result<(), DhcpServiceError> {
+        if let Some(lease) = &self.previous_lease {
+            debug!(
+                "Attempting to release DHCPv6 lease for MAC: {}",
+                &self.network_config.container_mac_addr
+            );
+            self.client
+                .release(lease)
+                .await
+                .map_err(|e| DhcpServiceError::new(Bug, e.to_string()))
+        } else {
+            debug!(
+                "No previous DHCPv6 lease to release for MAC: {}",
+                &self.network_config.container_mac_addr
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Running DHCP service of either family, used to track the renewal tasks.
+#[derive(Debug, Clone)]
+pub enum DhcpService {
+    V4(Arc<Mutex<DhcpV4Service>>),
+    V6(Arc<Mutex<DhcpV6Service>>),
+}
+
+impl DhcpService {
+    /// Release the current lease of the service, best effort.
+    pub async fn release_lease(&self) -> Result<(), DhcpServiceError> {
+        match self {
+            DhcpService::V4(service) => service.lock().await.release_lease().await,
+            DhcpService::V6(service) => service.lock().await.release_lease().await,
+        }
+    }
+}
+
+/// T1, T2 and the given lifetime of a cached lease, reduced by the time that
+/// passed since it was acquired. A lease of unknown age is renewed right away.
+fn remaining_times(lease: &NetavarkLease, lifetime: u32) -> (u32, u32, u32) {
+    if lease.acquired == 0 {
+        return (0, lease.t2, lifetime);
+    }
+    let elapsed = u32::try_from(unix_now().saturating_sub(lease.acquired)).unwrap_or(u32::MAX);
+    (
+        lease.t1.saturating_sub(elapsed),
+        lease.t2.saturating_sub(elapsed),
+        lifetime.saturating_sub(elapsed),
+    )
+}
+
+fn parse_lease_addr<T: std::str::FromStr>(addr: &str) -> Result<T, DhcpServiceError>
+where
+    T::Err: std::fmt::Display,
+{
+    addr.parse().map_err(|e| {
+        DhcpServiceError::new(
+            InvalidArgument,
+            format!("invalid address {addr} in cached lease: {e}"),
+        )
+    })
+}
+
+/// Build a DUID-UUID from the container id so it stays stable for the
+/// lifetime of the container.
+fn container_id_duid(container_id: &str) -> DhcpV6Duid {
+    let hash = Sha256::digest(container_id.as_bytes());
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&hash[..16]);
+    DhcpV6Duid::UUID(DhcpV6DuidUuid::new(u128::from_be_bytes(uuid)))
+}
+
+/// The DHCPv6 client sends from the link local address which can only be
+/// used once duplicate address detection finished, wait for it.
+async fn wait_for_link_local(
+    socket: &mut netlink::Socket<NetlinkRoute>,
+    index: u32,
+    timeout: u32,
+) -> Result<Ipv6Addr, DhcpServiceError> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout.into());
+    loop {
+        let addresses = socket
+            .dump_addresses(Some(index))
+            .map_err(|e| DhcpServiceError::new(Bug, e.to_string()))?;
+        for msg in addresses.iter().filter(|m| m.header.index == index) {
+            if msg.header.flags.contains(AddressHeaderFlags::Tentative) {
+                continue;
+            }
+            let mut addr = None;
+            let mut tentative = false;
+            for nla in msg.attributes.iter() {
+                match nla {
+                    AddressAttribute::Address(IpAddr::V6(ip)) => addr = Some(*ip),
+                    AddressAttribute::Flags(flags) => {
+                        tentative =
+                            flags.intersects(AddressFlags::Tentative | AddressFlags::Dadfailed)
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(ip) = addr {
+                if !tentative && ip.is_unicast_link_local() {
+                    return Ok(ip);
+                }
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(DhcpServiceError::new(
+                Timeout,
+                "timeout waiting for the ipv6 link local address".to_string(),
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+impl From<DhcpError> for DhcpServiceError {
+    fn from(err: DhcpError) -> Self {
+        match err.kind() {
+            mozim::ErrorKind::Timeout => DhcpServiceError::new(Timeout, err.to_string()),
+            mozim::ErrorKind::InvalidArgument => {
+                DhcpServiceError::new(InvalidArgument, err.to_string())
+            }
+            mozim::ErrorKind::NoLease => DhcpServiceError::new(NoLease, err.to_string()),
+            _ => DhcpServiceError::new(Bug, err.to_string()),
+        }
+    }
+}
+
 impl std::fmt::Display for DhcpServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.msg)
@@ -196,12 +505,63 @@ impl From<DhcpServiceError> for Status {
     }
 }
 
-pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>) {
+/// Outcome of a run of the renewal loop.
+pub enum LeaseEvent {
+    /// A new or renewed lease was acquired.
+    Renewed(Box<NetavarkLease>),
+    /// The client failed and will retry.
+    Failed(String),
+}
+
+/// Called after every renewal and failure, used to keep the proxy cache and
+/// the renewal state up to date.
+pub type LeaseNotifier = Arc<dyn Fn(LeaseEvent) + Send + Sync>;
+
+/// State of a renewal task as reported by the proxy, times are unix seconds.
+#[derive(Debug, Default, Clone)]
+pub struct RenewalState {
+    /// When the current lease was acquired, 0 for a lease restored from the
+    /// cache until the first renewal.
+    pub acquired: u64,
+    pub t1: u32,
+    pub t2: u32,
+    pub last_error: Option<(u64, String)>,
+}
+
+impl RenewalState {
+    pub fn renewed(&mut self, lease: &NetavarkLease) {
+        self.acquired = unix_now();
+        self.t1 = lease.t1;
+        self.t2 = lease.t2;
+    }
+
+    pub fn failed(&mut self, err: String) {
+        self.last_error = Some((unix_now(), err));
+    }
+
+    /// Time of the next renewal (T1) or rebind (T2), 0 when unknown.
+    pub fn next(&self, t: u32) -> u64 {
+        if self.acquired == 0 {
+            return 0;
+        }
+        self.acquired + u64::from(t)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>, notify: LeaseNotifier) {
     let mut client = service_arc.lock().await;
     loop {
         let lease_result = client.client.run().await;
         match lease_result {
             Ok(DhcpV4State::Done(lease)) => {
+                let lease = with_ms_classless_routes(lease);
                 log::info!(
                     "got new lease for mac {}: {:?}",
                     &client.network_config.container_mac_addr,
@@ -212,6 +572,8 @@ pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>) {
                     if old_lease.yiaddr != lease.yiaddr
                         || old_lease.subnet_mask != lease.subnet_mask
                         || old_lease.gateways != lease.gateways
+                        || old_lease.mtu != lease.mtu
+                        || old_lease.classless_routes != lease.classless_routes
                     {
                         log::info!(
                             "ip or gateway for mac {} changed, update address",
@@ -227,6 +589,7 @@ pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>) {
                         }
                     }
                 }
+                notify(LeaseEvent::Renewed(Box::new(client.netavark_lease(&lease))));
                 client.previous_lease = Some(*lease);
             }
             Ok(state) => {
@@ -241,6 +604,7 @@ pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>) {
                     "Failed to acquire DHCPv4 lease for {}: {err}",
                     &client.network_config.container_mac_addr
                 );
+                notify(LeaseEvent::Failed(err.to_string()));
                 log::info!(
                     "Retrying DHCPv4 proxy for {}",
                     &client.network_config.container_mac_addr
@@ -250,6 +614,86 @@ pub async fn process_client_stream(service_arc: Arc<Mutex<DhcpV4Service>>) {
         }
     }
 }
+pub async fn process_client_stream_v6(
+    service_arc: Arc<Mutex<DhcpV6Service>>,
+    notify: LeaseNotifier,
+) {
+    let mut client = service_arc.lock().await;
+    loop {
+        match client.client.run().await {
+            Ok(DhcpV6State::Done(lease)) => {
+                log::info!(
+                    "got new DHCPv6 lease for mac {}: {:?}",
+                    &client.network_config.container_mac_addr,
+                    &lease
+                );
+                if let Some(old_lease) = &client.previous_lease {
+                    if old_lease.address != lease.address {
+                        log::info!(
+                            "ipv6 address for mac {} changed, update address",
+                            &client.network_config.container_mac_addr
+                        );
+                        if let Err(err) = update_lease_ip_v6(
+                            &client.network_config.ns_path,
+                            &client.network_config.container_iface,
+                            old_lease.address,
+                            lease.address,
+                        ) {
+                            log::error!("{err}");
+                        }
+                    }
+                }
+                notify(LeaseEvent::Renewed(Box::new(client.netavark_lease(&lease))));
+                client.previous_lease = Some(*lease);
+            }
+            Ok(state) => {
+                log::debug!(
+                    "DHCPv6 client on {} for {} enter {state} state",
+                    client.network_config.container_iface,
+                    client.network_config.container_mac_addr
+                );
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to acquire DHCPv6 lease for {}: {err}",
+                    &client.network_config.container_mac_addr
+                );
+                notify(LeaseEvent::Failed(err.to_string()));
+                log::info!(
+                    "Retrying DHCPv6 client for {}",
+                    &client.network_config.container_mac_addr
+                );
+                client.client.clean_up();
+            }
+        }
+    }
+}
+
+fn update_lease_ip_v6(
+    netns: &str,
+    interface: &str,
+    old_addr: Ipv6Addr,
+    new_addr: Ipv6Addr,
+) -> NetavarkResult<()> {
+    let (_, netns) =
+        core_utils::open_netlink_sockets(netns).wrap("failed to open netlink socket in netns")?;
+    let mut sock = netns.netlink;
+    let link = sock
+        .get_link(LinkID::Name(interface.to_string()))
+        .wrap("get interface in netns")?;
+    sock.add_addr(
+        link.header.index,
+        &ipnet::IpNet::V6(ipnet::Ipv6Net::new(new_addr, 128)?),
+    )
+    .wrap("add new addr")?;
+    sock.del_addr(
+        link.header.index,
+        &ipnet::IpNet::V6(ipnet::Ipv6Net::new(old_addr, 128)?),
+    )
+    .wrap("remove old addrs")?;
+    Ok(())
+}
+
 fn update_lease_ip(
     netns: &str,
     interface: &str,
@@ -267,49 +711,81 @@ fn update_lease_ip(
         ipnet::Ipv4Net::with_netmask(new_lease.yiaddr, new_lease.subnet_mask),
         "create ipnet from new lease"
     )?;
+    let link = sock
+        .get_link(LinkID::Name(interface.to_string()))
+        .wrap("get interface in netns")?;
+
+    if new_lease.mtu != old_lease.mtu {
+        if let Some(mtu) = new_lease.mtu.filter(|mtu| *mtu > 0) {
+            sock.set_mtu(LinkID::ID(link.header.index), mtu.into())
+                .wrap("set mtu")?;
+        }
+    }
 
     if new_net != old_net {
-        let link = sock
-            .get_link(LinkID::Name(interface.to_string()))
-            .wrap("get interface in netns")?;
         sock.add_addr(link.header.index, &ipnet::IpNet::V4(new_net))
             .wrap("add new addr")?;
         sock.del_addr(link.header.index, &ipnet::IpNet::V4(old_net))
             .wrap("remove old addrs")?;
     }
-    if new_lease.gateways != old_lease.gateways {
-        if let Some(gws) = &old_lease.gateways {
-            let old_gw = gws.first();
-            if let Some(gw) = old_gw {
-                let route = Route::Ipv4 {
-                    dest: ipnet::Ipv4Net::new(Ipv4Addr::new(0, 0, 0, 0), 0)?,
-                    gw: Some(*gw),
-                    metric: None,
-                    route_type: RouteType::Unicast,
-                };
-                match sock.del_route(&route) {
-                    Ok(_) => {}
-                    Err(err) => match err.unwrap() {
-                        // special case do not error if route does not exists
-                        NetavarkError::Netlink(e) if -e.raw_code() == libc::ESRCH => {}
-                        _ => return Err(err).wrap("delete old default route"),
-                    },
-                };
-            }
+
+    // RFC 3442: the router option is ignored when classless static routes are sent
+    let old_routes = lease_routes(old_lease);
+    let new_routes = lease_routes(new_lease);
+    if new_lease.gateways != old_lease.gateways || new_routes != old_routes {
+        if !old_routes.is_empty() {
+            ip::del_classless_routes(&mut sock, &old_routes)?;
+        } else if let Some(gw) = old_lease.gateways.as_ref().and_then(|gws| gws.first()) {
+            let route = Route::Ipv4 {
+                dest: ipnet::Ipv4Net::new(Ipv4Addr::new(0, 0, 0, 0), 0)?,
+                gw: Some(*gw),
+                metric: None,
+                route_type: RouteType::Unicast,
+            };
+            match sock.del_route(&route) {
+                Ok(_) => {}
+                Err(err) => match err.unwrap() {
+                    // special case do not error if route does not exists
+                    NetavarkError::Netlink(e) if -e.raw_code() == libc::ESRCH => {}
+                    _ => return Err(err).wrap("delete old default route"),
+                },
+            };
         }
-        if let Some(gws) = &new_lease.gateways {
-            let new_gw = gws.first();
-            if let Some(gw) = new_gw {
-                let route = Route::Ipv4 {
-                    dest: ipnet::Ipv4Net::new(Ipv4Addr::new(0, 0, 0, 0), 0)?,
-                    gw: Some(*gw),
-                    metric: None,
-                    route_type: RouteType::Unicast,
-                };
-                sock.add_route(&route)?;
-            }
+        if !new_routes.is_empty() {
+            ip::add_classless_routes(&mut sock, link.header.index, &new_routes)?;
+        } else if let Some(gw) = new_lease.gateways.as_ref().and_then(|gws| gws.first()) {
+            let route = Route::Ipv4 {
+                dest: ipnet::Ipv4Net::new(Ipv4Addr::new(0, 0, 0, 0), 0)?,
+                gw: Some(*gw),
+                metric: None,
+                route_type: RouteType::Unicast,
+            };
+            sock.add_route(&route)?;
         }
     }
 
     Ok(())
 }
+
+/// Some servers only send the classless static routes in option 249, use
+/// them when option 121 is missing.
+fn with_ms_classless_routes(mut lease: Box<MozimV4Lease>) -> Box<MozimV4Lease> {
+    if lease.classless_routes.is_none() {
+        lease.classless_routes = ms_classless_routes(&lease);
+    }
+    lease
+}
+
+/// The classless static routes of the mozim lease, invalid prefixes are skipped.
+fn lease_routes(lease: &MozimV4Lease) -> Vec<LeaseRoute> {
+    lease
+        .classless_routes
+        .iter()
+        .flatten()
+        .filter_map(|r| {
+            ipnet::Ipv4Net::new(r.destination, r.prefix_length)
+                .ok()
+                .map(|dest| (dest, r.router))
+        })
+        .collect()
+}
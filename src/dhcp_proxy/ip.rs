@@ -7,14 +7,20 @@
 
 pub use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, Lease};
 pub use crate::dhcp_proxy::types::{CustomErr, ProxyError};
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
 use crate::network::core_utils;
 use crate::network::netlink::Socket;
-use crate::network::netlink_route::{LinkID, NetlinkRoute};
-use ipnet::IpNet;
+use crate::network::netlink_route::{LinkID, NetlinkRoute, Route};
+use ipnet::{IpNet, Ipv4Net};
 use log::debug;
+use netlink_packet_route::route::RouteType;
 use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
 
+/// A route of the DHCP classless static route option, a gateway of 0.0.0.0
+/// means the destination is on link.
+pub type LeaseRoute = (Ipv4Net, Ipv4Addr);
+
 /*
    Information that came back in the DHCP lease like name_servers,
    domain and host names, etc. will be implemented in podman; not here.
@@ -25,9 +31,11 @@ struct MacVLAN {
     address: IpAddr,
     gateways: Vec<IpNet>,
     interface: String,
-    // Unset right now
-    // mtu: u32,
+    // 0 keeps the interface mtu
+    mtu: u32,
     prefix_length: u8,
+    // classless static routes, when set the gateways are not used
+    routes: Vec<LeaseRoute>,
 }
 
 trait Address<T> {
@@ -84,13 +92,25 @@ fn test_handle_gws() {
 // IPV4 implementation
 impl Address<Ipv4Addr> for MacVLAN {
     fn new(l: &NetavarkLease, interface: &str) -> Result<MacVLAN, ProxyError> {
-        debug!("new ipv4 macvlan for {interface}");
+        debug!("new macvlan address for {interface}");
         let address = match IpAddr::from_str(&l.yiaddr) {
             Ok(a) => a,
             Err(e) => {
                 return Err(ProxyError::new(format!("bad address: {e}")));
             }
         };
+        if l.is_v6 {
+            // DHCPv6 has no prefix length or gateways, the address is added as /128
+            // and the routes are learned from router advertisements.
+            return Ok(MacVLAN {
+                address,
+                gateways: Vec::new(),
+                interface: interface.to_string(),
+                mtu: 0,
+                prefix_length: 128,
+                routes: Vec::new(),
+            });
+        }
         let gateways = match handle_gws(l.gateways.clone(), &l.subnet_mask) {
             Ok(g) => g,
             Err(e) => {
@@ -101,13 +121,14 @@ impl Address<Ipv4Addr> for MacVLAN {
             Ok(u) => u as u8,
             Err(e) => return Err(ProxyError::new(e.to_string())),
         };
+        let routes = lease_routes(l)?;
         Ok(MacVLAN {
             address,
             gateways,
             interface: interface.to_string(),
-            // Disabled for now
-            // mtu: l.mtu,
+            mtu: l.mtu,
             prefix_length,
+            routes,
         })
     }
 
@@ -124,6 +145,14 @@ impl Address<Ipv4Addr> for MacVLAN {
 
     // add one or more routes to the container namespace
     fn add_gws(&self, nls: &mut Socket<NetlinkRoute>) -> Result<(), ProxyError> {
+        // RFC 3442: the router option must be ignored when classless static
+        // routes are sent
+        if !self.routes.is_empty() {
+            debug!("adding classless static routes to {}", self.interface);
+            let dev = nls.get_link(LinkID::Name(self.interface.clone()))?;
+            return add_classless_routes(nls, dev.header.index, &self.routes)
+                .map_err(|e| ProxyError::new(e.to_string()));
+        }
         debug!("adding gateways to {}", self.interface);
         match core_utils::add_default_routes(nls, &self.gateways, None) {
             Ok(_) => Ok(()),
@@ -132,12 +161,80 @@ impl Address<Ipv4Addr> for MacVLAN {
     }
 }
 
+impl MacVLAN {
+    fn set_mtu(&self, nls: &mut Socket<NetlinkRoute>) -> Result<(), ProxyError> {
+        if self.mtu == 0 {
+            return Ok(());
+        }
+        debug!("setting mtu {} on {}", self.mtu, self.interface);
+        nls.set_mtu(LinkID::Name(self.interface.clone()), self.mtu)
+            .map_err(|e| ProxyError::new(format!("set mtu: {e}")))
+    }
+}
+
+/// Parse the classless static routes of the lease.
+pub fn lease_routes(l: &NetavarkLease) -> Result<Vec<LeaseRoute>, ProxyError> {
+    l.classless_routes
+        .iter()
+        .map(|r| {
+            let dest = Ipv4Net::from_str(&r.destination)
+                .map_err(|e| ProxyError::new(format!("bad route {}: {e}", r.destination)))?;
+            let gw = Ipv4Addr::from_str(&r.gateway)
+                .map_err(|e| ProxyError::new(format!("bad route gateway {}: {e}", r.gateway)))?;
+            Ok((dest, gw))
+        })
+        .collect()
+}
+
+fn classless_route(route: &LeaseRoute) -> Route {
+    let (dest, gw) = *route;
+    Route::Ipv4 {
+        dest,
+        gw: if gw.is_unspecified() { None } else { Some(gw) },
+        metric: None,
+        route_type: RouteType::Unicast,
+    }
+}
+
+/// Add the classless static routes on the given link.
+pub fn add_classless_routes(
+    nls: &mut Socket<NetlinkRoute>,
+    link_index: u32,
+    routes: &[LeaseRoute],
+) -> NetavarkResult<()> {
+    for route in routes {
+        let route = classless_route(route);
+        nls.add_link_route(&route, link_index)
+            .wrap(format!("add classless static route {route}"))?;
+    }
+    Ok(())
+}
+
+/// Remove the classless static routes, routes that no longer exist are ignored.
+pub fn del_classless_routes(
+    nls: &mut Socket<NetlinkRoute>,
+    routes: &[LeaseRoute],
+) -> NetavarkResult<()> {
+    for route in routes {
+        let route = classless_route(route);
+        match nls.del_route(&route) {
+            Ok(_) => {}
+            Err(err) => match err.unwrap() {
+                NetavarkError::Netlink(e) if -e.raw_code() == libc::ESRCH => {}
+                _ => return Err(err).wrap(format!("delete classless static route {route}")),
+            },
+        }
+    }
+    Ok(())
+}
+
 // setup takes the DHCP lease and some additional information and
 // applies the TCP/IP information to the namespace.
 pub fn setup(lease: &NetavarkLease, interface: &str, ns_path: &str) -> Result<(), ProxyError> {
     debug!("setting up {interface}");
     let vlan = MacVLAN::new(lease, interface)?;
     let (_, mut netns) = core_utils::open_netlink_sockets(ns_path)?;
+    vlan.set_mtu(&mut netns.netlink)?;
     vlan.add_ip(&mut netns.netlink)?;
     vlan.add_gws(&mut netns.netlink)
 }
@@ -1,6 +1,6 @@
 extern crate core;
 
-use crate::dhcp_proxy::lib::g_rpc::{Lease, NetworkConfig};
+use crate::dhcp_proxy::lib::g_rpc::{Empty, Lease, LeaseList, LeaseRequest, NetworkConfig};
 use crate::error::NetavarkError;
 use std::convert::TryFrom;
 use std::error::Error;
@@ -22,27 +22,57 @@ pub mod g_rpc {
     include!(concat!(env!("OUT_DIR"), "/netavark_proxy.rs"));
     use crate::dhcp_proxy::lib::VectorConv;
     use crate::dhcp_proxy::types::{CustomErr, ProxyError};
-    use mozim::DhcpV4Lease;
+    use mozim::{DhcpV4ClasslessRoute, DhcpV4Lease, DhcpV6Lease};
     use std::convert::TryFrom;
-    use std::net::Ipv4Addr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
     use std::str::FromStr;
 
+    // DHCPv4 domain search option, see RFC 3397
+    pub const DHCPV4_OPTION_DOMAIN_SEARCH: u8 = 119;
+    // Microsoft classless static route option, same format as option 121
+    pub const DHCPV4_OPTION_MS_CLASSLESS_ROUTES: u8 = 249;
+    // DHCPv6 option codes, see RFC 3646
+    const DHCPV6_OPTION_SERVERID: u16 = 2;
+    const DHCPV6_OPTION_DNS_SERVERS: u16 = 23;
+    const DHCPV6_OPTION_DOMAIN_LIST: u16 = 24;
+
     impl Lease {
         /// Add mac address to a lease
         pub fn add_mac_address(&mut self, mac_addr: &String) {
             self.mac_address = mac_addr.to_string()
         }
-        /// Update the domain name of the lease
+        /// Update the domain name of the lease, an empty name keeps the
+        /// domain name sent by the DHCP server
         pub fn add_domain_name(&mut self, domain_name: &String) {
-            self.domain_name = domain_name.to_string();
+            if !domain_name.is_empty() {
+                self.domain_name = domain_name.to_string();
+            }
+        }
+    }
+
+    impl From<&DhcpV4ClasslessRoute> for ClasslessRoute {
+        fn from(r: &DhcpV4ClasslessRoute) -> ClasslessRoute {
+            ClasslessRoute {
+                destination: format!("{}/{}", r.destination, r.prefix_length),
+                gateway: r.router.to_string(),
+            }
         }
     }
 
     impl From<DhcpV4Lease> for Lease {
         fn from(l: DhcpV4Lease) -> Lease {
             // Since these fields are optional as per mozim. Match them first and then set them
-            let domain_name = l.domain_name.unwrap_or_default();
+            let domain_name = l.domain_name.clone().unwrap_or_default();
             let mtu = l.mtu.unwrap_or(0) as u32;
+            let domain_search = v4_option_data(&l, DHCPV4_OPTION_DOMAIN_SEARCH)
+                .map(|data| parse_domain_names(&data))
+                .unwrap_or_default();
+            let classless_routes = l
+                .classless_routes
+                .iter()
+                .flatten()
+                .map(ClasslessRoute::from)
+                .collect();
 
             Lease {
                 t1: l.t1_sec,
@@ -62,10 +92,178 @@ pub mod g_rpc {
                 ntp_servers: handle_ip_vectors(l.ntp_srvs),
                 host_name: l.host_name.unwrap_or_else(|| String::from("")),
                 is_v6: false,
+                classless_routes,
+                domain_search,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<DhcpV6Lease> for Lease {
+        fn from(l: DhcpV6Lease) -> Lease {
+            let dns_servers = v6_option_data(&l, DHCPV6_OPTION_DNS_SERVERS)
+                .map(|opts| parse_v6_addrs(&opts))
+                .unwrap_or_default();
+            let domain_search: Vec<String> = v6_option_data(&l, DHCPV6_OPTION_DOMAIN_LIST)
+                .map(|opts| {
+                    opts.iter()
+                        .flat_map(|data| parse_domain_names(data))
+                        .collect()
+                })
+                .unwrap_or_default();
+            // only a single domain name can be returned, use the first one
+            // from the search list
+            let domain_name = domain_search.first().cloned().unwrap_or_default();
+            let server_duid = v6_option_data(&l, DHCPV6_OPTION_SERVERID)
+                .and_then(|opts| opts.into_iter().next())
+                .unwrap_or_default();
+            let srv_id = if l.srv_ip.is_unspecified() {
+                "".to_string()
+            } else {
+                l.srv_ip.to_string()
+            };
+
+            Lease {
+                t1: l.t1_sec,
+                t2: l.t2_sec,
+                lease_time: l.valid_time_sec,
+                // DHCPv6 does not carry the mtu, it is sent in router advertisements
+                mtu: 0,
+                domain_name,
+                mac_address: "".to_string(),
+                siaddr: "".to_string(),
+                yiaddr: l.address.to_string(),
+                srv_id,
+                // DHCPv6 addresses have no prefix length, the address is
+                // always configured as /128 and the on-link prefix and
+                // gateways are learned from router advertisements.
+                subnet_mask: "".to_string(),
+                broadcast_addr: "".to_string(),
+                dns_servers,
+                gateways: vec![],
+                ntp_servers: l.ntp_srvs,
+                host_name: "".to_string(),
+                is_v6: true,
+                classless_routes: vec![],
+                domain_search,
+                iaid: l.iaid,
+                preferred_time: l.preferred_time_sec,
+                server_duid,
+                acquired: 0,
             }
         }
     }
 
+    /// the raw data of a DHCPv4 option, mozim returns it with the leading
+    /// option code and length
+    fn v4_option_data(l: &DhcpV4Lease, code: u8) -> Option<Vec<u8>> {
+        l.get_option_raw(code)
+            .map(|data| data.get(2..).unwrap_or_default().to_vec())
+    }
+
+    /// the routes of the Microsoft classless static route option, used by
+    /// servers which do not send option 121
+    pub fn ms_classless_routes(l: &DhcpV4Lease) -> Option<Vec<DhcpV4ClasslessRoute>> {
+        v4_option_data(l, DHCPV4_OPTION_MS_CLASSLESS_ROUTES)
+            .and_then(|data| parse_classless_routes(&data))
+    }
+
+    /// parse classless static routes as encoded in RFC 3442, the destination
+    /// only carries the significant octets of the prefix
+    fn parse_classless_routes(data: &[u8]) -> Option<Vec<DhcpV4ClasslessRoute>> {
+        let mut routes = Vec::new();
+        let mut rest = data;
+        while let Some((&prefix_length, tail)) = rest.split_first() {
+            if prefix_length > 32 {
+                return None;
+            }
+            let significant = usize::from(prefix_length).div_ceil(8);
+            let mut destination = [0u8; 4];
+            destination[..significant].copy_from_slice(tail.get(..significant)?);
+            let router: [u8; 4] = tail.get(significant..significant + 4)?.try_into().ok()?;
+            routes.push(DhcpV4ClasslessRoute {
+                destination: Ipv4Addr::from(destination),
+                prefix_length,
+                router: Ipv4Addr::from(router),
+            });
+            rest = &tail[significant + 4..];
+        }
+        Some(routes)
+    }
+
+    /// the raw data of each DHCPv6 option with the given code, without the
+    /// leading option code and length
+    fn v6_option_data(l: &DhcpV6Lease, code: u16) -> Option<Vec<Vec<u8>>> {
+        l.get_option_raw(code).map(|opts| {
+            opts.into_iter()
+                .map(|data| data.get(4..).unwrap_or_default().to_vec())
+                .collect()
+        })
+    }
+
+    /// parse the raw DHCPv6 option data as list of ipv6 addresses
+    fn parse_v6_addrs(opts: &[Vec<u8>]) -> Vec<String> {
+        opts.iter()
+            .flat_map(|data| data.chunks_exact(16))
+            .map(|chunk| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(chunk);
+                Ipv6Addr::from(octets).to_string()
+            })
+            .collect()
+    }
+
+    /// parse a list of DNS names encoded as labels (RFC 1035 section 3.1),
+    /// compression pointers as allowed in the DHCPv4 domain search option
+    /// (RFC 3397) are followed
+    fn parse_domain_names(data: &[u8]) -> Vec<String> {
+        let mut domains = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            match read_domain_name(data, pos) {
+                Some((name, next)) => {
+                    if !name.is_empty() {
+                        domains.push(name);
+                    }
+                    pos = next;
+                }
+                // truncated option, ignore the rest
+                None => break,
+            }
+        }
+        domains
+    }
+
+    /// read the name starting at pos, returns the name and the position of
+    /// the next name
+    fn read_domain_name(data: &[u8], mut pos: usize) -> Option<(String, usize)> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut next = None;
+        let mut jumps = 0;
+        loop {
+            let len = *data.get(pos)? as usize;
+            if len == 0 {
+                pos += 1;
+                break;
+            }
+            if len & 0xc0 == 0xc0 {
+                let offset = ((len & 0x3f) << 8) | *data.get(pos + 1)? as usize;
+                next.get_or_insert(pos + 2);
+                // a pointer loop would never end
+                jumps += 1;
+                if jumps > data.len() {
+                    return None;
+                }
+                pos = offset;
+                continue;
+            }
+            let label = data.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            pos += 1 + len;
+        }
+        Some((labels.join("."), next.unwrap_or(pos)))
+    }
+
     impl TryFrom<Lease> for DhcpV4Lease {
         type Error = ProxyError;
         fn try_from(l: Lease) -> Result<Self, ProxyError> {
@@ -142,6 +340,74 @@ pub mod g_rpc {
         }
     }
 
+    #[test]
+    fn test_parse_v6_options() {
+        let mut dns = Vec::from(Ipv6Addr::from_str("fd00::1").unwrap().octets());
+        dns.extend_from_slice(&Ipv6Addr::from_str("fd00::2").unwrap().octets());
+        assert_eq!(parse_v6_addrs(&[dns]), vec!["fd00::1", "fd00::2"]);
+
+        let domains = b"\x07example\x03com\x00\x03lab\x07example\x03com\x00".to_vec();
+        assert_eq!(
+            parse_domain_names(&domains),
+            vec!["example.com", "lab.example.com"]
+        );
+        // truncated names are ignored
+        assert!(parse_domain_names(b"\x07exam").is_empty());
+    }
+
+    #[test]
+    fn test_parse_compressed_domain_names() {
+        // RFC 3397 example: eng.apple.com. and marketing.apple.com.
+        let domains = b"\x03eng\x05apple\x03com\x00\x09marketing\xc0\x04".to_vec();
+        assert_eq!(
+            parse_domain_names(&domains),
+            vec!["eng.apple.com", "marketing.apple.com"]
+        );
+        // pointer loops are rejected
+        assert!(parse_domain_names(b"\x03foo\xc0\x00").is_empty());
+    }
+
+    #[test]
+    fn test_parse_classless_routes() {
+        // 10.0.0.0/8 via 192.168.1.1 and the default route via 192.168.1.254
+        let data = [8, 10, 192, 168, 1, 1, 0, 192, 168, 1, 254];
+        let routes = parse_classless_routes(&data).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].destination, Ipv4Addr::new(10, 0, 0, 0));
+        assert_eq!(routes[0].prefix_length, 8);
+        assert_eq!(routes[0].router, Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(routes[1].destination, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(routes[1].prefix_length, 0);
+        assert_eq!(routes[1].router, Ipv4Addr::new(192, 168, 1, 254));
+        // truncated routes and invalid prefixes are rejected
+        assert!(parse_classless_routes(&[24, 10, 0, 0, 192, 168]).is_none());
+        assert!(parse_classless_routes(&[33, 10, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_v4_lease_options() {
+        let mut lease = DhcpV4Lease::default();
+        lease.yiaddr = Ipv4Addr::new(10, 0, 0, 5);
+        lease.mtu = Some(1400);
+        lease.ntp_srvs = Some(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        lease.classless_routes = Some(vec![DhcpV4ClasslessRoute {
+            destination: Ipv4Addr::new(192, 168, 0, 0),
+            prefix_length: 16,
+            router: Ipv4Addr::new(10, 0, 0, 254),
+        }]);
+        let lease = Lease::from(lease);
+        assert_eq!(lease.mtu, 1400);
+        assert_eq!(lease.ntp_servers, vec!["10.0.0.1"]);
+        assert_eq!(
+            lease.classless_routes,
+            vec![ClasslessRoute {
+                destination: "192.168.0.0/16".to_string(),
+                gateway: "10.0.0.254".to_string(),
+            }]
+        );
+        assert!(lease.domain_search.is_empty());
+    }
+
     #[test]
     fn test_handle_gw() {
         use std::str::FromStr;
@@ -277,6 +543,46 @@ impl NetworkConfig {
     }
 }
 
+impl LeaseRequest {
+    /// get_leases is a wrapper function to get the cached leases of a
+    /// container, and the state of their renewal, from the nvproxy
+    ///
+    /// # Arguments
+    ///
+    /// * `p`: path to uds
+    ///
+    /// returns: Result<LeaseList, NetavarkError>
+    pub async fn get_leases(self, p: &str) -> Result<LeaseList, NetavarkError> {
+        let mut client = NetworkConfig::get_client(p.to_string()).await?;
+        match client.get_lease(Request::new(self)).await {
+            Ok(l) => Ok(l.into_inner()),
+            Err(e) => Err(NetavarkError::msg(format!(
+                "get DHCP lease info: {}",
+                e.message()
+            ))),
+        }
+    }
+}
+
+/// list_leases is a wrapper function to get all leases cached by the nvproxy
+/// and the state of their renewal
+///
+/// # Arguments
+///
+/// * `p`: path to uds
+///
+/// returns: Result<LeaseList, NetavarkError>
+pub async fn list_leases(p: &str) -> Result<LeaseList, NetavarkError> {
+    let mut client = NetworkConfig::get_client(p.to_string()).await?;
+    match client.list_leases(Request::new(Empty {})).await {
+        Ok(l) => Ok(l.into_inner()),
+        Err(e) => Err(NetavarkError::msg(format!(
+            "list DHCP leases: {}",
+            e.message()
+        ))),
+    }
+}
+
 trait VectorConv {
     fn to_v4_addrs(&self) -> Result<Option<Vec<Ipv4Addr>>, AddrParseError>;
 }
@@ -20,6 +20,9 @@ pub const DEFAULT_TIMEOUT: u32 = 8;
 pub const PROXY_SOCK_NAME: &str = "nv-proxy.sock";
 // Where leases are stored on the filesystem
 pub const CACHE_FILE_NAME: &str = "nv-proxy.lease";
+// Where the network configs of the leases are stored, used to restore the
+// renewal tasks when the proxy restarts
+pub const NETWORK_CACHE_FILE_NAME: &str = "nv-proxy.networks";
 // Seconds until the service should exit
 pub const DEFAULT_INACTIVITY_TIMEOUT: u64 = 300;
 
@@ -94,6 +97,19 @@ pub fn get_cache_fqname(run_dir: Option<&str>) -> PathBuf {
     Path::new(&run_dir).join(CACHE_FILE_NAME)
 }
 
+/// Returns the fully qualified path of the network config cache file
+/// including the file name
+///
+/// # Arguments
+///
+/// * `run_dir`:
+///
+/// returns: PathBuf
+pub fn get_network_cache_fqname(run_dir: Option<&str>) -> PathBuf {
+    let run_dir = get_run_dir(run_dir);
+    Path::new(&run_dir).join(NETWORK_CACHE_FILE_NAME)
+}
+
 #[cfg(test)]
 mod conf_tests {
     use crate::dhcp_proxy::proxy_conf::{
@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use commands::{setup, teardown};
+use commands::{get_lease, list_leases, setup, teardown};
 use std::process;
 
 use netavark::dhcp_proxy::lib::g_rpc::NetworkConfig;
@@ -27,6 +27,10 @@ enum SubCommand {
     Setup(setup::Setup),
     /// Undo any configuration applied via setup command.
     Teardown(teardown::Teardown),
+    /// List the leases of the proxy and the state of their renewal.
+    ListLeases(list_leases::ListLeases),
+    /// Show the leases of a container and the state of their renewal.
+    GetLease(get_lease::GetLease),
     // Display info about netavark.
     // Version(version::Version),
 }
@@ -42,10 +46,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file
         .unwrap_or_else(|| DEFAULT_NETWORK_CONFIG.to_string());
     let uds_path = opts.uds.unwrap_or_else(|| DEFAULT_UDS_PATH.to_string());
-    let input_config = NetworkConfig::load(&file)?;
+    // only setup and teardown take a network configuration
     let result = match opts.subcmd {
-        SubCommand::Setup(s) => s.exec(&uds_path, input_config).await,
-        SubCommand::Teardown(t) => t.exec(&uds_path, input_config).await,
+        SubCommand::Setup(s) => {
+            let input_config = NetworkConfig::load(&file)?;
+            s.exec(&uds_path, input_config).await.map(to_json)
+        }
+        SubCommand::Teardown(t) => {
+            let input_config = NetworkConfig::load(&file)?;
+            t.exec(&uds_path, input_config).await.map(to_json)
+        }
+        SubCommand::ListLeases(l) => l.exec(&uds_path).await.map(to_json),
+        SubCommand::GetLease(g) => g.exec(&uds_path).await.map(to_json),
     };
     let r = match result {
         Ok(r) => r,
@@ -61,3 +73,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", pp.unwrap_or_else(|_| "".to_string()));
     Ok(())
 }
+
+fn to_json<T: serde::Serialize>(v: T) -> serde_json::Value {
+    serde_json::to_value(v).unwrap_or_default()
+}
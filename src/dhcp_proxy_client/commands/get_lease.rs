@@ -0,0 +1,29 @@
+use clap::Parser;
+use log::debug;
+use netavark::{
+    dhcp_proxy::lib::g_rpc::{LeaseList, LeaseRequest},
+    error::NetavarkError,
+};
+
+#[derive(Parser, Debug)]
+#[clap(group(clap::ArgGroup::new("select").required(true)))]
+pub struct GetLease {
+    /// Id of the container
+    #[clap(long, group = "select")]
+    container_id: Option<String>,
+    /// Mac address of the container interface
+    #[clap(long, group = "select")]
+    mac: Option<String>,
+}
+
+impl GetLease {
+    pub async fn exec(&self, p: &str) -> Result<LeaseList, NetavarkError> {
+        debug!("Getting lease");
+        LeaseRequest {
+            container_id: self.container_id.clone().unwrap_or_default(),
+            container_mac_addr: self.mac.clone().unwrap_or_default(),
+        }
+        .get_leases(p)
+        .await
+    }
+}
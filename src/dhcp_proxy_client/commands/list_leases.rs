@@ -0,0 +1,16 @@
+use clap::Parser;
+use log::debug;
+use netavark::{
+    dhcp_proxy::lib::{g_rpc::LeaseList, list_leases},
+    error::NetavarkError,
+};
+
+#[derive(Parser, Debug)]
+pub struct ListLeases {}
+
+impl ListLeases {
+    pub async fn exec(&self, p: &str) -> Result<LeaseList, NetavarkError> {
+        debug!("Listing leases");
+        list_leases(p).await
+    }
+}
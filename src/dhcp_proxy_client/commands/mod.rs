@@ -1,3 +1,5 @@
+pub mod get_lease;
+pub mod list_leases;
 pub mod setup;
 pub mod teardown;
 // pub mod version;
@@ -1,16 +1,20 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::network::internal_types;
-use crate::network::internal_types::{PortForwardConfig, TearDownNetwork, TeardownPortForward};
+use crate::network::internal_types::{
+    EgressRule, IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork,
+    TeardownPortForward,
+};
 use crate::network::types::PortMapping;
 use crate::{firewall, wrap};
 use core::convert::TryFrom;
 use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Deref;
 use std::vec::Vec;
 use zbus::{
     blocking::Connection,
-    zvariant::{Array, OwnedValue, Signature, Value},
+    zvariant::{Array, DynamicType, OwnedValue, Signature, Structure, Value},
 };
 
 const ZONENAME: &str = "netavark_zone";
@@ -18,14 +22,84 @@ const POLICYNAME: &str = "netavark_policy";
 const PORTPOLICYNAME: &str = "netavark_portfwd";
 const HOSTFWDPOLICYNAME: &str = "netavark_host_fwd";
 const HOSTTOZONEPOLICYNAME: &str = "netavark_zone_acc";
+const ISOLATIONPOLICYNAME: &str = "netavark_isolation";
+// Must run before POLICYNAME accepts the traffic.
+const ISOLATIONPOLICYPRIORITY: i16 = -10;
+// Subnets of all networks, and of the networks with isolation enabled.
+const NETWORKSIPSET: &str = "netavark_networks";
+const NETWORKSIPSET6: &str = "netavark_networks6";
+const ISOLATEDIPSET: &str = "netavark_isolated";
+const ISOLATEDIPSET6: &str = "netavark_isolated6";
+// Prefix of the ipsets with the subnets of the networks in an isolation group.
+const GROUPIPSETPREFIX: &str = "netavark_group_";
+const EGRESSPOLICYNAME: &str = "netavark_egress";
+// Must run after the isolation policy but before POLICYNAME accepts the traffic.
+const EGRESSPOLICYPRIORITY: i16 = -5;
 
 // Firewalld driver - uses a dbus connection to communicate with firewalld.
 pub struct FirewallD {
+    conn: FirewallDConnection,
+}
+
+pub fn new(
     conn: Connection,
+    dry_run: bool,
+) -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
+    Ok(Box::new(FirewallD {
+        conn: FirewallDConnection { conn, dry_run },
+    }))
 }
 
-pub fn new(conn: Connection) -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
-    Ok(Box::new(FirewallD { conn }))
+/// The dbus connection to firewalld. In dry run mode the methods which change
+/// the firewalld config are printed instead of called, reading is still done.
+pub struct FirewallDConnection {
+    conn: Connection,
+    dry_run: bool,
+}
+
+impl Deref for FirewallDConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl FirewallDConnection {
+    /// Call a firewalld method which changes the firewalld config.
+    fn call_update<B>(
+        &self,
+        path: &str,
+        interface: &str,
+        method: &str,
+        body: &B,
+    ) -> NetavarkResult<()>
+    where
+        B: serde::Serialize + DynamicType,
+    {
+        if self.dry_run {
+            let msg = zbus::Message::method_call(path, method)?
+                .interface(interface)?
+                .build(body)?;
+            let body = msg.body();
+            // a method without arguments has no body to decode
+            let args = match body.deserialize::<Structure>() {
+                Ok(args) => args.to_string(),
+                Err(_) => "()".to_string(),
+            };
+            println!("{interface}.{method}{args}");
+            return Ok(());
+        }
+
+        let _ = self.conn.call_method(
+            Some("org.fedoraproject.FirewallD1"),
+            path,
+            Some(interface),
+            method,
+            body,
+        )?;
+        Ok(())
+    }
 }
 
 impl firewall::FirewallDriver for FirewallD {
@@ -38,6 +112,9 @@ impl firewall::FirewallDriver for FirewallD {
         network_setup: internal_types::SetupNetwork,
         _dbus_con: &Option<Connection>,
     ) -> NetavarkResult<()> {
+        if network_setup.offload {
+            warn!("offload is not supported by the firewalld driver, ignoring it");
+        }
         let mut need_reload = false;
 
         need_reload |= match create_zone_if_not_exist(&self.conn, ZONENAME) {
@@ -49,8 +126,9 @@ impl firewall::FirewallDriver for FirewallD {
                 ))
             }
         };
+        // The subnets are masqueraded by rich rules, so ipv6 subnets can be excluded.
         need_reload |= match add_policy_if_not_exist(
-            &self.conn, POLICYNAME, ZONENAME, "ANY", "ACCEPT", true, None,
+            &self.conn, POLICYNAME, ZONENAME, "ANY", "ACCEPT", false, None,
         ) {
             Ok(b) => b,
             Err(e) => {
@@ -112,12 +190,77 @@ impl firewall::FirewallDriver for FirewallD {
             }
         };
 
+        // Traffic between the networks stays in our zone, isolation is done
+        // in a policy from the zone to itself.
+        need_reload |= match add_policy_if_not_exist(
+            &self.conn,
+            ISOLATIONPOLICYNAME,
+            ZONENAME,
+            ZONENAME,
+            "CONTINUE",
+            false,
+            Some(ISOLATIONPOLICYPRIORITY),
+        ) {
+            Ok(b) => b,
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("Error creating policy {ISOLATIONPOLICYNAME}"),
+                    e,
+                ))
+            }
+        };
+        need_reload |= match add_policy_if_not_exist(
+            &self.conn,
+            EGRESSPOLICYNAME,
+            ZONENAME,
+            "ANY",
+            "CONTINUE",
+            false,
+            Some(EGRESSPOLICYPRIORITY),
+        ) {
+            Ok(b) => b,
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("Error creating policy {EGRESSPOLICYNAME}"),
+                    e,
+                ))
+            }
+        };
+        for (ipset, family) in [
+            (NETWORKSIPSET, "inet"),
+            (NETWORKSIPSET6, "inet6"),
+            (ISOLATEDIPSET, "inet"),
+            (ISOLATEDIPSET6, "inet6"),
+        ] {
+            need_reload |= match create_ipset_if_not_exist(&self.conn, ipset, family) {
+                Ok(b) => b,
+                Err(e) => {
+                    return Err(NetavarkError::wrap(
+                        format!("Error creating ipset {ipset}"),
+                        e,
+                    ))
+                }
+            };
+        }
+        if let IsolateOption::Group(group) = &network_setup.isolation {
+            for (ipset, family) in get_group_ipsets(group) {
+                need_reload |= match create_ipset_if_not_exist(&self.conn, &ipset, family) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        return Err(NetavarkError::wrap(
+                            format!("Error creating ipset {ipset}"),
+                            e,
+                        ))
+                    }
+                };
+            }
+        }
+
         if need_reload {
             debug!("Reloading firewalld config to bring up zone and policy");
-            let _ = self.conn.call_method(
-                Some("org.fedoraproject.FirewallD1"),
+            self.conn.call_update(
                 "/org/fedoraproject/FirewallD1",
-                Some("org.fedoraproject.FirewallD1"),
+                "org.fedoraproject.FirewallD1",
                 "reload",
                 &(),
             )?;
@@ -125,8 +268,8 @@ impl firewall::FirewallDriver for FirewallD {
 
         // MUST come after the reload; otherwise the zone we made might not be
         // in the running config.
-        if let Some(nets) = network_setup.subnets {
-            match add_source_subnets_to_zone(&self.conn, ZONENAME, &nets) {
+        if let Some(nets) = &network_setup.subnets {
+            match add_source_subnets_to_zone(&self.conn, ZONENAME, nets) {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(NetavarkError::wrap(
@@ -137,6 +280,26 @@ impl firewall::FirewallDriver for FirewallD {
             };
         }
 
+        if let Err(e) = setup_masquerade(&self.conn, &network_setup) {
+            return Err(NetavarkError::wrap(
+                format!(
+                    "Error adding masquerade rules for network {}",
+                    network_setup.network_id
+                ),
+                e,
+            ));
+        }
+
+        if let Err(e) = setup_isolation(&self.conn, &network_setup) {
+            return Err(NetavarkError::wrap(
+                format!(
+                    "Error adding isolation rules for network {}",
+                    network_setup.network_id
+                ),
+                e,
+            ));
+        }
+
         Ok(())
     }
 
@@ -145,13 +308,33 @@ impl firewall::FirewallDriver for FirewallD {
             return Ok(());
         }
 
+        // Networks set up by older versions have no isolation rules and the
+        // ipsets might not exist, so this must not fail the teardown.
+        if let Err(e) = teardown_isolation(&self.conn, &tear.config) {
+            warn!(
+                "Error removing isolation rules for network {}: {e}",
+                tear.config.network_id
+            );
+        }
+
+        if let Some(subnets) = &tear.config.subnets {
+            let rules = get_masquerade_rich_rules(subnets, true);
+            if let Err(e) = remove_policy_rich_rules(&self.conn, POLICYNAME, |rule| {
+                rules.iter().any(|r| r == rule)
+            }) {
+                warn!(
+                    "Error removing masquerade rules for network {}: {e}",
+                    tear.config.network_id
+                );
+            }
+        }
+
         if let Some(subnets) = tear.config.subnets {
             for subnet in subnets {
                 debug!("Removing subnet {subnet} from zone {ZONENAME}");
-                let _ = self.conn.call_method(
-                    Some("org.fedoraproject.FirewallD1"),
+                self.conn.call_update(
                     "/org/fedoraproject/FirewallD1",
-                    Some("org.fedoraproject.FirewallD1.zone"),
+                    "org.fedoraproject.FirewallD1.zone",
                     "removeSource",
                     &(ZONENAME, subnet.to_string()),
                 )?;
@@ -173,6 +356,22 @@ impl firewall::FirewallDriver for FirewallD {
         // case.
         // I don't think there's a safer way, unfortunately.
 
+        // firewalld rich rules cannot count connections
+        if let Some(ports) = setup_portfw.port_mappings {
+            if let Some(port) = ports.iter().find(|p| p.connection_limit.is_some()) {
+                return Err(NetavarkError::msg(format!(
+                    "connection_limit for port {} is not supported by the firewalld driver",
+                    port.host_port
+                )));
+            }
+            if let Some(port) = ports.iter().find(|p| p.service.is_some()) {
+                return Err(NetavarkError::msg(format!(
+                    "service for port {} is not supported by the firewalld driver",
+                    port.host_port
+                )));
+            }
+        }
+
         let sig_ssss = match Signature::try_from("(ssss)") {
             Ok(s) => s,
             Err(e) => {
@@ -203,6 +402,30 @@ impl firewall::FirewallDriver for FirewallD {
         // Only one of them will win and be active, though.
         if let Some(ports) = setup_portfw.port_mappings {
             for port in ports {
+                // Port forwarding restricted to some sources always needs rich rules.
+                if let Some(sources) = &port.allowed_sources {
+                    for ctr_ip in [setup_portfw.container_ip_v4, setup_portfw.container_ip_v6]
+                        .iter()
+                        .flatten()
+                    {
+                        for rule in get_allowed_sources_rich_rules(port, sources, ctr_ip)? {
+                            debug!("Adding pf rule: {rule}");
+                            rich_rules.append(Value::new(rule))?;
+                        }
+                    }
+                    if let Some(v4) = setup_portfw.container_ip_v4 {
+                        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+                        if ["", "0.0.0.0", "127.0.0.1"].contains(&port.host_ip.as_str())
+                            && sources.iter().any(|s| s.contains(&localhost))
+                        {
+                            let rule = get_localhost_pf_rich_rule(port, &v4);
+                            debug!("Adding localhost pf rule: {rule}");
+                            localhost_rich_rules.append(Value::new(rule))?;
+                        }
+                    }
+                    continue;
+                }
+
                 if !port.host_ip.is_empty() && port.host_ip != "0.0.0.0" && port.host_ip != "::" {
                     // Have to special-case forwarding off localhost.
                     if port.host_ip == "127.0.0.1" {
@@ -368,6 +591,28 @@ impl firewall::FirewallDriver for FirewallD {
             update_policy_config(&self.conn, HOSTFWDPOLICYNAME, new_policy_config)?;
         }
 
+        if let Err(e) = setup_rate_limits(&self.conn, &setup_portfw) {
+            return Err(NetavarkError::wrap(
+                format!(
+                    "Error adding rate limit rules for container {}",
+                    setup_portfw.container_id
+                ),
+                e,
+            ));
+        }
+
+        // Also called without egress_allow to remove the rules of an earlier
+        // setup of the container ips.
+        if let Err(e) = setup_egress(&self.conn, &setup_portfw) {
+            return Err(NetavarkError::wrap(
+                format!(
+                    "Error adding egress rules for container {}",
+                    setup_portfw.container_id
+                ),
+                e,
+            ));
+        }
+
         info!(
             "Successfully added port-forwarding rules for container {}",
             setup_portfw.container_id
@@ -617,12 +862,31 @@ impl firewall::FirewallDriver for FirewallD {
         }
         update_policy_config(&self.conn, HOSTFWDPOLICYNAME, new_localhost_policy_config)?;
 
+        // Containers set up by older versions have no egress policy, so this
+        // must not fail the teardown.
+        if let Err(e) = teardown_egress(&self.conn, &teardown_pf.config) {
+            warn!(
+                "Error removing egress rules for container {}: {e}",
+                teardown_pf.config.container_id
+            );
+        }
+        if let Err(e) = teardown_rate_limits(&self.conn, &teardown_pf.config) {
+            warn!(
+                "Error removing rate limit rules for container {}: {e}",
+                teardown_pf.config.container_id
+            );
+        }
+
+        if !self.conn.dry_run {
+            firewall::flush_port_conntrack(&teardown_pf.config);
+        }
+
         Ok(())
     }
 }
 
 /// Create a firewalld zone to hold all our interfaces.
-fn create_zone_if_not_exist(conn: &Connection, zone_name: &str) -> NetavarkResult<bool> {
+fn create_zone_if_not_exist(conn: &FirewallDConnection, zone_name: &str) -> NetavarkResult<bool> {
     debug!("Creating firewall zone {zone_name}");
 
     // First, double-check if the zone exists in the running config.
@@ -669,10 +933,9 @@ fn create_zone_if_not_exist(conn: &Connection, zone_name: &str) -> NetavarkResul
     // errors - but I really don't want to deal with matching error strings and
     // the complexities that could entail.
     // TODO: We can add a description to the zone, should do that.
-    let _ = conn.call_method(
-        Some("org.fedoraproject.FirewallD1"),
+    conn.call_update(
         "/org/fedoraproject/FirewallD1/config",
-        Some("org.fedoraproject.FirewallD1.config"),
+        "org.fedoraproject.FirewallD1.config",
         "addZone2",
         &(zone_name, HashMap::<&str, &Value>::new()),
     )?;
@@ -682,7 +945,7 @@ fn create_zone_if_not_exist(conn: &Connection, zone_name: &str) -> NetavarkResul
 
 /// Add source subnets to the zone.
 pub fn add_source_subnets_to_zone(
-    conn: &Connection,
+    conn: &FirewallDConnection,
     zone_name: &str,
     subnets: &[ipnet::IpNet],
 ) -> NetavarkResult<()> {
@@ -707,10 +970,9 @@ pub fn add_source_subnets_to_zone(
 
         debug!("Adding subnet {net} to zone {zone_name} as source");
 
-        let _ = conn.call_method(
-            Some("org.fedoraproject.FirewallD1"),
+        conn.call_update(
             "/org/fedoraproject/FirewallD1",
-            Some("org.fedoraproject.FirewallD1.zone"),
+            "org.fedoraproject.FirewallD1.zone",
             "changeZoneOfSource",
             &(zone_name, net.to_string()),
         )?;
@@ -721,7 +983,7 @@ pub fn add_source_subnets_to_zone(
 
 /// Add a policy object for the zone to handle masquerading.
 fn add_policy_if_not_exist(
-    conn: &Connection,
+    conn: &FirewallDConnection,
     policy_name: &str,
     ingress_zone_name: &str,
     egress_zone_name: &str,
@@ -794,10 +1056,9 @@ fn add_policy_if_not_exist(
 
     // Policy does not exist, create it.
     // Returns object path, which we don't need.
-    let _ = conn.call_method(
-        Some("org.fedoraproject.FirewallD1"),
+    conn.call_update(
         "/org/fedoraproject/FirewallD1/config",
-        Some("org.fedoraproject.FirewallD1.config"),
+        "org.fedoraproject.FirewallD1.config",
         "addPolicy",
         &(policy_name, &policy_opts),
     )?;
@@ -805,6 +1066,515 @@ fn add_policy_if_not_exist(
     Ok(true)
 }
 
+/// Create a permanent hash:net ipset for the given family ("inet" or "inet6").
+/// Returns true if firewalld must be reloaded for the ipset to be usable.
+fn create_ipset_if_not_exist(
+    conn: &FirewallDConnection,
+    name: &str,
+    family: &str,
+) -> NetavarkResult<bool> {
+    let ipsets_msg = conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1",
+        Some("org.fedoraproject.FirewallD1.ipset"),
+        "getIPSets",
+        &(),
+    )?;
+    let body = ipsets_msg.body();
+    let ipsets: Vec<&str> = wrap!(body.deserialize(), "Error decoding ipset list response")?;
+    if ipsets.contains(&name) {
+        debug!("IPSet {name} exists and is running");
+        return Ok(false);
+    }
+
+    let perm_ipsets_msg = conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1/config",
+        Some("org.fedoraproject.FirewallD1.config"),
+        "getIPSetNames",
+        &(),
+    )?;
+    let body = perm_ipsets_msg.body();
+    let perm_ipsets: Vec<&str> = wrap!(
+        body.deserialize(),
+        "Error decoding permanent ipset list response"
+    )?;
+    if perm_ipsets.contains(&name) {
+        debug!("IPSet {name} exists and is not running");
+        return Ok(true);
+    }
+
+    debug!("Creating firewalld ipset {name}");
+    // (version, short, description, type, options, entries)
+    let settings = (
+        "",
+        name,
+        "netavark network subnets",
+        "hash:net",
+        HashMap::from([("family", family)]),
+        Vec::<&str>::new(),
+    );
+    conn.call_update(
+        "/org/fedoraproject/FirewallD1/config",
+        "org.fedoraproject.FirewallD1.config",
+        "addIPSet",
+        &(name, settings),
+    )?;
+
+    Ok(true)
+}
+
+/// Add or remove an entry of a runtime ipset, does nothing when the entry is
+/// already in the wanted state.
+fn set_ipset_entry(
+    conn: &FirewallDConnection,
+    ipset: &str,
+    entry: &str,
+    add: bool,
+) -> NetavarkResult<()> {
+    let query_msg = match conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1",
+        Some("org.fedoraproject.FirewallD1.ipset"),
+        "queryEntry",
+        &(ipset, entry),
+    ) {
+        Ok(msg) => Some(msg),
+        // A dry run did not create the ipset, so it has no entries yet.
+        Err(_) if conn.dry_run => None,
+        Err(e) => return Err(e.into()),
+    };
+    let exists: bool = match query_msg {
+        Some(msg) => wrap!(msg.body().deserialize(), "Error decoding ipset entry query")?,
+        None => false,
+    };
+    if exists == add {
+        return Ok(());
+    }
+
+    let method = if add { "addEntry" } else { "removeEntry" };
+    debug!("{method} {entry} in ipset {ipset}");
+    conn.call_update(
+        "/org/fedoraproject/FirewallD1",
+        "org.fedoraproject.FirewallD1.ipset",
+        method,
+        &(ipset, entry),
+    )?;
+    Ok(())
+}
+
+/// The ipv4 and ipv6 ipset of an isolation group with their family.
+fn get_group_ipsets(group: &str) -> [(String, &'static str); 2] {
+    [
+        (format!("{GROUPIPSETPREFIX}{group}"), "inet"),
+        (format!("{GROUPIPSETPREFIX}{group}6"), "inet6"),
+    ]
+}
+
+/// The ipsets the subnet of a network is a member of.
+fn get_subnet_ipsets(subnet: &ipnet::IpNet, isolation: &IsolateOption) -> Vec<String> {
+    let (networks, isolated) = match subnet {
+        ipnet::IpNet::V4(_) => (NETWORKSIPSET, ISOLATEDIPSET),
+        ipnet::IpNet::V6(_) => (NETWORKSIPSET6, ISOLATEDIPSET6),
+    };
+    match isolation {
+        IsolateOption::Never => vec![networks.to_string()],
+        IsolateOption::Normal | IsolateOption::Strict => {
+            vec![networks.to_string(), isolated.to_string()]
+        }
+        IsolateOption::Group(group) => {
+            let [(group4, _), (group6, _)] = get_group_ipsets(group);
+            let group = if subnet.addr().is_ipv4() {
+                group4
+            } else {
+                group6
+            };
+            vec![networks.to_string(), isolated.to_string(), group]
+        }
+    }
+}
+
+/// Get the isolation rich rules of a network. Like the nftables driver a
+/// network with normal isolation cannot reach other isolated networks, with
+/// strict isolation it cannot reach any other network. A network in an
+/// isolation group can only reach the networks of the same group. Traffic
+/// routed between the subnets of the network itself is still allowed.
+fn get_isolation_rich_rules(network_setup: &SetupNetwork) -> Vec<String> {
+    let subnets = match &network_setup.subnets {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+    let mut rules = Vec::new();
+    for subnet in subnets {
+        let (family, networks, isolated) = match subnet {
+            ipnet::IpNet::V4(_) => ("ipv4", NETWORKSIPSET, ISOLATEDIPSET),
+            ipnet::IpNet::V6(_) => ("ipv6", NETWORKSIPSET6, ISOLATEDIPSET6),
+        };
+        let ipset = match &network_setup.isolation {
+            IsolateOption::Never => continue,
+            IsolateOption::Normal => isolated,
+            IsolateOption::Strict => networks,
+            IsolateOption::Group(group) => {
+                let [(group4, _), (group6, _)] = get_group_ipsets(group);
+                let group = if subnet.addr().is_ipv4() {
+                    group4
+                } else {
+                    group6
+                };
+                rules.push(format!("rule priority=\"-1\" family=\"{family}\" source address=\"{subnet}\" destination ipset=\"{group}\" accept"));
+                networks
+            }
+        };
+        for other in subnets {
+            if other != subnet && other.addr().is_ipv6() == subnet.addr().is_ipv6() {
+                rules.push(format!("rule priority=\"-1\" family=\"{family}\" source address=\"{subnet}\" destination address=\"{other}\" accept"));
+            }
+        }
+        rules.push(format!(
+            "rule family=\"{family}\" source address=\"{subnet}\" destination ipset=\"{ipset}\" drop"
+        ));
+    }
+    rules
+}
+
+/// Get the rich rules of a policy from its configuration.
+fn get_policy_rich_rules(
+    policy_config: &HashMap<String, OwnedValue>,
+) -> NetavarkResult<Vec<String>> {
+    match policy_config.get("rich_rules") {
+        Some(a) => match a.try_to_owned()?.into() {
+            Value::Array(arr) => arr
+                .iter()
+                .map(|rule| match rule {
+                    Value::Str(s) => Ok(s.to_string()),
+                    _ => Err(NetavarkError::msg(
+                        "Rich rule that was not a string encountered",
+                    )),
+                })
+                .collect(),
+            _ => Err(NetavarkError::msg(
+                "rich_rules in firewalld policy object has a bad type",
+            )),
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Add the rich rules to the policy, rules which already exist are skipped.
+fn add_policy_rich_rules(
+    conn: &FirewallDConnection,
+    policy: &str,
+    rules: Vec<String>,
+) -> NetavarkResult<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let policy_config = get_policy_config(conn, policy.to_string())?;
+    let mut rich_rules = get_policy_rich_rules(&policy_config)?;
+    let old_len = rich_rules.len();
+    for rule in rules {
+        if !rich_rules.contains(&rule) {
+            debug!("Adding rule to policy {policy}: {rule}");
+            rich_rules.push(rule);
+        }
+    }
+    if rich_rules.len() == old_len {
+        return Ok(());
+    }
+
+    let value_rich_rules = Value::new(rich_rules);
+    let mut new_policy_config = HashMap::<&str, &Value>::new();
+    new_policy_config.insert("rich_rules", &value_rich_rules);
+    update_policy_config(conn, policy, new_policy_config)
+}
+
+/// Remove all rich rules from the policy for which remove returns true.
+fn remove_policy_rich_rules<F: Fn(&str) -> bool>(
+    conn: &FirewallDConnection,
+    policy: &str,
+    remove: F,
+) -> NetavarkResult<()> {
+    let policy_config = get_policy_config(conn, policy.to_string())?;
+    let mut rich_rules = get_policy_rich_rules(&policy_config)?;
+    let old_len = rich_rules.len();
+    rich_rules.retain(|rule| !remove(rule));
+    if rich_rules.len() == old_len {
+        return Ok(());
+    }
+
+    let value_rich_rules = Value::new(rich_rules);
+    let mut new_policy_config = HashMap::<&str, &Value>::new();
+    new_policy_config.insert("rich_rules", &value_rich_rules);
+    update_policy_config(conn, policy, new_policy_config)
+}
+
+/// Get the masquerade rich rules for the subnets, ipv6 subnets are only
+/// masqueraded with ipv6_nat.
+fn get_masquerade_rich_rules(subnets: &[ipnet::IpNet], ipv6_nat: bool) -> Vec<String> {
+    subnets
+        .iter()
+        .filter_map(|subnet| {
+            let family = match subnet {
+                ipnet::IpNet::V4(_) => "ipv4",
+                ipnet::IpNet::V6(_) if ipv6_nat => "ipv6",
+                ipnet::IpNet::V6(_) => return None,
+            };
+            Some(format!(
+                "rule family=\"{family}\" source address=\"{subnet}\" masquerade"
+            ))
+        })
+        .collect()
+}
+
+/// Add the masquerade rules of the network to the zone policy. A policy created by
+/// an older version masquerades all subnets of the zone, it is switched to rich
+/// rules for the subnets already in the zone.
+fn setup_masquerade(
+    conn: &FirewallDConnection,
+    network_setup: &SetupNetwork,
+) -> NetavarkResult<()> {
+    let subnets = network_setup.subnets.as_deref().unwrap_or_default();
+    let mut rules = get_masquerade_rich_rules(subnets, network_setup.ipv6_nat);
+
+    let policy_config = get_policy_config(conn, POLICYNAME.to_string())?;
+    let masquerade = match policy_config.get("masquerade") {
+        Some(v) => wrap!(bool::try_from(v), "masquerade in firewalld policy object")?,
+        None => false,
+    };
+    if !masquerade {
+        return add_policy_rich_rules(conn, POLICYNAME, rules);
+    }
+
+    let sources_msg = conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1",
+        Some("org.fedoraproject.FirewallD1.zone"),
+        "getSources",
+        &ZONENAME,
+    )?;
+    let sources_body = sources_msg.body();
+    let sources: Vec<String> = wrap!(
+        sources_body.deserialize(),
+        "Error decoding zone sources response"
+    )?;
+    let others: Vec<ipnet::IpNet> = sources
+        .iter()
+        .filter_map(|s| s.parse().ok())
+        .filter(|net| !subnets.contains(net))
+        .collect();
+    rules.extend(get_masquerade_rich_rules(&others, true));
+
+    let mut rich_rules = get_policy_rich_rules(&policy_config)?;
+    for rule in rules {
+        if !rich_rules.contains(&rule) {
+            rich_rules.push(rule);
+        }
+    }
+    debug!("Replacing masquerade of policy {POLICYNAME} with rich rules");
+    let value_rich_rules = Value::new(rich_rules);
+    let value_masquerade = Value::new(false);
+    let mut new_policy_config = HashMap::<&str, &Value>::new();
+    new_policy_config.insert("rich_rules", &value_rich_rules);
+    new_policy_config.insert("masquerade", &value_masquerade);
+    update_policy_config(conn, POLICYNAME, new_policy_config)
+}
+
+/// Add the subnets of the network to the isolation ipsets and the isolation
+/// rules of the network to the isolation policy.
+fn setup_isolation(conn: &FirewallDConnection, network_setup: &SetupNetwork) -> NetavarkResult<()> {
+    if let Some(subnets) = &network_setup.subnets {
+        for subnet in subnets {
+            for ipset in get_subnet_ipsets(subnet, &network_setup.isolation) {
+                set_ipset_entry(conn, &ipset, &subnet.to_string(), true)?;
+            }
+        }
+    }
+
+    add_policy_rich_rules(
+        conn,
+        ISOLATIONPOLICYNAME,
+        get_isolation_rich_rules(network_setup),
+    )
+}
+
+/// Undo setup_isolation() for the network.
+fn teardown_isolation(
+    conn: &FirewallDConnection,
+    network_setup: &SetupNetwork,
+) -> NetavarkResult<()> {
+    if let Some(subnets) = &network_setup.subnets {
+        for subnet in subnets {
+            // Also check the isolated set, the isolate option might have been
+            // changed since the setup.
+            let isolation = match &network_setup.isolation {
+                IsolateOption::Group(_) => &network_setup.isolation,
+                _ => &IsolateOption::Normal,
+            };
+            for ipset in get_subnet_ipsets(subnet, isolation) {
+                set_ipset_entry(conn, &ipset, &subnet.to_string(), false)?;
+            }
+        }
+    }
+
+    let rules = get_isolation_rich_rules(network_setup);
+    if rules.is_empty() {
+        return Ok(());
+    }
+    remove_policy_rich_rules(conn, ISOLATIONPOLICYNAME, |rule| {
+        rules.iter().any(|r| r == rule)
+    })
+}
+
+/// Get the egress rich rules for a container ip. The allowed destinations of
+/// the same ip family are accepted, everything else from the container is
+/// dropped by a rule with a later priority.
+fn get_egress_rich_rules(ctr_ip: &IpAddr, egress_allow: &[EgressRule]) -> Vec<String> {
+    let family = get_rich_rule_ip_family(ctr_ip);
+    let mut rules = Vec::new();
+    for rule in egress_allow
+        .iter()
+        .filter(|r| r.destination.addr().is_ipv4() == ctr_ip.is_ipv4())
+    {
+        let proto = match (&rule.protocol, rule.port) {
+            (Some(proto), Some(port)) => format!(" port port=\"{port}\" protocol=\"{proto}\""),
+            (Some(proto), None) => format!(" protocol value=\"{proto}\""),
+            (None, _) => String::new(),
+        };
+        rules.push(format!(
+            "rule family=\"{family}\" source address=\"{ctr_ip}\" destination address=\"{}\"{proto} accept",
+            rule.destination
+        ));
+    }
+    rules.push(format!(
+        "rule priority=\"1\" family=\"{family}\" source address=\"{ctr_ip}\" drop"
+    ));
+    rules
+}
+
+/// Set the egress rules of the container in the egress policy. Rules with a
+/// container ip as source from an earlier setup, i.e. before a firewall-reload,
+/// are replaced so changed or removed options do not leave them behind.
+fn setup_egress(
+    conn: &FirewallDConnection,
+    setup_portfw: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let sources = get_container_ip_matches(setup_portfw, "source");
+    if sources.is_empty() {
+        return Ok(());
+    }
+    let policy_config = get_policy_config(conn, EGRESSPOLICYNAME.to_string())?;
+    let old_rules = get_policy_rich_rules(&policy_config)?;
+    let mut rich_rules: Vec<String> = old_rules
+        .iter()
+        .filter(|rule| !sources.iter().any(|s| rule.contains(s)))
+        .cloned()
+        .collect();
+    if !setup_portfw.egress_allow.is_empty() {
+        for ip in [setup_portfw.container_ip_v4, setup_portfw.container_ip_v6]
+            .iter()
+            .flatten()
+        {
+            rich_rules.append(&mut get_egress_rich_rules(ip, &setup_portfw.egress_allow));
+        }
+    }
+
+    let mut sorted_old = old_rules.clone();
+    sorted_old.sort();
+    let mut sorted_new = rich_rules.clone();
+    sorted_new.sort();
+    if sorted_old == sorted_new {
+        return Ok(());
+    }
+    debug!(
+        "Setting egress rules of container {}",
+        setup_portfw.container_id
+    );
+    let value_rich_rules = Value::new(rich_rules);
+    let mut new_policy_config = HashMap::<&str, &Value>::new();
+    new_policy_config.insert("rich_rules", &value_rich_rules);
+    update_policy_config(conn, EGRESSPOLICYNAME, new_policy_config)
+}
+
+/// Get the match strings for rich rules with the container ips as source or destination.
+fn get_container_ip_matches(config: &PortForwardConfig, field: &str) -> Vec<String> {
+    [config.container_ip_v4, config.container_ip_v6]
+        .iter()
+        .flatten()
+        .map(|ip| format!("{field} address=\"{ip}\""))
+        .collect()
+}
+
+/// Remove all egress rules with a container ip as source.
+fn teardown_egress(conn: &FirewallDConnection, config: &PortForwardConfig) -> NetavarkResult<()> {
+    let sources = get_container_ip_matches(config, "source");
+    if sources.is_empty() {
+        return Ok(());
+    }
+    remove_policy_rich_rules(conn, EGRESSPOLICYNAME, |rule| {
+        sources.iter().any(|s| rule.contains(s))
+    })
+}
+
+/// Get the rate limit rich rules for a container ip. New connections to the
+/// container port from outside of the container networks, i.e. the forwarded
+/// host port, are accepted up to the limit and all others are dropped by a rule
+/// with a later priority. Established connections are accepted by firewalld
+/// before any policy.
+fn get_rate_limit_rich_rules(port: &PortMapping, ctr_ip: &IpAddr) -> Vec<String> {
+    let rate = match port.rate_limit {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+    let family = get_rich_rule_ip_family(ctr_ip);
+    let networks = if ctr_ip.is_ipv6() {
+        NETWORKSIPSET6
+    } else {
+        NETWORKSIPSET
+    };
+    let ctr_port = get_rich_rule_port(port.container_port, port.range);
+    let matches = format!(
+        "family=\"{family}\" source NOT ipset=\"{networks}\" destination address=\"{ctr_ip}\" port port=\"{ctr_port}\" protocol=\"{}\"",
+        port.protocol
+    );
+    vec![
+        format!("rule {matches} accept limit value=\"{rate}/s\""),
+        format!("rule priority=\"1\" {matches} drop"),
+    ]
+}
+
+/// Add the rate limit rules of the container ports to the policy for the
+/// traffic forwarded into our zone.
+fn setup_rate_limits(
+    conn: &FirewallDConnection,
+    setup_portfw: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let mut rules = Vec::new();
+    for port in setup_portfw.port_mappings.iter().flatten() {
+        for ip in [setup_portfw.container_ip_v4, setup_portfw.container_ip_v6]
+            .iter()
+            .flatten()
+        {
+            rules.append(&mut get_rate_limit_rich_rules(port, ip));
+        }
+    }
+    // On firewall-reload the rules might already exist.
+    add_policy_rich_rules(conn, HOSTTOZONEPOLICYNAME, rules)
+}
+
+/// Remove all rate limit rules with a container ip as destination.
+fn teardown_rate_limits(
+    conn: &FirewallDConnection,
+    config: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let destinations = get_container_ip_matches(config, "destination");
+    if destinations.is_empty() {
+        return Ok(());
+    }
+    remove_policy_rich_rules(conn, HOSTTOZONEPOLICYNAME, |rule| {
+        destinations.iter().any(|d| rule.contains(d))
+    })
+}
+
 /// Make a port-forward tuple for firewalld
 /// Port forward rules are a 4-tuple of:
 /// (port, protocol, to-port, to-addr)
@@ -835,16 +1605,21 @@ fn make_port_tuple(port: &PortMapping, addr: &str) -> (String, String, String, S
 
 /// Get the configuration of the given policy.
 fn get_policy_config(
-    conn: &Connection,
+    conn: &FirewallDConnection,
     policy_name: String,
 ) -> NetavarkResult<HashMap<String, OwnedValue>> {
-    let policy_config_msg = conn.call_method(
+    let policy_config_msg = match conn.call_method(
         Some("org.fedoraproject.FirewallD1"),
         "/org/fedoraproject/FirewallD1",
         Some("org.fedoraproject.FirewallD1.policy"),
         "getPolicySettings",
         &policy_name,
-    )?;
+    ) {
+        Ok(msg) => msg,
+        // A dry run did not create the policy, so it has no settings yet.
+        Err(_) if conn.dry_run => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
     let mut policy_config: HashMap<String, OwnedValue> = HashMap::new();
     match policy_config_msg
         .body()
@@ -867,14 +1642,13 @@ fn get_policy_config(
 
 /// Update a policy config object
 fn update_policy_config(
-    conn: &Connection,
+    conn: &FirewallDConnection,
     policy_name: &str,
     new_config: HashMap<&str, &Value>,
 ) -> NetavarkResult<()> {
-    match conn.call_method(
-        Some("org.fedoraproject.FirewallD1"),
+    match conn.call_update(
         "/org/fedoraproject/FirewallD1",
-        Some("org.fedoraproject.FirewallD1.policy"),
+        "org.fedoraproject.FirewallD1.policy",
         "setPolicySettings",
         &(policy_name, new_config),
     ) {
@@ -882,7 +1656,7 @@ fn update_policy_config(
         Err(e) => {
             return Err(NetavarkError::wrap(
                 format!("Failed to update firewalld policy {policy_name} port forwarding rules"),
-                e.into(),
+                e,
             ))
         }
     };
@@ -919,6 +1693,51 @@ fn get_port_forwarding_hostip_rich_rule(port: &PortMapping, ctr_ip: &IpAddr) ->
     )
 }
 
+/// Get the rich rules to forward a port from each allowed source of the
+/// container IP family. Localhost is handled by get_localhost_pf_rich_rule().
+fn get_allowed_sources_rich_rules(
+    port: &PortMapping,
+    sources: &[ipnet::IpNet],
+    ctr_ip: &IpAddr,
+) -> NetavarkResult<Vec<String>> {
+    let host_ip: Option<IpAddr> = match port.host_ip.as_str() {
+        "" => None,
+        "0.0.0.0" if ctr_ip.is_ipv4() => None,
+        "::" if ctr_ip.is_ipv6() => None,
+        "0.0.0.0" | "::" | "127.0.0.1" | "::1" => return Ok(Vec::new()),
+        ip => match ip.parse::<IpAddr>() {
+            Ok(i) if i.is_ipv4() == ctr_ip.is_ipv4() => Some(i),
+            Ok(_) => return Ok(Vec::new()),
+            Err(_) => {
+                return Err(NetavarkError::msg(format!(
+                    "invalid host ip \"{}\" provided for port {}",
+                    port.host_ip, port.host_port
+                )));
+            }
+        },
+    };
+
+    let ip_family = get_rich_rule_ip_family(ctr_ip);
+    let host_port = get_rich_rule_port(port.host_port, port.range);
+    let ctr_port = get_rich_rule_port(port.container_port, port.range);
+    let destination = match host_ip {
+        Some(ip) => format!(" destination address=\"{ip}\""),
+        None => String::new(),
+    };
+
+    Ok(sources
+        .iter()
+        .filter(|s| s.addr().is_ipv4() == ctr_ip.is_ipv4())
+        .map(|source| {
+            format!(
+                "rule family=\"{ip_family}\" source address=\"{}\"{destination} forward-port port=\"{host_port}\" protocol=\"{}\" to-port=\"{ctr_port}\" to-addr=\"{ctr_ip}\"",
+                source.trunc(),
+                port.protocol
+            )
+        })
+        .collect())
+}
+
 /// Get a localhost port forwarding rich rule. IPv4 only.
 fn get_localhost_pf_rich_rule(port: &PortMapping, ctr_ip: &IpAddr) -> String {
     let host_port = get_rich_rule_port(port.host_port, port.range);
@@ -991,7 +1810,11 @@ pub fn add_firewalld_if_possible(dbus_conn: &Option<Connection>, net: &ipnet::Ip
     }
     debug!("Adding firewalld rules for network {net}");
 
-    match add_source_subnets_to_zone(conn, "trusted", &[*net]) {
+    let conn = FirewallDConnection {
+        conn: conn.clone(),
+        dry_run: false,
+    };
+    match add_source_subnets_to_zone(&conn, "trusted", &[*net]) {
         Ok(_) => {}
         Err(e) => warn!("Error adding subnet {net} from firewalld trusted zone: {e}"),
     }
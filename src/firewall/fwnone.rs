@@ -38,7 +38,10 @@ impl firewall::FirewallDriver for Fwnone {
         Ok(())
     }
 
-    fn teardown_port_forward(&self, _tear: TeardownPortForward) -> NetavarkResult<()> {
+    // No rules to remove, but flows forwarded to the container by rules of the
+    // user must not stick to the old container ip either.
+    fn teardown_port_forward(&self, tear: TeardownPortForward) -> NetavarkResult<()> {
+        firewall::flush_port_conntrack(&tear.config);
         Ok(())
     }
 }
@@ -1,16 +1,20 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::network::internal_types::{
-    PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
+    PortForwardConfig, Service, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
-use log::{debug, info};
+use crate::network::netlink::Socket;
+use crate::network::netlink_netfilter::NetlinkNetfilter;
+use log::{debug, info, warn};
+use std::ops::RangeInclusive;
 use zbus::blocking::Connection;
 
 pub mod firewalld;
 pub mod fwnone;
 pub mod nft;
+pub mod port_conflicts;
 pub mod state;
 
-const FIREWALLD: &str = "firewalld";
+pub(crate) const FIREWALLD: &str = "firewalld";
 const NFTABLES: &str = "nftables";
 const NONE: &str = "none";
 
@@ -35,10 +39,71 @@ pub trait FirewallDriver {
     /// Tear down port-forwarding firewall rules for a single container.
     fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()>;
 
+    /// Set up or update the rules of a load balanced service for its current
+    /// backends, a service without backends must be removed.
+    /// Drivers which do not support services must reject port mappings with a
+    /// service in setup_port_forward().
+    fn update_service(&self, _service: &Service) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    /// Compare the rules of all networks, containers and services in the
+    /// firewall config with the live firewall and print the missing and extra
+    /// rules. With repair set the missing rules are added again, extra rules
+    /// are never removed. Returns true if nothing was missing or extra.
+    fn check(&self, _conf: state::FirewallConfig, _repair: bool) -> NetavarkResult<bool> {
+        Err(NetavarkError::Message(format!(
+            "firewall check is not supported by the {} firewall driver",
+            self.driver_name()
+        )))
+    }
+
     /// Return the name of the driver.
     fn driver_name(&self) -> &str;
 }
 
+/// Delete the conntrack entries of the published ports of the container.
+/// Otherwise flows, mostly UDP ones, keep using the stale DNAT to the old
+/// container ip until they time out. Errors are only logged as they must not
+/// fail the teardown.
+pub(crate) fn flush_port_conntrack(config: &PortForwardConfig) {
+    let ports: Vec<(u8, RangeInclusive<u16>)> = match config.port_mappings {
+        Some(ports) => ports
+            .iter()
+            .filter_map(|port| {
+                let proto = match port.protocol.as_str() {
+                    "tcp" => libc::IPPROTO_TCP,
+                    "udp" => libc::IPPROTO_UDP,
+                    "sctp" => libc::IPPROTO_SCTP,
+                    _ => return None,
+                };
+                let end = port.host_port.saturating_add(port.range.max(1) - 1);
+                Some((proto as u8, port.host_port..=end))
+            })
+            .collect(),
+        None => return,
+    };
+    if ports.is_empty() {
+        return;
+    }
+
+    let mut sock = match Socket::<NetlinkNetfilter>::new() {
+        Ok(sock) => sock,
+        Err(e) => {
+            warn!("failed to open netfilter netlink socket to flush conntrack entries: {e}");
+            return;
+        }
+    };
+    for ip in [config.container_ip_v4, config.container_ip_v6]
+        .into_iter()
+        .flatten()
+    {
+        if let Err(e) = sock.delete_conntrack_entries(ip, &ports) {
+            warn!("failed to flush conntrack entries of container ip {ip}: {e}");
+        }
+    }
+}
+
 /// Types of firewall backend
 enum FirewallImpl {
     Firewalld(Connection),
@@ -104,19 +169,21 @@ fn get_default_fw_impl() -> NetavarkResult<FirewallImpl> {
 }
 
 /// Get the preferred firewall implementation for the current system
-/// configuration.
+/// configuration. In dry run mode the driver prints the changes it would make
+/// instead of applying them.
 pub fn get_supported_firewall_driver(
     driver_name: Option<String>,
+    dry_run: bool,
 ) -> NetavarkResult<Box<dyn FirewallDriver>> {
     match get_firewall_impl(driver_name) {
         Ok(fw) => match fw {
             FirewallImpl::Firewalld(conn) => {
                 info!("Using firewalld firewall driver");
-                firewalld::new(conn)
+                firewalld::new(conn, dry_run)
             }
             FirewallImpl::Nftables => {
                 info!("Using nftables firewall driver");
-                nft::new()
+                nft::new(dry_run)
             }
             FirewallImpl::Fwnone => {
                 info!("Not using firewall");
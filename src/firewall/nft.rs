@@ -2,9 +2,14 @@ use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
 use crate::firewall::firewalld;
 use crate::network::internal_types;
-use crate::network::internal_types::IsolateOption;
-use crate::network::types::PortMapping;
+use crate::network::internal_types::{EgressRule, IsolateOption};
+use crate::network::netlink::Socket;
+use crate::network::netlink_netfilter::NftDeletion;
+use crate::network::netlink_route::{LinkID, NetlinkRoute};
+use crate::network::types::{PortMapping, ServiceBalance};
 use ipnet::IpNet;
+use netlink_packet_route::link::LinkAttribute;
+use netlink_packet_route::route::RouteAttribute;
 use nftables::batch::Batch;
 use nftables::expr;
 use nftables::helper::{self};
@@ -12,8 +17,9 @@ use nftables::schema;
 use nftables::stmt;
 use nftables::types;
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Deref;
 
 const TABLENAME: &str = "netavark";
@@ -28,11 +34,22 @@ const MASKCHAIN: &str = "NETAVARK-HOSTPORT-SETMARK";
 const ISOLATION1CHAIN: &str = "NETAVARK-ISOLATION-1";
 const ISOLATION2CHAIN: &str = "NETAVARK-ISOLATION-2";
 const ISOLATION3CHAIN: &str = "NETAVARK-ISOLATION-3";
+const OFFLOADCHAIN: &str = "NETAVARK-OFFLOAD";
+
+const FLOWTABLENAME: &str = "NETAVARK-FLOWTABLE";
+
+/// Prefix of the per isolation group sets with the bridges of the group members
+const ISOLATION_GROUP_SET_PREFIX: &str = "nv_isolation_group_";
 
 pub(crate) const MAX_HASH_SIZE: usize = 13;
 
 const MASK: u32 = 0x2000;
 
+// Suffixes for the set and maps of a subnet dnat chain
+const PORT_SET_SUFFIX: &str = "ports";
+const ADDR_MAP_SUFFIX: &str = "to_addr";
+const PORT_MAP_SUFFIX: &str = "to_port";
+
 const MULTICAST_NET_V4: &str = "224.0.0.0/4";
 const MULTICAST_NET_V6: &str = "ff00::/8";
 
@@ -46,10 +63,24 @@ const FILTERPRIO: i32 = 0;
 
 const IPV4_LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
-pub struct Nftables {}
+pub struct Nftables {
+    mode: Mode,
+}
+
+/// What is done with the generated rulesets.
+enum Mode {
+    /// apply them to the live ruleset
+    Apply,
+    /// print them as json instead of applying them
+    DryRun,
+    /// collect their commands to compare them with the live ruleset, the
+    /// rules are generated as if the netavark table was empty
+    Collect(RefCell<Vec<schema::NfCmd<'static>>>),
+}
 
-pub fn new() -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
-    Ok(Box::new(Nftables {}))
+pub fn new(dry_run: bool) -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
+    let mode = if dry_run { Mode::DryRun } else { Mode::Apply };
+    Ok(Box::new(Nftables { mode }))
 }
 
 impl firewall::FirewallDriver for Nftables {
@@ -105,7 +136,7 @@ impl firewall::FirewallDriver for Nftables {
 
         // dnat rules. Not used here, but need to be created first, because they have rules that must be first in their chains.
         // A lot of these are thus conditional on if the rule already exists or not.
-        let existing_rules = get_netavark_rules()?;
+        let existing_rules = self.get_existing_rules()?;
 
         // Two extra chains, not hooked to anything, for our NAT pf rules
         batch.add(make_basic_chain(Cow::Borrowed(DNATCHAIN)));
@@ -271,13 +302,42 @@ impl firewall::FirewallDriver for Nftables {
         // If and only if isolation is enabled: add isolation chains.
         // Some isolation rules are shared. Other rules are specific to one type
         // of isolation.
-        if let IsolateOption::Normal | IsolateOption::Strict = network_setup.isolation {
+        if let IsolateOption::Normal | IsolateOption::Strict | IsolateOption::Group(_) =
+            network_setup.isolation
+        {
             // NETAVARK-ISOLATION-1: iifname <bridgename> oifname != <bridgename> jump NETAVARK-ISOLATION-{2,3}
             // (Exact target varies based on Strict vs Normal Isolation - strict goes to 3, otherwise 2)
-            let isolation_1_jump_target = if let IsolateOption::Strict = network_setup.isolation {
-                ISOLATION3CHAIN
-            } else {
-                ISOLATION2CHAIN
+            // A group member is strictly isolated except for the bridges in the group set:
+            // NETAVARK-ISOLATION-1: iifname <bridgename> oifname != @<group set> jump NETAVARK-ISOLATION-3
+            let (isolation_1_jump_target, isolation_1_oifname) = match &network_setup.isolation {
+                IsolateOption::Strict => (
+                    ISOLATION3CHAIN,
+                    Cow::Borrowed(network_setup.bridge_name.as_str()),
+                ),
+                IsolateOption::Group(group) => {
+                    let set_name = get_isolation_group_set_name(group);
+                    // Adding the existing set of the group is a no-op.
+                    batch.add(schema::NfListObject::Set(Box::new(schema::Set {
+                        family: types::NfFamily::INet,
+                        table: Cow::Borrowed(TABLENAME),
+                        name: Cow::Owned(set_name.clone()),
+                        set_type: schema::SetTypeValue::Single(schema::SetType::Ifname),
+                        ..schema::Set::default()
+                    })));
+                    batch.add(schema::NfListObject::Element(schema::Element {
+                        family: types::NfFamily::INet,
+                        table: Cow::Borrowed(TABLENAME),
+                        name: Cow::Owned(set_name.clone()),
+                        elem: Cow::Owned(vec![expr::Expression::String(Cow::Borrowed(
+                            &network_setup.bridge_name,
+                        ))]),
+                    }));
+                    (ISOLATION3CHAIN, Cow::Owned(format!("@{set_name}")))
+                }
+                _ => (
+                    ISOLATION2CHAIN,
+                    Cow::Borrowed(network_setup.bridge_name.as_str()),
+                ),
             };
             if get_matching_rules_in_chain(&existing_rules, ISOLATION1CHAIN, &match_our_bridge)
                 .is_empty()
@@ -302,9 +362,7 @@ impl firewall::FirewallDriver for Nftables {
                                     key: expr::MetaKey::Oifname,
                                 },
                             )),
-                            right: expr::Expression::String(Cow::Borrowed(
-                                &network_setup.bridge_name,
-                            )),
+                            right: expr::Expression::String(isolation_1_oifname),
                             op: stmt::Operator::NEQ,
                         }),
                         get_jump_action(Cow::Borrowed(isolation_1_jump_target)),
@@ -356,6 +414,42 @@ impl firewall::FirewallDriver for Nftables {
             ));
         }
 
+        // If offload is enabled: add the bridge and uplink devices to the flowtable,
+        // the established connections are added to it by the rules in the offload chain.
+        let uplinks = if network_setup.offload {
+            let uplinks = get_uplink_devices()?;
+            if uplinks.is_empty() {
+                log::warn!(
+                    "Not offloading network {}, no uplink device with a default route found",
+                    network_setup.network_id
+                );
+            }
+            uplinks
+        } else {
+            Vec::new()
+        };
+        if !uplinks.is_empty() {
+            // Adding an existing flowtable only adds the new devices to it.
+            let mut devices = uplinks.clone();
+            devices.push(network_setup.bridge_name.clone());
+            batch.add(make_flowtable(devices));
+            batch.add(make_basic_chain(Cow::Borrowed(OFFLOADCHAIN)));
+
+            // Forward chain: jump NETAVARK-OFFLOAD
+            if get_matching_rules_in_chain(
+                &existing_rules,
+                FORWARDCHAIN,
+                get_rule_matcher_jump_to(OFFLOADCHAIN.to_string()),
+            )
+            .is_empty()
+            {
+                batch.add(make_rule(
+                    Cow::Borrowed(FORWARDCHAIN),
+                    Cow::Owned(vec![get_jump_action(Cow::Borrowed(OFFLOADCHAIN))]),
+                ));
+            }
+        }
+
         // Basic forwarding for all subnets
         if let Some(nets) = network_setup.subnets {
             for subnet in nets {
@@ -363,7 +457,29 @@ impl firewall::FirewallDriver for Nftables {
 
                 // Add us to firewalld if necessary.
                 // Do this first, as firewalld doesn't wipe our rules - so after a reload, we skip everything below.
-                firewalld::add_firewalld_if_possible(dbus_conn, &subnet);
+                if let Mode::Apply = self.mode {
+                    firewalld::add_firewalld_if_possible(dbus_conn, &subnet);
+                }
+
+                // The port forwarding chain, set and maps of the subnet. The containers only
+                // add their ports as elements, see setup_port_forward().
+                let dnat_chain = get_subnet_chain_name(subnet, &network_setup.network_id, true);
+                if get_set(
+                    &existing_rules,
+                    &get_dnat_set_name(&dnat_chain, PORT_MAP_SUFFIX),
+                )
+                .is_none()
+                {
+                    match get_chain(&existing_rules, &dnat_chain) {
+                        // A chain without the maps was created by an older version.
+                        Some(c) => self.migrate_legacy_dnat_chain(&existing_rules, c, subnet)?,
+                        None => {
+                            for obj in get_subnet_dnat_objects(subnet, dnat_chain) {
+                                batch.add(obj);
+                            }
+                        }
+                    }
+                }
 
                 // Do we already have a chain for the subnet?
                 if get_chain(&existing_rules, &chain).is_some() {
@@ -430,7 +546,9 @@ impl firewall::FirewallDriver for Nftables {
                         }
                     }
                     IpNet::V6(_) => {
-                        if let Some(addr6) = network_setup.outbound_addr6 {
+                        if !network_setup.ipv6_nat {
+                            log::trace!("IPv6 NAT disabled, not creating a MASQUERADE rule");
+                        } else if let Some(addr6) = network_setup.outbound_addr6 {
                             log::trace!("Creating IPv6 SNAT rule with outbound address {addr6}");
                             batch.add(make_rule(
                                 chain.clone(),
@@ -540,12 +658,23 @@ impl firewall::FirewallDriver for Nftables {
                         get_jump_action(chain.clone()),
                     ]),
                 ));
+                // Offload chain: ip saddr <subnet> iifname <bridgename> oifname <uplinks> ct state established flow add @NETAVARK-FLOWTABLE
+                if !uplinks.is_empty() {
+                    batch.add(make_rule(
+                        Cow::Borrowed(OFFLOADCHAIN),
+                        Cow::Owned(get_offload_rule(
+                            &subnet,
+                            &network_setup.bridge_name,
+                            &uplinks,
+                        )),
+                    ));
+                }
             }
         }
 
         let rules = batch.to_nftables();
 
-        helper::apply_ruleset(&rules)?;
+        self.apply_ruleset(&rules)?;
 
         Ok(())
     }
@@ -555,6 +684,7 @@ impl firewall::FirewallDriver for Nftables {
 
         let existing_rules = get_netavark_rules()?;
 
+        let mut offload_rules: Vec<schema::Rule> = Vec::new();
         if let Some(nets) = tear.config.subnets {
             for subnet in nets {
                 // Match subnet, either saddr or daddr.
@@ -600,6 +730,11 @@ impl firewall::FirewallDriver for Nftables {
                     POSTROUTINGCHAIN,
                     match_subnet,
                 ));
+                offload_rules.append(&mut get_matching_rules_in_chain(
+                    &existing_rules,
+                    OFFLOADCHAIN,
+                    match_subnet,
+                ));
 
                 log::debug!("Removing {} rules", to_remove.len());
 
@@ -613,8 +748,30 @@ impl firewall::FirewallDriver for Nftables {
                     batch.delete(schema::NfListObject::Chain(c));
                 }
 
+                // Remove the port forwarding chain with all jumps to it, then its set and maps.
+                let dnat_chain = get_subnet_chain_name(subnet, &tear.config.network_id, true);
+                for rule in get_matching_rules_in_chain(
+                    &existing_rules,
+                    DNATCHAIN,
+                    get_rule_matcher_jump_to(dnat_chain.to_string()),
+                ) {
+                    batch.delete(schema::NfListObject::Rule(rule));
+                }
+                if let Some(c) = get_chain(&existing_rules, &dnat_chain) {
+                    batch.delete(schema::NfListObject::Chain(c));
+                }
+                for suffix in [PORT_SET_SUFFIX, ADDR_MAP_SUFFIX, PORT_MAP_SUFFIX] {
+                    if let Some(set) =
+                        get_set(&existing_rules, &get_dnat_set_name(&dnat_chain, suffix))
+                    {
+                        batch.delete(set);
+                    }
+                }
+
                 // After all nftables work is done, remove us from firewalld.
-                firewalld::rm_firewalld_if_possible(&subnet);
+                if let Mode::Apply = self.mode {
+                    firewalld::rm_firewalld_if_possible(&subnet);
+                }
             }
         }
 
@@ -645,9 +802,34 @@ impl firewall::FirewallDriver for Nftables {
             batch.delete(schema::NfListObject::Rule(rule));
         }
 
+        // Remove the bridge from its isolation group, the last member removes the
+        // whole set. Look at all groups, the isolate option might have been changed
+        // since the setup.
+        for (set, members) in get_isolation_group_sets(&existing_rules) {
+            if !members.contains(&tear.config.bridge_name) {
+                continue;
+            }
+            if members.len() == 1 {
+                batch.delete(schema::NfListObject::Set(Box::new(set)));
+            } else {
+                batch.delete(schema::NfListObject::Element(schema::Element {
+                    family: types::NfFamily::INet,
+                    table: Cow::Borrowed(TABLENAME),
+                    name: set.name,
+                    elem: Cow::Owned(vec![expr::Expression::String(Cow::Owned(
+                        tear.config.bridge_name.clone(),
+                    ))]),
+                }));
+            }
+        }
+
+        if !offload_rules.is_empty() {
+            remove_offload_rules(&mut batch, &existing_rules, offload_rules);
+        }
+
         let rules = batch.to_nftables();
 
-        helper::apply_ruleset(&rules)?;
+        self.apply_ruleset(&rules)?;
         Ok(())
     }
 
@@ -660,7 +842,10 @@ impl firewall::FirewallDriver for Nftables {
 
         let mut batch = Batch::new();
 
-        let existing_rules = get_netavark_rules()?;
+        // The ports are only elements in the set and maps of the subnet, the
+        // ruleset is read to not add the DNS and port rules twice and to replace
+        // the egress chains of an earlier setup of the container ips.
+        let existing_rules = self.get_existing_rules()?;
 
         // Need DNAT rules for DNS if Aardvark is not on port 53.
         // Only need one per DNS server IP, so check if they already exist first.
@@ -715,36 +900,106 @@ impl firewall::FirewallDriver for Nftables {
             }
         }
 
-        if let Some(ip_v4) = setup_portfw.container_ip_v4 {
-            if let Some(subnet_v4) = setup_portfw.subnet_v4 {
-                for rule in get_dnat_rules_for_addr_family(
-                    ip_v4,
-                    subnet_v4,
-                    &setup_portfw.network_id,
-                    &existing_rules,
-                    &setup_portfw,
-                )? {
-                    batch.add(rule);
+        // Restrict the container egress traffic, the chain is keyed by the container ip.
+        // A chain left by an earlier setup of the ip, i.e. before a reload, is flushed
+        // and refilled so changed options apply, or removed when there are none.
+        for ip in [setup_portfw.container_ip_v4, setup_portfw.container_ip_v6]
+            .into_iter()
+            .flatten()
+        {
+            let chain = get_egress_chain_name(&ip, &setup_portfw.network_id);
+            let jump_rules = get_matching_rules_in_chain(
+                &existing_rules,
+                FORWARDCHAIN,
+                get_rule_matcher_jump_to(chain.to_string()),
+            );
+            let chain_exists = get_chain(&existing_rules, &chain).is_some();
+            if setup_portfw.egress_allow.is_empty() {
+                for rule in jump_rules {
+                    batch.delete(schema::NfListObject::Rule(schema::Rule {
+                        family: types::NfFamily::INet,
+                        table: Cow::Borrowed(TABLENAME),
+                        chain: Cow::Borrowed(FORWARDCHAIN),
+                        handle: rule.handle,
+                        ..schema::Rule::default()
+                    }));
+                }
+                if chain_exists {
+                    batch.delete(make_basic_chain(chain));
                 }
+                continue;
+            }
+
+            if chain_exists {
+                batch.add_cmd(schema::NfCmd::Flush(schema::FlushObject::Chain(
+                    schema::Chain {
+                        family: types::NfFamily::INet,
+                        table: Cow::Borrowed(TABLENAME),
+                        name: chain.clone(),
+                        ..schema::Chain::default()
+                    },
+                )));
+            }
+            for obj in get_egress_rules(ip, chain.clone(), &setup_portfw.egress_allow) {
+                batch.add(obj);
+            }
+            if jump_rules.is_empty() {
+                // Forward chain: ip saddr <container ip> jump <egress chain>
+                // Inserted so it runs before the network accept rules.
+                batch.add_cmd(schema::NfCmd::Insert(make_rule(
+                    Cow::Borrowed(FORWARDCHAIN),
+                    Cow::Owned(vec![
+                        get_ip_match(&ip, "saddr", stmt::Operator::EQ),
+                        get_jump_action(chain),
+                    ]),
+                )));
             }
         }
-        if let Some(ip_v6) = setup_portfw.container_ip_v6 {
-            if let Some(subnet_v6) = setup_portfw.subnet_v6 {
-                for rule in get_dnat_rules_for_addr_family(
-                    ip_v6,
-                    subnet_v6,
-                    &setup_portfw.network_id,
-                    &existing_rules,
-                    &setup_portfw,
-                )? {
+
+        for objects in get_port_forward_objects(&setup_portfw)? {
+            for element in objects.elements {
+                batch.add(schema::NfListObject::Element(element));
+            }
+            // The rules of an earlier setup, i.e. on a reload, are kept.
+            for rule in objects.jump_rules {
+                if !object_exists(&existing_rules, &rule) {
                     batch.add(rule);
                 }
             }
+            // The limits must be checked before the traffic is translated, so insert
+            // them in reverse to keep their order at the start of the chain. If some
+            // are missing the others are replaced as well to keep their order.
+            if objects
+                .limit_rules
+                .iter()
+                .all(|rule| object_exists(&existing_rules, rule))
+            {
+                continue;
+            }
+            for object in existing_rules.objects.iter() {
+                if let schema::NfObject::ListObject(schema::NfListObject::Rule(rule)) = object {
+                    if objects.limit_rules.iter().any(|r| match r {
+                        schema::NfListObject::Rule(r) => cmp_rules(r, rule),
+                        _ => false,
+                    }) {
+                        batch.delete(schema::NfListObject::Rule(schema::Rule {
+                            family: types::NfFamily::INet,
+                            table: Cow::Borrowed(TABLENAME),
+                            chain: Cow::Owned(rule.chain.to_string()),
+                            handle: rule.handle,
+                            ..schema::Rule::default()
+                        }));
+                    }
+                }
+            }
+            for rule in objects.limit_rules.into_iter().rev() {
+                batch.add_cmd(schema::NfCmd::Insert(rule));
+            }
         }
 
         let rules = batch.to_nftables();
 
-        helper::apply_ruleset(&rules)?;
+        self.apply_ruleset(&rules)?;
 
         Ok(())
     }
@@ -755,25 +1010,59 @@ impl firewall::FirewallDriver for Nftables {
     ) -> NetavarkResult<()> {
         let mut batch = Batch::new();
 
-        let existing_rules = get_netavark_rules()?;
-
-        let dnat_chain_v4 = teardown_pf
-            .config
-            .subnet_v4
-            .map(|s| get_subnet_chain_name(s, &teardown_pf.config.network_id, true));
-        let dnat_chain_v6 = teardown_pf
-            .config
-            .subnet_v6
-            .map(|s| get_subnet_chain_name(s, &teardown_pf.config.network_id, true));
+        // On a complete teardown the subnet dnat chains, sets and maps were already
+        // removed with the network, see teardown_network().
+        let port_objects = if teardown_pf.complete_teardown {
+            Vec::new()
+        } else {
+            get_port_forward_objects(&teardown_pf.config)?
+        };
 
-        if let Some(ip_v4) = teardown_pf.config.container_ip_v4 {
-            if let Some(subnet_v4) = teardown_pf.config.subnet_v4 {
-                delete_port_rules(ip_v4, subnet_v4, &teardown_pf, &existing_rules, &mut batch)?;
+        // Only read the ruleset, which is slow with many rules, when rules must be removed.
+        let has_port_rules = port_objects
+            .iter()
+            .any(|o| !o.jump_rules.is_empty() || !o.limit_rules.is_empty());
+        let existing_rules = if teardown_pf.complete_teardown
+            || has_port_rules
+            || !teardown_pf.config.egress_allow.is_empty()
+        {
+            get_netavark_rules()?
+        } else {
+            schema::Nftables {
+                objects: Cow::Owned(vec![]),
             }
+        };
+
+        let mut elements = Vec::new();
+        for objects in &port_objects {
+            elements.extend(objects.elements.iter().cloned());
+            delete_port_rules(
+                objects.jump_rules.iter().chain(objects.limit_rules.iter()),
+                &existing_rules,
+                &mut batch,
+            );
         }
-        if let Some(ip_v6) = teardown_pf.config.container_ip_v6 {
-            if let Some(subnet_v6) = teardown_pf.config.subnet_v6 {
-                delete_port_rules(ip_v6, subnet_v6, &teardown_pf, &existing_rules, &mut batch)?;
+
+        // Always remove the egress chains, they belong to this container only.
+        if !teardown_pf.config.egress_allow.is_empty() {
+            for ip in [
+                teardown_pf.config.container_ip_v4,
+                teardown_pf.config.container_ip_v6,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let chain = get_egress_chain_name(&ip, &teardown_pf.config.network_id);
+                for rule in get_matching_rules_in_chain(
+                    &existing_rules,
+                    FORWARDCHAIN,
+                    get_rule_matcher_jump_to(chain.to_string()),
+                ) {
+                    batch.delete(schema::NfListObject::Rule(rule));
+                }
+                if let Some(c) = get_chain(&existing_rules, &chain) {
+                    batch.delete(schema::NfListObject::Chain(c));
+                }
             }
         }
 
@@ -800,23 +1089,435 @@ impl firewall::FirewallDriver for Nftables {
             for rule in get_matching_rules_in_chain(&existing_rules, DNATCHAIN, match_dns_dnat) {
                 batch.delete(schema::NfListObject::Rule(rule));
             }
+        }
+
+        let rules = batch.to_nftables();
+
+        self.apply_ruleset(&rules)?;
+
+        self.delete_elements(elements)?;
+
+        firewall::flush_port_conntrack(&teardown_pf.config);
+        Ok(())
+    }
+
+    fn check(&self, conf: firewall::state::FirewallConfig, repair: bool) -> NetavarkResult<bool> {
+        let live_rules = get_netavark_rules()?;
+
+        // Generate the complete rules of everything in the config, labeled
+        // with what they belong to.
+        let collector = Nftables {
+            mode: Mode::Collect(RefCell::new(Vec::new())),
+        };
+        let mut expected: Vec<(String, schema::NfCmd)> = Vec::new();
+        for net in conf.net_confs {
+            let owner = format!("network {}", net.network_id);
+            collector.setup_network(net, &None)?;
+            for cmd in collector.take_collected() {
+                expected.push((owner.clone(), cmd));
+            }
+        }
+        for port in &conf.port_confs {
+            let owner = format!(
+                "container {} in network {}",
+                port.container_id, port.network_id
+            );
+            collector.setup_port_forward(port.into(), &None)?;
+            for cmd in collector.take_collected() {
+                expected.push((owner.clone(), cmd));
+            }
+        }
+        for service in &conf.services {
+            let owner = format!("service {}", service.name);
+            collector.update_service(service)?;
+            for cmd in collector.take_collected() {
+                expected.push((owner.clone(), cmd));
+            }
+        }
+
+        // Rules shared by networks are generated for every network, only
+        // report them once.
+        let mut missing: Vec<&schema::NfCmd> = Vec::new();
+        for (owner, cmd) in &expected {
+            let object = match cmd {
+                schema::NfCmd::Add(obj) | schema::NfCmd::Insert(obj) => obj,
+                _ => continue,
+            };
+            if object_exists(&live_rules, object) || missing.contains(&cmd) {
+                continue;
+            }
+            println!("missing for {owner}: {}", serde_json::to_string(object)?);
+            missing.push(cmd);
+        }
 
-            if let Some(v4) = dnat_chain_v4 {
-                if let Some(c) = get_chain(&existing_rules, &v4) {
-                    batch.delete(schema::NfListObject::Chain(c));
+        let expected_objects: Vec<&schema::NfListObject> = expected
+            .iter()
+            .filter_map(|(_, cmd)| match cmd {
+                schema::NfCmd::Add(obj) | schema::NfCmd::Insert(obj) => Some(obj),
+                _ => None,
+            })
+            .collect();
+        let mut extra = 0;
+        for object in live_rules.objects.iter() {
+            let (name, elems) = match object {
+                schema::NfObject::ListObject(schema::NfListObject::Rule(rule)) => {
+                    let known = expected_objects.iter().any(|obj| match obj {
+                        schema::NfListObject::Rule(r) => cmp_rules(r, rule),
+                        _ => false,
+                    });
+                    if !known {
+                        println!("extra: {}", serde_json::to_string(object)?);
+                        extra += 1;
+                    }
+                    continue;
+                }
+                schema::NfObject::ListObject(schema::NfListObject::Set(set)) => {
+                    (&set.name, set.elem.as_deref())
+                }
+                schema::NfObject::ListObject(schema::NfListObject::Map(map)) => {
+                    (&map.name, map.elem.as_deref())
+                }
+                _ => continue,
+            };
+            for elem in elems.unwrap_or_default() {
+                let known = expected_objects.iter().any(|obj| match obj {
+                    schema::NfListObject::Element(e) => e.name == *name && e.elem.contains(elem),
+                    _ => false,
+                });
+                if !known {
+                    let element = schema::NfListObject::Element(schema::Element {
+                        family: types::NfFamily::INet,
+                        table: Cow::Borrowed(TABLENAME),
+                        name: name.clone(),
+                        elem: Cow::Owned(vec![elem.clone()]),
+                    });
+                    println!("extra: {}", serde_json::to_string(&element)?);
+                    extra += 1;
+                }
+            }
+        }
+
+        // Rules after a terminal verdict never match, so the chains with rules in the
+        // wrong order or with missing rules are rebuilt in the expected order.
+        let chains = get_expected_chains(&expected);
+        let mut reordered = 0;
+        let mut rebuild: Vec<&str> = Vec::new();
+        for (chain, rules) in &chains {
+            if !is_chain_ordered(&live_rules, chain, rules) {
+                println!("wrong order: chain {chain}");
+                reordered += 1;
+                rebuild.push(chain);
+            } else if missing
+                .iter()
+                .any(|cmd| get_cmd_rule(cmd).is_some_and(|r| r.chain == *chain))
+            {
+                rebuild.push(chain);
+            }
+        }
+
+        if repair && (!missing.is_empty() || !rebuild.is_empty()) {
+            let mut batch = Batch::new();
+            for cmd in missing.iter().filter(|cmd| get_cmd_rule(cmd).is_none()) {
+                batch.add_cmd((*cmd).clone());
+            }
+            for (chain, rules) in chains.iter().filter(|(c, _)| rebuild.contains(c)) {
+                if get_chain(&live_rules, chain).is_some() {
+                    batch.add_cmd(schema::NfCmd::Flush(schema::FlushObject::Chain(
+                        schema::Chain {
+                            family: types::NfFamily::INet,
+                            table: Cow::Borrowed(TABLENAME),
+                            name: Cow::Owned(chain.to_string()),
+                            ..schema::Chain::default()
+                        },
+                    )));
+                }
+                for rule in rules {
+                    batch.add(schema::NfListObject::Rule(rule.rule.clone()));
+                }
+                // Extra rules are kept, after the expected ones.
+                for rule in get_matching_rules_in_chain(&live_rules, chain, |live| {
+                    !rules.iter().any(|r| cmp_rules(r.rule, live))
+                }) {
+                    batch.add(schema::NfListObject::Rule(schema::Rule {
+                        handle: None,
+                        index: None,
+                        ..rule
+                    }));
                 }
             }
-            if let Some(v6) = dnat_chain_v6 {
-                if let Some(c) = get_chain(&existing_rules, &v6) {
+            helper::apply_ruleset(&batch.to_nftables())?;
+            log::info!(
+                "Added {} missing firewall objects, rebuilt {} chains",
+                missing.len(),
+                rebuild.len()
+            );
+        }
+
+        Ok(missing.is_empty() && extra == 0 && reordered == 0)
+    }
+
+    fn update_service(&self, service: &internal_types::Service) -> NetavarkResult<()> {
+        let mut batch = Batch::new();
+
+        let existing_rules = self.get_existing_rules()?;
+
+        let chain = get_service_chain_name(&service.name);
+        match get_chain(&existing_rules, &chain) {
+            Some(c) => {
+                // The map of backends is part of the dnat rule, so always
+                // recreate the rules of the service chain.
+                batch.add_cmd(schema::NfCmd::Flush(schema::FlushObject::Chain(c.clone())));
+                if service.backends.is_empty() {
+                    log::debug!("Removing service {}", service.name);
+                    for rule in get_matching_rules_in_chain(
+                        &existing_rules,
+                        DNATCHAIN,
+                        get_rule_matcher_jump_to(chain.to_string()),
+                    ) {
+                        batch.delete(schema::NfListObject::Rule(rule));
+                    }
                     batch.delete(schema::NfListObject::Chain(c));
                 }
             }
+            None => {
+                if service.backends.is_empty() {
+                    return Ok(());
+                }
+                batch.add(make_basic_chain(chain.clone()));
+                for rule in get_service_jump_rules(service, chain.clone())? {
+                    batch.add(rule);
+                }
+            }
+        }
+
+        if !service.backends.is_empty() {
+            for rule in get_service_rules(service, chain) {
+                batch.add(rule);
+            }
         }
 
         let rules = batch.to_nftables();
 
-        helper::apply_ruleset(&rules)?;
+        self.apply_ruleset(&rules)?;
+
+        Ok(())
+    }
+}
+
+impl Nftables {
+    /// Apply the ruleset, depending on the mode it is only printed or collected.
+    fn apply_ruleset(&self, rules: &schema::Nftables) -> NetavarkResult<()> {
+        match &self.mode {
+            Mode::Apply => helper::apply_ruleset(rules)?,
+            Mode::DryRun => println!("{}", serde_json::to_string(rules)?),
+            Mode::Collect(cmds) => {
+                // Round trip through json to get an owned copy of the commands.
+                let rules: schema::Nftables<'static> =
+                    serde_json::from_value(serde_json::to_value(rules)?)?;
+                cmds.borrow_mut()
+                    .extend(rules.objects.iter().filter_map(|object| match object {
+                        schema::NfObject::CmdObject(cmd) => Some(cmd.clone()),
+                        schema::NfObject::ListObject(_) => None,
+                    }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the live netavark table, empty when collecting the rules.
+    fn get_existing_rules(&self) -> Result<schema::Nftables<'static>, helper::NftablesError> {
+        match self.mode {
+            Mode::Collect(_) => Ok(schema::Nftables {
+                objects: Cow::Owned(vec![]),
+            }),
+            _ => get_netavark_rules(),
+        }
+    }
+
+    /// Take the collected commands, elements are split so every command adds
+    /// a single one.
+    fn take_collected(&self) -> Vec<schema::NfCmd<'static>> {
+        let cmds = match &self.mode {
+            Mode::Collect(cmds) => cmds.take(),
+            _ => return Vec::new(),
+        };
+        let mut result = Vec::with_capacity(cmds.len());
+        for cmd in cmds {
+            match cmd {
+                schema::NfCmd::Add(schema::NfListObject::Element(element)) => {
+                    for elem in element.elem.iter() {
+                        result.push(schema::NfCmd::Add(schema::NfListObject::Element(
+                            schema::Element {
+                                elem: Cow::Owned(vec![elem.clone()]),
+                                ..element.clone()
+                            },
+                        )));
+                    }
+                }
+                cmd => result.push(cmd),
+            }
+        }
+        result
+    }
+
+    /// Older versions forwarded every port with a dnat rule in the subnet dnat chain,
+    /// reached by a jump rule per port in the dnat chain. Replace these rules with the
+    /// set and maps of the subnet in one batch, so the ports are forwarded throughout.
+    fn migrate_legacy_dnat_chain(
+        &self,
+        existing_rules: &schema::Nftables<'static>,
+        chain: schema::Chain<'static>,
+        subnet: IpNet,
+    ) -> NetavarkResult<()> {
+        log::info!(
+            "Migrating the port forwarding rules of chain {}",
+            chain.name
+        );
+        let mut batch = Batch::new();
+        let mut elements = PortElements::default();
+        for rule in get_matching_rules_in_chain(existing_rules, &chain.name, |_| true) {
+            if let Some((protocol, host_ip, host_port, ctr_ip, ctr_port)) =
+                parse_legacy_dnat_rule(&rule)
+            {
+                if ctr_ip.is_ipv4() == subnet.addr().is_ipv4() {
+                    elements.push(&protocol, host_ip, host_port, ctr_ip, ctr_port, true)?;
+                }
+            }
+        }
+        for rule in get_matching_rules_in_chain(
+            existing_rules,
+            DNATCHAIN,
+            get_rule_matcher_jump_to(chain.name.to_string()),
+        ) {
+            batch.delete(schema::NfListObject::Rule(rule));
+        }
+        let name: Cow<'static, str> = Cow::Owned(chain.name.to_string());
+        batch.add_cmd(schema::NfCmd::Flush(schema::FlushObject::Chain(chain)));
+        for obj in get_subnet_dnat_objects(subnet, name.clone()) {
+            batch.add(obj);
+        }
+        for element in elements.into_elements(&name) {
+            batch.add(schema::NfListObject::Element(element));
+        }
+        self.apply_ruleset(&batch.to_nftables())
+    }
+
+    /// Get the live elements of a map, empty when collecting the rules or when the map
+    /// does not exist.
+    fn get_map_elements(&self, name: &str) -> NetavarkResult<Vec<expr::Expression<'static>>> {
+        if let Mode::Collect(_) = self.mode {
+            return Ok(Vec::new());
+        }
+        let ruleset = match helper::get_current_ruleset_with_args(
+            None::<&str>,
+            ["list", "map", "inet", TABLENAME, name],
+        ) {
+            Ok(ruleset) => ruleset,
+            Err(helper::NftablesError::NftFailed { ref stderr, .. })
+                if stderr.contains("No such file or directory") =>
+            {
+                return Ok(Vec::new())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(ruleset
+            .objects
+            .iter()
+            .filter_map(|object| match object {
+                schema::NfObject::ListObject(schema::NfListObject::Map(map)) => {
+                    map.elem.as_ref().map(|elem| elem.to_vec())
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect())
+    }
+
+    /// Delete the port elements from the sets and maps. An element which does not exist
+    /// fails the whole batch, e.g. when the ruleset was flushed without firewall-reload,
+    /// so on errors retry every element on its own and ignore the missing ones.
+    fn delete_elements(&self, elements: Vec<schema::Element>) -> NetavarkResult<()> {
+        if elements.is_empty() {
+            return Ok(());
+        }
+
+        // Map elements are deleted by key only, so keep the keys which are mapped
+        // to another container now, e.g. after a host port was reused.
+        let mut foreign_keys = Vec::new();
+        for element in &elements {
+            let mappings: Vec<_> = element
+                .elem
+                .iter()
+                .filter_map(|e| match e {
+                    expr::Expression::List(mapping) if mapping.len() == 2 => Some(mapping),
+                    _ => None,
+                })
+                .collect();
+            if mappings.is_empty() {
+                continue;
+            }
+            for live in self.get_map_elements(&element.name)? {
+                if let expr::Expression::List(live) = live {
+                    if live.len() == 2
+                        && mappings
+                            .iter()
+                            .any(|mapping| mapping[0] == live[0] && mapping[1] != live[1])
+                    {
+                        foreign_keys.push(live[0].clone());
+                    }
+                }
+            }
+        }
+
+        // Only the key is needed to delete a map element.
+        let elements: Vec<schema::Element> = elements
+            .into_iter()
+            .map(|element| schema::Element {
+                elem: Cow::Owned(
+                    element
+                        .elem
+                        .iter()
+                        .map(|e| match e {
+                            expr::Expression::List(mapping) if mapping.len() == 2 => {
+                                mapping[0].clone()
+                            }
+                            e => e.clone(),
+                        })
+                        .filter(|key| !foreign_keys.contains(key))
+                        .collect(),
+                ),
+                ..element
+            })
+            .filter(|element| !element.elem.is_empty())
+            .collect();
+        if elements.is_empty() {
+            return Ok(());
+        }
 
+        let mut batch = Batch::new();
+        for element in &elements {
+            batch.delete(schema::NfListObject::Element(element.clone()));
+        }
+        if let Err(err) = self.apply_ruleset(&batch.to_nftables()) {
+            log::debug!("Failed to remove port elements, retrying one by one: {err}");
+            for element in &elements {
+                for elem in element.elem.iter() {
+                    let mut batch = Batch::new();
+                    batch.delete(schema::NfListObject::Element(schema::Element {
+                        elem: Cow::Owned(vec![elem.clone()]),
+                        ..element.clone()
+                    }));
+                    match self.apply_ruleset(&batch.to_nftables()) {
+                        Ok(_) => {}
+                        Err(NetavarkError::Nftables(helper::NftablesError::NftFailed {
+                            ref stderr,
+                            ..
+                        })) if stderr.contains("No such file or directory") => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -832,42 +1533,29 @@ fn cmp_rules(rule1: &schema::Rule, rule2: &schema::Rule) -> bool {
     false
 }
 
-fn delete_port_rules<'a>(
-    ip: IpAddr,
-    subnet: IpNet,
-    teardown_pf: &internal_types::TeardownPortForward,
+/// Delete the existing rules which match the given port rules.
+fn delete_port_rules<'a, 'b>(
+    port_rules: impl Iterator<Item = &'b schema::NfListObject<'b>>,
     existing_rules: &schema::Nftables<'a>,
     batch: &mut Batch<'a>,
-) -> NetavarkResult<()> {
-    let port_rules = get_dnat_rules_for_addr_family(
-        ip,
-        subnet,
-        &teardown_pf.config.network_id,
-        existing_rules,
-        &teardown_pf.config,
-    )?;
+) {
+    let port_rules: Vec<&schema::Rule> = port_rules
+        .filter_map(|r| match r {
+            schema::NfListObject::Rule(r) => Some(r),
+            _ => None,
+        })
+        .collect();
+    if port_rules.is_empty() {
+        return;
+    }
 
     for object in existing_rules.objects.deref() {
-        match object {
-            schema::NfObject::CmdObject(_) => continue,
-            schema::NfObject::ListObject(list) => match list {
-                schema::NfListObject::Rule(rule) => {
-                    for port_rule in &port_rules {
-                        match port_rule {
-                            schema::NfListObject::Rule(r) => {
-                                if cmp_rules(r, rule) {
-                                    batch.delete(list.clone());
-                                }
-                            }
-                            _ => continue,
-                        }
-                    }
-                }
-                _ => continue,
-            },
+        if let schema::NfObject::ListObject(list @ schema::NfListObject::Rule(rule)) = object {
+            if port_rules.iter().any(|r| cmp_rules(r, rule)) {
+                batch.delete(list.clone());
+            }
         }
     }
-    Ok(())
 }
 
 /// Convert a subnet into a chain name.
@@ -893,35 +1581,308 @@ fn get_subnet_chain_name(subnet: IpNet, net_id: &str, dnat: bool) -> Cow<'_, str
     }
 }
 
-/// Get a statement to match the given destination bridge.
-/// Always matches using ==.
-fn get_dest_bridge_match(bridge: &str) -> stmt::Statement<'_> {
-    stmt::Statement::Match(stmt::Match {
-        left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
-            key: expr::MetaKey::Oifname,
-        })),
-        right: expr::Expression::String(Cow::Borrowed(bridge)),
-        op: stmt::Operator::EQ,
-    })
-}
+/// Convert a container ip into the name of its egress chain.
+fn get_egress_chain_name(ip: &IpAddr, net_id: &str) -> Cow<'static, str> {
+    let ip_clean = ip.to_string().replace('.', "_").replace(':', "-");
+    let net_id_clean = if net_id.len() > 8 {
+        net_id.split_at(8).0
+    } else {
+        net_id
+    };
 
-/// Get a statement to match the given IP address.
-/// Field should be either "saddr" or "daddr" for matching source or destination.
-fn get_ip_match<'a>(ip: &IpAddr, field: &'a str, op: stmt::Operator) -> stmt::Statement<'a> {
-    stmt::Statement::Match(stmt::Match {
-        left: ip_to_payload(ip, field),
-        right: expr::Expression::String(Cow::Owned(ip.to_string())),
-        op,
-    })
+    Cow::Owned(format!("nv_{net_id_clean}_{ip_clean}_egress"))
 }
 
-/// Convert a single IP into a Payload field.
-/// Basically, pasts in "ip" or "ip6" in protocol field based on whether this is a v4 or v6 address.
-fn ip_to_payload<'a>(addr: &IpAddr, field: &'a str) -> expr::Expression<'a> {
-    let proto = match addr {
-        IpAddr::V4(_) => "ip",
-        IpAddr::V6(_) => "ip6",
-    };
+/// Create the egress chain for a container ip, it returns for all allowed
+/// destinations of the same ip family and drops everything else.
+fn get_egress_rules<'a>(
+    ip: IpAddr,
+    chain: Cow<'a, str>,
+    egress_allow: &[EgressRule],
+) -> Vec<schema::NfListObject<'a>> {
+    let mut objects = vec![make_basic_chain(chain.clone())];
+
+    // <chain> ct state related,established return
+    objects.push(make_rule(
+        chain.clone(),
+        Cow::Owned(vec![
+            stmt::Statement::Match(stmt::Match {
+                left: expr::Expression::Named(expr::NamedExpression::CT(expr::CT {
+                    key: Cow::Borrowed("state"),
+                    family: None,
+                    dir: None,
+                })),
+                right: expr::Expression::List(vec![
+                    expr::Expression::String(Cow::Borrowed("established")),
+                    expr::Expression::String(Cow::Borrowed("related")),
+                ]),
+                op: stmt::Operator::IN,
+            }),
+            stmt::Statement::Return(None),
+        ]),
+    ));
+
+    // <chain> ip daddr <destination> [meta l4proto <protocol>] [<protocol> dport <port>] return
+    for rule in egress_allow
+        .iter()
+        .filter(|r| r.destination.addr().is_ipv4() == ip.is_ipv4())
+    {
+        let mut statements = vec![get_subnet_match(
+            &rule.destination,
+            "daddr",
+            stmt::Operator::EQ,
+        )];
+        if let Some(proto) = &rule.protocol {
+            statements.push(match rule.port {
+                Some(port) => stmt::Statement::Match(stmt::Match {
+                    left: expr::Expression::Named(expr::NamedExpression::Payload(
+                        expr::Payload::PayloadField(expr::PayloadField {
+                            protocol: Cow::Owned(proto.clone()),
+                            field: Cow::Borrowed("dport"),
+                        }),
+                    )),
+                    right: expr::Expression::Number(port as u32),
+                    op: stmt::Operator::EQ,
+                }),
+                None => stmt::Statement::Match(stmt::Match {
+                    left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                        key: expr::MetaKey::L4proto,
+                    })),
+                    right: expr::Expression::String(Cow::Owned(proto.clone())),
+                    op: stmt::Operator::EQ,
+                }),
+            });
+        }
+        statements.push(stmt::Statement::Return(None));
+        objects.push(make_rule(chain.clone(), Cow::Owned(statements)));
+    }
+
+    // <chain> drop
+    objects.push(make_rule(
+        chain,
+        Cow::Owned(vec![stmt::Statement::Drop(None)]),
+    ));
+
+    objects
+}
+
+/// Convert a service name into its chain name.
+fn get_service_chain_name(name: &str) -> Cow<'static, str> {
+    Cow::Owned(format!("nv_svc_{name}"))
+}
+
+/// Get a statement to match the packet family, "ipv4" or "ipv6".
+fn get_nfproto_match(family: &'static str) -> stmt::Statement<'static> {
+    stmt::Statement::Match(stmt::Match {
+        left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+            key: expr::MetaKey::Nfproto,
+        })),
+        right: expr::Expression::String(Cow::Borrowed(family)),
+        op: stmt::Operator::EQ,
+    })
+}
+
+/// Get a statement to match the host port of a service.
+fn get_service_dport_match(service: &internal_types::Service) -> stmt::Statement<'static> {
+    stmt::Statement::Match(stmt::Match {
+        left: expr::Expression::Named(expr::NamedExpression::Payload(expr::Payload::PayloadField(
+            expr::PayloadField {
+                protocol: Cow::Owned(service.protocol.clone()),
+                field: Cow::Borrowed("dport"),
+            },
+        ))),
+        right: expr::Expression::Number(service.host_port as u32),
+        op: stmt::Operator::EQ,
+    })
+}
+
+/// Create the rules in the dnat chain that jump to the service chain.
+fn get_service_jump_rules(
+    service: &internal_types::Service,
+    chain: Cow<'static, str>,
+) -> NetavarkResult<Vec<schema::NfListObject<'static>>> {
+    // dnat chain: [ip daddr <host ip> | meta nfproto <family>] <protocol> dport <port> jump <service chain>
+    let mut statements = Vec::with_capacity(3);
+    match service.host_ip.as_str() {
+        "" => {}
+        "0.0.0.0" => statements.push(get_nfproto_match("ipv4")),
+        "::" => statements.push(get_nfproto_match("ipv6")),
+        ip => match ip.parse::<IpAddr>() {
+            Ok(i) => statements.push(get_ip_match(&i, "daddr", stmt::Operator::EQ)),
+            Err(_) => {
+                return Err(NetavarkError::msg(format!(
+                    "invalid host ip \"{}\" provided for service {}",
+                    service.host_ip, service.name
+                )));
+            }
+        },
+    }
+    statements.push(get_service_dport_match(service));
+    statements.push(get_jump_action(chain));
+    Ok(vec![make_rule(
+        Cow::Borrowed(DNATCHAIN),
+        Cow::Owned(statements),
+    )])
+}
+
+/// Create the rules of the service chain, per ip family the hairpin rules to
+/// mark the traffic from the backend subnets and a dnat rule which picks the
+/// backend from a map.
+fn get_service_rules(
+    service: &internal_types::Service,
+    chain: Cow<'static, str>,
+) -> Vec<schema::NfListObject<'static>> {
+    let mut rules = Vec::new();
+
+    // A host ip restricts the service to its family.
+    let host_ip: Option<IpAddr> = match service.host_ip.as_str() {
+        "" => None,
+        "0.0.0.0" => Some(IPV4_LOCALHOST),
+        "::" => Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        ip => ip.parse().ok(),
+    };
+
+    for (family, is_ipv4) in [("ipv4", true), ("ipv6", false)] {
+        if host_ip.is_some_and(|i| i.is_ipv4() != is_ipv4) {
+            continue;
+        }
+        let backends: Vec<&internal_types::ServiceBackend> = service
+            .backends
+            .iter()
+            .filter(|b| b.ip.is_ipv4() == is_ipv4)
+            .collect();
+        if backends.is_empty() {
+            continue;
+        }
+
+        // Service chain: ip saddr <subnet> jump SETMARKCHAIN
+        let mut subnets: Vec<IpNet> = backends.iter().map(|b| b.subnet.trunc()).collect();
+        subnets.dedup();
+        for subnet in subnets {
+            rules.push(make_rule(
+                chain.clone(),
+                Cow::Owned(vec![
+                    get_subnet_match(&subnet, "saddr", stmt::Operator::EQ),
+                    get_jump_action(Cow::Borrowed(MASKCHAIN)),
+                ]),
+            ));
+        }
+        // Service chain: ip saddr 127.0.0.1 jump SETMARKCHAIN
+        if is_ipv4 {
+            rules.push(make_rule(
+                chain.clone(),
+                Cow::Owned(vec![
+                    get_ip_match(&IPV4_LOCALHOST, "saddr", stmt::Operator::EQ),
+                    get_jump_action(Cow::Borrowed(MASKCHAIN)),
+                ]),
+            ));
+        }
+
+        // Service chain: meta nfproto <family> dnat ip to <numgen | jhash> mod <n> map { 0 : <ip>, ... }:<port>
+        let backend_mod = backends.len() as u32;
+        let key = match service.balance {
+            ServiceBalance::RoundRobin | ServiceBalance::Random => {
+                expr::Expression::Named(expr::NamedExpression::Numgen(expr::Numgen {
+                    mode: if service.balance == ServiceBalance::Random {
+                        expr::NgMode::Random
+                    } else {
+                        expr::NgMode::Inc
+                    },
+                    ng_mod: backend_mod,
+                    offset: None,
+                }))
+            }
+            ServiceBalance::SourceHash => {
+                expr::Expression::Named(expr::NamedExpression::JHash(expr::JHash {
+                    hash_mod: backend_mod,
+                    offset: None,
+                    expr: Box::new(expr::Expression::Named(expr::NamedExpression::Payload(
+                        expr::Payload::PayloadField(expr::PayloadField {
+                            protocol: Cow::Borrowed(if is_ipv4 { "ip" } else { "ip6" }),
+                            field: Cow::Borrowed("saddr"),
+                        }),
+                    ))),
+                    seed: None,
+                }))
+            }
+        };
+        let map = backends
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                expr::SetItem::Mapping(
+                    expr::Expression::Number(i as u32),
+                    expr::Expression::String(Cow::Owned(b.ip.to_string())),
+                )
+            })
+            .collect();
+        rules.push(make_rule(
+            chain.clone(),
+            Cow::Owned(vec![
+                get_nfproto_match(family),
+                stmt::Statement::DNAT(Some(stmt::NAT {
+                    addr: Some(expr::Expression::Named(expr::NamedExpression::Map(
+                        Box::new(expr::Map {
+                            key,
+                            data: expr::Expression::Named(expr::NamedExpression::Set(map)),
+                        }),
+                    ))),
+                    family: Some(if is_ipv4 {
+                        stmt::NATFamily::IP
+                    } else {
+                        stmt::NATFamily::IP6
+                    }),
+                    port: Some(expr::Expression::Number(service.container_port as u32)),
+                    flags: None,
+                })),
+            ]),
+        ));
+    }
+
+    rules
+}
+
+/// Get a statement to match the given destination bridge.
+/// Always matches using ==.
+fn get_dest_bridge_match(bridge: &str) -> stmt::Statement<'_> {
+    stmt::Statement::Match(stmt::Match {
+        left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+            key: expr::MetaKey::Oifname,
+        })),
+        right: expr::Expression::String(Cow::Borrowed(bridge)),
+        op: stmt::Operator::EQ,
+    })
+}
+
+/// Get a statement to match the given IP address.
+/// Field should be either "saddr" or "daddr" for matching source or destination.
+fn get_ip_match<'a>(ip: &IpAddr, field: &'a str, op: stmt::Operator) -> stmt::Statement<'a> {
+    stmt::Statement::Match(stmt::Match {
+        left: ip_to_payload(ip, field),
+        right: expr::Expression::String(Cow::Owned(ip.to_string())),
+        op,
+    })
+}
+
+/// Get a statement to match the given source subnet.
+/// nft lists a prefix covering a single address as plain address, so use
+/// the same form to be able to compare the rule when removing it.
+fn get_source_match(net: &IpNet) -> stmt::Statement<'static> {
+    let net = net.trunc();
+    if net.prefix_len() == net.max_prefix_len() {
+        get_ip_match(&net.addr(), "saddr", stmt::Operator::EQ)
+    } else {
+        get_subnet_match(&net, "saddr", stmt::Operator::EQ)
+    }
+}
+
+/// Convert a single IP into a Payload field.
+/// Basically, pasts in "ip" or "ip6" in protocol field based on whether this is a v4 or v6 address.
+fn ip_to_payload<'a>(addr: &IpAddr, field: &'a str) -> expr::Expression<'a> {
+    let proto = match addr {
+        IpAddr::V4(_) => "ip",
+        IpAddr::V6(_) => "ip6",
+    };
 
     expr::Expression::Named(expr::NamedExpression::Payload(expr::Payload::PayloadField(
         expr::PayloadField {
@@ -986,186 +1947,462 @@ fn get_dport_cond(port: &PortMapping) -> stmt::Statement<'_> {
     })
 }
 
-/// Make the first container DNAT chain rule, which is used for both IP and IPv6 DNAT.
-fn get_subnet_dport_match<'a>(
+/// Create the rules that drop new connections over the rate and connection
+/// limits of the port. The nat chain only sees the first packet of a
+/// connection, so these only count new connections.
+fn get_port_limit_rules<'a>(
     dnat_chain: Cow<'a, str>,
-    subnet: &Option<IpNet>,
-    host_ip_match: &Option<stmt::Statement<'a>>,
-    dport_match: &stmt::Statement<'a>,
-) -> schema::NfListObject<'a> {
-    // <dnat_chain> ip saddr <subnet> ip daddr <host IP> <protocol> dport <port(s)> jump MARKCHAIN
-    let mut statements: Vec<stmt::Statement> = Vec::new();
-    if let Some(net) = &subnet {
-        statements.push(get_subnet_match(net, "saddr", stmt::Operator::EQ));
+    port: &PortMapping,
+    host_ip_cond: &Option<stmt::Statement<'a>>,
+    dport_cond: &stmt::Statement<'a>,
+) -> Vec<schema::NfListObject<'a>> {
+    let mut limit_stmts = Vec::new();
+    // Container dnat chain: [ip daddr <host IP>] <proto> dport <port(s)> limit rate over <n>/second burst <n> packets drop
+    // Set the burst, nft always lists it and we must be able to compare the rule on teardown.
+    if let Some(rate) = port.rate_limit {
+        limit_stmts.push(stmt::Statement::Limit(stmt::Limit {
+            rate,
+            rate_unit: None,
+            per: Some(Cow::Borrowed("second")),
+            burst: Some(rate),
+            burst_unit: None,
+            inv: Some(true),
+        }));
     }
-
-    if let Some(stmt) = host_ip_match {
-        statements.push(stmt.clone());
+    // Container dnat chain: [ip daddr <host IP>] <proto> dport <port(s)> ct count over <n> drop
+    if let Some(count) = port.connection_limit {
+        limit_stmts.push(stmt::Statement::CTCount(stmt::CTCount {
+            val: expr::Expression::Number(count),
+            inv: Some(true),
+        }));
     }
 
-    statements.push(dport_match.to_owned());
-    statements.push(get_jump_action(Cow::Borrowed(MASKCHAIN)));
-    make_rule(dnat_chain, Cow::Owned(statements))
+    limit_stmts
+        .into_iter()
+        .map(|limit| {
+            let mut statements: Vec<stmt::Statement> = Vec::new();
+            if let Some(stmt) = host_ip_cond {
+                statements.push(stmt.clone());
+            }
+            statements.push(dport_cond.clone());
+            statements.push(limit);
+            statements.push(stmt::Statement::Drop(None));
+            make_rule(dnat_chain.clone(), Cow::Owned(statements))
+        })
+        .collect()
+}
+
+/// Get the name of a set or map of the subnet dnat chain.
+fn get_dnat_set_name(dnat_chain: &str, suffix: &str) -> Cow<'static, str> {
+    Cow::Owned(format!("{dnat_chain}_{suffix}"))
 }
 
-/// Create DNAT rules for each port to be forwarded.
-/// Used for both IP and IPv6 DNAT.
-fn get_dnat_port_rules<'a>(
+/// Get the key to look up packets in the set and maps of a subnet dnat chain:
+/// meta l4proto . ip daddr . th dport
+fn get_port_lookup_key<'a>(subnet: &IpNet) -> expr::Expression<'a> {
+    expr::Expression::Named(expr::NamedExpression::Concat(vec![
+        expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+            key: expr::MetaKey::L4proto,
+        })),
+        subnet_to_payload(subnet, "daddr"),
+        expr::Expression::Named(expr::NamedExpression::Payload(expr::Payload::PayloadField(
+            expr::PayloadField {
+                protocol: Cow::Borrowed("th"),
+                field: Cow::Borrowed("dport"),
+            },
+        ))),
+    ]))
+}
+
+/// Create the dnat chain of a subnet with the set of its published ports and the maps
+/// to translate them. The set and maps are keyed by protocol, host ip and host port,
+/// a wildcard host ip is stored as zero length prefix so they need the interval flag.
+fn get_subnet_dnat_objects<'a>(
+    subnet: IpNet,
     dnat_chain: Cow<'a, str>,
-    port: &'a PortMapping,
-    ip: &IpAddr,
-    host_ip_cond: &Option<stmt::Statement<'a>>,
 ) -> Vec<schema::NfListObject<'a>> {
-    let mut rules: Vec<schema::NfListObject> = Vec::new();
-
-    // Container dnat chain: ip daddr <host IP> <proto> dport <port> dnat to <container ip: container port>
-    // Unfortunately: We don't have range support in the schema. So we need 1 rule per port.
-    let range = if port.range == 0 { 1 } else { port.range };
-    for i in 0..range {
-        let host_port: u32 = (port.host_port + i) as u32;
-        let ctr_port: u32 = (port.container_port + i) as u32;
-
-        let mut statements: Vec<stmt::Statement> = Vec::new();
-        if let Some(stmt) = host_ip_cond {
-            statements.push(stmt.clone());
-        }
-        statements.push(stmt::Statement::Match(stmt::Match {
-            left: expr::Expression::Named(expr::NamedExpression::Payload(
-                expr::Payload::PayloadField(expr::PayloadField {
-                    protocol: Cow::Borrowed(&port.protocol),
-                    field: Cow::Borrowed("dport"),
-                }),
-            )),
-            right: expr::Expression::Number(host_port),
-            op: stmt::Operator::EQ,
-        }));
-        statements.push(stmt::Statement::DNAT(Some(stmt::NAT {
-            addr: Some(expr::Expression::String(Cow::Owned(ip.to_string()))),
-            family: Some(if ip.is_ipv6() {
-                stmt::NATFamily::IP6
-            } else {
-                stmt::NATFamily::IP
-            }),
-            port: Some(expr::Expression::Number(ctr_port)),
-            flags: None,
+    let (addr_type, nat_family) = match subnet {
+        IpNet::V4(_) => (schema::SetType::Ipv4Addr, stmt::NATFamily::IP),
+        IpNet::V6(_) => (schema::SetType::Ipv6Addr, stmt::NATFamily::IP6),
+    };
+    let key_type = schema::SetTypeValue::Concatenated(Cow::Owned(vec![
+        schema::SetType::InetProto,
+        addr_type,
+        schema::SetType::InetService,
+    ]));
+    let flags = Some(HashSet::from([schema::SetFlag::Interval]));
+
+    let ports_set = get_dnat_set_name(&dnat_chain, PORT_SET_SUFFIX);
+    let addr_map = get_dnat_set_name(&dnat_chain, ADDR_MAP_SUFFIX);
+    let port_map = get_dnat_set_name(&dnat_chain, PORT_MAP_SUFFIX);
+
+    let mut objects = vec![
+        make_basic_chain(dnat_chain.clone()),
+        schema::NfListObject::Set(Box::new(schema::Set {
+            family: types::NfFamily::INet,
+            table: Cow::Borrowed(TABLENAME),
+            name: ports_set.clone(),
+            set_type: key_type.clone(),
+            flags: flags.clone(),
+            ..schema::Set::default()
+        })),
+    ];
+    for (name, data_type) in [
+        (addr_map.clone(), addr_type),
+        (port_map.clone(), schema::SetType::InetService),
+    ] {
+        objects.push(schema::NfListObject::Map(Box::new(schema::Map {
+            family: types::NfFamily::INet,
+            table: Cow::Borrowed(TABLENAME),
+            name,
+            set_type: key_type.clone(),
+            map: schema::SetTypeValue::Single(data_type),
+            flags: flags.clone(),
+            ..schema::Map::default()
         })));
-        rules.push(make_rule(dnat_chain.clone(), Cow::Owned(statements)));
     }
 
-    rules
+    // The chain is only entered for published ports of the subnet, so mark all
+    // hairpin and localhost traffic for masquerading.
+    // Subnet dnat chain: ip saddr <subnet> jump SETMARKCHAIN
+    objects.push(make_rule(
+        dnat_chain.clone(),
+        Cow::Owned(vec![
+            get_subnet_match(&subnet, "saddr", stmt::Operator::EQ),
+            get_jump_action(Cow::Borrowed(MASKCHAIN)),
+        ]),
+    ));
+    // This rule is only used for v4.
+    if subnet.addr().is_ipv4() {
+        // Subnet dnat chain: ip saddr 127.0.0.1 jump SETMARKCHAIN
+        objects.push(make_rule(
+            dnat_chain.clone(),
+            Cow::Owned(vec![
+                get_ip_match(&IPV4_LOCALHOST, "saddr", stmt::Operator::EQ),
+                get_jump_action(Cow::Borrowed(MASKCHAIN)),
+            ]),
+        ));
+    }
+
+    // Subnet dnat chain: dnat ip to <key> map @<chain>_to_addr : <key> map @<chain>_to_port
+    let key = get_port_lookup_key(&subnet);
+    let lookup = |map: Cow<'a, str>| {
+        expr::Expression::Named(expr::NamedExpression::Map(Box::new(expr::Map {
+            key: key.clone(),
+            data: expr::Expression::String(Cow::Owned(format!("@{map}"))),
+        })))
+    };
+    objects.push(make_rule(
+        dnat_chain.clone(),
+        Cow::Owned(vec![stmt::Statement::DNAT(Some(stmt::NAT {
+            addr: Some(lookup(addr_map)),
+            family: Some(nat_family),
+            port: Some(lookup(port_map)),
+            flags: None,
+        }))]),
+    ));
+
+    // dnat chain: meta l4proto . ip daddr . th dport @<chain>_ports jump <subnet dnat chain>
+    objects.push(make_rule(
+        Cow::Borrowed(DNATCHAIN),
+        Cow::Owned(vec![
+            stmt::Statement::Match(stmt::Match {
+                left: key,
+                right: expr::Expression::String(Cow::Owned(format!("@{ports_set}"))),
+                op: stmt::Operator::EQ,
+            }),
+            get_jump_action(dnat_chain),
+        ]),
+    ));
+
+    objects
 }
 
-fn get_dnat_rules_for_addr_family<'a>(
+/// Parse a legacy port forwarding rule:
+/// [ip daddr <host ip>] <protocol> dport <host port> dnat ip to <container ip>:<container port>
+fn parse_legacy_dnat_rule(
+    rule: &schema::Rule,
+) -> Option<(String, Option<IpAddr>, u16, IpAddr, u16)> {
+    let mut host_ip = None;
+    let mut dport = None;
+    let mut dnat = None;
+    for statement in rule.expr.iter() {
+        match statement {
+            stmt::Statement::Match(stmt::Match {
+                left:
+                    expr::Expression::Named(expr::NamedExpression::Payload(
+                        expr::Payload::PayloadField(field),
+                    )),
+                right,
+                op: stmt::Operator::EQ,
+            }) => match (field.field.as_ref(), right) {
+                ("daddr", expr::Expression::String(ip)) => host_ip = Some(ip.parse().ok()?),
+                ("dport", expr::Expression::Number(port)) => {
+                    dport = Some((field.protocol.to_string(), u16::try_from(*port).ok()?))
+                }
+                _ => return None,
+            },
+            stmt::Statement::DNAT(Some(stmt::NAT {
+                addr: Some(expr::Expression::String(addr)),
+                port: Some(expr::Expression::Number(port)),
+                ..
+            })) => dnat = Some((addr.parse().ok()?, u16::try_from(*port).ok()?)),
+            _ => return None,
+        }
+    }
+    let ((protocol, host_port), (ctr_ip, ctr_port)) = (dport?, dnat?);
+    Some((protocol, host_ip, host_port, ctr_ip, ctr_port))
+}
+
+/// The nftables objects to forward the ports of a container in one subnet.
+struct PortForwardObjects<'a> {
+    /// Elements of the port set and dnat maps of the subnet.
+    elements: Vec<schema::Element<'a>>,
+    /// Rules to jump to the subnet dnat chain for ports with allowed sources.
+    jump_rules: Vec<schema::NfListObject<'a>>,
+    /// Rules at the start of the subnet dnat chain to enforce the port limits.
+    limit_rules: Vec<schema::NfListObject<'a>>,
+}
+
+/// Get the port forwarding objects for both ip families of the container.
+fn get_port_forward_objects<'a>(
+    config: &internal_types::PortForwardConfig<'a>,
+) -> NetavarkResult<Vec<PortForwardObjects<'a>>> {
+    let mut objects = Vec::with_capacity(2);
+    for (ip, subnet) in [
+        (config.container_ip_v4, config.subnet_v4),
+        (config.container_ip_v6, config.subnet_v6),
+    ] {
+        if let (Some(ip), Some(subnet)) = (ip, subnet) {
+            objects.push(get_port_forward_objects_for_addr_family(
+                ip, subnet, config,
+            )?);
+        }
+    }
+    Ok(objects)
+}
+
+fn get_port_forward_objects_for_addr_family<'a>(
     ip: IpAddr,
     subnet: IpNet,
-    net_id: &'a str,
-    existing_rules: &schema::Nftables,
-    setup_portfw: &internal_types::PortForwardConfig<'a>,
-) -> NetavarkResult<Vec<schema::NfListObject<'a>>> {
-    let mut rules: Vec<schema::NfListObject> = Vec::new();
-
-    if let Some(ports) = setup_portfw.port_mappings {
-        let subnet_dnat_chain = get_subnet_chain_name(subnet, net_id, true);
-
-        // Make the chain if it does not exist
-        if get_chain(existing_rules, &subnet_dnat_chain).is_none() {
-            rules.push(make_basic_chain(subnet_dnat_chain.clone()));
-        }
-
-        for port in ports {
-            // Condition to match destination ports (ports on the host)
-            let dport_cond = get_dport_cond(port);
-            // Destination address is only if user set an IP on the host to bind to.
-            // Used by multiple rules in this section.
-            // We need to ignore wildcards, but only if our IP family matches the wildcard.
-            // If it doesn't, don't add any rules.
-            let daddr: Option<IpAddr> = if !port.host_ip.is_empty() {
-                if port.host_ip == "0.0.0.0" {
-                    if ip.is_ipv6() {
-                        continue;
-                    }
-                    None
-                } else if port.host_ip == "::" {
-                    if ip.is_ipv4() {
-                        continue;
-                    }
-                    None
-                } else {
-                    match port.host_ip.parse() {
-                        Ok(i) => Some(i),
-                        Err(_) => {
-                            return Err(NetavarkError::msg(format!(
-                                "invalid host ip \"{}\" provided for port {}",
-                                port.host_ip, port.host_port
-                            )));
-                        }
-                    }
+    config: &internal_types::PortForwardConfig<'a>,
+) -> NetavarkResult<PortForwardObjects<'a>> {
+    let mut objects = PortForwardObjects {
+        elements: Vec::new(),
+        jump_rules: Vec::new(),
+        limit_rules: Vec::new(),
+    };
+
+    let ports = match config.port_mappings {
+        Some(ports) => ports,
+        None => return Ok(objects),
+    };
+
+    let subnet_dnat_chain: Cow<'a, str> =
+        Cow::Owned(get_subnet_chain_name(subnet, &config.network_id, true).into_owned());
+
+    let mut elements = PortElements::default();
+
+    for port in ports {
+        // Load balanced services are set up in update_service().
+        if port.service.is_some() {
+            continue;
+        }
+
+        // Condition to match destination ports (ports on the host)
+        let dport_cond = get_dport_cond(port);
+        // Destination address is only if user set an IP on the host to bind to.
+        // Used by multiple rules in this section.
+        // We need to ignore wildcards, but only if our IP family matches the wildcard.
+        // If it doesn't, don't add any rules.
+        let daddr: Option<IpAddr> = if !port.host_ip.is_empty() {
+            if port.host_ip == "0.0.0.0" {
+                if ip.is_ipv6() {
+                    continue;
                 }
-            } else {
                 None
-            };
-
-            // Do not add rules where the address family of host address does not match container address.
-            if let Some(host_ip) = daddr {
-                if ip.is_ipv4() != host_ip.is_ipv4() {
+            } else if port.host_ip == "::" {
+                if ip.is_ipv4() {
                     continue;
                 }
+                None
+            } else {
+                match port.host_ip.parse() {
+                    Ok(i) => Some(i),
+                    Err(_) => {
+                        return Err(NetavarkError::msg(format!(
+                            "invalid host ip \"{}\" provided for port {}",
+                            port.host_ip, port.host_port
+                        )));
+                    }
+                }
             }
+        } else {
+            None
+        };
 
-            let mut jump_statements = Vec::with_capacity(3);
-            let daddr_cond: Option<stmt::Statement> = daddr.map(|i| {
-                let daddr = get_ip_match(&i, "daddr", stmt::Operator::EQ);
-                jump_statements.push(daddr.clone());
-                daddr
-            });
-            jump_statements.push(dport_cond.clone());
-            jump_statements.push(get_jump_action(subnet_dnat_chain.clone()));
-
-            // dnat chain: [ip daddr <ip>] <protocol> dport <port> jump <container_dnat_chain>
-            rules.push(make_rule(
-                Cow::Borrowed(DNATCHAIN),
-                Cow::Owned(jump_statements),
-            ));
+        // Do not add rules where the address family of host address does not match container address.
+        if let Some(host_ip) = daddr {
+            if ip.is_ipv4() != host_ip.is_ipv4() {
+                continue;
+            }
+        }
 
-            // Container dnat chain: ip saddr <subnet> ip daddr <host IP> <proto> dport <port(s)> jump SETMARKCHAIN
-            rules.push(get_subnet_dport_match(
-                subnet_dnat_chain.clone(),
-                &Some(subnet),
-                &daddr_cond,
-                &dport_cond,
-            ));
+        // Only forward traffic from the allowed sources of our IP family.
+        // If there are none for this family, don't add any rules.
+        let saddr_conds: Option<Vec<stmt::Statement>> = match &port.allowed_sources {
+            Some(sources) => {
+                let conds: Vec<_> = sources
+                    .iter()
+                    .filter(|s| s.addr().is_ipv4() == ip.is_ipv4())
+                    .map(get_source_match)
+                    .collect();
+                if conds.is_empty() {
+                    continue;
+                }
+                Some(conds)
+            }
+            None => None,
+        };
 
-            // This rule is only used for v4.
-            if ip.is_ipv4() {
-                // Container dnat chain: ip saddr 127.0.0.1 ip daddr <host IP> <proto> dport <port(s)> jump SETMARKCHAIN
-                let mut localhost_jump_statements: Vec<stmt::Statement> = Vec::new();
-                localhost_jump_statements.push(get_ip_match(
-                    &IPV4_LOCALHOST,
-                    "saddr",
-                    stmt::Operator::EQ,
-                ));
-                if let Some(stmt) = &daddr_cond {
-                    localhost_jump_statements.push(stmt.clone());
+        let daddr_cond: Option<stmt::Statement> =
+            daddr.map(|i| get_ip_match(&i, "daddr", stmt::Operator::EQ));
+
+        // dnat chain: ip saddr <source> [ip daddr <ip>] <protocol> dport <port> jump <subnet_dnat_chain>
+        // Ports with allowed sources are not in the port set, they need their own rules.
+        if let Some(saddr_conds) = &saddr_conds {
+            for saddr in saddr_conds {
+                let mut jump_statements = Vec::with_capacity(4);
+                jump_statements.push(saddr.clone());
+                if let Some(daddr) = &daddr_cond {
+                    jump_statements.push(daddr.clone());
                 }
-                localhost_jump_statements.push(dport_cond.clone());
-                localhost_jump_statements.push(get_jump_action(Cow::Borrowed(MASKCHAIN)));
-                rules.push(make_rule(
-                    subnet_dnat_chain.clone(),
-                    Cow::Owned(localhost_jump_statements),
+                jump_statements.push(dport_cond.clone());
+                jump_statements.push(get_jump_action(subnet_dnat_chain.clone()));
+                objects.jump_rules.push(make_rule(
+                    Cow::Borrowed(DNATCHAIN),
+                    Cow::Owned(jump_statements),
                 ));
             }
+        }
 
-            rules.append(&mut get_dnat_port_rules(
-                subnet_dnat_chain.clone(),
-                port,
-                &ip,
-                &daddr_cond,
-            ));
+        // Limits must be checked before any traffic is translated.
+        objects.limit_rules.append(&mut get_port_limit_rules(
+            subnet_dnat_chain.clone(),
+            port,
+            &daddr_cond,
+            &dport_cond,
+        ));
+
+        // Unfortunately: We don't have range support in the schema. So we need 1 element per port.
+        let range = if port.range == 0 { 1 } else { port.range };
+        for i in 0..range {
+            elements.push(
+                &port.protocol,
+                daddr,
+                port.host_port + i,
+                ip,
+                port.container_port + i,
+                saddr_conds.is_none(),
+            )?;
+        }
+    }
+
+    objects.elements = elements.into_elements(&subnet_dnat_chain);
+    Ok(objects)
+}
+
+/// The elements of the port set and dnat maps of a subnet:
+/// <protocol> . <host ip> . <host port> [: <container ip> | <container port>]
+#[derive(Default)]
+struct PortElements<'a> {
+    /// protocol, host ip and host port of the elements, None is the wildcard host ip
+    forwarded: Vec<(String, Option<IpAddr>, u16)>,
+    set_keys: Vec<expr::Expression<'a>>,
+    addr_elems: Vec<expr::Expression<'a>>,
+    port_elems: Vec<expr::Expression<'a>>,
+}
+
+impl<'a> PortElements<'a> {
+    /// Add the elements to forward a host port to the container, ports with allowed
+    /// sources are not in the set, they are only looked up after their jump rules.
+    /// The set and maps are intervals and a wildcard host ip contains all others, so
+    /// a host port can only be forwarded once per protocol with overlapping host ips.
+    fn push(
+        &mut self,
+        protocol: &str,
+        host_ip: Option<IpAddr>,
+        host_port: u16,
+        ctr_ip: IpAddr,
+        ctr_port: u16,
+        in_set: bool,
+    ) -> NetavarkResult<()> {
+        let display_ip = |ip: Option<IpAddr>| match ip {
+            Some(ip) => ip.to_string(),
+            None if ctr_ip.is_ipv4() => "0.0.0.0".to_string(),
+            None => "::".to_string(),
+        };
+        if let Some((_, other, _)) = self.forwarded.iter().find(|(proto, ip, port)| {
+            proto == protocol
+                && *port == host_port
+                && (ip.is_none() || host_ip.is_none() || *ip == host_ip)
+        }) {
+            return Err(NetavarkError::msg(format!(
+                "overlapping port mappings for host port {host_port}/{protocol} with host ips {} and {}",
+                display_ip(*other),
+                display_ip(host_ip)
+            )));
         }
+        self.forwarded
+            .push((protocol.to_string(), host_ip, host_port));
+
+        // A wildcard host ip matches every address of the family.
+        let daddr_elem = match host_ip {
+            Some(addr) => expr::Expression::String(Cow::Owned(addr.to_string())),
+            None => expr::Expression::Named(expr::NamedExpression::Prefix(expr::Prefix {
+                addr: Box::new(expr::Expression::String(Cow::Borrowed(
+                    if ctr_ip.is_ipv4() { "0.0.0.0" } else { "::" },
+                ))),
+                len: 0,
+            })),
+        };
+        let key = expr::Expression::Named(expr::NamedExpression::Concat(vec![
+            expr::Expression::String(Cow::Owned(protocol.to_string())),
+            daddr_elem,
+            expr::Expression::Number(host_port as u32),
+        ]));
+        if in_set {
+            self.set_keys.push(key.clone());
+        }
+        self.addr_elems.push(expr::Expression::List(vec![
+            key.clone(),
+            expr::Expression::String(Cow::Owned(ctr_ip.to_string())),
+        ]));
+        self.port_elems.push(expr::Expression::List(vec![
+            key,
+            expr::Expression::Number(ctr_port as u32),
+        ]));
+        Ok(())
     }
 
-    // TODO fix this clone here, problem is subnet_dnat_chain is dropped but the rules have references to it
-    Ok(rules)
+    fn into_elements(self, dnat_chain: &str) -> Vec<schema::Element<'a>> {
+        let mut elements = Vec::new();
+        for (suffix, elem) in [
+            (PORT_SET_SUFFIX, self.set_keys),
+            (ADDR_MAP_SUFFIX, self.addr_elems),
+            (PORT_MAP_SUFFIX, self.port_elems),
+        ] {
+            if !elem.is_empty() {
+                elements.push(schema::Element {
+                    family: types::NfFamily::INet,
+                    table: Cow::Borrowed(TABLENAME),
+                    name: get_dnat_set_name(dnat_chain, suffix),
+                    elem: Cow::Owned(elem),
+                });
+            }
+        }
+        elements
+    }
 }
 
 /// Make a DNAT rule to allow DNS traffic to a DNS server on a non-standard port (53 -> actual port).
@@ -1213,6 +2450,94 @@ fn make_dns_dnat_rule(dns_ip: &IpAddr, dns_port: u16) -> schema::NfListObject<'_
     schema::NfListObject::Rule(rule)
 }
 
+/// Create the flowtable with the given devices, it is always in our overall netavark table.
+fn make_flowtable<'a>(devices: Vec<String>) -> schema::NfListObject<'a> {
+    schema::NfListObject::FlowTable(schema::FlowTable {
+        family: types::NfFamily::INet,
+        table: Cow::Borrowed(TABLENAME),
+        name: Cow::Borrowed(FLOWTABLENAME),
+        handle: None,
+        hook: Some(types::NfHook::Ingress),
+        prio: Some(0),
+        dev: Some(Cow::Owned(devices.into_iter().map(Cow::Owned).collect())),
+    })
+}
+
+/// Make the conditions to offload the established connections from the subnet
+/// through one of the uplinks.
+fn get_offload_rule<'a>(
+    subnet: &IpNet,
+    bridge: &'a str,
+    uplinks: &'a [String],
+) -> Vec<stmt::Statement<'a>> {
+    let oifname = match uplinks {
+        [uplink] => expr::Expression::String(Cow::Borrowed(uplink)),
+        uplinks => expr::Expression::Named(expr::NamedExpression::Set(
+            uplinks
+                .iter()
+                .map(|u| expr::SetItem::Element(expr::Expression::String(Cow::Borrowed(u))))
+                .collect(),
+        )),
+    };
+    vec![
+        get_subnet_match(subnet, "saddr", stmt::Operator::EQ),
+        stmt::Statement::Match(stmt::Match {
+            left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                key: expr::MetaKey::Iifname,
+            })),
+            right: expr::Expression::String(Cow::Borrowed(bridge)),
+            op: stmt::Operator::EQ,
+        }),
+        stmt::Statement::Match(stmt::Match {
+            left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                key: expr::MetaKey::Oifname,
+            })),
+            right: oifname,
+            op: stmt::Operator::EQ,
+        }),
+        stmt::Statement::Match(stmt::Match {
+            left: expr::Expression::Named(expr::NamedExpression::CT(expr::CT {
+                key: Cow::Borrowed("state"),
+                family: None,
+                dir: None,
+            })),
+            right: expr::Expression::String(Cow::Borrowed("established")),
+            op: stmt::Operator::IN,
+        }),
+        stmt::Statement::Flow(stmt::Flow {
+            op: stmt::SetOp::Add,
+            flowtable: Cow::Owned(format!("@{FLOWTABLENAME}")),
+        }),
+    ]
+}
+
+/// Get the names of the devices with a default route, the traffic of an offloaded
+/// network leaves the host through them.
+fn get_uplink_devices() -> NetavarkResult<Vec<String>> {
+    let mut sock = Socket::<NetlinkRoute>::new()?;
+    let mut devices: Vec<String> = Vec::new();
+    for route in sock.dump_routes(None)? {
+        if route.header.destination_prefix_length != 0 {
+            continue;
+        }
+        for attr in route.attributes {
+            let RouteAttribute::Oif(index) = attr else {
+                continue;
+            };
+            let link = sock.get_link(LinkID::ID(index))?;
+            for attr in link.attributes {
+                if let LinkAttribute::IfName(name) = attr {
+                    if !devices.contains(&name) {
+                        devices.push(name);
+                    }
+                }
+            }
+        }
+    }
+    devices.sort();
+    Ok(devices)
+}
+
 /// Create a statement to jump to the given target
 fn get_jump_action(target: Cow<str>) -> stmt::Statement {
     stmt::Statement::Jump(stmt::JumpTarget { target })
@@ -1355,6 +2680,311 @@ fn get_chain<'a>(base_rules: &schema::Nftables<'a>, chain: &str) -> Option<schem
     None
 }
 
+/// A rule of a chain with the owners which generate it.
+struct ChainRule<'a, 'b> {
+    rule: &'b schema::Rule<'a>,
+    owners: Vec<&'b str>,
+}
+
+fn get_cmd_rule<'a, 'b>(cmd: &'b schema::NfCmd<'a>) -> Option<&'b schema::Rule<'a>> {
+    match cmd {
+        schema::NfCmd::Add(schema::NfListObject::Rule(rule))
+        | schema::NfCmd::Insert(schema::NfListObject::Rule(rule)) => Some(rule),
+        _ => None,
+    }
+}
+
+/// Get the rules of each chain in the order the commands create them, rules
+/// generated by several owners are only added by the first one.
+fn get_expected_chains<'a, 'b>(
+    expected: &'b [(String, schema::NfCmd<'a>)],
+) -> Vec<(&'b str, Vec<ChainRule<'a, 'b>>)> {
+    let mut chains: Vec<(&str, Vec<ChainRule>)> = Vec::new();
+    for (owner, cmd) in expected {
+        let rule = match get_cmd_rule(cmd) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        let rules = match chains.iter().position(|(c, _)| *c == rule.chain) {
+            Some(i) => &mut chains[i].1,
+            None => {
+                chains.push((&rule.chain, Vec::new()));
+                &mut chains.last_mut().expect("chain was just added").1
+            }
+        };
+        if let Some(existing) = rules.iter_mut().find(|r| cmp_rules(r.rule, rule)) {
+            if !existing.owners.contains(&owner.as_str()) {
+                existing.owners.push(owner);
+            }
+            continue;
+        }
+        let chain_rule = ChainRule {
+            rule,
+            owners: vec![owner],
+        };
+        match cmd {
+            schema::NfCmd::Insert(_) => rules.insert(0, chain_rule),
+            _ => rules.push(chain_rule),
+        }
+    }
+    chains
+}
+
+/// Check the order of the known rules in the live chain. The order only matters
+/// between the rules of one owner and around the rules shared by all owners, the
+/// rules of different containers or networks may be in any order.
+fn is_chain_ordered(rules: &schema::Nftables, chain: &str, expected: &[ChainRule]) -> bool {
+    let positions: Vec<usize> = get_matching_rules_in_chain(rules, chain, |_| true)
+        .iter()
+        .filter_map(|live| expected.iter().position(|r| cmp_rules(r.rule, live)))
+        .collect();
+    for (i, a) in positions.iter().enumerate() {
+        for b in &positions[i + 1..] {
+            let (first, second) = (&expected[*b], &expected[*a]);
+            if a > b
+                && (first.owners.len() > 1
+                    || second.owners.len() > 1
+                    || first.owners.iter().any(|o| second.owners.contains(o)))
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Check if the object exists in the ruleset, elements are only compared by
+/// their first elem.
+fn object_exists(rules: &schema::Nftables, object: &schema::NfListObject) -> bool {
+    match object {
+        schema::NfListObject::Table(table) => rules.objects.iter().any(|o| {
+            matches!(o, schema::NfObject::ListObject(schema::NfListObject::Table(t)) if t.name == table.name)
+        }),
+        schema::NfListObject::Chain(chain) => get_chain(rules, &chain.name).is_some(),
+        schema::NfListObject::Rule(rule) => rules.objects.iter().any(|o| {
+            matches!(o, schema::NfObject::ListObject(schema::NfListObject::Rule(r)) if cmp_rules(r, rule))
+        }),
+        schema::NfListObject::Set(set) => get_set(rules, &set.name).is_some(),
+        schema::NfListObject::Map(map) => get_set(rules, &map.name).is_some(),
+        schema::NfListObject::Element(element) => {
+            let elems = match get_set(rules, &element.name) {
+                Some(schema::NfListObject::Set(set)) => set.elem,
+                Some(schema::NfListObject::Map(map)) => map.elem,
+                _ => return false,
+            };
+            match (elems, element.elem.first()) {
+                (Some(elems), Some(elem)) => elems.contains(elem),
+                _ => false,
+            }
+        }
+        _ => true,
+    }
+}
+
+/// Remove the offload rules of a network and shrink the flowtable to the devices
+/// still used by the other networks. Devices cannot be removed from a flowtable
+/// with the json syntax, so the flowtable is recreated when it has to shrink.
+fn remove_offload_rules<'a>(
+    batch: &mut Batch<'a>,
+    existing_rules: &schema::Nftables<'a>,
+    offload_rules: Vec<schema::Rule<'a>>,
+) {
+    let remaining = get_matching_rules_in_chain(existing_rules, OFFLOADCHAIN, |r| {
+        !offload_rules.iter().any(|o| o.handle == r.handle)
+    });
+    let flowtable = match get_flowtable(existing_rules) {
+        Some(ft) => ft,
+        None => {
+            for rule in offload_rules {
+                batch.delete(schema::NfListObject::Rule(rule));
+            }
+            return;
+        }
+    };
+
+    if remaining.is_empty() {
+        log::debug!("Removing flowtable, no network uses offload anymore");
+        for rule in get_matching_rules_in_chain(
+            existing_rules,
+            FORWARDCHAIN,
+            get_rule_matcher_jump_to(OFFLOADCHAIN.to_string()),
+        )
+        .into_iter()
+        .chain(offload_rules)
+        {
+            batch.delete(schema::NfListObject::Rule(rule));
+        }
+        if let Some(c) = get_chain(existing_rules, OFFLOADCHAIN) {
+            batch.delete(schema::NfListObject::Chain(c));
+        }
+        batch.delete(schema::NfListObject::FlowTable(flowtable));
+        return;
+    }
+
+    // The kernel already removed deleted devices, e.g. our bridge, from the
+    // flowtable so only keep the ones which still exist.
+    let current: Vec<String> = flowtable
+        .dev
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    let devices: Vec<String> = get_offload_devices(&remaining)
+        .into_iter()
+        .filter(|d| current.contains(d))
+        .collect();
+    if devices.len() == current.len() {
+        for rule in offload_rules {
+            batch.delete(schema::NfListObject::Rule(rule));
+        }
+        return;
+    }
+
+    log::debug!("Recreating flowtable with devices {devices:?}");
+    batch.add_cmd(schema::NfCmd::Flush(schema::FlushObject::Chain(
+        schema::Chain {
+            family: types::NfFamily::INet,
+            table: Cow::Borrowed(TABLENAME),
+            name: Cow::Borrowed(OFFLOADCHAIN),
+            ..schema::Chain::default()
+        },
+    )));
+    batch.delete(schema::NfListObject::FlowTable(flowtable));
+    batch.add(make_flowtable(devices));
+    for rule in remaining {
+        batch.add(schema::NfListObject::Rule(schema::Rule {
+            handle: None,
+            index: None,
+            ..rule
+        }));
+    }
+}
+
+/// Get the flowtable of the Netavark table.
+fn get_flowtable<'a>(base_rules: &schema::Nftables<'a>) -> Option<schema::FlowTable<'a>> {
+    base_rules.objects.iter().find_map(|object| match object {
+        schema::NfObject::ListObject(schema::NfListObject::FlowTable(ft))
+            if ft.name == FLOWTABLENAME =>
+        {
+            Some(ft.clone())
+        }
+        _ => None,
+    })
+}
+
+/// Get the devices the offload rules match on, i.e. the bridges and their uplinks.
+fn get_offload_devices(rules: &[schema::Rule]) -> Vec<String> {
+    let mut devices: Vec<String> = Vec::new();
+    let mut add = |dev: &expr::Expression| {
+        if let expr::Expression::String(s) = dev {
+            if !devices.iter().any(|d| d == s) {
+                devices.push(s.to_string());
+            }
+        }
+    };
+    for rule in rules {
+        for statement in rule.expr.iter() {
+            let m = match statement {
+                stmt::Statement::Match(m) => m,
+                _ => continue,
+            };
+            match &m.left {
+                expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                    key: expr::MetaKey::Iifname | expr::MetaKey::Oifname,
+                })) => {}
+                _ => continue,
+            }
+            match &m.right {
+                expr::Expression::Named(expr::NamedExpression::Set(items)) => {
+                    for item in items {
+                        if let expr::SetItem::Element(e) = item {
+                            add(e);
+                        }
+                    }
+                }
+                e => add(e),
+            }
+        }
+    }
+    devices
+}
+
+fn get_isolation_group_set_name(group: &str) -> String {
+    format!("{ISOLATION_GROUP_SET_PREFIX}{group}")
+}
+
+/// Get the isolation group sets with the bridge names of their members.
+fn get_isolation_group_sets<'a>(
+    base_rules: &schema::Nftables<'a>,
+) -> Vec<(schema::Set<'a>, Vec<String>)> {
+    let mut sets = Vec::new();
+    for object in base_rules.objects.iter() {
+        if let schema::NfObject::ListObject(schema::NfListObject::Set(set)) = object {
+            if !set.name.starts_with(ISOLATION_GROUP_SET_PREFIX) {
+                continue;
+            }
+            let members = set
+                .elem
+                .iter()
+                .flat_map(|elem| elem.iter())
+                .filter_map(|e| match e {
+                    expr::Expression::String(s) => Some(s.to_string()),
+                    _ => None,
+                })
+                .collect();
+            sets.push((set.as_ref().clone(), members));
+        }
+    }
+    sets
+}
+
+/// Get the set or map with the given name, as object to delete it.
+fn get_set<'a>(base_rules: &schema::Nftables<'a>, name: &str) -> Option<schema::NfListObject<'a>> {
+    for object in base_rules.objects.iter() {
+        match object {
+            schema::NfObject::ListObject(obj @ schema::NfListObject::Set(set)) => {
+                if set.name == name {
+                    return Some(obj.clone());
+                }
+            }
+            schema::NfObject::ListObject(obj @ schema::NfListObject::Map(map)) => {
+                if map.name == name {
+                    return Some(obj.clone());
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    None
+}
+
+/// Whether the deleted table or chain belongs to the netavark table and was
+/// not removed by netavark itself, e.g. by a `nft flush ruleset`. Netavark
+/// never deletes its table nor the chains shared by all networks.
+pub(crate) fn is_foreign_deletion(deletion: &NftDeletion) -> bool {
+    if deletion.family != libc::NFPROTO_INET as u8 || deletion.table != TABLENAME {
+        return false;
+    }
+    match &deletion.chain {
+        None => true,
+        Some(chain) => [
+            INPUTCHAIN,
+            FORWARDCHAIN,
+            POSTROUTINGCHAIN,
+            PREROUTINGCHAIN,
+            OUTPUTCHAIN,
+            DNATCHAIN,
+            MASKCHAIN,
+            ISOLATION1CHAIN,
+            ISOLATION2CHAIN,
+            ISOLATION3CHAIN,
+        ]
+        .contains(&chain.as_str()),
+    }
+}
+
 fn get_netavark_rules() -> Result<schema::Nftables<'static>, helper::NftablesError> {
     match helper::get_current_ruleset_with_args(None::<&str>, ["list", "table", "inet", TABLENAME])
     {
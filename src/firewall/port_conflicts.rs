@@ -0,0 +1,276 @@
+//! Detection of host port conflicts before the port forwarding is set up.
+//!
+//! The DNAT rules of a port mapping take the traffic of the host port, so a
+//! host port used by the port mapping of another container or by a socket on
+//! the host would silently steal the traffic of the other one. Port mappings
+//! with a service, which share the host port on purpose, are not checked.
+
+use std::{net::IpAddr, ops::RangeInclusive};
+
+use log::warn;
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::{
+        internal_types::PortForwardConfig,
+        netlink::Socket,
+        netlink_sock_diag::{NetlinkSockDiag, TCP_CLOSE, TCP_LISTEN},
+        types::PortMapping,
+    },
+};
+
+/// A port mapping of another container, with the network of its port config or
+/// None for host ports reserved by its setup.
+pub type UsedPort<'a> = (&'a str, Option<&'a str>, &'a PortMapping);
+
+/// Fail if a host port of the container is already used by the port mapping
+/// of another container or by a socket on the host. This is called by
+/// write_fw_config() with the firewall state locked, so a concurrent setup
+/// cannot use the same host port after the check.
+pub fn check_port_conflicts<'a>(
+    used: impl Iterator<Item = UsedPort<'a>>,
+    spf: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let ports: Vec<&PortMapping> = match spf.port_mappings {
+        Some(ports) => ports.iter().filter(|p| is_checked(p)).collect(),
+        None => return Ok(()),
+    };
+    if ports.is_empty() {
+        return Ok(());
+    }
+
+    for (container_id, network_name, used) in used {
+        if !is_checked(used) {
+            continue;
+        }
+        for port in &ports {
+            if let Some(p) = get_conflicting_port(port, used) {
+                let network = match network_name {
+                    Some(name) => format!(" in network {name}"),
+                    None => String::new(),
+                };
+                return Err(NetavarkError::msg(format!(
+                    "host port {p}/{} is already used by container {container_id}{network}",
+                    port.protocol
+                )));
+            }
+        }
+    }
+
+    let mut sock = match Socket::<NetlinkSockDiag>::new() {
+        Ok(sock) => sock,
+        Err(e) => {
+            warn!("failed to open sock_diag netlink socket to check host ports: {e}");
+            return Ok(());
+        }
+    };
+    for protocol in ["tcp", "udp", "sctp"] {
+        let proto_ports: Vec<&&PortMapping> =
+            ports.iter().filter(|p| p.protocol == protocol).collect();
+        if proto_ports.is_empty() {
+            continue;
+        }
+        let (proto_num, states) = match protocol {
+            "tcp" => (libc::IPPROTO_TCP, [TCP_LISTEN]),
+            // bound but unconnected udp sockets are in the close state
+            "udp" => (libc::IPPROTO_UDP, [TCP_CLOSE]),
+            _ => (libc::IPPROTO_SCTP, [TCP_LISTEN]),
+        };
+        for family in [libc::AF_INET, libc::AF_INET6] {
+            // The kernel might not support sock_diag for the protocol, e.g.
+            // without the sctp_diag module, the conflict cannot be detected then.
+            let addrs = match sock.dump_socket_addresses(family, proto_num, &states) {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    warn!("failed to list the {protocol} sockets of the host: {e}");
+                    continue;
+                }
+            };
+            for port in &proto_ports {
+                let range = get_host_port_range(port);
+                if let Some(addr) = addrs.iter().find(|addr| {
+                    range.contains(&addr.port())
+                        && socket_ip_overlaps(&addr.ip(), &parse_host_ip(&port.host_ip))
+                }) {
+                    return Err(NetavarkError::msg(format!(
+                        "host port {}/{protocol} is already used by a socket on the host bound to {addr}",
+                        addr.port()
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_checked(port: &PortMapping) -> bool {
+    port.service.is_none()
+}
+
+fn get_host_port_range(port: &PortMapping) -> RangeInclusive<u16> {
+    port.host_port..=port.host_port.saturating_add(port.range.max(1) - 1)
+}
+
+/// None for an empty host ip, which means all addresses of both families.
+fn parse_host_ip(host_ip: &str) -> Option<Option<IpAddr>> {
+    if host_ip.is_empty() {
+        return Some(None);
+    }
+    // the firewall driver reports the invalid ip
+    host_ip.parse().ok().map(Some)
+}
+
+fn host_ips_overlap(a: &Option<Option<IpAddr>>, b: &Option<Option<IpAddr>>) -> bool {
+    match (a, b) {
+        (Some(None), _) | (_, Some(None)) => true,
+        (Some(Some(a)), Some(Some(b))) => {
+            a.is_ipv4() == b.is_ipv4() && (a.is_unspecified() || b.is_unspecified() || a == b)
+        }
+        _ => false,
+    }
+}
+
+/// A socket bound to the IPv6 wildcard address also receives IPv4 traffic
+/// unless it is IPv6 only, which is not known here.
+fn socket_ip_overlaps(socket_ip: &IpAddr, host_ip: &Option<Option<IpAddr>>) -> bool {
+    if socket_ip.is_ipv6() && socket_ip.is_unspecified() {
+        return host_ip.is_some();
+    }
+    host_ips_overlap(&Some(Some(*socket_ip)), host_ip)
+}
+
+/// Return the first host port both port mappings use.
+fn get_conflicting_port(a: &PortMapping, b: &PortMapping) -> Option<u16> {
+    if a.protocol != b.protocol
+        || !host_ips_overlap(&parse_host_ip(&a.host_ip), &parse_host_ip(&b.host_ip))
+    {
+        return None;
+    }
+    let (a, b) = (get_host_port_range(a), get_host_port_range(b));
+    let start = *a.start().max(b.start());
+    (start <= *a.end().min(b.end())).then_some(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn port(host_ip: &str, host_port: u16, range: u16, protocol: &str) -> PortMapping {
+        PortMapping {
+            container_port: 80,
+            host_ip: host_ip.to_string(),
+            host_port,
+            protocol: protocol.to_string(),
+            range,
+            allowed_sources: None,
+            rate_limit: None,
+            connection_limit: None,
+            service: None,
+            service_balance: None,
+        }
+    }
+
+    #[test]
+    fn test_get_conflicting_port() {
+        let a = port("", 8080, 1, "tcp");
+        assert_eq!(
+            get_conflicting_port(&a, &port("", 8080, 1, "tcp")),
+            Some(8080)
+        );
+        assert_eq!(get_conflicting_port(&a, &port("", 8080, 1, "udp")), None);
+        assert_eq!(get_conflicting_port(&a, &port("", 8081, 1, "tcp")), None);
+        assert_eq!(
+            get_conflicting_port(&port("", 8075, 10, "tcp"), &port("", 8080, 3, "tcp")),
+            Some(8080)
+        );
+        assert_eq!(
+            get_conflicting_port(&a, &port("127.0.0.1", 8080, 1, "tcp")),
+            Some(8080)
+        );
+        assert_eq!(
+            get_conflicting_port(
+                &port("127.0.0.1", 8080, 1, "tcp"),
+                &port("127.0.0.2", 8080, 1, "tcp")
+            ),
+            None
+        );
+        assert_eq!(
+            get_conflicting_port(
+                &port("0.0.0.0", 8080, 1, "tcp"),
+                &port("127.0.0.1", 8080, 1, "tcp")
+            ),
+            Some(8080)
+        );
+        assert_eq!(
+            get_conflicting_port(
+                &port("::", 8080, 1, "tcp"),
+                &port("127.0.0.1", 8080, 1, "tcp")
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_socket_ip_overlaps() {
+        let any4: IpAddr = "0.0.0.0".parse().unwrap();
+        let any6: IpAddr = "::".parse().unwrap();
+        let lo: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(socket_ip_overlaps(&any6, &parse_host_ip("10.0.0.1")));
+        assert!(socket_ip_overlaps(&any4, &parse_host_ip("")));
+        assert!(socket_ip_overlaps(&any4, &parse_host_ip("10.0.0.1")));
+        assert!(!socket_ip_overlaps(&any4, &parse_host_ip("fd00::1")));
+        assert!(socket_ip_overlaps(&lo, &parse_host_ip("")));
+        assert!(!socket_ip_overlaps(&lo, &parse_host_ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn test_check_port_conflicts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host_port = listener.local_addr().unwrap().port();
+        let ports = Some(vec![port("", host_port, 1, "tcp")]);
+        let other_ip_ports = Some(vec![port("127.0.0.2", host_port, 1, "tcp")]);
+        let dns_server_ips = Vec::new();
+        let spf = |ports| PortForwardConfig {
+            container_id: "123".to_string(),
+            network_id: "abc".to_string(),
+            port_mappings: ports,
+            network_name: "podman".to_string(),
+            network_hash_name: "abc".to_string(),
+            container_ip_v4: Some("10.88.0.2".parse().unwrap()),
+            subnet_v4: Some("10.88.0.0/16".parse().unwrap()),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 53,
+            dns_server_ips: &dns_server_ips,
+            egress_allow: Vec::new(),
+        };
+
+        let err = check_port_conflicts(std::iter::empty(), &spf(&ports)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "host port {host_port}/tcp is already used by a socket on the host bound to 127.0.0.1:{host_port}"
+            )
+        );
+        check_port_conflicts(std::iter::empty(), &spf(&other_ip_ports)).unwrap();
+
+        let used = port("127.0.0.2", host_port, 1, "tcp");
+        let err = check_port_conflicts(
+            [("456", Some("net"), &used)].into_iter(),
+            &spf(&other_ip_ports),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("host port {host_port}/tcp is already used by container 456 in network net")
+        );
+        let err = check_port_conflicts([("456", None, &used)].into_iter(), &spf(&other_ip_ports))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("host port {host_port}/tcp is already used by container 456")
+        );
+    }
+}
@@ -5,11 +5,17 @@ use std::{
 };
 
 use fs2::FileExt;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
     error::{NetavarkError, NetavarkResult},
-    network::internal_types::{PortForwardConfig, PortForwardConfigOwned, SetupNetwork},
+    firewall::port_conflicts::check_port_conflicts,
+    network::{
+        internal_types::{
+            PortForwardConfig, PortForwardConfigOwned, Service, ServiceBackend, SetupNetwork,
+        },
+        types::PortMapping,
+    },
     wrap,
 };
 
@@ -18,12 +24,16 @@ use crate::{
 //                 - firewall-driver -> name of the firewall driver
 //                 - networks/$netID -> network config setup
 //                 - ports/$netID_$conID -> port config
+//                 - services/$name -> load balanced service with all backends
+//                 - reserved-ports/$conID -> host ports allocated by a running setup
 
 const FIREWALL_DIR: &str = "firewall";
 const FIREWALL_DRIVER_FILE: &str = "firewall-driver";
 const FIREWALL_LOCK_FILE: &str = "firewall-reload.lock";
 const NETWORK_CONF_DIR: &str = "networks";
 const PORT_CONF_DIR: &str = "ports";
+const SERVICE_CONF_DIR: &str = "services";
+const RESERVED_PORTS_DIR: &str = "reserved-ports";
 
 struct FilePaths {
     fw_driver_file: PathBuf,
@@ -134,6 +144,37 @@ pub fn write_fw_config(
     port_conf: &PortForwardConfig,
 ) -> NetavarkResult<()> {
     let paths = get_file_paths(config_dir, network_id, container_id, true)?;
+    if port_conf
+        .port_mappings
+        .as_ref()
+        .is_some_and(|ports| !ports.is_empty())
+    {
+        // Check the host ports under the same lock which stores them, otherwise a
+        // concurrent setup could check the same host ports before they are written.
+        // The same container can be connected to more than one network with the
+        // same port mappings.
+        let port_confs: Vec<_> = read_dir_conf::<PortForwardConfigOwned>(
+            firewall_config_dir(config_dir).join(PORT_CONF_DIR),
+        )?
+        .into_iter()
+        .filter(|c| c.container_id != container_id)
+        .collect();
+        let reserved = read_reserved_ports(config_dir, container_id)?;
+        let used = port_confs
+            .iter()
+            .flat_map(|c| {
+                c.port_mappings
+                    .iter()
+                    .flatten()
+                    .map(|p| (c.container_id.as_str(), Some(c.network_name.as_str()), p))
+            })
+            .chain(reserved.iter().flat_map(|r| {
+                r.port_mappings
+                    .iter()
+                    .map(|p| (r.container_id.as_str(), None, p))
+            }));
+        check_port_conflicts(used, port_conf)?;
+    }
     fs_err!(
         File::create,
         &paths.fw_driver_file,
@@ -189,6 +230,212 @@ pub fn remove_fw_config(
     Ok(())
 }
 
+/// Read the port mappings of the container in the network from its port config,
+/// returns None when there is no config.
+pub fn read_port_mappings(
+    config_dir: &Path,
+    network_id: &str,
+    container_id: &str,
+) -> NetavarkResult<Option<Vec<PortMapping>>> {
+    let paths = get_file_paths(config_dir, network_id, container_id, false)?;
+    let content = wrap!(
+        ignore_enoent!(fs::read_to_string(&paths.port_conf_file), return Ok(None)),
+        format!("read port config {:?}", paths.port_conf_file.display())
+    )?;
+    let conf: PortForwardConfigOwned = serde_json::from_str(&content)?;
+    Ok(Some(conf.port_mappings.unwrap_or_default()))
+}
+
+fn service_conf_dir(config_dir: &Path) -> PathBuf {
+    firewall_config_dir(config_dir).join(SERVICE_CONF_DIR)
+}
+
+fn read_service_conf(path: &Path) -> NetavarkResult<Option<Service>> {
+    let content = wrap!(
+        ignore_enoent!(fs::read_to_string(path), return Ok(None)),
+        format!("read service config {:?}", path.display())
+    )?;
+    Ok(Some(serde_json::from_str(&content)?))
+}
+
+/// Add the backends of a container to the service config and call apply with
+/// the service with all its backends while holding the lock, so concurrent
+/// updates of the service are applied in order. Unlike the other configs
+/// services are stored for rootless as well, the backends are needed for
+/// every setup.
+pub fn add_service_backends<F, T>(
+    config_dir: &Path,
+    service: &Service,
+    apply: F,
+) -> NetavarkResult<T>
+where
+    F: FnOnce(&Service) -> NetavarkResult<T>,
+{
+    // only used for the lock
+    let _paths = get_file_paths(config_dir, "", "", false)?;
+    let dir = service_conf_dir(config_dir);
+    fs_err!(fs::create_dir_all, &dir, "create service config dir")?;
+    let path = dir.join(&service.name);
+
+    let mut conf = match read_service_conf(&path)? {
+        Some(conf) => {
+            if !conf.same_frontend(service) {
+                return Err(NetavarkError::msg(format!(
+                    "port mapping does not match the existing service {}",
+                    service.name
+                )));
+            }
+            conf
+        }
+        None => Service {
+            backends: Vec::new(),
+            ..service.clone()
+        },
+    };
+    // setup might run again for the same container, e.g. after a failed teardown
+    conf.backends
+        .retain(|b| !service.backends.iter().any(|n| n.ip == b.ip));
+    conf.backends.extend(service.backends.iter().cloned());
+
+    let file = fs_err!(File::create, &path, "create service config")?;
+    serde_json::to_writer(file, &conf)?;
+    apply(&conf)
+}
+
+/// Remove the backends of a container from the service config and call apply
+/// with the service with the remaining backends while holding the lock, the
+/// config is removed once no backends are left. Returns None if the service
+/// does not exist.
+pub fn remove_service_backends<F, T>(
+    config_dir: &Path,
+    name: &str,
+    backends: &[ServiceBackend],
+    apply: F,
+) -> NetavarkResult<Option<T>>
+where
+    F: FnOnce(&Service) -> NetavarkResult<T>,
+{
+    // only used for the lock
+    let _paths = get_file_paths(config_dir, "", "", false)?;
+    let path = service_conf_dir(config_dir).join(name);
+
+    let mut conf = match read_service_conf(&path)? {
+        Some(conf) => conf,
+        None => return Ok(None),
+    };
+    conf.backends.retain(|b| {
+        !backends
+            .iter()
+            .any(|r| r.container_id == b.container_id && r.ip == b.ip)
+    });
+
+    if conf.backends.is_empty() {
+        fs_err!(remove_file_ignore_enoent, &path, "remove service config")?;
+    } else {
+        let file = fs_err!(File::create, &path, "create service config")?;
+        serde_json::to_writer(file, &conf)?;
+    }
+    apply(&conf).map(Some)
+}
+
+fn reserved_ports_dir(config_dir: &Path) -> PathBuf {
+    firewall_config_dir(config_dir).join(RESERVED_PORTS_DIR)
+}
+
+/// Host ports allocated for a container which are not in its port configs yet.
+#[derive(Serialize, Deserialize)]
+struct ReservedPorts {
+    container_id: String,
+    port_mappings: Vec<PortMapping>,
+}
+
+/// Read the host ports reserved by the setup of other containers.
+fn read_reserved_ports(
+    config_dir: &Path,
+    container_id: &str,
+) -> NetavarkResult<Vec<ReservedPorts>> {
+    let dir = reserved_ports_dir(config_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(read_dir_conf::<ReservedPorts>(dir)?
+        .into_iter()
+        .filter(|r| r.container_id != container_id)
+        .collect())
+}
+
+/// Allocate host ports for the container while holding the lock. allocate gets the
+/// port mappings of the port configs of all containers and of the host ports reserved
+/// by other setups, and the services. The port mappings it returns are reserved until
+/// release_host_ports(), so a concurrent setup cannot use the same host ports before
+/// the drivers stored them in the port configs.
+pub fn reserve_host_ports<F>(
+    config_dir: &Path,
+    container_id: &str,
+    allocate: F,
+) -> NetavarkResult<()>
+where
+    F: FnOnce(Vec<PortMapping>, Vec<Service>) -> NetavarkResult<Vec<PortMapping>>,
+{
+    let _paths = get_file_paths(config_dir, "", "", true)?;
+    let (used, services) = read_used_host_ports(config_dir, container_id)?;
+
+    let reserved = ReservedPorts {
+        container_id: container_id.to_string(),
+        port_mappings: allocate(used, services)?,
+    };
+    let dir = reserved_ports_dir(config_dir);
+    fs_err!(fs::create_dir_all, &dir, "create reserved ports dir")?;
+    let file = fs_err!(
+        File::create,
+        &dir.join(container_id),
+        "create reserved ports"
+    )?;
+    serde_json::to_writer(file, &reserved)?;
+    Ok(())
+}
+
+/// Read the port mappings of the port configs of all containers and of the host
+/// ports reserved by other setups, and the services. Nothing is locked or created,
+/// reserve_host_ports() must be used to allocate ports.
+pub fn read_used_host_ports(
+    config_dir: &Path,
+    container_id: &str,
+) -> NetavarkResult<(Vec<PortMapping>, Vec<Service>)> {
+    let port_dir = firewall_config_dir(config_dir).join(PORT_CONF_DIR);
+    let mut used: Vec<PortMapping> = if port_dir.exists() {
+        read_dir_conf::<PortForwardConfigOwned>(port_dir)?
+            .into_iter()
+            .flat_map(|c| c.port_mappings.unwrap_or_default())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    used.extend(
+        read_reserved_ports(config_dir, container_id)?
+            .into_iter()
+            .flat_map(|r| r.port_mappings),
+    );
+    let service_dir = service_conf_dir(config_dir);
+    let services = if service_dir.exists() {
+        read_dir_conf(service_dir)?
+    } else {
+        Vec::new()
+    };
+    Ok((used, services))
+}
+
+/// Remove the host ports reserved for the container.
+pub fn release_host_ports(config_dir: &Path, container_id: &str) -> NetavarkResult<()> {
+    // only used for the lock
+    let _paths = get_file_paths(config_dir, "", "", false)?;
+    fs_err!(
+        remove_file_ignore_enoent,
+        &reserved_ports_dir(config_dir).join(container_id),
+        "remove reserved ports"
+    )
+}
+
 pub struct FirewallConfig {
     /// Name of the firewall driver
     pub driver: String,
@@ -196,6 +443,8 @@ pub struct FirewallConfig {
     pub net_confs: Vec<SetupNetwork>,
     /// All port forwarding configs
     pub port_confs: Vec<PortForwardConfigOwned>,
+    /// All load balanced services
+    pub services: Vec<Service>,
 
     /// Lock file for the firewall code to prevent us from adding rules while the state files
     /// have been removed in the meantime.
@@ -218,11 +467,18 @@ pub fn read_fw_config(config_dir: &Path) -> NetavarkResult<Option<FirewallConfig
 
     let net_confs = read_dir_conf(paths.net_conf_file)?;
     let port_confs = read_dir_conf(paths.port_conf_file)?;
+    let service_dir = service_conf_dir(config_dir);
+    let services = if service_dir.exists() {
+        read_dir_conf(service_dir)?
+    } else {
+        Vec::new()
+    };
 
     Ok(Some(FirewallConfig {
         driver,
         net_confs,
         port_confs,
+        services,
         lock_file: paths.lock_file,
     }))
 }
@@ -251,6 +507,7 @@ mod tests {
     use std::net::{IpAddr, Ipv4Addr};
 
     use crate::network::internal_types::IsolateOption;
+    use crate::network::types::ServiceBalance;
 
     use super::*;
     use tempfile::Builder;
@@ -274,6 +531,8 @@ mod tests {
             dns_port: 53,
             outbound_addr4: None,
             outbound_addr6: None,
+            offload: false,
+            ipv6_nat: true,
         };
         let net_conf_json = r#"{"subnets":["10.0.0.0/24"],"bridge_name":"bridge","network_id":"c2c8a073252874648259997d53b0a1bffa491e21f04bc1bf8609266359931395","network_hash_name":"hash","isolation":"Never","dns_port":53}"#;
 
@@ -290,6 +549,7 @@ mod tests {
             subnet_v6: None,
             dns_port: 53,
             dns_server_ips: &vec![],
+            egress_allow: vec![],
         };
         let port_conf_json = r#"{"container_id":"123","network_id":"c2c8a073252874648259997d53b0a1bffa491e21f04bc1bf8609266359931395","port_mappings":null,"network_name":"name","network_hash_name":"hash","container_ip_v4":"10.0.0.2","subnet_v4":"10.0.0.0/24","container_ip_v6":null,"subnet_v6":null,"dns_port":53,"dns_server_ips":[]}"#;
 
@@ -341,6 +601,120 @@ mod tests {
         assert!(res.is_ok(), "remove_fw_config failed second time");
     }
 
+    #[test]
+    fn test_service_backends() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+
+        let backend = |id: &str, ip: &str| ServiceBackend {
+            container_id: id.to_string(),
+            ip: ip.parse().unwrap(),
+            subnet: "10.0.0.0/24".parse().unwrap(),
+        };
+        let service = Service {
+            name: "web".to_string(),
+            protocol: "tcp".to_string(),
+            host_ip: "".to_string(),
+            host_port: 8080,
+            container_port: 80,
+            balance: ServiceBalance::RoundRobin,
+            backends: vec![backend("1", "10.0.0.2")],
+        };
+
+        let res = add_service_backends(config_dir, &service, |s| Ok(s.clone())).unwrap();
+        assert_eq!(res.backends, vec![backend("1", "10.0.0.2")]);
+        // adding the same backend again must not duplicate it
+        let res = add_service_backends(config_dir, &service, |s| Ok(s.clone())).unwrap();
+        assert_eq!(res.backends.len(), 1, "no duplicated backend");
+
+        let second = Service {
+            backends: vec![backend("2", "10.0.0.3")],
+            ..service.clone()
+        };
+        let res = add_service_backends(config_dir, &second, |s| Ok(s.clone())).unwrap();
+        assert_eq!(
+            res.backends,
+            vec![backend("1", "10.0.0.2"), backend("2", "10.0.0.3")]
+        );
+
+        let other_port = Service {
+            host_port: 8081,
+            ..second.clone()
+        };
+        let res = add_service_backends(config_dir, &other_port, |s| Ok(s.clone()));
+        assert!(res.is_err(), "different host port must be rejected");
+
+        // the services are part of the firewall config for reloads
+        let fw_dir = config_dir.join(FIREWALL_DIR);
+        fs::create_dir_all(fw_dir.join(NETWORK_CONF_DIR)).unwrap();
+        fs::create_dir_all(fw_dir.join(PORT_CONF_DIR)).unwrap();
+        fs::write(fw_dir.join(FIREWALL_DRIVER_FILE), "nftables").unwrap();
+        let res = read_fw_config(config_dir).unwrap().expect("fw config");
+        assert_eq!(res.services.len(), 1, "one service");
+        drop(res);
+
+        let res = remove_service_backends(config_dir, "web", &service.backends, |s| Ok(s.clone()))
+            .unwrap();
+        assert_eq!(res.unwrap().backends, vec![backend("2", "10.0.0.3")]);
+        let res = remove_service_backends(config_dir, "web", &second.backends, |s| Ok(s.clone()))
+            .unwrap();
+        assert!(res.unwrap().backends.is_empty(), "no backends left");
+        assert!(
+            !service_conf_dir(config_dir).join("web").exists(),
+            "service config should not exists"
+        );
+        let res = remove_service_backends(config_dir, "web", &second.backends, |s| Ok(s.clone()))
+            .unwrap();
+        assert!(res.is_none(), "service is gone");
+    }
+
+    #[test]
+    fn test_reserve_host_ports() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+
+        let port = |host_port: u16| PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port,
+            protocol: "tcp".to_string(),
+            range: 1,
+            allowed_sources: None,
+            rate_limit: None,
+            connection_limit: None,
+            service: None,
+            service_balance: None,
+        };
+
+        reserve_host_ports(config_dir, "1", |used, services| {
+            assert!(used.is_empty(), "no used ports");
+            assert!(services.is_empty(), "no services");
+            Ok(vec![port(40000)])
+        })
+        .unwrap();
+        // the container does not see its own reservation
+        reserve_host_ports(config_dir, "1", |used, _| {
+            assert!(used.is_empty(), "own reservation not used");
+            Ok(vec![port(40000)])
+        })
+        .unwrap();
+        reserve_host_ports(config_dir, "2", |used, _| {
+            assert_eq!(used, vec![port(40000)], "reservation of the other setup");
+            Ok(vec![port(40001)])
+        })
+        .unwrap();
+
+        release_host_ports(config_dir, "1").unwrap();
+        release_host_ports(config_dir, "2").unwrap();
+        reserve_host_ports(config_dir, "3", |used, _| {
+            assert!(used.is_empty(), "reservations released");
+            Ok(Vec::new())
+        })
+        .unwrap();
+        // releasing twice is no error
+        release_host_ports(config_dir, "1").unwrap();
+    }
+
     #[test]
     fn test_read_fw_config_empty() {
         let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
@@ -4,8 +4,10 @@ use clap::{Parser, Subcommand};
 
 use netavark::commands::create;
 use netavark::commands::dhcp_proxy;
+use netavark::commands::firewall;
 use netavark::commands::firewall_reload;
 use netavark::commands::firewalld_reload;
+use netavark::commands::nftables_watch;
 use netavark::commands::setup;
 use netavark::commands::teardown;
 use netavark::commands::update;
@@ -58,6 +60,11 @@ enum SubCommand {
     // Re-applies firewall rules for all networks.
     #[command(name = "firewall-reload")]
     FirewallReload,
+    /// Watch for the deletion of the netavark nftables table and reload fw rules
+    #[command(name = "nftables-watch")]
+    NftablesWatch,
+    /// Render or check the firewall rules.
+    Firewall(firewall::Firewall),
 }
 
 fn main() {
@@ -93,6 +100,14 @@ fn main() {
         SubCommand::DHCPProxy(proxy) => dhcp_proxy::serve(proxy),
         SubCommand::FirewallDReload => firewalld_reload::listen(config),
         SubCommand::FirewallReload => firewall_reload::firewall_reload(config),
+        SubCommand::NftablesWatch => nftables_watch::watch(config),
+        SubCommand::Firewall(firewall) => firewall.exec(
+            opts.file,
+            config,
+            opts.firewall_driver,
+            opts.plugin_directories,
+            rootless,
+        ),
     };
 
     match result {
@@ -18,7 +18,7 @@ use crate::{
     exec_netns,
     firewall::{
         nft::MAX_HASH_SIZE,
-        state::{remove_fw_config, write_fw_config},
+        state::{add_service_backends, remove_fw_config, remove_service_backends, write_fw_config},
     },
     network::{constants, sysctl::disable_ipv6_autoconf, types},
 };
@@ -36,15 +36,21 @@ use netlink_packet_route::{
 
 use super::{
     constants::{
-        ISOLATE_OPTION_FALSE, ISOLATE_OPTION_STRICT, ISOLATE_OPTION_TRUE,
-        NO_CONTAINER_INTERFACE_ERROR, OPTION_HOST_INTERFACE_NAME, OPTION_ISOLATE, OPTION_METRIC,
-        OPTION_MODE, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE, OPTION_OUTBOUND_ADDR4,
-        OPTION_OUTBOUND_ADDR6, OPTION_VLAN, OPTION_VRF, VALID_BRIDGE_OPTS,
+        ISOLATE_OPTION_FALSE, ISOLATE_OPTION_GROUP_PREFIX, ISOLATE_OPTION_STRICT,
+        ISOLATE_OPTION_TRUE, MAX_ISOLATE_GROUP_LEN, NO_CONTAINER_INTERFACE_ERROR,
+        OPTION_EGRESS_ALLOW, OPTION_HOST_INTERFACE_NAME, OPTION_IPV6_NAT, OPTION_IPV6_NDP_PROXY,
+        OPTION_ISOLATE, OPTION_METRIC, OPTION_MODE, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE,
+        OPTION_OFFLOAD, OPTION_OUTBOUND_ADDR4, OPTION_OUTBOUND_ADDR6, OPTION_VLAN, OPTION_VRF,
+        VALID_BRIDGE_OPTS,
+    },
+    core_utils::{
+        self, get_egress_limit, get_ipam_addresses, is_using_systemd, join_netns, parse_option,
+        CoreUtils,
     },
-    core_utils::{self, get_ipam_addresses, is_using_systemd, join_netns, parse_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::{
-        IPAMAddresses, IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork,
+        parse_egress_rules, EgressLimit, EgressRule, IPAMAddresses, IsolateOption,
+        PortForwardConfig, Service, ServiceBackend, SetupNetwork, TearDownNetwork,
         TeardownPortForward,
     },
     sysctl,
@@ -90,6 +96,11 @@ struct InternalData {
     outbound_addr4: Option<Ipv4Addr>,
     /// outbound IPv6 address for SNAT
     outbound_addr6: Option<Ipv6Addr>,
+    /// uplink interface which answers the neighbour solicitations for the
+    /// ipv6 container addresses
+    ipv6_ndp_proxy: Option<String>,
+    /// bandwidth limit of the traffic sent by the container
+    egress_limit: Option<EgressLimit>,
 }
 
 pub struct Bridge<'a> {
@@ -111,6 +122,15 @@ impl<'a> Bridge<'a> {
     pub fn new(info: DriverInfo<'a>) -> Self {
         Bridge { info, data: None }
     }
+
+    /// Set the mtu of the bridge and veths, used by drivers which wrap the bridge
+    /// and know a better default than the default route interface. Must be
+    /// called after validate().
+    pub(crate) fn set_mtu(&mut self, mtu: u32) {
+        if let Some(data) = self.data.as_mut() {
+            data.mtu = mtu;
+        }
+    }
 }
 
 impl driver::NetworkDriver for Bridge<'_> {
@@ -157,11 +177,25 @@ impl driver::NetworkDriver for Bridge<'_> {
             }
         }
 
-        // Parse outbound address options early to catch errors
+        // Parse outbound address, offload and egress options early to catch errors
         let outbound_addr4: Option<Ipv4Addr> =
             parse_option(&self.info.network.options, OPTION_OUTBOUND_ADDR4)?;
         let outbound_addr6: Option<Ipv6Addr> =
             parse_option(&self.info.network.options, OPTION_OUTBOUND_ADDR6)?;
+        parse_option::<bool>(&self.info.network.options, OPTION_OFFLOAD)?;
+        let ipv6_nat: Option<bool> = parse_option(&self.info.network.options, OPTION_IPV6_NAT)?;
+        if ipv6_nat == Some(false) && outbound_addr6.is_some() {
+            return Err(NetavarkError::msg(format!(
+                "{OPTION_OUTBOUND_ADDR6} cannot be used with {OPTION_IPV6_NAT}=false"
+            )));
+        }
+        let ipv6_ndp_proxy: Option<String> =
+            parse_option(&self.info.network.options, OPTION_IPV6_NDP_PROXY)?;
+        get_egress_allow_option(&self.info.per_network_opts.options)?;
+        let egress_limit = get_egress_limit(
+            &self.info.network.options,
+            &self.info.per_network_opts.options,
+        )?;
 
         self.data = Some(InternalData {
             bridge_interface_name: bridge_name,
@@ -178,6 +212,8 @@ impl driver::NetworkDriver for Bridge<'_> {
             vlan,
             outbound_addr4,
             outbound_addr6,
+            ipv6_ndp_proxy,
+            egress_limit,
         });
         Ok(())
     }
@@ -217,11 +253,17 @@ impl driver::NetworkDriver for Bridge<'_> {
             self.info.netns_container,
         )?;
 
+        if let Some(uplink) = &data.ipv6_ndp_proxy {
+            add_ndp_proxy_entries(host_sock, uplink, &data.ipam.container_addresses)?;
+        }
+
         //  StatusBlock response
         let mut response = types::StatusBlock {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            ntp_servers: None,
+            port_mappings: None,
         };
         // interfaces map, but we only ever expect one, for response
         let mut interfaces: HashMap<String, types::NetInterface> = HashMap::new();
@@ -230,22 +272,24 @@ impl driver::NetworkDriver for Bridge<'_> {
         // a dhcp lease.  it will also perform the IP address assignment
         // to the container interface.
         let subnets = if data.ipam.dhcp_enabled {
-            let (subnets, dns_servers, domain_name) = get_dhcp_lease(
+            let lease = get_dhcp_lease(
                 &data.bridge_interface_name,
                 &data.container_interface_name,
                 self.info.netns_path,
                 &container_veth_mac,
                 self.info.container_hostname.as_deref().unwrap_or(""),
                 self.info.container_id,
+                self.info.network,
             )?;
             // do not overwrite dns servers set by dns podman flag
             if !self.info.container_dns_servers.is_some() {
-                response.dns_server_ips = dns_servers;
+                response.dns_server_ips = lease.dns_servers;
             }
-            if domain_name.is_some() {
-                response.dns_search_domains = domain_name;
+            if lease.domain_names.is_some() {
+                response.dns_search_domains = lease.domain_names;
             }
-            subnets
+            response.ntp_servers = lease.ntp_servers;
+            lease.subnets
         } else {
             data.ipam.net_addresses.clone()
         };
@@ -362,7 +406,8 @@ impl driver::NetworkDriver for Bridge<'_> {
         if let BridgeMode::Managed = data.mode {
             // if the network is internal do not setup firewall rules
             if !self.info.network.internal {
-                self.setup_firewall(data)?
+                self.setup_firewall(data)?;
+                response.port_mappings = self.info.port_mappings.clone().filter(|p| !p.is_empty());
             }
         }
         if let Some(w) = sysctl_writer {
@@ -372,6 +417,28 @@ impl driver::NetworkDriver for Bridge<'_> {
         Ok((response, aardvark_entry))
     }
 
+    fn render_firewall(&self) -> NetavarkResult<()> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before render")),
+        };
+
+        if data.mode == BridgeMode::Unmanaged || self.info.network.internal {
+            return Ok(());
+        }
+
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
+            &data.ipam.container_addresses,
+            &data.ipam.nameservers,
+            data.isolate.clone(),
+            data.bridge_interface_name.clone(),
+            data.outbound_addr4,
+            data.outbound_addr6,
+        )?;
+        render_firewall(&self.info, sn, spf)
+    }
+
     fn teardown(
         &self,
         netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
@@ -391,6 +458,20 @@ impl driver::NetworkDriver for Bridge<'_> {
                 .unwrap_or_else(|err| error_list.push(err))
         }
 
+        match parse_option::<String>(&self.info.network.options, OPTION_IPV6_NDP_PROXY) {
+            Ok(Some(uplink)) => {
+                match get_ipam_addresses(self.info.per_network_opts, self.info.network) {
+                    Ok(ipam) => {
+                        remove_ndp_proxy_entries(host_sock, &uplink, &ipam.container_addresses)
+                            .unwrap_or_else(|err| error_list.push(err))
+                    }
+                    Err(err) => error_list.push(err),
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error_list.push(err),
+        }
+
         let bridge_name = get_interface_name(self.info.network.network_interface.clone())?;
 
         let complete_teardown = match remove_link(
@@ -451,81 +532,12 @@ fn get_interface_name(name: Option<String>) -> NetavarkResult<String> {
 }
 
 impl<'a> Bridge<'a> {
-    fn get_firewall_conf(
-        &'a self,
-        container_addresses: &Vec<IpNet>,
-        nameservers: &'a Vec<IpAddr>,
-        isolate: IsolateOption,
-        bridge_name: String,
-        outbound_addr4: Option<Ipv4Addr>,
-        outbound_addr6: Option<Ipv6Addr>,
-    ) -> NetavarkResult<(SetupNetwork, PortForwardConfig<'a>)> {
-        let id_network_hash =
-            CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE);
-        let sn = SetupNetwork {
-            subnets: self
-                .info
-                .network
-                .subnets
-                .as_ref()
-                .map(|nets| nets.iter().map(|n| n.subnet).collect()),
-            bridge_name,
-            network_id: self.info.network.id.clone(),
-            network_hash_name: id_network_hash.clone(),
-            isolation: isolate,
-            dns_port: self.info.dns_port,
-            outbound_addr4,
-            outbound_addr6,
-        };
-
-        let mut has_ipv4 = false;
-        let mut has_ipv6 = false;
-        let mut addr_v4: Option<IpAddr> = None;
-        let mut addr_v6: Option<IpAddr> = None;
-        let mut net_v4: Option<IpNet> = None;
-        let mut net_v6: Option<IpNet> = None;
-        for net in container_addresses {
-            match net {
-                IpNet::V4(v4) => {
-                    if has_ipv4 {
-                        continue;
-                    }
-                    addr_v4 = Some(IpAddr::V4(v4.addr()));
-                    net_v4 = Some(IpNet::new(v4.network().into(), v4.prefix_len())?);
-                    has_ipv4 = true;
-                }
-                IpNet::V6(v6) => {
-                    if has_ipv6 {
-                        continue;
-                    }
-
-                    addr_v6 = Some(IpAddr::V6(v6.addr()));
-                    net_v6 = Some(IpNet::new(v6.network().into(), v6.prefix_len())?);
-                    has_ipv6 = true;
-                }
-            }
-        }
-        let spf = PortForwardConfig {
-            container_id: self.info.container_id.clone(),
-            network_id: self.info.network.id.clone(),
-            port_mappings: self.info.port_mappings,
-            network_name: self.info.network.name.clone(),
-            network_hash_name: id_network_hash,
-            container_ip_v4: addr_v4,
-            subnet_v4: net_v4,
-            container_ip_v6: addr_v6,
-            subnet_v6: net_v6,
-            dns_port: self.info.dns_port,
-            dns_server_ips: nameservers,
-        };
-        Ok((sn, spf))
-    }
-
     fn setup_firewall(&self, data: &InternalData) -> NetavarkResult<()> {
-        let (sn, spf) = self.get_firewall_conf(
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
             &data.ipam.container_addresses,
             &data.ipam.nameservers,
-            data.isolate,
+            data.isolate.clone(),
             data.bridge_interface_name.clone(),
             data.outbound_addr4,
             data.outbound_addr6,
@@ -542,11 +554,15 @@ impl<'a> Bridge<'a> {
             )?;
         }
 
+        let services = get_services(&spf);
+
         let system_dbus = zbus::blocking::Connection::system().ok();
 
         self.info.firewall.setup_network(sn, &system_dbus)?;
 
         self.info.firewall.setup_port_forward(spf, &system_dbus)?;
+
+        setup_services(&self.info, services)?;
         Ok(())
     }
 
@@ -564,7 +580,7 @@ impl<'a> Bridge<'a> {
                 Some(d) => (
                     &d.ipam.container_addresses,
                     &d.ipam.nameservers,
-                    d.isolate,
+                    d.isolate.clone(),
                     d.outbound_addr4,
                     d.outbound_addr6,
                 ),
@@ -609,7 +625,8 @@ impl<'a> Bridge<'a> {
                 }
             };
 
-        let (sn, spf) = self.get_firewall_conf(
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
             container_addresses_ref,
             nameservers_ref,
             isolate,
@@ -638,16 +655,92 @@ impl<'a> Bridge<'a> {
             self.info.firewall.teardown_network(tn)?;
         }
 
+        let services = get_services(&spf);
+
         let tpf = TeardownPortForward {
             config: spf,
             complete_teardown,
         };
 
         self.info.firewall.teardown_port_forward(tpf)?;
+
+        teardown_services(&self.info, services)?;
         Ok(())
     }
 }
 
+/// Build the firewall configuration for the network and the container port forwarding.
+pub(crate) fn get_firewall_conf<'a>(
+    info: &DriverInfo<'a>,
+    container_addresses: &[IpNet],
+    nameservers: &'a Vec<IpAddr>,
+    isolate: IsolateOption,
+    bridge_name: String,
+    outbound_addr4: Option<Ipv4Addr>,
+    outbound_addr6: Option<Ipv6Addr>,
+) -> NetavarkResult<(SetupNetwork, PortForwardConfig<'a>)> {
+    let id_network_hash = CoreUtils::create_network_hash(&info.network.name, MAX_HASH_SIZE);
+    let sn = SetupNetwork {
+        subnets: info
+            .network
+            .subnets
+            .as_ref()
+            .map(|nets| nets.iter().map(|n| n.subnet).collect()),
+        bridge_name,
+        network_id: info.network.id.clone(),
+        network_hash_name: id_network_hash.clone(),
+        isolation: isolate,
+        dns_port: info.dns_port,
+        outbound_addr4,
+        outbound_addr6,
+        offload: parse_option(&info.network.options, OPTION_OFFLOAD)?.unwrap_or(false),
+        ipv6_nat: parse_option(&info.network.options, OPTION_IPV6_NAT)?.unwrap_or(true),
+    };
+
+    let mut has_ipv4 = false;
+    let mut has_ipv6 = false;
+    let mut addr_v4: Option<IpAddr> = None;
+    let mut addr_v6: Option<IpAddr> = None;
+    let mut net_v4: Option<IpNet> = None;
+    let mut net_v6: Option<IpNet> = None;
+    for net in container_addresses {
+        match net {
+            IpNet::V4(v4) => {
+                if has_ipv4 {
+                    continue;
+                }
+                addr_v4 = Some(IpAddr::V4(v4.addr()));
+                net_v4 = Some(IpNet::new(v4.network().into(), v4.prefix_len())?);
+                has_ipv4 = true;
+            }
+            IpNet::V6(v6) => {
+                if has_ipv6 {
+                    continue;
+                }
+
+                addr_v6 = Some(IpAddr::V6(v6.addr()));
+                net_v6 = Some(IpNet::new(v6.network().into(), v6.prefix_len())?);
+                has_ipv6 = true;
+            }
+        }
+    }
+    let spf = PortForwardConfig {
+        container_id: info.container_id.clone(),
+        network_id: info.network.id.clone(),
+        port_mappings: info.port_mappings,
+        network_name: info.network.name.clone(),
+        network_hash_name: id_network_hash,
+        container_ip_v4: addr_v4,
+        subnet_v4: net_v4,
+        container_ip_v6: addr_v6,
+        subnet_v6: net_v6,
+        dns_port: info.dns_port,
+        dns_server_ips: nameservers,
+        egress_allow: get_egress_allow_option(&info.per_network_opts.options)?,
+    };
+    Ok((sn, spf))
+}
+
 // sysctl forward
 const IPV4_FORWARD: &str = "net/ipv4/ip_forward";
 const IPV6_FORWARD: &str = "net/ipv6/conf/all/forwarding";
@@ -918,6 +1011,13 @@ fn create_veth_pair<'fd>(
         ));
     }
 
+    // The traffic sent by the container is received by the host side veth, it is
+    // policed there so the container cannot remove the limit from its netns.
+    if let Some(limit) = &data.egress_limit {
+        host.set_ingress_police(host_link, limit.rate, limit.burst)
+            .wrap("set egress limit on host veth")?;
+    }
+
     if let Some(vid) = data.vlan {
         host.set_vlan_id(
             host_link,
@@ -1002,6 +1102,51 @@ fn create_veth_pair<'fd>(
     Ok(mac)
 }
 
+/// Create a veth pair which is not connected to any bridge, the host side is
+/// named host_interface_name. No default routes are added, the caller must set
+/// up the routing itself. Returns the container veth mac address.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn create_standalone_veth_pair<'fd>(
+    host: &mut Socket<NetlinkRoute>,
+    netns: &mut Socket<NetlinkRoute>,
+    host_interface_name: &str,
+    container_interface_name: &str,
+    mac_address: Option<Vec<u8>>,
+    container_addresses: Vec<IpNet>,
+    ipv6_enabled: bool,
+    hostns_fd: BorrowedFd<'fd>,
+    netns_fd: BorrowedFd<'fd>,
+    mtu: u32,
+) -> NetavarkResult<String> {
+    let data = InternalData {
+        container_interface_name: container_interface_name.to_string(),
+        host_interface_name: host_interface_name.to_string(),
+        bridge_interface_name: String::new(),
+        mac_address,
+        ipam: IPAMAddresses {
+            container_addresses,
+            dhcp_enabled: false,
+            gateway_addresses: Vec::new(),
+            routes: Vec::new(),
+            ipv6_enabled,
+            net_addresses: Vec::new(),
+            nameservers: Vec::new(),
+        },
+        mtu,
+        isolate: IsolateOption::Never,
+        metric: None,
+        mode: BridgeMode::Managed,
+        no_default_route: true,
+        vrf: None,
+        vlan: None,
+        outbound_addr4: None,
+        outbound_addr6: None,
+        ipv6_ndp_proxy: None,
+        egress_limit: None,
+    };
+    create_veth_pair(host, netns, &data, 0, None, true, hostns_fd, netns_fd, mtu)
+}
+
 /// Make sure the LinkMessage is of type bridge and if vlan is set also checks
 /// that the bridge has vlan_filtering enabled and if not enables it. Returns
 /// the link id or errors when the link is not a bridge.
@@ -1146,7 +1291,59 @@ fn remove_link(
     Ok(false)
 }
 
-fn get_isolate_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<IsolateOption> {
+/// Answer the neighbour solicitations for the ipv6 container addresses on the uplink,
+/// so upstream routers can reach the containers of a network without NAT.
+fn add_ndp_proxy_entries(
+    host: &mut Socket<NetlinkRoute>,
+    uplink: &str,
+    addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    let link = host
+        .get_link(LinkID::Name(uplink.to_string()))
+        .wrap(format!("get {OPTION_IPV6_NDP_PROXY} interface {uplink}"))?;
+    let mut enabled = false;
+    for addr in addresses {
+        if let IpNet::V6(net) = addr {
+            if !enabled {
+                sysctl::apply_sysctl_value(format!("net/ipv6/conf/{uplink}/proxy_ndp"), "1")?;
+                enabled = true;
+            }
+            host.add_neighbour_proxy(link.header.index, &IpAddr::V6(net.addr()))
+                .wrap(format!("add ndp proxy entry on {uplink}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_ndp_proxy_entries(
+    host: &mut Socket<NetlinkRoute>,
+    uplink: &str,
+    addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    let link = host
+        .get_link(LinkID::Name(uplink.to_string()))
+        .wrap(format!("get {OPTION_IPV6_NDP_PROXY} interface {uplink}"))?;
+    for addr in addresses {
+        if let IpNet::V6(net) = addr {
+            match host.del_neighbour_proxy(link.header.index, &IpAddr::V6(net.addr())) {
+                Ok(_) => {}
+                // already gone, e.g. the uplink was recreated
+                Err(NetavarkError::Netlink(ref e)) if -e.raw_code() == libc::ENOENT => {}
+                Err(err) => {
+                    return Err(NetavarkError::wrap(
+                        format!("delete ndp proxy entry on {uplink}"),
+                        err,
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn get_isolate_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<IsolateOption> {
     let isolate: String = match parse_option(opts, OPTION_ISOLATE)? {
         Some(i) => i,
         // if no option default to strict isolation
@@ -1157,14 +1354,113 @@ fn get_isolate_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<
         ISOLATE_OPTION_STRICT => IsolateOption::Strict,
         ISOLATE_OPTION_TRUE => IsolateOption::Normal,
         ISOLATE_OPTION_FALSE => IsolateOption::Never,
-        _ => {
-            return Err(NetavarkError::msg(format!(
-                "invalid isolate option \"{isolate}\""
-            )))
-        }
+        _ => match isolate.strip_prefix(ISOLATE_OPTION_GROUP_PREFIX) {
+            Some(group) => {
+                // the name is used in nftables set and firewalld ipset names
+                if group.is_empty()
+                    || group.len() > MAX_ISOLATE_GROUP_LEN
+                    || !group
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    return Err(NetavarkError::msg(format!(
+                        "invalid isolation group \"{group}\", must be 1-{MAX_ISOLATE_GROUP_LEN} characters of [a-zA-Z0-9_-]"
+                    )));
+                }
+                IsolateOption::Group(group.to_string())
+            }
+            None => {
+                return Err(NetavarkError::msg(format!(
+                    "invalid isolate option \"{isolate}\""
+                )))
+            }
+        },
     })
 }
 
+/// Get the services of the port mappings with the container as only backend.
+pub(crate) fn get_services(spf: &PortForwardConfig) -> Vec<Service> {
+    let mut backends = Vec::with_capacity(2);
+    for (ip, subnet) in [
+        (spf.container_ip_v4, spf.subnet_v4),
+        (spf.container_ip_v6, spf.subnet_v6),
+    ] {
+        if let (Some(ip), Some(subnet)) = (ip, subnet) {
+            backends.push(ServiceBackend {
+                container_id: spf.container_id.clone(),
+                ip,
+                subnet,
+            });
+        }
+    }
+
+    match spf.port_mappings {
+        Some(ports) => ports
+            .iter()
+            .filter_map(|port| {
+                port.service
+                    .as_ref()
+                    .map(|name| Service::new(name, port, backends.clone()))
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Run the firewall setup on a dry run firewall driver. Nothing is written to the
+/// state, so services only get the container as backend.
+pub(crate) fn render_firewall(
+    info: &DriverInfo,
+    sn: SetupNetwork,
+    spf: PortForwardConfig,
+) -> NetavarkResult<()> {
+    let services = get_services(&spf);
+    let system_dbus = zbus::blocking::Connection::system().ok();
+
+    info.firewall.setup_network(sn, &system_dbus)?;
+    info.firewall.setup_port_forward(spf, &system_dbus)?;
+    for service in services {
+        info.firewall.update_service(&service)?;
+    }
+    Ok(())
+}
+
+/// Add the container to the services and update the firewall rules with all backends.
+pub(crate) fn setup_services(info: &DriverInfo, services: Vec<Service>) -> NetavarkResult<()> {
+    for service in services {
+        add_service_backends(info.config_dir, &service, |service| {
+            info.firewall.update_service(service)
+        })?;
+    }
+    Ok(())
+}
+
+/// Remove the container from the services and update the firewall rules with
+/// the remaining backends.
+pub(crate) fn teardown_services(info: &DriverInfo, services: Vec<Service>) -> NetavarkResult<()> {
+    for service in services {
+        remove_service_backends(
+            info.config_dir,
+            &service.name,
+            &service.backends,
+            |service| info.firewall.update_service(service),
+        )?;
+    }
+    Ok(())
+}
+
+/// Parse the per container egress_allow option, an empty list means no restriction.
+pub(crate) fn get_egress_allow_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Vec<EgressRule>> {
+    match opts.as_ref().and_then(|map| map.get(OPTION_EGRESS_ALLOW)) {
+        Some(val) => parse_egress_rules(val).map_err(|err| {
+            NetavarkError::msg(format!("unable to parse \"{OPTION_EGRESS_ALLOW}\": {err}"))
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
 fn get_bridge_mode_from_string(mode: Option<&str>) -> NetavarkResult<BridgeMode> {
     match mode {
         // default to l3 when unset
@@ -1,5 +1,7 @@
 //Following module contains all the network constants
 
+use std::net::{Ipv4Addr, Ipv6Addr};
+
 // default search domain
 pub static PODMAN_DEFAULT_SEARCH_DOMAIN: &str = "dns.podman";
 
@@ -7,15 +9,24 @@ pub static PODMAN_DEFAULT_SEARCH_DOMAIN: &str = "dns.podman";
 pub const IPAM_HOSTLOCAL: &str = "host-local";
 pub const IPAM_DHCP: &str = "dhcp";
 pub const IPAM_NONE: &str = "none";
+/// ipam option to disable DHCPv4 for ipv6 only dhcp networks
+pub const IPAM_OPTION_DHCP_IPV4: &str = "dhcp_ipv4";
 
 pub const DRIVER_BRIDGE: &str = "bridge";
 pub const DRIVER_IPVLAN: &str = "ipvlan";
 pub const DRIVER_MACVLAN: &str = "macvlan";
+pub const DRIVER_VXLAN: &str = "vxlan";
+pub const DRIVER_HOST_DEVICE: &str = "host-device";
+pub const DRIVER_ROUTED: &str = "routed";
+pub const DRIVER_WIREGUARD: &str = "wireguard";
 
 pub const OPTION_ISOLATE: &str = "isolate";
 pub const ISOLATE_OPTION_TRUE: &str = "true";
 pub const ISOLATE_OPTION_FALSE: &str = "false";
 pub const ISOLATE_OPTION_STRICT: &str = "strict";
+pub const ISOLATE_OPTION_GROUP_PREFIX: &str = "group:";
+/// the group name is part of the firewalld ipset names which are limited to 31 chars
+pub const MAX_ISOLATE_GROUP_LEN: usize = 15;
 pub const OPTION_MTU: &str = "mtu";
 pub const OPTION_MODE: &str = "mode";
 pub const OPTION_METRIC: &str = "metric";
@@ -26,6 +37,23 @@ pub const OPTION_VLAN: &str = "vlan";
 pub const OPTION_HOST_INTERFACE_NAME: &str = "host_interface_name";
 pub const OPTION_OUTBOUND_ADDR4: &str = "outbound_addr4";
 pub const OPTION_OUTBOUND_ADDR6: &str = "outbound_addr6";
+pub const OPTION_OFFLOAD: &str = "offload";
+pub const OPTION_IPV6_NAT: &str = "ipv6_nat";
+pub const OPTION_IPV6_NDP_PROXY: &str = "ipv6_ndp_proxy";
+pub const OPTION_VNI: &str = "vni";
+pub const OPTION_VXLAN_LOCAL: &str = "vxlan_local";
+pub const OPTION_VXLAN_REMOTE: &str = "vxlan_remote";
+pub const OPTION_VXLAN_GROUP: &str = "vxlan_group";
+pub const OPTION_VXLAN_PORT: &str = "vxlan_port";
+pub const OPTION_VXLAN_DEV: &str = "vxlan_dev";
+pub const OPTION_WG_PRIVATE_KEY_FILE: &str = "wg_private_key_file";
+pub const OPTION_WG_LISTEN_PORT: &str = "wg_listen_port";
+pub const OPTION_WG_PEERS: &str = "wg_peers";
+/// per container option, destinations the container may send traffic to
+pub const OPTION_EGRESS_ALLOW: &str = "egress_allow";
+/// per network and per container options, shape the traffic sent by the container
+pub const OPTION_EGRESS_RATE: &str = "egress_rate";
+pub const OPTION_EGRESS_BURST: &str = "egress_burst";
 
 pub const MACVLAN_MODE_PRIVATE: &str = "private";
 pub const MACVLAN_MODE_VEPA: &str = "vepa";
@@ -42,6 +70,18 @@ pub const DEFAULT_CONFIG_DIR: &str = "/run/containers/networks";
 
 pub const MAX_INTERFACE_NAME_LEN: usize = 15;
 
+/// IANA assigned udp port for vxlan.
+pub const DEFAULT_VXLAN_PORT: u16 = 4789;
+/// Outer ethernet + ip + udp + vxlan header size, used to lower the default mtu.
+pub const VXLAN_OVERHEAD: u32 = 50;
+
+/// Default interface name prefix for routed networks.
+pub const ROUTED_DEFAULT_INTERFACE_PREFIX: &str = "nvr";
+/// Link local gateway used as next hop inside containers on routed networks,
+/// the host veth answers for it via proxy arp/ndp.
+pub const ROUTED_GATEWAY_V4: Ipv4Addr = Ipv4Addr::new(169, 254, 1, 1);
+pub const ROUTED_GATEWAY_V6: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
 // ValidMacVlanModes is the list of valid option constants for the macvlan driver.
 pub const VALID_BRIDGE_OPTS: &[&str] = &[
     OPTION_MODE,
@@ -51,6 +91,11 @@ pub const VALID_BRIDGE_OPTS: &[&str] = &[
     OPTION_NO_DEFAULT_ROUTE,
     OPTION_VRF,
     OPTION_VLAN,
+    OPTION_OFFLOAD,
+    OPTION_IPV6_NAT,
+    OPTION_IPV6_NDP_PROXY,
+    OPTION_EGRESS_RATE,
+    OPTION_EGRESS_BURST,
 ];
 
 // ValidMacVlanModes is the list of valid option constants for the macvlan driver.
@@ -60,4 +105,41 @@ pub const VALID_VLAN_OPTS: &[&str] = &[
     OPTION_METRIC,
     OPTION_NO_DEFAULT_ROUTE,
     OPTION_BCLIM,
+    OPTION_EGRESS_RATE,
+    OPTION_EGRESS_BURST,
+];
+
+// VALID_VXLAN_OPTS is the list of valid option constants for the vxlan driver.
+pub const VALID_VXLAN_OPTS: &[&str] = &[
+    OPTION_MTU,
+    OPTION_ISOLATE,
+    OPTION_METRIC,
+    OPTION_NO_DEFAULT_ROUTE,
+    OPTION_VNI,
+    OPTION_VXLAN_LOCAL,
+    OPTION_VXLAN_REMOTE,
+    OPTION_VXLAN_GROUP,
+    OPTION_VXLAN_PORT,
+    OPTION_VXLAN_DEV,
+];
+
+// VALID_HOST_DEVICE_OPTS is the list of valid option constants for the host-device driver.
+pub const VALID_HOST_DEVICE_OPTS: &[&str] = &[OPTION_MTU, OPTION_METRIC, OPTION_NO_DEFAULT_ROUTE];
+
+// VALID_ROUTED_OPTS is the list of valid option constants for the routed driver.
+pub const VALID_ROUTED_OPTS: &[&str] = &[
+    OPTION_MTU,
+    OPTION_ISOLATE,
+    OPTION_METRIC,
+    OPTION_NO_DEFAULT_ROUTE,
+    OPTION_OUTBOUND_ADDR4,
+    OPTION_OUTBOUND_ADDR6,
+];
+
+// VALID_WIREGUARD_OPTS is the list of valid option constants for the wireguard driver.
+pub const VALID_WIREGUARD_OPTS: &[&str] = &[
+    OPTION_MTU,
+    OPTION_WG_PRIVATE_KEY_FILE,
+    OPTION_WG_LISTEN_PORT,
+    OPTION_WG_PEERS,
 ];
@@ -642,6 +642,61 @@ pub fn get_default_route_interface(
     Err(NetavarkError::msg("failed to get default route interface"))
 }
 
+/// Get the egress limit of the container, the per container egress_rate and
+/// egress_burst options take precedence over the ones of the network.
+pub fn get_egress_limit(
+    network_opts: &Option<HashMap<String, String>>,
+    container_opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<internal_types::EgressLimit>> {
+    let rate: Option<internal_types::EgressRate> =
+        match parse_option(container_opts, constants::OPTION_EGRESS_RATE)? {
+            Some(rate) => Some(rate),
+            None => parse_option(network_opts, constants::OPTION_EGRESS_RATE)?,
+        };
+    let burst: Option<internal_types::EgressBurst> =
+        match parse_option(container_opts, constants::OPTION_EGRESS_BURST)? {
+            Some(burst) => Some(burst),
+            None => parse_option(network_opts, constants::OPTION_EGRESS_BURST)?,
+        };
+    match rate {
+        Some(rate) => Ok(Some(internal_types::EgressLimit::new(rate, burst))),
+        None if burst.is_some() => Err(NetavarkError::msg(format!(
+            "{} requires {}",
+            constants::OPTION_EGRESS_BURST,
+            constants::OPTION_EGRESS_RATE
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Shape the traffic sent by the container with a tbf qdisc on its interface.
+pub fn set_egress_limit(
+    netns: &mut Socket<NetlinkRoute>,
+    if_name: &str,
+    limit: &internal_types::EgressLimit,
+) -> NetavarkResult<()> {
+    let link = netns.get_link(LinkID::Name(if_name.to_string()))?;
+    netns
+        .set_tbf_qdisc(link.header.index, limit.rate, limit.burst, limit.limit())
+        .wrap(format!("set egress limit on {if_name}"))
+}
+
+/// Remove the qdisc of set_egress_limit(), a missing interface or qdisc is not
+/// an error.
+pub fn remove_egress_limit(netns: &mut Socket<NetlinkRoute>, if_name: &str) -> NetavarkResult<()> {
+    let result = netns
+        .get_link(LinkID::Name(if_name.to_string()))
+        .and_then(|link| netns.del_root_qdisc(link.header.index));
+    match result {
+        Err(NetavarkError::Netlink(ref e))
+            if -e.raw_code() == libc::ENODEV || -e.raw_code() == libc::ENOENT =>
+        {
+            Ok(())
+        }
+        result => result.wrap(format!("remove egress limit from {if_name}")),
+    }
+}
+
 pub fn get_mtu_from_iface_attributes(attributes: &[LinkAttribute]) -> NetavarkResult<u32> {
     for nla in attributes.iter() {
         if let LinkAttribute::Mtu(mtu) = nla {
@@ -33,6 +33,27 @@ pub fn new_network(
                 create.create_opts,
             )?;
         }
+        constants::DRIVER_VXLAN => {
+            check_used = create.create_opts.check_used_subnets;
+            setup_vxlan_options(&mut network)?;
+            create_bridge(
+                &mut network,
+                &create.used,
+                check_used,
+                true,
+                create.create_opts,
+            )?;
+        }
+        constants::DRIVER_ROUTED => {
+            check_used = create.create_opts.check_used_subnets;
+            create_routed(&mut network, &create.used, check_used, create.create_opts)?;
+        }
+        constants::DRIVER_WIREGUARD => {
+            create_wireguard(&mut network)?;
+        }
+        constants::DRIVER_HOST_DEVICE => {
+            create_host_device(&mut network)?;
+        }
         constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => {
             create_ipvlan_macvlan(&mut network)?;
         }
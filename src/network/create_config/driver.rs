@@ -5,11 +5,15 @@ use crate::network::core_utils::CoreUtils;
 use crate::network::create_config::subnet::{
     get_free_ipv4_network_subnet, get_free_ipv6_network_subnet,
 };
+use crate::network::host_device::parse_host_device_opts;
 use crate::network::netlink::Socket;
 use crate::network::netlink_route::NetlinkRoute;
 use crate::network::plugin::exec_plugin_common;
+use crate::network::routed::{parse_routed_opts, MAX_INTERFACE_PREFIX_LEN};
 use crate::network::types::{CreateOpts, Network, Used};
 use crate::network::vlan::parse_vlan_opts;
+use crate::network::vxlan::parse_vxlan_opts;
+use crate::network::wireguard::parse_wireguard_opts;
 use netlink_packet_route::link::LinkAttribute;
 use regex::Regex;
 use std::collections::HashMap;
@@ -42,6 +46,39 @@ pub fn setup_bridge_options(network: &mut Network) -> NetavarkResult<(bool, bool
     Ok((true, true))
 }
 
+pub fn setup_vxlan_options(network: &mut Network) -> NetavarkResult<()> {
+    let vxlan_opts = parse_vxlan_opts(&network.options, true)?;
+    // we need at least one way to reach other hosts
+    if vxlan_opts.remotes.is_empty() && vxlan_opts.group.is_none() {
+        return Err(NetavarkError::msg(format!(
+            "vxlan driver requires either the {} or {} option",
+            constants::OPTION_VXLAN_REMOTE,
+            constants::OPTION_VXLAN_GROUP
+        )));
+    }
+    if let Some(dev) = &vxlan_opts.dev {
+        if let Some(interface_names) = get_link_names() {
+            if !interface_names.contains(dev) {
+                return Err(NetavarkError::msg(format!(
+                    "vxlan device {} does not exist",
+                    dev
+                )));
+            }
+        }
+    }
+
+    // The encapsulation needs some space so lower the default mtu to not
+    // fragment every full sized packet.
+    let network_opts = network.options.get_or_insert_with(HashMap::new);
+    if !network_opts.contains_key(constants::OPTION_MTU) {
+        network_opts.insert(
+            constants::OPTION_MTU.to_string(),
+            (1500 - constants::VXLAN_OVERHEAD).to_string(),
+        );
+    }
+    Ok(())
+}
+
 pub fn create_bridge(
     network: &mut Network,
     used: &Used,
@@ -214,6 +251,146 @@ pub fn create_ipvlan_macvlan(network: &mut Network) -> NetavarkResult<()> {
     Ok(())
 }
 
+pub fn create_host_device(network: &mut Network) -> NetavarkResult<()> {
+    match &network.network_interface {
+        Some(interface) if !interface.is_empty() => {
+            if let Some(interface_names) = get_link_names() {
+                if !interface_names.contains(interface) {
+                    return Err(NetavarkError::msg(format!(
+                        "host device {} does not exist",
+                        interface
+                    )));
+                }
+            }
+        }
+        _ => {
+            return Err(NetavarkError::msg(
+                "host-device driver requires a network interface",
+            ))
+        }
+    }
+
+    // like macvlan, the container is directly on the host network so there
+    // is no place where aardvark-dns could listen
+    network.dns_enabled = false;
+
+    let ipam_opts = network.ipam_options.get_or_insert_with(HashMap::new);
+    let ipam_driver = ipam_opts.get("driver").map(|s| s.as_str()).unwrap_or("");
+    let subnets_len = network.subnets.as_ref().map_or(0, |s| s.len());
+    match ipam_driver {
+        "" => {
+            let driver = if subnets_len == 0 {
+                constants::IPAM_DHCP
+            } else {
+                constants::IPAM_HOSTLOCAL
+            };
+            ipam_opts.insert("driver".to_string(), driver.to_string());
+        }
+        constants::IPAM_HOSTLOCAL if subnets_len == 0 => {
+            return Err(NetavarkError::msg(
+                "host-device driver needs at least one subnet specified when the host-local ipam driver is set",
+            ));
+        }
+        constants::IPAM_DHCP if subnets_len > 0 => {
+            return Err(NetavarkError::msg(
+                "ipam driver dhcp set but subnets are set",
+            ));
+        }
+        _ => {}
+    }
+
+    // validate the given options, we do not need them but just check to make sure they are valid
+    parse_host_device_opts(&network.options, true)?;
+
+    Ok(())
+}
+
+pub fn create_routed(
+    network: &mut Network,
+    used: &Used,
+    check_used: bool,
+    opts: CreateOpts,
+) -> NetavarkResult<()> {
+    // The network interface is only the name prefix of the host veths, the
+    // container id hash is appended to it for each container.
+    match &network.network_interface {
+        Some(prefix) => {
+            if used.interfaces.contains(prefix) {
+                return Err(NetavarkError::msg(format!(
+                    "interface prefix {} already in use",
+                    prefix
+                )));
+            }
+        }
+        None => {
+            network.network_interface = Some(get_free_device_name(
+                &Some(constants::ROUTED_DEFAULT_INTERFACE_PREFIX.to_string()),
+                &used.interfaces,
+                &used.names,
+            )?);
+        }
+    }
+    if let Some(prefix) = &network.network_interface {
+        if prefix.len() > MAX_INTERFACE_PREFIX_LEN {
+            return Err(NetavarkError::msg(format!(
+                "routed interface prefix {} is longer than {} characters",
+                prefix, MAX_INTERFACE_PREFIX_LEN
+            )));
+        }
+    }
+
+    // there is no address on the host for aardvark-dns to listen on
+    network.dns_enabled = false;
+
+    if network
+        .ipam_options
+        .as_ref()
+        .and_then(|ipam_opts| ipam_opts.get("driver"))
+        .is_some_and(|driver| driver == constants::IPAM_DHCP)
+    {
+        return Err(NetavarkError::msg(
+            "ipam driver dhcp is not supported with the routed driver",
+        ));
+    }
+
+    // validate the given options, we do not need them but just check to make sure they are valid
+    parse_routed_opts(&network.options, true)?;
+
+    // subnet allocation works exactly like for bridge networks
+    create_bridge(network, used, check_used, false, opts)
+}
+
+pub fn create_wireguard(network: &mut Network) -> NetavarkResult<()> {
+    // the interface only exists in the container, there is no place where
+    // aardvark-dns could listen
+    network.dns_enabled = false;
+
+    let ipam_opts = network.ipam_options.get_or_insert_with(HashMap::new);
+    let ipam_driver = ipam_opts.get("driver").map(|s| s.as_str()).unwrap_or("");
+    let subnets_len = network.subnets.as_ref().map_or(0, |s| s.len());
+    match ipam_driver {
+        "" | constants::IPAM_HOSTLOCAL => {
+            if subnets_len == 0 {
+                return Err(NetavarkError::msg(
+                    "wireguard driver needs at least one subnet specified",
+                ));
+            }
+            ipam_opts.insert("driver".to_string(), constants::IPAM_HOSTLOCAL.to_string());
+        }
+        constants::IPAM_DHCP => {
+            return Err(NetavarkError::msg(
+                "ipam driver dhcp is not supported with the wireguard driver",
+            ));
+        }
+        _ => {}
+    }
+
+    // validate the given options, we do not need them but just check to make sure they are valid
+    parse_wireguard_opts(&network.options, true)?;
+
+    Ok(())
+}
+
 fn get_free_device_name(
     default_interface_name: &Option<String>,
     used_interfaces: &[String],
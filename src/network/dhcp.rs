@@ -4,15 +4,47 @@ use ipnet::IpNet;
 use std::net::IpAddr;
 use std::str::FromStr;
 
-use crate::dhcp_proxy::lib::g_rpc::NetworkConfig;
+use crate::dhcp_proxy::lib::g_rpc::{Lease, NetworkConfig, Version};
 use crate::dhcp_proxy::proxy_conf::DEFAULT_UDS_PATH;
 
-use super::core_utils;
 use super::driver::DriverInfo;
+use super::{constants, core_utils, types};
 use crate::network::netlink::Socket;
 use crate::network::netlink_route::{LinkID, NetlinkRoute};
 
-pub type DhcpLeaseInfo = (Vec<NetAddress>, Option<Vec<IpAddr>>, Option<Vec<String>>);
+/// The information of the DHCP leases returned in the StatusBlock.
+#[derive(Debug, Default)]
+pub struct DhcpLeaseInfo {
+    pub subnets: Vec<NetAddress>,
+    pub dns_servers: Option<Vec<IpAddr>>,
+    /// domain name followed by the domain search list
+    pub domain_names: Option<Vec<String>>,
+    pub ntp_servers: Option<Vec<String>>,
+}
+
+impl DhcpLeaseInfo {
+    /// merge the lease info of the second ip version into this one
+    fn merge(&mut self, other: DhcpLeaseInfo) {
+        self.subnets.extend(other.subnets);
+        if let Some(dns) = other.dns_servers {
+            self.dns_servers.get_or_insert_with(Vec::new).extend(dns);
+        }
+        if let Some(domains) = other.domain_names {
+            append_unique(self.domain_names.get_or_insert_with(Vec::new), domains);
+        }
+        if let Some(ntp) = other.ntp_servers {
+            append_unique(self.ntp_servers.get_or_insert_with(Vec::new), ntp);
+        }
+    }
+}
+
+fn append_unique(current: &mut Vec<String>, values: Vec<String>) {
+    for value in values {
+        if !current.contains(&value) {
+            current.push(value);
+        }
+    }
+}
 
 /// dhcp performs the connection to the nv-proxy over grpc where it
 /// requests it to perform a lease via the host's network interface
@@ -25,6 +57,7 @@ pub type DhcpLeaseInfo = (Vec<NetAddress>, Option<Vec<IpAddr>>, Option<Vec<Strin
 /// * `container_network_interface`: container network interface (eth0)
 /// * `ns_path`: path to the container netns
 /// * `container_macvlan_mac`: mac address of the container network interface above.
+/// * `network`: the network, it decides which ip versions are requested.
 ///
 /// returns: Result<Vec<NetAddress, Global>, NetavarkError>
 ///
@@ -40,19 +73,76 @@ pub fn get_dhcp_lease(
     container_macvlan_mac: &str,
     container_hostname: &str,
     container_id: &str,
+    network: &types::Network,
 ) -> NetavarkResult<DhcpLeaseInfo> {
-    let nvp_config = NetworkConfig {
+    let (ipv4, ipv6) = get_dhcp_versions(network)?;
+    let nvp_config = |version: Version| NetworkConfig {
         host_iface: host_network_interface.to_string(),
         // TODO add in domain name support
         domain_name: "".to_string(),
         host_name: container_hostname.to_string(),
-        version: 0,
+        version: version.into(),
         ns_path: ns_path.to_string(),
         container_iface: container_network_interface.to_string(),
         container_mac_addr: container_macvlan_mac.to_string(),
         container_id: container_id.to_string(),
     };
-    let lease = match tokio::task::LocalSet::new().block_on(
+
+    let mut info = DhcpLeaseInfo::default();
+    if ipv4 {
+        let lease = request_lease(nvp_config(Version::V4))?;
+        info.merge(parse_lease(lease)?);
+    }
+    if ipv6 {
+        let result = request_lease(nvp_config(Version::V6)).and_then(parse_lease);
+        match result {
+            Ok(lease_info) => info.merge(lease_info),
+            Err(err) => {
+                // do not keep the ipv4 lease around when the setup fails
+                if ipv4 {
+                    if let Err(e) = release_dhcp_lease(
+                        host_network_interface,
+                        container_network_interface,
+                        ns_path,
+                        container_macvlan_mac,
+                    ) {
+                        log::warn!("failed to release DHCP lease: {e}");
+                    }
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Returns which ip versions should be requested via DHCP. IPv6 is requested
+/// when the network has ipv6 enabled, IPv4 unless disabled with the
+/// dhcp_ipv4=false ipam option for ipv6 only networks.
+fn get_dhcp_versions(network: &types::Network) -> NetavarkResult<(bool, bool)> {
+    let ipv4 = match network
+        .ipam_options
+        .as_ref()
+        .and_then(|opts| opts.get(constants::IPAM_OPTION_DHCP_IPV4))
+    {
+        Some(value) => value.parse::<bool>().map_err(|e| {
+            NetavarkError::msg(format!(
+                "invalid ipam option {}: {e}",
+                constants::IPAM_OPTION_DHCP_IPV4
+            ))
+        })?,
+        None => true,
+    };
+    if !ipv4 && !network.ipv6_enabled {
+        return Err(NetavarkError::msg(
+            "dhcp ipam driver requires ipv4 or ipv6 to be enabled",
+        ));
+    }
+    Ok((ipv4, network.ipv6_enabled))
+}
+
+fn request_lease(nvp_config: NetworkConfig) -> NetavarkResult<Lease> {
+    match tokio::task::LocalSet::new().block_on(
         match &tokio::runtime::Builder::new_current_thread()
             .enable_io()
             .enable_time()
@@ -65,23 +155,33 @@ pub fn get_dhcp_lease(
         },
         nvp_config.get_lease(DEFAULT_UDS_PATH),
     ) {
-        Ok(l) => l,
-        Err(e) => {
-            return Err(NetavarkError::msg(format!("unable to obtain lease: {e}")));
-        }
-    };
+        Ok(l) => Ok(l),
+        Err(e) => Err(NetavarkError::msg(format!("unable to obtain lease: {e}"))),
+    }
+}
 
+fn parse_lease(lease: Lease) -> NetavarkResult<DhcpLeaseInfo> {
     // Note: technically DHCP can return multiple gateways but
     // we are just plucking the one. gw may also not exist.
-    let gw = if !lease.gateways.is_empty() {
-        match IpAddr::from_str(&lease.gateways[0]) {
+    // With classless static routes the router option is ignored (RFC 3442),
+    // the gateway of the default route is used instead.
+    let gw = if !lease.classless_routes.is_empty() {
+        lease
+            .classless_routes
+            .iter()
+            .find(|r| r.destination == "0.0.0.0/0")
+            .map(|r| r.gateway.as_str())
+    } else {
+        lease.gateways.first().map(|g| g.as_str())
+    };
+    let gw = match gw {
+        Some(g) => match IpAddr::from_str(g) {
             Ok(g) => Some(g),
             Err(e) => {
                 return Err(NetavarkError::msg(format!("bad gateway address: {e}")));
             }
-        }
-    } else {
-        None
+        },
+        None => None,
     };
 
     let dns_servers = if !lease.dns_servers.is_empty() {
@@ -97,8 +197,18 @@ pub fn get_dhcp_lease(
     } else {
         None
     };
-    let domain_name = if !lease.domain_name.is_empty() {
-        Some(vec![lease.domain_name])
+    let mut domain_names = Vec::new();
+    if !lease.domain_name.is_empty() {
+        domain_names.push(lease.domain_name);
+    }
+    append_unique(&mut domain_names, lease.domain_search);
+    let domain_names = if !domain_names.is_empty() {
+        Some(domain_names)
+    } else {
+        None
+    };
+    let ntp_servers = if !lease.ntp_servers.is_empty() {
+        Some(lease.ntp_servers)
     } else {
         None
     };
@@ -107,13 +217,17 @@ pub fn get_dhcp_lease(
         Ok(i) => i,
         Err(e) => return Err(NetavarkError::Message(e.to_string())),
     };
-    let subnet_mask = match std::net::Ipv4Addr::from_str(&lease.subnet_mask) {
-        Ok(s) => s,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    // DHCPv6 addresses are always configured as /128
+    let prefix_len = if lease.is_v6 {
+        128
+    } else {
+        match std::net::Ipv4Addr::from_str(&lease.subnet_mask) {
+            Ok(s) => u32::from(s).count_ones() as u8,
+            Err(e) => return Err(NetavarkError::Message(e.to_string())),
+        }
     };
 
-    let prefix_len = u32::from(subnet_mask).count_ones();
-    let ip = match IpNet::new(ip_addr, prefix_len as u8) {
+    let ip = match IpNet::new(ip_addr, prefix_len) {
         Ok(i) => i,
         Err(e) => return Err(NetavarkError::msg(e.to_string())),
     };
@@ -122,7 +236,12 @@ pub fn get_dhcp_lease(
         ipnet: ip,
     };
 
-    Ok((vec![ns], dns_servers, domain_name))
+    Ok(DhcpLeaseInfo {
+        subnets: vec![ns],
+        dns_servers,
+        domain_names,
+        ntp_servers,
+    })
 }
 
 pub fn release_dhcp_lease(
@@ -9,9 +9,13 @@ use std::{ffi::OsString, net::IpAddr, os::fd::BorrowedFd, path::Path};
 use super::{
     bridge::Bridge,
     constants,
+    host_device::HostDevice,
     plugin::PluginDriver,
+    routed::Routed,
     types::{Network, PerNetworkOptions, PortMapping, StatusBlock},
     vlan::Vlan,
+    vxlan::Vxlan,
+    wireguard::Wireguard,
 };
 use crate::network::netlink::Socket;
 use crate::network::netlink_route::NetlinkRoute;
@@ -48,6 +52,12 @@ pub trait NetworkDriver {
         netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
     ) -> NetavarkResult<()>;
 
+    /// print the firewall rules setup would create with a dry run firewall
+    /// driver, must be called after validate()
+    fn render_firewall(&self) -> NetavarkResult<()> {
+        Ok(())
+    }
+
     /// return the network name
     fn network_name(&self) -> String;
 }
@@ -59,6 +69,10 @@ pub fn get_network_driver<'a>(
     match info.network.driver.as_str() {
         constants::DRIVER_BRIDGE => Ok(Box::new(Bridge::new(info))),
         constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => Ok(Box::new(Vlan::new(info))),
+        constants::DRIVER_VXLAN => Ok(Box::new(Vxlan::new(info))),
+        constants::DRIVER_HOST_DEVICE => Ok(Box::new(HostDevice::new(info))),
+        constants::DRIVER_ROUTED => Ok(Box::new(Routed::new(info))),
+        constants::DRIVER_WIREGUARD => Ok(Box::new(Wireguard::new(info))),
 
         name => {
             if let Some(dirs) = plugins_directories {
@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error};
+use netlink_packet_route::link::LinkFlags;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+    exec_netns,
+    network::{
+        core_utils::join_netns,
+        dhcp::{dhcp_teardown, get_dhcp_lease},
+        netlink::Socket,
+        netlink_route::{LinkID, NetlinkRoute},
+        sysctl::disable_ipv6_autoconf,
+    },
+    wrap,
+};
+
+use super::{
+    constants::{OPTION_METRIC, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE, VALID_HOST_DEVICE_OPTS},
+    core_utils::{self, get_ipam_addresses, get_mac_address, parse_option},
+    driver::{self, DriverInfo},
+    internal_types::IPAMAddresses,
+    types::{NetInterface, StatusBlock},
+};
+
+const NO_HOST_DEVICE_ERROR: &str = "no host device name given";
+
+struct InternalData {
+    /// name of the device on the host
+    host_device_name: String,
+    /// name of the device inside the container
+    container_interface_name: String,
+    /// ip addresses
+    ipam: IPAMAddresses,
+    /// mtu for the device (0 keeps the current mtu)
+    mtu: u32,
+    /// Route metric for default routes added to the network
+    metric: Option<u32>,
+    /// if set, no default gateway will be added
+    no_default_route: bool,
+}
+
+/// Original device settings which must be restored when the device is
+/// moved back to the host on teardown.
+#[derive(Serialize, Deserialize)]
+struct HostDeviceState {
+    name: String,
+    mtu: u32,
+    /// state files written by older versions do not have it, the device was
+    /// always set up on the host then
+    #[serde(default = "default_up")]
+    up: bool,
+}
+
+fn default_up() -> bool {
+    true
+}
+
+pub struct HostDevice<'a> {
+    info: DriverInfo<'a>,
+    data: Option<InternalData>,
+}
+
+impl<'a> HostDevice<'a> {
+    pub fn new(info: DriverInfo<'a>) -> Self {
+        Self { info, data: None }
+    }
+}
+
+impl driver::NetworkDriver for HostDevice<'_> {
+    fn network_name(&self) -> String {
+        self.info.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        let host_device_name =
+            get_host_device_name(self.info.network.network_interface.as_deref())?;
+        let opts = parse_host_device_opts(&self.info.network.options, false)?;
+
+        let mut ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+        // Remove gateways when marked as internal network
+        if self.info.network.internal {
+            ipam.gateway_addresses = Vec::new();
+        }
+
+        // keep the host name of the device if no container name is given
+        let container_interface_name = match self.info.per_network_opts.interface_name.as_str() {
+            "" => host_device_name.clone(),
+            name => name.to_string(),
+        };
+
+        self.data = Some(InternalData {
+            host_device_name,
+            container_interface_name,
+            ipam,
+            mtu: opts.mtu.unwrap_or(0),
+            // use the same lower than default metric as macvlan
+            metric: Some(opts.metric.unwrap_or(99)),
+            no_default_route: opts.no_default_route.unwrap_or(false),
+        });
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before setup()")),
+        };
+
+        debug!("Setup network {}", self.info.network.name);
+        debug!(
+            "Moving host device {} into container as {} with IP addresses {:?}",
+            data.host_device_name, data.container_interface_name, data.ipam.container_addresses
+        );
+
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        let mac_address = self.setup_device(host_sock, netns_sock, data)?;
+
+        let mut response = StatusBlock {
+            dns_server_ips: Some(Vec::<IpAddr>::new()),
+            dns_search_domains: Some(Vec::<String>::new()),
+            interfaces: Some(HashMap::new()),
+            ntp_servers: None,
+            port_mappings: None,
+        };
+
+        // The device no longer exists on the host so the dhcp proxy must run
+        // the client in the container netns, this is done when no host
+        // interface is given.
+        let subnets = if data.ipam.dhcp_enabled {
+            let lease = get_dhcp_lease(
+                "",
+                &data.container_interface_name,
+                self.info.netns_path,
+                &mac_address,
+                self.info.container_hostname.as_deref().unwrap_or(""),
+                self.info.container_id,
+                self.info.network,
+            )?;
+            // do not overwrite dns servers set by dns podman flag
+            if !self.info.container_dns_servers.is_some() {
+                response.dns_server_ips = lease.dns_servers;
+            }
+            if lease.domain_names.is_some() {
+                response.dns_search_domains = lease.domain_names;
+            }
+            response.ntp_servers = lease.ntp_servers;
+            lease.subnets
+        } else {
+            data.ipam.net_addresses.clone()
+        };
+
+        let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
+        interfaces.insert(
+            data.container_interface_name.clone(),
+            NetInterface {
+                mac_address,
+                subnets: Option::from(subnets),
+            },
+        );
+        let _ = response.interfaces.insert(interfaces);
+        Ok((response, None))
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<()> {
+        let (host_sock, netns_sock) = netlink_sockets;
+        let mut error_list = NetavarkErrorList::new();
+
+        let network_device_name =
+            get_host_device_name(self.info.network.network_interface.as_deref())?;
+
+        if let Err(err) = dhcp_teardown(&self.info, netns_sock) {
+            error_list.push(err);
+        }
+
+        let routes = core_utils::create_route_list(&self.info.network.routes)?;
+        for route in routes.iter() {
+            netns_sock
+                .del_route(route)
+                .unwrap_or_else(|err| error_list.push(err))
+        }
+
+        let state_path = get_state_path(
+            self.info.config_dir,
+            &self.info.network.id,
+            self.info.container_id,
+        );
+        let state = match read_state(&state_path) {
+            Ok(s) => s,
+            Err(err) => {
+                // just log we still try to move the device back
+                error!("failed to read host device state: {err}");
+                None
+            }
+        };
+
+        // prefer the recorded name, the network config could have been changed
+        let (host_device_name, mtu, up) = match state {
+            Some(s) => (s.name, Some(s.mtu), s.up),
+            None => (network_device_name, None, true),
+        };
+        let container_interface_name = match self.info.per_network_opts.interface_name.as_str() {
+            "" => host_device_name.as_str(),
+            name => name,
+        };
+
+        // keep the state for a retried teardown when the device could not be returned
+        match return_device(
+            host_sock,
+            netns_sock,
+            &self.info,
+            container_interface_name,
+            &host_device_name,
+            mtu,
+            up,
+        ) {
+            Ok(()) => {
+                if let Err(err) = fs::remove_file(&state_path) {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        error_list.push(NetavarkError::wrap(
+                            format!("failed to remove {}", state_path.display()),
+                            err.into(),
+                        ));
+                    }
+                }
+            }
+            Err(err) => error_list.push(err),
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+        Ok(())
+    }
+}
+
+impl HostDevice<'_> {
+    /// Move the device into the container, configure it and return its mac address.
+    fn setup_device(
+        &self,
+        host: &mut Socket<NetlinkRoute>,
+        netns: &mut Socket<NetlinkRoute>,
+        data: &InternalData,
+    ) -> NetavarkResult<String> {
+        let link = host
+            .get_link(LinkID::Name(data.host_device_name.clone()))
+            .wrap(format!("get host device {}", data.host_device_name))?;
+        let mtu = core_utils::get_mtu_from_iface_attributes(&link.attributes)?;
+
+        // must be written before the device is moved, otherwise we could not
+        // restore it when a later step fails and podman calls teardown
+        write_state(
+            &get_state_path(
+                self.info.config_dir,
+                &self.info.network.id,
+                self.info.container_id,
+            ),
+            &HostDeviceState {
+                name: data.host_device_name.clone(),
+                mtu,
+                up: link.header.flags.contains(LinkFlags::Up),
+            },
+        )?;
+
+        host.set_link_ns(link.header.index, self.info.netns_container)
+            .wrap(format!(
+                "move host device {} into netns",
+                data.host_device_name
+            ))?;
+
+        let dev = netns
+            .get_link(LinkID::Name(data.host_device_name.clone()))
+            .wrap("get host device in netns")?;
+        if data.container_interface_name != data.host_device_name {
+            netns
+                .set_link_name(dev.header.index, data.container_interface_name.clone())
+                .wrap(format!(
+                    "rename host device to {}",
+                    data.container_interface_name
+                ))?;
+        }
+        if data.mtu != 0 {
+            netns
+                .set_mtu(LinkID::ID(dev.header.index), data.mtu)
+                .wrap("set host device mtu")?;
+        }
+
+        exec_netns!(self.info.netns_host, self.info.netns_container, {
+            disable_ipv6_autoconf(&data.container_interface_name)
+        })?;
+
+        for addr in &data.ipam.container_addresses {
+            netns
+                .add_addr(dev.header.index, addr)
+                .wrap("add ip addr to host device")?;
+        }
+
+        netns
+            .set_up(LinkID::ID(dev.header.index))
+            .wrap("set host device up")?;
+
+        if !data.no_default_route {
+            core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
+        }
+
+        // add static routes
+        for route in data.ipam.routes.iter() {
+            netns.add_route(route)?
+        }
+
+        get_mac_address(dev.attributes)
+    }
+}
+
+/// Restore the original name, mtu and up state of the device and move it back
+/// to the host.
+fn return_device(
+    host: &mut Socket<NetlinkRoute>,
+    netns: &mut Socket<NetlinkRoute>,
+    info: &DriverInfo,
+    container_interface_name: &str,
+    host_device_name: &str,
+    mtu: Option<u32>,
+    up: bool,
+) -> NetavarkResult<()> {
+    let dev = netns
+        .get_link(LinkID::Name(container_interface_name.to_string()))
+        .wrap(format!(
+            "get host device {container_interface_name} in netns"
+        ))?;
+
+    // the device must be down to be renamed
+    netns
+        .set_down(LinkID::ID(dev.header.index))
+        .wrap("set host device down")?;
+    if container_interface_name != host_device_name {
+        netns
+            .set_link_name(dev.header.index, host_device_name.to_string())
+            .wrap(format!("rename host device back to {host_device_name}"))?;
+    }
+    if let Some(mtu) = mtu {
+        netns
+            .set_mtu(LinkID::ID(dev.header.index), mtu)
+            .wrap("restore host device mtu")?;
+    }
+
+    netns
+        .set_link_ns(dev.header.index, info.netns_host)
+        .wrap(format!(
+            "move host device {host_device_name} back to the host"
+        ))?;
+
+    if up {
+        host.set_up(LinkID::Name(host_device_name.to_string()))
+            .wrap("set host device up")?;
+    }
+    Ok(())
+}
+
+fn get_host_device_name(name: Option<&str>) -> NetavarkResult<String> {
+    match name {
+        None | Some("") => Err(NetavarkError::msg(NO_HOST_DEVICE_ERROR)),
+        Some(n) => Ok(n.to_string()),
+    }
+}
+
+fn get_state_path(config_dir: &Path, network_id: &str, container_id: &str) -> PathBuf {
+    config_dir
+        .join("host-device")
+        .join(format!("{network_id}_{container_id}"))
+}
+
+fn write_state(path: &Path, state: &HostDeviceState) -> NetavarkResult<()> {
+    if let Some(dir) = path.parent() {
+        wrap!(fs::create_dir_all(dir), "create host-device state dir")?;
+    }
+    let file = wrap!(File::create(path), "create host-device state file")?;
+    serde_json::to_writer(file, state)?;
+    Ok(())
+}
+
+fn read_state(path: &Path) -> NetavarkResult<Option<HostDeviceState>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+}
+
+pub fn parse_host_device_opts(
+    opts: &Option<HashMap<String, String>>,
+    strict: bool,
+) -> NetavarkResult<HostDeviceOptions> {
+    if strict {
+        if let Some(invalid_key) = opts.as_ref().and_then(|m| {
+            m.keys()
+                .find(|k| !VALID_HOST_DEVICE_OPTS.contains(&k.as_str()))
+        }) {
+            return Err(NetavarkError::msg(format!(
+                "unsupported host-device network option: {}",
+                invalid_key
+            )));
+        }
+    }
+
+    let mtu = parse_option(opts, OPTION_MTU)?;
+    let metric = parse_option(opts, OPTION_METRIC)?;
+    let no_default_route = parse_option(opts, OPTION_NO_DEFAULT_ROUTE)?;
+    Ok(HostDeviceOptions {
+        mtu,
+        metric,
+        no_default_route,
+    })
+}
+
+pub struct HostDeviceOptions {
+    pub mtu: Option<u32>,
+    pub metric: Option<u32>,
+    pub no_default_route: Option<bool>,
+}
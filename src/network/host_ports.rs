@@ -0,0 +1,288 @@
+//! Allocation of the host ports of port mappings without a host port.
+//!
+//! A mapping with host port 0 gets the first free port, or block of ports for
+//! a range, of the local port range. A port is free when no socket on the
+//! host is bound to it and no other port mapping in the firewall state uses it.
+//! The allocated ports are reserved in the firewall state until the setup is
+//! done, by then the drivers stored them in the port configs. They are only
+//! stored there, so teardown restores them from the port configs.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::RangeInclusive,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, warn};
+use nix::{errno::Errno, sys::socket};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    firewall::state::{
+        read_port_mappings, read_used_host_ports, release_host_ports, reserve_host_ports,
+    },
+    network::{internal_types::Service, types::PortMapping},
+};
+
+const LOCAL_PORT_RANGE_PATH: &str = "/proc/sys/net/ipv4/ip_local_port_range";
+/// kernel default of net.ipv4.ip_local_port_range
+const DEFAULT_LOCAL_PORT_RANGE: RangeInclusive<u16> = 32768..=60999;
+
+/// Host ports reserved for a container in the firewall state, the reservation
+/// is released when this is dropped.
+pub struct HostPortReservation {
+    config_dir: PathBuf,
+    container_id: String,
+}
+
+impl Drop for HostPortReservation {
+    fn drop(&mut self) {
+        if let Err(err) = release_host_ports(&self.config_dir, &self.container_id) {
+            error!(
+                "failed to release the host ports of container {}: {}",
+                self.container_id, err
+            );
+        }
+    }
+}
+
+/// Allocate the host ports of the mappings with host port 0, they stay reserved
+/// until the returned reservation is dropped.
+pub fn allocate_host_ports(
+    config_dir: &Path,
+    container_id: &str,
+    ports: &mut [PortMapping],
+) -> NetavarkResult<Option<HostPortReservation>> {
+    if !ports.iter().any(|p| p.host_port == 0) {
+        return Ok(None);
+    }
+
+    reserve_host_ports(config_dir, container_id, |used_ports, services| {
+        allocate_free_ports(&used_ports, &services, ports)?;
+        Ok(ports.to_vec())
+    })?;
+
+    Ok(Some(HostPortReservation {
+        config_dir: config_dir.to_path_buf(),
+        container_id: container_id.to_string(),
+    }))
+}
+
+/// Set the host ports of the mappings with host port 0 to the ports setup would
+/// allocate right now, without reserving them.
+pub fn preview_host_ports(
+    config_dir: &Path,
+    container_id: &str,
+    ports: &mut [PortMapping],
+) -> NetavarkResult<()> {
+    if !ports.iter().any(|p| p.host_port == 0) {
+        return Ok(());
+    }
+    let (used_ports, services) = read_used_host_ports(config_dir, container_id)?;
+    allocate_free_ports(&used_ports, &services, ports)
+}
+
+/// Set the host port of the mappings with host port 0 to ports which are not
+/// used by other mappings or services and not bound on the host.
+fn allocate_free_ports(
+    used_ports: &[PortMapping],
+    services: &[Service],
+    ports: &mut [PortMapping],
+) -> NetavarkResult<()> {
+    // (protocol, host ports) used by netavark
+    let mut used: Vec<(String, RangeInclusive<u16>)> = ports
+        .iter()
+        .filter(|p| p.host_port != 0)
+        .chain(used_ports.iter())
+        .map(get_host_port_range)
+        .collect();
+    for service in services {
+        used.push((
+            service.protocol.clone(),
+            service.host_port..=service.host_port,
+        ));
+    }
+    let local_range = get_local_port_range();
+    for port in ports.iter_mut().filter(|p| p.host_port == 0) {
+        let is_free = |p: u16| {
+            !used
+                .iter()
+                .any(|(proto, range)| *proto == port.protocol && range.contains(&p))
+                && !is_port_bound(&port.protocol, &port.host_ip, p)
+        };
+        port.host_port =
+            find_free_ports(&local_range, port.range.max(1), is_free).ok_or_else(|| {
+                NetavarkError::msg(format!(
+                    "no free host port for container port {}/{} in range {}-{}",
+                    port.container_port,
+                    port.protocol,
+                    local_range.start(),
+                    local_range.end()
+                ))
+            })?;
+        debug!(
+            "Allocated host port {} for container port {}/{}",
+            port.host_port, port.container_port, port.protocol
+        );
+        used.push(get_host_port_range(port));
+    }
+    Ok(())
+}
+
+/// Replace host port 0 of the mappings with the ports allocated on setup,
+/// they are read from the firewall state of the first network which has one.
+pub fn restore_host_ports(
+    config_dir: &Path,
+    network_ids: &[&str],
+    container_id: &str,
+    ports: &mut [PortMapping],
+) -> NetavarkResult<()> {
+    if !ports.iter().any(|p| p.host_port == 0) {
+        return Ok(());
+    }
+
+    for network_id in network_ids {
+        let mut stored = match read_port_mappings(config_dir, network_id, container_id)? {
+            Some(stored) => stored,
+            None => continue,
+        };
+        for port in ports.iter_mut().filter(|p| p.host_port == 0) {
+            // The mappings only differ in the host port, each stored one is
+            // used once in case the same mapping was given more than once.
+            let found = stored.iter().position(|s| {
+                PortMapping {
+                    host_port: 0,
+                    ..s.clone()
+                } == *port
+            });
+            match found {
+                Some(i) => port.host_port = stored.swap_remove(i).host_port,
+                None => warn!(
+                    "allocated host port for container port {}/{} not found",
+                    port.container_port, port.protocol
+                ),
+            }
+        }
+        return Ok(());
+    }
+    warn!(
+        "firewall state of container {container_id} not found, cannot restore allocated host ports"
+    );
+    Ok(())
+}
+
+fn get_host_port_range(port: &PortMapping) -> (String, RangeInclusive<u16>) {
+    let end = port.host_port.saturating_add(port.range.max(1) - 1);
+    (port.protocol.clone(), port.host_port..=end)
+}
+
+fn get_local_port_range() -> RangeInclusive<u16> {
+    let content = match fs::read_to_string(LOCAL_PORT_RANGE_PATH) {
+        Ok(c) => c,
+        Err(e) => {
+            debug!("failed to read {LOCAL_PORT_RANGE_PATH}, using the default range: {e}");
+            return DEFAULT_LOCAL_PORT_RANGE;
+        }
+    };
+    let mut parts = content.split_whitespace().map(str::parse::<u16>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(start)), Some(Ok(end))) if start > 0 && start <= end => start..=end,
+        _ => DEFAULT_LOCAL_PORT_RANGE,
+    }
+}
+
+/// Find the first block of count ports in the range for which is_free is
+/// true, returns the first port of the block.
+fn find_free_ports<F: Fn(u16) -> bool>(
+    range: &RangeInclusive<u16>,
+    count: u16,
+    is_free: F,
+) -> Option<u16> {
+    let end = *range.end() as u32;
+    let mut start = *range.start() as u32;
+    while start + count as u32 - 1 <= end {
+        // no block can contain a used port, continue after it
+        match (start..start + count as u32).find(|p| !is_free(*p as u16)) {
+            Some(used) => start = used + 1,
+            None => return Some(start as u16),
+        }
+    }
+    None
+}
+
+/// Check if a socket on the host is bound to the port by binding it ourselves,
+/// an empty host ip checks the IPv4 and IPv6 wildcard address.
+fn is_port_bound(protocol: &str, host_ip: &str, port: u16) -> bool {
+    let (sock_type, sock_proto) = match protocol {
+        "tcp" => (socket::SockType::Stream, socket::SockProtocol::Tcp),
+        "udp" => (socket::SockType::Datagram, socket::SockProtocol::Udp),
+        "sctp" => (socket::SockType::Stream, socket::SockProtocol::Sctp),
+        _ => return false,
+    };
+    let ips = if host_ip.is_empty() {
+        vec![
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ]
+    } else {
+        match host_ip.parse() {
+            Ok(ip) => vec![ip],
+            // the firewall driver reports the invalid ip
+            Err(_) => return false,
+        }
+    };
+
+    for ip in ips {
+        let family = match ip {
+            IpAddr::V4(_) => socket::AddressFamily::Inet,
+            IpAddr::V6(_) => socket::AddressFamily::Inet6,
+        };
+        // The protocol or ip family might not be available, nothing can be
+        // bound to the port then.
+        let sock = match socket::socket(
+            family,
+            sock_type,
+            socket::SockFlag::SOCK_CLOEXEC,
+            sock_proto,
+        ) {
+            Ok(sock) => sock,
+            Err(_) => continue,
+        };
+        let addr = socket::SockaddrStorage::from(SocketAddr::new(ip, port));
+        if let Err(Errno::EADDRINUSE) = socket::bind(sock.as_raw_fd(), &addr) {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_find_free_ports() {
+        let range = 100..=110;
+        assert_eq!(find_free_ports(&range, 1, |_| true), Some(100));
+        assert_eq!(find_free_ports(&range, 3, |p| p != 101), Some(102));
+        assert_eq!(find_free_ports(&range, 3, |p| p < 108), Some(100));
+        assert_eq!(find_free_ports(&range, 11, |_| true), Some(100));
+        assert_eq!(find_free_ports(&range, 12, |_| true), None);
+        assert_eq!(find_free_ports(&range, 2, |p| p % 2 == 0), None);
+        assert_eq!(find_free_ports(&(65534..=65535), 2, |_| true), Some(65534));
+    }
+
+    #[test]
+    fn test_is_port_bound() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(is_port_bound("tcp", "127.0.0.1", port));
+        assert!(is_port_bound("tcp", "", port));
+        drop(listener);
+        assert!(!is_port_bound("tcp", "127.0.0.1", port));
+    }
+}
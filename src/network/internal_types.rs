@@ -1,6 +1,7 @@
 use crate::network::netlink_route::Route;
 use crate::network::types;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
 /// Teardown contains options for tearing down behind a container
 #[derive(Debug)]
@@ -34,6 +35,20 @@ pub struct SetupNetwork {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub outbound_addr6: Option<Ipv6Addr>,
+    /// offload established connections of the network with a flowtable
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub offload: bool,
+    /// masquerade the traffic of the ipv6 subnets, false for globally routable prefixes
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub ipv6_nat: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
 }
 
 #[derive(Debug)]
@@ -76,6 +91,168 @@ pub struct PortForwardConfigGeneric<Ports, IpAddresses> {
     pub dns_port: u16,
     /// dns servers IPs where forwarding rule to port 53 from dns_port are necessary
     pub dns_server_ips: IpAddresses,
+    /// if not empty the container may only send traffic to these destinations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub egress_allow: Vec<EgressRule>,
+}
+
+/// EgressRule is one entry of the egress_allow container option, written as
+/// `[<protocol>[:<port>]@]<destination>`, e.g. `10.0.0.0/8` or `tcp:443@0.0.0.0/0`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EgressRule {
+    /// destination subnet, a single address is a /32 or /128
+    pub destination: ipnet::IpNet,
+    /// tcp, udp or sctp, any protocol if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    /// destination port, any port if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+impl FromStr for EgressRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (proto_port, dest) = match s.split_once('@') {
+            Some((p, d)) => (Some(p), d),
+            None => (None, s),
+        };
+        let destination = match dest.parse::<ipnet::IpNet>() {
+            Ok(net) => net.trunc(),
+            Err(_) => match dest.parse::<IpAddr>() {
+                Ok(ip) => ipnet::IpNet::from(ip),
+                Err(_) => return Err(format!("invalid destination \"{dest}\"")),
+            },
+        };
+
+        let (protocol, port) = match proto_port {
+            None => (None, None),
+            Some(pp) => {
+                let (proto, port) = match pp.split_once(':') {
+                    Some((proto, port)) => (
+                        proto,
+                        Some(
+                            port.parse::<u16>()
+                                .map_err(|_| format!("invalid port \"{port}\""))?,
+                        ),
+                    ),
+                    None => (pp, None),
+                };
+                match proto {
+                    "tcp" | "udp" | "sctp" => (Some(proto.to_string()), port),
+                    _ => return Err(format!("invalid protocol \"{proto}\"")),
+                }
+            }
+        };
+
+        Ok(EgressRule {
+            destination,
+            protocol,
+            port,
+        })
+    }
+}
+
+/// Parse the comma separated list of the egress_allow option.
+pub fn parse_egress_rules(s: &str) -> Result<Vec<EgressRule>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(EgressRule::from_str)
+        .collect()
+}
+
+/// EgressRate is the value of the egress_rate option in bits per second, written
+/// as a number with an optional bit, kbit, mbit, gbit or tbit suffix, e.g. `100mbit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EgressRate(pub u64);
+
+impl FromStr for EgressRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, unit) = split_unit(s);
+        let factor: u64 = match unit.to_lowercase().as_str() {
+            "" | "bit" => 1,
+            "kbit" => 1_000,
+            "mbit" => 1_000_000,
+            "gbit" => 1_000_000_000,
+            "tbit" => 1_000_000_000_000,
+            _ => return Err(format!("invalid rate unit \"{unit}\"")),
+        };
+        match num.parse::<u64>().ok().and_then(|n| n.checked_mul(factor)) {
+            // the kernel needs at least one byte per second
+            Some(rate) if rate >= 8 => Ok(EgressRate(rate)),
+            _ => Err(format!("invalid rate \"{s}\"")),
+        }
+    }
+}
+
+/// EgressBurst is the value of the egress_burst option in bytes, written as a
+/// number with an optional b, kb, mb or gb suffix, the units are powers of 1024.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EgressBurst(pub u32);
+
+impl FromStr for EgressBurst {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, unit) = split_unit(s);
+        let factor: u32 = match unit.to_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1 << 10,
+            "m" | "mb" => 1 << 20,
+            "g" | "gb" => 1 << 30,
+            _ => return Err(format!("invalid size unit \"{unit}\"")),
+        };
+        match num.parse::<u32>().ok().and_then(|n| n.checked_mul(factor)) {
+            Some(burst) if burst > 0 => Ok(EgressBurst(burst)),
+            _ => Err(format!("invalid size \"{s}\"")),
+        }
+    }
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+/// EgressLimit is the token bucket which shapes the traffic sent by the container.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EgressLimit {
+    /// rate in bytes per second
+    pub rate: u64,
+    /// size of the bucket in bytes, i.e. how much can be sent at once
+    pub burst: u32,
+}
+
+/// Queueing latency used to size the backlog of the token bucket, the same
+/// as the CNI bandwidth plugin uses.
+const EGRESS_LATENCY_MS: u64 = 25;
+/// Minimum default burst, a smaller bucket drops GSO packets of 64k.
+const MIN_DEFAULT_EGRESS_BURST: u64 = 64 << 10;
+
+impl EgressLimit {
+    /// Create the limit for the rate, the burst defaults to what the rate
+    /// allows in 10ms but at least 64k.
+    pub fn new(rate: EgressRate, burst: Option<EgressBurst>) -> Self {
+        let rate = rate.0 / 8;
+        let burst = match burst {
+            Some(burst) => burst.0,
+            None => (rate / 100)
+                .max(MIN_DEFAULT_EGRESS_BURST)
+                .min(u32::MAX as u64) as u32,
+        };
+        EgressLimit { rate, burst }
+    }
+
+    /// Number of bytes which can wait for tokens before packets are dropped.
+    pub fn limit(&self) -> u32 {
+        (self.rate * EGRESS_LATENCY_MS / 1000)
+            .saturating_add(self.burst as u64)
+            .min(u32::MAX as u64) as u32
+    }
 }
 
 // Some trickery to define two struct one with references and one with owned data,
@@ -100,8 +277,65 @@ impl<'a> From<&'a PortForwardConfigOwned> for PortForwardConfig<'a> {
             subnet_v6: p.subnet_v6,
             dns_port: p.dns_port,
             dns_server_ips: &p.dns_server_ips,
+            egress_allow: p.egress_allow.clone(),
+        }
+    }
+}
+
+/// Service is a load balanced host port shared by the port mappings of
+/// multiple containers, all the containers are backends of the service.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Service {
+    /// name of the service
+    pub name: String,
+    /// protocol of the port mapping
+    pub protocol: String,
+    /// host ip of the port mapping, a wildcard if empty
+    pub host_ip: String,
+    /// host port of the port mapping
+    pub host_port: u16,
+    /// port the backends listen on
+    pub container_port: u16,
+    /// how a backend is picked for new connections
+    pub balance: types::ServiceBalance,
+    /// containers the connections are forwarded to
+    pub backends: Vec<ServiceBackend>,
+}
+
+/// ServiceBackend is a container ip of a service.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServiceBackend {
+    /// id of container
+    pub container_id: String,
+    /// ip address of the container
+    pub ip: IpAddr,
+    /// subnet associated with the ip address
+    pub subnet: ipnet::IpNet,
+}
+
+impl Service {
+    /// Create the service of a port mapping with the backends of one container.
+    pub fn new(name: &str, port: &types::PortMapping, backends: Vec<ServiceBackend>) -> Self {
+        Service {
+            name: name.to_string(),
+            protocol: port.protocol.clone(),
+            host_ip: port.host_ip.clone(),
+            host_port: port.host_port,
+            container_port: port.container_port,
+            balance: port.service_balance.unwrap_or_default(),
+            backends,
         }
     }
+
+    /// Check that two port mappings of the service use the same ports and options.
+    pub fn same_frontend(&self, other: &Service) -> bool {
+        self.name == other.name
+            && self.protocol == other.protocol
+            && self.host_ip == other.host_ip
+            && self.host_port == other.host_port
+            && self.container_port == other.container_port
+            && self.balance == other.balance
+    }
 }
 
 /// IPAMAddresses is used to pass ipam information around
@@ -119,9 +353,96 @@ pub struct IPAMAddresses {
 }
 
 // IsolateOption is used to select isolate option value
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum IsolateOption {
     Strict,
     Normal,
     Never,
+    /// isolated from all networks except the ones in the same group
+    Group(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_egress_rules() {
+        let rules = parse_egress_rules("10.0.0.0/8, tcp:443@0.0.0.0/0,udp@fd00::1").unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                EgressRule {
+                    destination: "10.0.0.0/8".parse().unwrap(),
+                    protocol: None,
+                    port: None,
+                },
+                EgressRule {
+                    destination: "0.0.0.0/0".parse().unwrap(),
+                    protocol: Some("tcp".to_string()),
+                    port: Some(443),
+                },
+                EgressRule {
+                    destination: "fd00::1/128".parse().unwrap(),
+                    protocol: Some("udp".to_string()),
+                    port: None,
+                },
+            ]
+        );
+        // host bits are dropped
+        assert_eq!(
+            parse_egress_rules("10.1.2.3/8").unwrap()[0].destination,
+            "10.0.0.0/8".parse::<ipnet::IpNet>().unwrap()
+        );
+
+        assert_eq!(
+            parse_egress_rules("icmp@10.0.0.0/8").unwrap_err(),
+            "invalid protocol \"icmp\""
+        );
+        assert_eq!(
+            parse_egress_rules("tcp:http@10.0.0.0/8").unwrap_err(),
+            "invalid port \"http\""
+        );
+        assert_eq!(
+            parse_egress_rules("example.com").unwrap_err(),
+            "invalid destination \"example.com\""
+        );
+    }
+
+    #[test]
+    fn test_egress_limit() {
+        assert_eq!("1000".parse(), Ok(EgressRate(1000)));
+        assert_eq!("100mbit".parse(), Ok(EgressRate(100_000_000)));
+        assert_eq!("2Gbit".parse(), Ok(EgressRate(2_000_000_000)));
+        assert_eq!(
+            "100mbps".parse::<EgressRate>(),
+            Err("invalid rate unit \"mbps\"".to_string())
+        );
+        assert_eq!(
+            "0kbit".parse::<EgressRate>(),
+            Err("invalid rate \"0kbit\"".to_string())
+        );
+        assert_eq!("1500".parse(), Ok(EgressBurst(1500)));
+        assert_eq!("64kb".parse(), Ok(EgressBurst(65536)));
+        assert_eq!("1m".parse(), Ok(EgressBurst(1 << 20)));
+        assert_eq!(
+            "8gb".parse::<EgressBurst>(),
+            Err("invalid size \"8gb\"".to_string())
+        );
+
+        let limit = EgressLimit::new(EgressRate(8_000_000), Some(EgressBurst(10_000)));
+        assert_eq!(
+            limit,
+            EgressLimit {
+                rate: 1_000_000,
+                burst: 10_000
+            }
+        );
+        assert_eq!(limit.limit(), 35_000);
+        assert_eq!(EgressLimit::new(EgressRate(8_000_000), None).burst, 65536);
+        assert_eq!(
+            EgressLimit::new(EgressRate(10_000_000_000), None).burst,
+            12_500_000
+        );
+    }
 }
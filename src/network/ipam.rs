@@ -0,0 +1,423 @@
+//! Built-in host-local ipam allocator.
+//!
+//! The allocations of each network are stored in a json file in the config dir,
+//! every read-modify-write cycle holds an exclusive lock on a separate lock file.
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, ErrorKind, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use fs2::FileExt;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::{constants, types},
+    wrap,
+};
+
+// File layout looks like this
+// $config/ipam/
+//             - $netID.json -> allocation state of the network
+//             - $netID.lock -> lock file for the state
+
+const IPAM_DIR: &str = "ipam";
+
+/// Allocation state of a single network.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct IpamState {
+    /// allocated ip address -> container id
+    #[serde(default)]
+    allocations: BTreeMap<IpAddr, String>,
+}
+
+/// Returns true when the network uses the host-local ipam driver and has subnets
+/// to allocate from.
+pub fn is_host_local(network: &types::Network) -> bool {
+    let driver = network
+        .ipam_options
+        .as_ref()
+        .and_then(|map| map.get("driver").map(String::as_str));
+    matches!(driver, Some(constants::IPAM_HOSTLOCAL) | None)
+        && network.subnets.as_ref().is_some_and(|s| !s.is_empty())
+}
+
+/// Allocate the addresses for the container, one per subnet.
+/// If static ips are given they are only recorded so that they are never handed
+/// out to another container, the caller chose them so they always take over an
+/// existing allocation. Allocating again for the same container returns the
+/// already allocated addresses.
+pub fn allocate_addresses(
+    config_dir: &Path,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    let (path, _lock) = lock_state(config_dir, network)?;
+    let mut state = read_state(&path)?;
+
+    let addresses = get_addresses(&state, network, container_id, static_ips)?;
+
+    for ip in &addresses {
+        state.allocations.insert(*ip, container_id.to_string());
+    }
+    write_state(&path, &state)?;
+    debug!(
+        "Allocated {:?} for container {} on network {}",
+        addresses, container_id, network.name
+    );
+    Ok(addresses)
+}
+
+/// Get the addresses allocate_addresses() would allocate right now, nothing is
+/// locked or written.
+pub fn preview_addresses(
+    config_dir: &Path,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    let state = read_state(&config_dir.join(IPAM_DIR).join(state_file_name(network)))?;
+    get_addresses(&state, network, container_id, static_ips)
+}
+
+fn get_addresses(
+    state: &IpamState,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    match static_ips {
+        Some(ips) => {
+            for ip in ips {
+                match state.allocations.get(ip) {
+                    Some(id) if id != container_id => {
+                        debug!("Static ip {ip} was allocated to container {id}, taking it over")
+                    }
+                    _ => {}
+                }
+            }
+            Ok(ips.clone())
+        }
+        None => {
+            let mut addresses = Vec::new();
+            for subnet in network.subnets.iter().flatten() {
+                let existing = state
+                    .allocations
+                    .iter()
+                    .find(|(ip, id)| *id == container_id && subnet.subnet.contains(*ip))
+                    .map(|(ip, _)| *ip);
+                let ip = match existing {
+                    Some(ip) => ip,
+                    None => next_free_address(subnet, state)?,
+                };
+                addresses.push(ip);
+            }
+            Ok(addresses)
+        }
+    }
+}
+
+/// Release all addresses of the container on the given network.
+pub fn release_addresses(
+    config_dir: &Path,
+    network: &types::Network,
+    container_id: &str,
+) -> NetavarkResult<()> {
+    let (path, _lock) = lock_state(config_dir, network)?;
+    let mut state = read_state(&path)?;
+    let len = state.allocations.len();
+    state.allocations.retain(|_, id| id != container_id);
+    if state.allocations.len() == len {
+        return Ok(());
+    }
+    if state.allocations.is_empty() {
+        return match fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(NetavarkError::wrap(
+                format!("remove ipam state {:?}", path.display()),
+                err.into(),
+            )),
+        };
+    }
+    write_state(&path, &state)
+}
+
+/// Allocations made during a setup, they are released again on drop unless
+/// the setup succeeded and [`IpamAllocations::commit`] was called.
+pub struct IpamAllocations {
+    config_dir: PathBuf,
+    container_id: String,
+    networks: Vec<types::Network>,
+}
+
+impl IpamAllocations {
+    pub fn new(config_dir: &Path, container_id: &str) -> Self {
+        IpamAllocations {
+            config_dir: config_dir.to_path_buf(),
+            container_id: container_id.to_string(),
+            networks: Vec::new(),
+        }
+    }
+
+    /// Allocate the container addresses for the network, see [`allocate_addresses`].
+    pub fn allocate(
+        &mut self,
+        network: &types::Network,
+        static_ips: Option<&Vec<IpAddr>>,
+    ) -> NetavarkResult<Vec<IpAddr>> {
+        let ips = allocate_addresses(&self.config_dir, network, &self.container_id, static_ips)?;
+        self.networks.push(network.clone());
+        Ok(ips)
+    }
+
+    /// Keep the allocations.
+    pub fn commit(mut self) {
+        self.networks.clear();
+    }
+}
+
+impl Drop for IpamAllocations {
+    fn drop(&mut self) {
+        for network in &self.networks {
+            if let Err(err) = release_addresses(&self.config_dir, network, &self.container_id) {
+                error!(
+                    "failed to release ip addresses on network {}: {}",
+                    network.name, err
+                );
+            }
+        }
+    }
+}
+
+/// Find the lowest free address in the lease range of the subnet. The network
+/// address, the ipv4 broadcast address and the gateway are never allocated.
+fn next_free_address(subnet: &types::Subnet, state: &IpamState) -> NetavarkResult<IpAddr> {
+    let net = subnet.subnet.trunc();
+    let (mut first, mut last) = (ip_to_u128(net.network()), ip_to_u128(net.broadcast()));
+    // skip the network address, for ipv4 the broadcast address as well
+    // unless the subnet is too small to have any other address
+    if last - first > 1 {
+        first += 1;
+        if net.addr().is_ipv4() {
+            last -= 1;
+        }
+    }
+
+    if let Some(range) = &subnet.lease_range {
+        if let Some(start) = parse_range_ip(&range.start_ip, &subnet.subnet)? {
+            first = first.max(start);
+        }
+        if let Some(end) = parse_range_ip(&range.end_ip, &subnet.subnet)? {
+            last = last.min(end);
+        }
+    }
+
+    let gateway = subnet.gateway.map(ip_to_u128);
+    let mut candidate = first;
+    while candidate <= last {
+        let ip = u128_to_ip(candidate, net.addr().is_ipv4());
+        if Some(candidate) != gateway && !state.allocations.contains_key(&ip) {
+            return Ok(ip);
+        }
+        candidate += 1;
+    }
+    Err(NetavarkError::msg(format!(
+        "no free ip addresses left in subnet {}",
+        subnet.subnet
+    )))
+}
+
+fn parse_range_ip(ip: &Option<String>, subnet: &ipnet::IpNet) -> NetavarkResult<Option<u128>> {
+    let ip = match ip {
+        Some(ip) => ip,
+        None => return Ok(None),
+    };
+    let addr = IpAddr::from_str(ip)
+        .map_err(|e| NetavarkError::msg(format!("invalid lease range ip {ip}: {e}")))?;
+    if !subnet.contains(&addr) {
+        return Err(NetavarkError::msg(format!(
+            "lease range ip {addr} not in subnet {subnet}"
+        )));
+    }
+    Ok(Some(ip_to_u128(addr)))
+}
+
+fn ip_to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+fn u128_to_ip(value: u128, ipv4: bool) -> IpAddr {
+    if ipv4 {
+        IpAddr::V4(Ipv4Addr::from(value as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(value))
+    }
+}
+
+/// Returns the state file path and the locked lock file, the lock is released
+/// when the file is dropped.
+fn lock_state(config_dir: &Path, network: &types::Network) -> NetavarkResult<(PathBuf, File)> {
+    let dir = config_dir.join(IPAM_DIR);
+    wrap!(
+        fs::create_dir_all(&dir),
+        format!("create ipam dir {:?}", dir.display())
+    )?;
+    let path = dir.join(state_file_name(network));
+    let lock_path = path.with_extension("lock");
+    let lock_file = wrap!(
+        File::create(&lock_path),
+        format!("create ipam lock file {:?}", lock_path.display())
+    )?;
+    wrap!(lock_file.lock_exclusive(), "lock ipam lock file")?;
+    Ok((path, lock_file))
+}
+
+fn state_file_name(network: &types::Network) -> String {
+    // the id is optional in the network config, fall back to the unique name
+    let name = if network.id.is_empty() {
+        &network.name
+    } else {
+        &network.id
+    };
+    format!("{name}.json")
+}
+
+fn read_state(path: &Path) -> NetavarkResult<IpamState> {
+    match File::open(path) {
+        Ok(f) => Ok(serde_json::from_reader(BufReader::new(f))?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(IpamState::default()),
+        Err(err) => Err(NetavarkError::wrap(
+            format!("open ipam state {:?}", path.display()),
+            err.into(),
+        )),
+    }
+}
+
+/// Write the state to a temporary file first and rename it so readers never
+/// see a partially written file.
+fn write_state(path: &Path, state: &IpamState) -> NetavarkResult<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = wrap!(
+        File::create(&tmp_path),
+        format!("create ipam state {:?}", tmp_path.display())
+    )?;
+    serde_json::to_writer(&file, state)?;
+    wrap!(file.flush(), "write ipam state")?;
+    wrap!(
+        fs::rename(&tmp_path, path),
+        format!("rename ipam state {:?}", path.display())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    fn network(subnets: Vec<types::Subnet>) -> types::Network {
+        serde_json::from_value(serde_json::json!({
+            "name": "podman1",
+            "id": "ed82e3a703682a9c09629d3cf45c1f1e7da5b32aeff3faf82837ef4d005356e6",
+            "driver": "bridge",
+            "subnets": subnets,
+            "ipv6_enabled": true,
+            "internal": false,
+            "dns_enabled": false,
+        }))
+        .unwrap()
+    }
+
+    fn subnet(subnet: &str, gateway: Option<&str>) -> types::Subnet {
+        types::Subnet {
+            subnet: subnet.parse().unwrap(),
+            gateway: gateway.map(|g| g.parse().unwrap()),
+            lease_range: None,
+        }
+    }
+
+    #[test]
+    fn test_allocate_and_release() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+        let net = network(vec![
+            subnet("10.88.0.0/24", Some("10.88.0.1")),
+            subnet("fd10:88:a::/64", Some("fd10:88:a::1")),
+        ]);
+
+        let ips = allocate_addresses(config_dir, &net, "c1", None).unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                "10.88.0.2".parse::<IpAddr>().unwrap(),
+                "fd10:88:a::2".parse().unwrap()
+            ]
+        );
+        // allocating again for the same container is stable
+        let again = allocate_addresses(config_dir, &net, "c1", None).unwrap();
+        assert_eq!(ips, again);
+
+        let ips2 = allocate_addresses(config_dir, &net, "c2", None).unwrap();
+        assert_eq!(ips2[0], "10.88.0.3".parse::<IpAddr>().unwrap());
+
+        release_addresses(config_dir, &net, "c1").unwrap();
+        let ips3 = allocate_addresses(config_dir, &net, "c3", None).unwrap();
+        assert_eq!(ips3[0], "10.88.0.2".parse::<IpAddr>().unwrap());
+
+        release_addresses(config_dir, &net, "c2").unwrap();
+        release_addresses(config_dir, &net, "c3").unwrap();
+        assert!(!config_dir
+            .join(IPAM_DIR)
+            .join(format!("{}.json", net.id))
+            .exists());
+    }
+
+    #[test]
+    fn test_lease_range() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+        let mut sub = subnet("10.88.0.0/24", Some("10.88.0.10"));
+        sub.lease_range = Some(types::LeaseRange {
+            start_ip: Some("10.88.0.10".to_string()),
+            end_ip: Some("10.88.0.12".to_string()),
+        });
+        let net = network(vec![sub]);
+
+        let ip1 = allocate_addresses(config_dir, &net, "c1", None).unwrap();
+        assert_eq!(ip1, vec!["10.88.0.11".parse::<IpAddr>().unwrap()]);
+        let ip2 = allocate_addresses(config_dir, &net, "c2", None).unwrap();
+        assert_eq!(ip2, vec!["10.88.0.12".parse::<IpAddr>().unwrap()]);
+        let err = allocate_addresses(config_dir, &net, "c3", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no free ip addresses left in subnet 10.88.0.0/24"
+        );
+    }
+
+    #[test]
+    fn test_static_ips_reserved() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+        let net = network(vec![subnet("10.88.0.0/24", Some("10.88.0.1"))]);
+
+        let static_ips = vec!["10.88.0.2".parse().unwrap()];
+        allocate_addresses(config_dir, &net, "c1", Some(&static_ips)).unwrap();
+        let ips = allocate_addresses(config_dir, &net, "c2", None).unwrap();
+        assert_eq!(ips, vec!["10.88.0.3".parse::<IpAddr>().unwrap()]);
+
+        // static ips always win over an existing allocation
+        allocate_addresses(config_dir, &net, "c3", Some(&ips)).unwrap();
+        release_addresses(config_dir, &net, "c2").unwrap();
+        let ips = allocate_addresses(config_dir, &net, "c4", None).unwrap();
+        assert_eq!(ips, vec!["10.88.0.4".parse::<IpAddr>().unwrap()]);
+    }
+}
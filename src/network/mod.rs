@@ -16,14 +16,23 @@ pub mod constants;
 pub mod core_utils;
 mod dhcp;
 pub mod driver;
+pub mod host_device;
+pub mod host_ports;
 pub mod internal_types;
+pub mod ipam;
 
 pub mod netlink;
+pub mod netlink_generic;
+pub mod netlink_netfilter;
 pub mod netlink_route;
+pub mod netlink_sock_diag;
 
 pub mod plugin;
+pub mod routed;
 pub mod sysctl;
 pub mod vlan;
+pub mod vxlan;
+pub mod wireguard;
 
 impl types::NetworkOptions {
     pub fn load(path: Option<OsString>) -> NetavarkResult<types::NetworkOptions> {
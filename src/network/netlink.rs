@@ -175,6 +175,65 @@ where
             }
         }
     }
+
+    /// subscribe to a multicast group, its messages are read with recv_events()
+    pub fn add_membership(&mut self, group: u32) -> NetavarkResult<()> {
+        wrap!(self.socket.add_membership(group), "add netlink membership")
+    }
+
+    /// wait for the next messages of the subscribed multicast groups, events
+    /// are not replies to our requests so the sequence number is not checked
+    pub fn recv_events(&mut self) -> NetavarkResult<Vec<P::Message>>
+    where
+        P::Message: NetlinkDeserializable + std::fmt::Debug,
+    {
+        // the io error is returned unwrapped so callers can handle ENOBUFS
+        let size = self.socket.recv(&mut &mut self.buffer[..], 0)?;
+        self.parse_events(size)
+    }
+
+    /// like recv_events() but returns None instead of waiting when no messages
+    /// are queued
+    pub fn try_recv_events(&mut self) -> NetavarkResult<Option<Vec<P::Message>>>
+    where
+        P::Message: NetlinkDeserializable + std::fmt::Debug,
+    {
+        let size = match self
+            .socket
+            .recv(&mut &mut self.buffer[..], libc::MSG_DONTWAIT)
+        {
+            Ok(size) => size,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        self.parse_events(size).map(Some)
+    }
+
+    fn parse_events(&mut self, size: usize) -> NetavarkResult<Vec<P::Message>>
+    where
+        P::Message: NetlinkDeserializable + std::fmt::Debug,
+    {
+        let mut result = Vec::new();
+        let mut buffer = &self.buffer[..size];
+        loop {
+            let rx_packet: NetlinkMessage<P::Message> = NetlinkMessage::deserialize(buffer)
+                .map_err(|e| {
+                    NetavarkError::Message(format!("failed to deserialize netlink message: {e}",))
+                })?;
+            trace!("read netlink event: {rx_packet:?}");
+
+            if let NetlinkPayload::InnerMessage(msg) = rx_packet.payload {
+                result.push(msg);
+            }
+
+            let len = rx_packet.header.length as usize;
+            if buffer.len() <= len || len == 0 {
+                return Ok(result);
+            }
+            buffer = &buffer[len..];
+        }
+    }
+
     pub fn make_netlink_request(
         &mut self,
         msg: P::Message,
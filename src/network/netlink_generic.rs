@@ -0,0 +1,304 @@
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::netlink::{expect_netlink_result, function, NetlinkFamily, Socket},
+};
+use log::info;
+use netlink_packet_core::{
+    DecodeError, DefaultNla, Emitable, NetlinkDeserializable, NetlinkHeader, NetlinkPayload,
+    NetlinkSerializable, Nla, NlaBuffer, NlasIterator, Parseable, NLA_F_NESTED, NLM_F_ACK,
+};
+use netlink_sys::protocols::NETLINK_GENERIC;
+
+/// Size of the generic netlink header (cmd, version, reserved).
+const GENL_HEADER_LEN: usize = 4;
+
+// generic netlink controller, see include/uapi/linux/genetlink.h
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+// wireguard, see include/uapi/linux/wireguard.h
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_SET_DEVICE: u8 = 1;
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+const WGDEVICE_A_FLAGS: u16 = 5;
+const WGDEVICE_A_LISTEN_PORT: u16 = 6;
+const WGDEVICE_A_PEERS: u16 = 8;
+const WGDEVICE_F_REPLACE_PEERS: u32 = 1 << 0;
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_FLAGS: u16 = 3;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+const WGPEER_A_ALLOWEDIPS: u16 = 9;
+const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 1 << 1;
+const WGALLOWEDIP_A_FAMILY: u16 = 1;
+const WGALLOWEDIP_A_IPADDR: u16 = 2;
+const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+/// Length of wireguard private and public keys.
+pub const WG_KEY_LEN: usize = 32;
+
+pub struct NetlinkGeneric;
+
+impl NetlinkFamily for NetlinkGeneric {
+    const PROTOCOL: isize = NETLINK_GENERIC;
+    type Message = GenlMessage;
+}
+
+/// Attribute of a generic netlink message, either raw bytes or nested attributes.
+#[derive(Debug, Clone)]
+pub enum GenlAttribute {
+    Value(u16, Vec<u8>),
+    Nested(u16, Vec<GenlAttribute>),
+}
+
+impl GenlAttribute {
+    fn u16(kind: u16, value: u16) -> Self {
+        GenlAttribute::Value(kind, value.to_ne_bytes().to_vec())
+    }
+
+    fn u32(kind: u16, value: u32) -> Self {
+        GenlAttribute::Value(kind, value.to_ne_bytes().to_vec())
+    }
+
+    fn string(kind: u16, value: &str) -> Self {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        GenlAttribute::Value(kind, bytes)
+    }
+}
+
+impl Nla for GenlAttribute {
+    fn value_len(&self) -> usize {
+        match self {
+            GenlAttribute::Value(_, v) => v.len(),
+            GenlAttribute::Nested(_, attrs) => attrs.as_slice().buffer_len(),
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            GenlAttribute::Value(kind, _) => *kind,
+            GenlAttribute::Nested(kind, _) => *kind | NLA_F_NESTED,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            GenlAttribute::Value(_, v) => buffer.copy_from_slice(v),
+            GenlAttribute::Nested(_, attrs) => attrs.as_slice().emit(buffer),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenlMessage {
+    /// the family id, used as netlink message type
+    pub family: u16,
+    pub cmd: u8,
+    pub version: u8,
+    /// attributes for requests
+    pub attributes: Vec<GenlAttribute>,
+    /// top level attributes of replies, nested ones are not parsed
+    pub reply_attributes: Vec<DefaultNla>,
+}
+
+impl GenlMessage {
+    fn new(family: u16, cmd: u8, version: u8, attributes: Vec<GenlAttribute>) -> Self {
+        GenlMessage {
+            family,
+            cmd,
+            version,
+            attributes,
+            reply_attributes: Vec::new(),
+        }
+    }
+}
+
+impl NetlinkSerializable for GenlMessage {
+    fn message_type(&self) -> u16 {
+        self.family
+    }
+
+    fn buffer_len(&self) -> usize {
+        GENL_HEADER_LEN + self.attributes.as_slice().buffer_len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer[0] = self.cmd;
+        buffer[1] = self.version;
+        buffer[2] = 0;
+        buffer[3] = 0;
+        self.attributes
+            .as_slice()
+            .emit(&mut buffer[GENL_HEADER_LEN..]);
+    }
+}
+
+impl NetlinkDeserializable for GenlMessage {
+    type Error = DecodeError;
+
+    fn deserialize(header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < GENL_HEADER_LEN {
+            return Err(DecodeError::from("generic netlink message too short"));
+        }
+        let mut reply_attributes = Vec::new();
+        for nla in NlasIterator::new(&payload[GENL_HEADER_LEN..]) {
+            let nla: NlaBuffer<&[u8]> = nla?;
+            reply_attributes.push(DefaultNla::parse(&nla)?);
+        }
+        Ok(GenlMessage {
+            family: header.message_type,
+            cmd: payload[0],
+            version: payload[1],
+            attributes: Vec::new(),
+            reply_attributes,
+        })
+    }
+}
+
+impl From<GenlMessage> for NetlinkPayload<GenlMessage> {
+    fn from(message: GenlMessage) -> Self {
+        NetlinkPayload::InnerMessage(message)
+    }
+}
+
+/// A wireguard peer as configured with WG_CMD_SET_DEVICE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WireguardPeer {
+    pub public_key: [u8; WG_KEY_LEN],
+    pub endpoint: Option<SocketAddr>,
+    pub allowed_ips: Vec<ipnet::IpNet>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl Socket<NetlinkGeneric> {
+    /// resolve the id of the generic netlink family with the given name
+    pub fn get_family_id(&mut self, name: &str) -> NetavarkResult<u16> {
+        let msg = GenlMessage::new(
+            GENL_ID_CTRL,
+            CTRL_CMD_GETFAMILY,
+            1,
+            vec![GenlAttribute::string(CTRL_ATTR_FAMILY_NAME, name)],
+        );
+        let mut result = self.make_netlink_request(msg, 0)?;
+        expect_netlink_result!(result, 1);
+        let msg = result.remove(0);
+        for nla in msg.reply_attributes {
+            if nla.kind() == CTRL_ATTR_FAMILY_ID && nla.value_len() == 2 {
+                let mut value = [0; 2];
+                nla.emit_value(&mut value);
+                return Ok(u16::from_ne_bytes(value));
+            }
+        }
+        Err(NetavarkError::msg(format!(
+            "generic netlink family {name} has no family id"
+        )))
+    }
+
+    /// configure the wireguard device, all existing peers are replaced
+    pub fn set_wireguard_device(
+        &mut self,
+        ifname: &str,
+        private_key: &[u8; WG_KEY_LEN],
+        listen_port: Option<u16>,
+        peers: &[WireguardPeer],
+    ) -> NetavarkResult<()> {
+        let family = self.get_family_id(WG_GENL_NAME)?;
+
+        let mut attributes = vec![
+            GenlAttribute::string(WGDEVICE_A_IFNAME, ifname),
+            GenlAttribute::Value(WGDEVICE_A_PRIVATE_KEY, private_key.to_vec()),
+            GenlAttribute::u32(WGDEVICE_A_FLAGS, WGDEVICE_F_REPLACE_PEERS),
+        ];
+        if let Some(port) = listen_port {
+            attributes.push(GenlAttribute::u16(WGDEVICE_A_LISTEN_PORT, port));
+        }
+        attributes.push(GenlAttribute::Nested(
+            WGDEVICE_A_PEERS,
+            peers
+                .iter()
+                .enumerate()
+                .map(|(i, peer)| GenlAttribute::Nested(i as u16, wireguard_peer_attributes(peer)))
+                .collect(),
+        ));
+
+        info!(
+            "Configuring wireguard device {ifname} with {} peer(s)",
+            peers.len()
+        );
+        let msg = GenlMessage::new(family, WG_CMD_SET_DEVICE, WG_GENL_VERSION, attributes);
+        let result = self.make_netlink_request(msg, NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+}
+
+fn wireguard_peer_attributes(peer: &WireguardPeer) -> Vec<GenlAttribute> {
+    let mut attributes = vec![
+        GenlAttribute::Value(WGPEER_A_PUBLIC_KEY, peer.public_key.to_vec()),
+        GenlAttribute::u32(WGPEER_A_FLAGS, WGPEER_F_REPLACE_ALLOWEDIPS),
+    ];
+    if let Some(endpoint) = &peer.endpoint {
+        attributes.push(GenlAttribute::Value(
+            WGPEER_A_ENDPOINT,
+            sockaddr_bytes(endpoint),
+        ));
+    }
+    if let Some(interval) = peer.persistent_keepalive {
+        attributes.push(GenlAttribute::u16(
+            WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+            interval,
+        ));
+    }
+    attributes.push(GenlAttribute::Nested(
+        WGPEER_A_ALLOWEDIPS,
+        peer.allowed_ips
+            .iter()
+            .enumerate()
+            .map(|(i, net)| {
+                let (family, addr) = match net.addr() {
+                    IpAddr::V4(v4) => (libc::AF_INET as u16, v4.octets().to_vec()),
+                    IpAddr::V6(v6) => (libc::AF_INET6 as u16, v6.octets().to_vec()),
+                };
+                GenlAttribute::Nested(
+                    i as u16,
+                    vec![
+                        GenlAttribute::u16(WGALLOWEDIP_A_FAMILY, family),
+                        GenlAttribute::Value(WGALLOWEDIP_A_IPADDR, addr),
+                        GenlAttribute::Value(WGALLOWEDIP_A_CIDR_MASK, vec![net.prefix_len()]),
+                    ],
+                )
+            })
+            .collect(),
+    ));
+    attributes
+}
+
+/// encode the address as struct sockaddr_in or sockaddr_in6
+fn sockaddr_bytes(addr: &SocketAddr) -> Vec<u8> {
+    match addr {
+        SocketAddr::V4(v4) => {
+            let mut buf = Vec::with_capacity(16);
+            buf.extend_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+            buf.extend_from_slice(&v4.port().to_be_bytes());
+            buf.extend_from_slice(&v4.ip().octets());
+            buf.extend_from_slice(&[0; 8]);
+            buf
+        }
+        SocketAddr::V6(v6) => {
+            let mut buf = Vec::with_capacity(28);
+            buf.extend_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+            buf.extend_from_slice(&v6.port().to_be_bytes());
+            buf.extend_from_slice(&v6.flowinfo().to_be_bytes());
+            buf.extend_from_slice(&v6.ip().octets());
+            buf.extend_from_slice(&v6.scope_id().to_ne_bytes());
+            buf
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::netlink::{NetlinkFamily, Socket},
+};
+use log::debug;
+use netlink_packet_core::{
+    DecodeError, DefaultNla, Emitable, NetlinkDeserializable, NetlinkHeader, NetlinkPayload,
+    NetlinkSerializable, Nla, NlaBuffer, NlasIterator, Parseable, NLA_TYPE_MASK, NLM_F_ACK,
+    NLM_F_DUMP,
+};
+use netlink_sys::protocols::NETLINK_NETFILTER;
+
+/// Size of the nfgenmsg header (family, version, res_id).
+const NFGEN_HEADER_LEN: usize = 4;
+
+// nfnetlink and ctnetlink, see include/uapi/linux/netfilter/nfnetlink.h
+// and include/uapi/linux/netfilter/nfnetlink_conntrack.h
+const NFNETLINK_V0: u8 = 0;
+const NFNL_SUBSYS_CTNETLINK: u16 = 1;
+const IPCTNL_MSG_CT_GET: u8 = 1;
+const IPCTNL_MSG_CT_DELETE: u8 = 2;
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_TUPLE_REPLY: u16 = 2;
+const CTA_ZONE: u16 = 18;
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_TUPLE_PROTO: u16 = 2;
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V6_SRC: u16 = 3;
+const CTA_PROTO_NUM: u16 = 1;
+const CTA_PROTO_DST_PORT: u16 = 3;
+
+// nf_tables events, see include/uapi/linux/netfilter/nf_tables.h
+const NFNL_SUBSYS_NFTABLES: u8 = 10;
+const NFNLGRP_NFTABLES: u32 = 7;
+const NFT_MSG_DELTABLE: u8 = 2;
+const NFT_MSG_DELCHAIN: u8 = 5;
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+
+pub struct NetlinkNetfilter;
+
+impl NetlinkFamily for NetlinkNetfilter {
+    const PROTOCOL: isize = NETLINK_NETFILTER;
+    type Message = CtMessage;
+}
+
+/// A ctnetlink message, the attributes are kept as raw nlas so the tuples of
+/// dumped entries can be sent back unchanged to delete them.
+#[derive(Debug, Clone)]
+pub struct CtMessage {
+    /// ctnetlink message type, e.g. IPCTNL_MSG_CT_GET
+    msg_type: u8,
+    /// address family of the conntrack entries
+    family: u8,
+    attributes: Vec<DefaultNla>,
+}
+
+impl CtMessage {
+    fn new(msg_type: u8, family: u8, attributes: Vec<DefaultNla>) -> Self {
+        CtMessage {
+            msg_type,
+            family,
+            attributes,
+        }
+    }
+
+    /// protocol number and destination port of the original direction
+    fn orig_proto_dport(&self) -> Option<(u8, u16)> {
+        let tuple = parse_attributes(&find_attribute(&self.attributes, CTA_TUPLE_ORIG)?).ok()?;
+        let proto = parse_attributes(&find_attribute(&tuple, CTA_TUPLE_PROTO)?).ok()?;
+        let num = *find_attribute(&proto, CTA_PROTO_NUM)?.first()?;
+        let port = find_attribute(&proto, CTA_PROTO_DST_PORT)?;
+        Some((num, u16::from_be_bytes(port.try_into().ok()?)))
+    }
+
+    /// source address of the reply direction, i.e. the address the connection
+    /// was forwarded to
+    fn reply_src(&self) -> Option<IpAddr> {
+        let tuple = parse_attributes(&find_attribute(&self.attributes, CTA_TUPLE_REPLY)?).ok()?;
+        let ip = parse_attributes(&find_attribute(&tuple, CTA_TUPLE_IP)?).ok()?;
+        if let Some(addr) = find_attribute(&ip, CTA_IP_V4_SRC) {
+            let octets: [u8; 4] = addr.try_into().ok()?;
+            return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+        }
+        let octets: [u8; 16] = find_attribute(&ip, CTA_IP_V6_SRC)?.try_into().ok()?;
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+    }
+}
+
+impl NetlinkSerializable for CtMessage {
+    fn message_type(&self) -> u16 {
+        (NFNL_SUBSYS_CTNETLINK << 8) | self.msg_type as u16
+    }
+
+    fn buffer_len(&self) -> usize {
+        NFGEN_HEADER_LEN + self.attributes.as_slice().buffer_len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer[0] = self.family;
+        buffer[1] = NFNETLINK_V0;
+        // res_id, not used by ctnetlink
+        buffer[2] = 0;
+        buffer[3] = 0;
+        self.attributes
+            .as_slice()
+            .emit(&mut buffer[NFGEN_HEADER_LEN..]);
+    }
+}
+
+impl NetlinkDeserializable for CtMessage {
+    type Error = DecodeError;
+
+    fn deserialize(header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < NFGEN_HEADER_LEN {
+            return Err(DecodeError::from("ctnetlink message too short"));
+        }
+        Ok(CtMessage::new(
+            (header.message_type & 0xff) as u8,
+            payload[0],
+            parse_attributes(&payload[NFGEN_HEADER_LEN..])?,
+        ))
+    }
+}
+
+pub struct NetlinkNftables;
+
+impl NetlinkFamily for NetlinkNftables {
+    const PROTOCOL: isize = NETLINK_NETFILTER;
+    type Message = NftMessage;
+}
+
+/// A nf_tables event message, only received so it is never serialized.
+#[derive(Debug, Clone)]
+pub struct NftMessage {
+    /// nfnetlink subsystem, only NFNL_SUBSYS_NFTABLES is expected
+    subsys: u8,
+    /// nf_tables message type, e.g. NFT_MSG_DELTABLE
+    msg_type: u8,
+    /// nftables family of the table
+    family: u8,
+    attributes: Vec<DefaultNla>,
+}
+
+/// A deleted nftables table, or a deleted chain of it.
+#[derive(Debug, PartialEq)]
+pub struct NftDeletion {
+    /// nftables family of the table, e.g. libc::NFPROTO_INET
+    pub family: u8,
+    pub table: String,
+    /// None when the whole table was deleted
+    pub chain: Option<String>,
+}
+
+impl NftMessage {
+    fn deletion(&self) -> Option<NftDeletion> {
+        if self.subsys != NFNL_SUBSYS_NFTABLES {
+            return None;
+        }
+        let string = |kind| {
+            let mut value = find_attribute(&self.attributes, kind)?;
+            // strip the nul terminator
+            value.pop_if(|b| *b == 0);
+            String::from_utf8(value).ok()
+        };
+        match self.msg_type {
+            NFT_MSG_DELTABLE => Some(NftDeletion {
+                family: self.family,
+                table: string(NFTA_TABLE_NAME)?,
+                chain: None,
+            }),
+            NFT_MSG_DELCHAIN => Some(NftDeletion {
+                family: self.family,
+                table: string(NFTA_CHAIN_TABLE)?,
+                chain: Some(string(NFTA_CHAIN_NAME)?),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl NetlinkDeserializable for NftMessage {
+    type Error = DecodeError;
+
+    fn deserialize(header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < NFGEN_HEADER_LEN {
+            return Err(DecodeError::from("nf_tables message too short"));
+        }
+        Ok(NftMessage {
+            subsys: (header.message_type >> 8) as u8,
+            msg_type: (header.message_type & 0xff) as u8,
+            family: payload[0],
+            attributes: parse_attributes(&payload[NFGEN_HEADER_LEN..])?,
+        })
+    }
+}
+
+impl From<CtMessage> for NetlinkPayload<CtMessage> {
+    fn from(message: CtMessage) -> Self {
+        NetlinkPayload::InnerMessage(message)
+    }
+}
+
+fn parse_attributes(buffer: &[u8]) -> Result<Vec<DefaultNla>, DecodeError> {
+    NlasIterator::new(buffer)
+        .map(|nla| {
+            let nla: NlaBuffer<&[u8]> = nla?;
+            DefaultNla::parse(&nla)
+        })
+        .collect()
+}
+
+/// value of the first attribute with the given type, the nla flags are ignored
+fn find_attribute(attributes: &[DefaultNla], kind: u16) -> Option<Vec<u8>> {
+    attributes
+        .iter()
+        .find(|nla| nla.kind() & NLA_TYPE_MASK == kind)
+        .map(|nla| {
+            let mut value = vec![0; nla.value_len()];
+            nla.emit_value(&mut value);
+            value
+        })
+}
+
+impl Socket<NetlinkNetfilter> {
+    /// delete the conntrack entries which were forwarded to the ip and whose
+    /// original destination port is in one of the (protocol number, ports) ranges,
+    /// returns the number of deleted entries
+    pub fn delete_conntrack_entries(
+        &mut self,
+        ip: IpAddr,
+        ports: &[(u8, RangeInclusive<u16>)],
+    ) -> NetavarkResult<usize> {
+        let family = match ip {
+            IpAddr::V4(_) => libc::AF_INET,
+            IpAddr::V6(_) => libc::AF_INET6,
+        } as u8;
+
+        // ctnetlink can only delete by the full tuple, so dump the table and
+        // delete the matching entries one by one like conntrack -D does.
+        let entries = self.make_netlink_request(
+            CtMessage::new(IPCTNL_MSG_CT_GET, family, Vec::new()),
+            NLM_F_DUMP,
+        )?;
+
+        let mut deleted = 0;
+        for entry in entries {
+            if entry.reply_src() != Some(ip) {
+                continue;
+            }
+            let matches = match entry.orig_proto_dport() {
+                Some((proto, port)) => ports
+                    .iter()
+                    .any(|(p, range)| *p == proto && range.contains(&port)),
+                None => false,
+            };
+            if !matches {
+                continue;
+            }
+
+            let attributes = entry
+                .attributes
+                .into_iter()
+                .filter(|nla| matches!(nla.kind() & NLA_TYPE_MASK, CTA_TUPLE_ORIG | CTA_ZONE))
+                .collect();
+            match self.make_netlink_request(
+                CtMessage::new(IPCTNL_MSG_CT_DELETE, family, attributes),
+                NLM_F_ACK,
+            ) {
+                Ok(_) => deleted += 1,
+                // the entry timed out in the meantime
+                Err(NetavarkError::Netlink(ref e)) if -e.raw_code() == libc::ENOENT => {}
+                Err(e) => return Err(e),
+            }
+        }
+        debug!("Deleted {deleted} conntrack entries forwarded to {ip}");
+        Ok(deleted)
+    }
+}
+
+impl Socket<NetlinkNftables> {
+    /// subscribe to the nf_tables events, they are read with recv_nftables_deletions()
+    pub fn subscribe_nftables_events(&mut self) -> NetavarkResult<()> {
+        self.add_membership(NFNLGRP_NFTABLES)
+    }
+
+    /// wait for the next nf_tables events and return the deleted tables and
+    /// chains, the result is empty when the events deleted nothing
+    pub fn recv_nftables_deletions(&mut self) -> NetavarkResult<Vec<NftDeletion>> {
+        Ok(self
+            .recv_events()?
+            .iter()
+            .filter_map(NftMessage::deletion)
+            .collect())
+    }
+
+    /// like recv_nftables_deletions() but returns None instead of waiting when
+    /// no events are queued
+    pub fn try_recv_nftables_deletions(&mut self) -> NetavarkResult<Option<Vec<NftDeletion>>> {
+        Ok(self
+            .try_recv_events()?
+            .map(|events| events.iter().filter_map(NftMessage::deletion).collect()))
+    }
+}
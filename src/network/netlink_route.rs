@@ -1,5 +1,5 @@
 use std::{
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     os::fd::{AsFd, AsRawFd, BorrowedFd},
 };
 
@@ -11,18 +11,59 @@ use crate::{
     },
 };
 use log::info;
-use netlink_packet_core::{NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL};
+use netlink_packet_core::{
+    DefaultNla, NLM_F_ACK, NLM_F_APPEND, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_REPLACE,
+};
 use netlink_packet_route::{
     address::AddressMessage,
     link::{
         AfSpecBridge, BridgeVlanInfo, BridgeVlanInfoFlags, InfoBridge, InfoData, InfoKind,
         LinkAttribute, LinkFlags, LinkInfo, LinkMessage,
     },
+    neighbour::{
+        NeighbourAddress, NeighbourAttribute, NeighbourFlags, NeighbourMessage, NeighbourState,
+    },
     route::{RouteAddress, RouteMessage, RouteProtocol, RouteScope, RouteType},
+    tc::{
+        TcAction, TcActionAttribute, TcActionOption, TcAttribute, TcFilterMatchAll,
+        TcFilterMatchAllOption, TcHandle, TcMessage, TcOption,
+    },
     AddressFamily, RouteNetlinkMessage,
 };
 use netlink_sys::protocols::NETLINK_ROUTE;
 
+// tbf qdisc options, see include/uapi/linux/pkt_sched.h
+const TBF_QDISC_KIND: &str = "tbf";
+const TCA_TBF_PARMS: u16 = 1;
+const TCA_TBF_RATE64: u16 = 4;
+const TCA_TBF_BURST: u16 = 6;
+/// Size of struct tc_tbf_qopt, two tc_ratespec followed by limit, buffer and mtu.
+const TC_TBF_QOPT_LEN: usize = 36;
+/// The kernel computes the transmission time of a packet from the rate
+/// itself, no rate table is needed.
+const TC_LINKLAYER_ETHERNET: u8 = 1;
+
+// ingress qdisc and police action, see include/uapi/linux/pkt_cls.h
+const INGRESS_QDISC_KIND: &str = "ingress";
+const INGRESS_QDISC_HANDLE: TcHandle = TcHandle {
+    major: u16::MAX,
+    minor: 0,
+};
+const POLICE_ACTION_KIND: &str = "police";
+const TCA_POLICE_TBF: u16 = 1;
+const TCA_POLICE_RATE: u16 = 2;
+const TCA_POLICE_RATE64: u16 = 8;
+/// Size of struct tc_police, index, action, limit, burst and mtu followed by
+/// the rate and peakrate tc_ratespec and refcnt, bindcnt and capab.
+const TC_POLICE_LEN: usize = 56;
+/// The kernel requires a rate table for the police action, its content is only
+/// used to detect an ATM link layer, so an empty one is sent with cell_log 3.
+const TC_RTAB_SIZE: usize = 1024;
+const TC_RTAB_CELL_LOG: u8 = 3;
+const TC_ACT_SHOT: i32 = 2;
+/// The burst of the police action is the time to send it in units of 64ns.
+const PSCHED_SHIFT: u32 = 6;
+
 #[derive(Clone)]
 pub struct CreateLinkOptions<'fd> {
     pub name: String,
@@ -214,6 +255,74 @@ impl Socket<NetlinkRoute> {
         Ok(())
     }
 
+    /// append a all zero mac forwarding entry pointing to the given remote,
+    /// performs the equivalent of "bridge fdb append 00:00:00:00:00:00 dev <link> dst <ip>"
+    pub fn add_fdb_entry(&mut self, link_id: u32, dst: &IpAddr) -> NetavarkResult<()> {
+        let mut msg = NeighbourMessage::default();
+        msg.header.family = AddressFamily::Bridge;
+        msg.header.ifindex = link_id;
+        msg.header.state = NeighbourState::Permanent;
+        msg.header.flags = NeighbourFlags::Own;
+        msg.attributes
+            .push(NeighbourAttribute::LinkLayerAddress(vec![0; 6]));
+        msg.attributes
+            .push(NeighbourAttribute::Destination(match dst {
+                IpAddr::V4(v4) => NeighbourAddress::Inet(*v4),
+                IpAddr::V6(v6) => NeighbourAddress::Inet6(*v6),
+            }));
+
+        let result = self.make_netlink_request(
+            RouteNetlinkMessage::NewNeighbour(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_APPEND,
+        )?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    /// add a proxy neighbour entry so the kernel answers neighbour requests for
+    /// the address on the given link, performs the equivalent of
+    /// "ip neigh add proxy <ip> dev <link>"
+    pub fn add_neighbour_proxy(&mut self, link_id: u32, dst: &IpAddr) -> NetavarkResult<()> {
+        let msg = Self::create_neighbour_proxy_msg(link_id, dst);
+
+        let result = self.make_netlink_request(
+            RouteNetlinkMessage::NewNeighbour(msg),
+            NLM_F_ACK | NLM_F_CREATE,
+        )?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    /// delete a proxy neighbour entry, performs the equivalent of
+    /// "ip neigh del proxy <ip> dev <link>"
+    pub fn del_neighbour_proxy(&mut self, link_id: u32, dst: &IpAddr) -> NetavarkResult<()> {
+        let msg = Self::create_neighbour_proxy_msg(link_id, dst);
+
+        let result =
+            self.make_netlink_request(RouteNetlinkMessage::DelNeighbour(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    fn create_neighbour_proxy_msg(link_id: u32, dst: &IpAddr) -> NeighbourMessage {
+        let mut msg = NeighbourMessage::default();
+        msg.header.ifindex = link_id;
+        msg.header.state = NeighbourState::Permanent;
+        msg.header.flags = NeighbourFlags::Proxy;
+        let addr = match dst {
+            IpAddr::V4(v4) => {
+                msg.header.family = AddressFamily::Inet;
+                NeighbourAddress::Inet(*v4)
+            }
+            IpAddr::V6(v6) => {
+                msg.header.family = AddressFamily::Inet6;
+                NeighbourAddress::Inet6(*v6)
+            }
+        };
+        msg.attributes.push(NeighbourAttribute::Destination(addr));
+        msg
+    }
+
     fn create_addr_msg(link_id: u32, addr: &ipnet::IpNet) -> AddressMessage {
         let mut msg = AddressMessage::default();
         msg.header.index = link_id;
@@ -343,6 +452,29 @@ impl Socket<NetlinkRoute> {
         Ok(())
     }
 
+    /// add a route bound to the given output interface, performs the equivalent
+    /// of "ip route add <dest> [via <gw>] dev <link>". Routes without gateway
+    /// are added with link scope.
+    pub fn add_link_route(&mut self, route: &Route, link_id: u32) -> NetavarkResult<()> {
+        let mut msg = Self::create_route_msg(route);
+        if !msg
+            .attributes
+            .iter()
+            .any(|a| matches!(a, netlink_packet_route::route::RouteAttribute::Gateway(_)))
+        {
+            msg.header.scope = RouteScope::Link;
+        }
+        msg.attributes
+            .push(netlink_packet_route::route::RouteAttribute::Oif(link_id));
+        info!("Adding route {route} on link {link_id}");
+
+        let result = self
+            .make_netlink_request(RouteNetlinkMessage::NewRoute(msg), NLM_F_ACK | NLM_F_CREATE)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn del_route(&mut self, route: &Route) -> NetavarkResult<()> {
         let msg = Self::create_route_msg(route);
         info!("Deleting route {route}");
@@ -461,6 +593,39 @@ impl Socket<NetlinkRoute> {
         Ok(())
     }
 
+    pub fn set_down(&mut self, id: LinkID) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+
+        match id {
+            LinkID::ID(id) => msg.header.index = id,
+            LinkID::Name(name) => msg.attributes.push(LinkAttribute::IfName(name)),
+        }
+
+        msg.header.flags = LinkFlags::empty();
+        msg.header.change_mask = LinkFlags::Up;
+
+        let result = self.make_netlink_request(RouteNetlinkMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn set_mtu(&mut self, id: LinkID, mtu: u32) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+
+        match id {
+            LinkID::ID(id) => msg.header.index = id,
+            LinkID::Name(name) => msg.attributes.push(LinkAttribute::IfName(name)),
+        }
+
+        msg.attributes.push(LinkAttribute::Mtu(mtu));
+
+        let result = self.make_netlink_request(RouteNetlinkMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn set_mac_address(&mut self, id: LinkID, mac: Vec<u8>) -> NetavarkResult<()> {
         let mut msg = LinkMessage::default();
 
@@ -476,6 +641,132 @@ impl Socket<NetlinkRoute> {
 
         Ok(())
     }
+
+    /// replace the root qdisc of the link with a token bucket filter, rate is in
+    /// bytes per second and burst and limit in bytes, performs the equivalent of
+    /// "tc qdisc replace dev <link> root tbf rate <rate> burst <burst> limit <limit>"
+    pub fn set_tbf_qdisc(
+        &mut self,
+        link_id: u32,
+        rate: u64,
+        burst: u32,
+        limit: u32,
+    ) -> NetavarkResult<()> {
+        let mut parms = vec![0; TC_TBF_QOPT_LEN];
+        // rate: cell_log, linklayer, overhead, cell_align, mpu, rate
+        parms[1] = TC_LINKLAYER_ETHERNET;
+        parms[8..12].copy_from_slice(&(rate.min(u32::MAX as u64) as u32).to_ne_bytes());
+        // the peakrate stays unset, then the buffer and mtu are not used
+        parms[24..28].copy_from_slice(&limit.to_ne_bytes());
+
+        let mut options = vec![
+            TcOption::Other(DefaultNla::new(TCA_TBF_PARMS, parms)),
+            TcOption::Other(DefaultNla::new(TCA_TBF_BURST, burst.to_ne_bytes().to_vec())),
+        ];
+        if rate > u32::MAX as u64 {
+            options.push(TcOption::Other(DefaultNla::new(
+                TCA_TBF_RATE64,
+                rate.to_ne_bytes().to_vec(),
+            )));
+        }
+
+        let mut msg = Self::create_root_qdisc_msg(link_id);
+        msg.attributes
+            .push(TcAttribute::Kind(TBF_QDISC_KIND.to_string()));
+        msg.attributes.push(TcAttribute::Options(options));
+
+        let result = self.make_netlink_request(
+            RouteNetlinkMessage::NewQueueDiscipline(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        )?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    /// delete the root qdisc of the link so the default one is used again,
+    /// performs the equivalent of "tc qdisc del dev <link> root"
+    pub fn del_root_qdisc(&mut self, link_id: u32) -> NetavarkResult<()> {
+        let msg = Self::create_root_qdisc_msg(link_id);
+
+        let result =
+            self.make_netlink_request(RouteNetlinkMessage::DelQueueDiscipline(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    /// police the traffic received by the link, rate is in bytes per second and
+    /// burst in bytes, packets over the rate are dropped, performs the equivalent of
+    /// "tc qdisc add dev <link> ingress" and
+    /// "tc filter add dev <link> ingress matchall action police rate <rate> burst <burst> mtu 4gb drop"
+    pub fn set_ingress_police(
+        &mut self,
+        link_id: u32,
+        rate: u64,
+        burst: u32,
+    ) -> NetavarkResult<()> {
+        let mut msg = TcMessage::with_index(link_id as i32);
+        msg.header.parent = TcHandle::INGRESS;
+        msg.header.handle = INGRESS_QDISC_HANDLE;
+        msg.attributes
+            .push(TcAttribute::Kind(INGRESS_QDISC_KIND.to_string()));
+
+        let result = self.make_netlink_request(
+            RouteNetlinkMessage::NewQueueDiscipline(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        let burst_ticks = (burst as u64 * 1_000_000_000 / rate.max(1)) >> PSCHED_SHIFT;
+        let mut parms = vec![0; TC_POLICE_LEN];
+        parms[4..8].copy_from_slice(&TC_ACT_SHOT.to_ne_bytes());
+        parms[12..16].copy_from_slice(&(burst_ticks.min(u32::MAX as u64) as u32).to_ne_bytes());
+        // without a mtu larger packets than 2k are dropped, which includes GSO packets
+        parms[16..20].copy_from_slice(&u32::MAX.to_ne_bytes());
+        // rate: cell_log, linklayer, overhead, cell_align, mpu, rate
+        parms[20] = TC_RTAB_CELL_LOG;
+        parms[21] = TC_LINKLAYER_ETHERNET;
+        parms[28..32].copy_from_slice(&(rate.min(u32::MAX as u64) as u32).max(1).to_ne_bytes());
+
+        let mut options = vec![
+            TcActionOption::Other(DefaultNla::new(TCA_POLICE_TBF, parms)),
+            TcActionOption::Other(DefaultNla::new(TCA_POLICE_RATE, vec![0; TC_RTAB_SIZE])),
+        ];
+        if rate > u32::MAX as u64 {
+            options.push(TcActionOption::Other(DefaultNla::new(
+                TCA_POLICE_RATE64,
+                rate.to_ne_bytes().to_vec(),
+            )));
+        }
+        let mut action = TcAction::default();
+        action
+            .attributes
+            .push(TcActionAttribute::Kind(POLICE_ACTION_KIND.to_string()));
+        action.attributes.push(TcActionAttribute::Options(options));
+
+        let mut msg = TcMessage::with_index(link_id as i32);
+        msg.header.parent = INGRESS_QDISC_HANDLE;
+        // priority 1 and all protocols
+        msg.header.info = 1 << 16 | (libc::ETH_P_ALL as u16).to_be() as u32;
+        msg.attributes
+            .push(TcAttribute::Kind(TcFilterMatchAll::KIND.to_string()));
+        msg.attributes
+            .push(TcAttribute::Options(vec![TcOption::MatchAll(
+                TcFilterMatchAllOption::Action(vec![action]),
+            )]));
+
+        let result = self.make_netlink_request(
+            RouteNetlinkMessage::NewTrafficFilter(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )?;
+        expect_netlink_result!(result, 0);
+        Ok(())
+    }
+
+    fn create_root_qdisc_msg(link_id: u32) -> TcMessage {
+        let mut msg = TcMessage::with_index(link_id as i32);
+        msg.header.parent = TcHandle::ROOT;
+        msg
+    }
 }
 
 impl CreateLinkOptions<'_> {
@@ -0,0 +1,134 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{
+    error::NetavarkResult,
+    network::netlink::{NetlinkFamily, Socket},
+};
+use netlink_packet_core::{
+    DecodeError, NetlinkDeserializable, NetlinkHeader, NetlinkPayload, NetlinkSerializable,
+    NLM_F_DUMP,
+};
+use netlink_sys::protocols::NETLINK_SOCK_DIAG;
+
+// see include/uapi/linux/sock_diag.h and include/uapi/linux/inet_diag.h
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+/// Size of struct inet_diag_req_v2.
+const INET_DIAG_REQ_V2_LEN: usize = 56;
+/// Size of struct inet_diag_msg without the attributes.
+const INET_DIAG_MSG_LEN: usize = 72;
+/// Offset of idiag_sport and idiag_src in struct inet_diag_msg.
+const INET_DIAG_MSG_SPORT: usize = 4;
+const INET_DIAG_MSG_SRC: usize = 8;
+
+/// Socket states of the kernel, used as bit in the states of a request.
+pub const TCP_CLOSE: u32 = 7;
+pub const TCP_LISTEN: u32 = 10;
+
+pub struct NetlinkSockDiag;
+
+impl NetlinkFamily for NetlinkSockDiag {
+    const PROTOCOL: isize = NETLINK_SOCK_DIAG;
+    type Message = SockDiagMessage;
+}
+
+#[derive(Debug, Clone)]
+pub enum SockDiagMessage {
+    /// dump request for the sockets of the family and protocol in the states
+    Request {
+        family: u8,
+        protocol: u8,
+        states: u32,
+    },
+    /// a dumped socket, only its local address is kept
+    Socket(SocketAddr),
+}
+
+impl NetlinkSerializable for SockDiagMessage {
+    fn message_type(&self) -> u16 {
+        SOCK_DIAG_BY_FAMILY
+    }
+
+    fn buffer_len(&self) -> usize {
+        match self {
+            SockDiagMessage::Request { .. } => INET_DIAG_REQ_V2_LEN,
+            SockDiagMessage::Socket(_) => 0,
+        }
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        if let SockDiagMessage::Request {
+            family,
+            protocol,
+            states,
+        } = self
+        {
+            buffer[..INET_DIAG_REQ_V2_LEN].fill(0);
+            buffer[0] = *family;
+            buffer[1] = *protocol;
+            // idiag_ext and pad stay zero, the socket id is not used for dumps
+            buffer[4..8].copy_from_slice(&states.to_ne_bytes());
+        }
+    }
+}
+
+impl NetlinkDeserializable for SockDiagMessage {
+    type Error = DecodeError;
+
+    fn deserialize(_header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < INET_DIAG_MSG_LEN {
+            return Err(DecodeError::from("inet_diag message too short"));
+        }
+        let port = u16::from_be_bytes([
+            payload[INET_DIAG_MSG_SPORT],
+            payload[INET_DIAG_MSG_SPORT + 1],
+        ]);
+        let src = &payload[INET_DIAG_MSG_SRC..INET_DIAG_MSG_SRC + 16];
+        let ip = match payload[0] as i32 {
+            libc::AF_INET => {
+                let octets: [u8; 4] = src[..4].try_into().unwrap_or_default();
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            libc::AF_INET6 => {
+                let octets: [u8; 16] = src.try_into().unwrap_or_default();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            family => {
+                return Err(DecodeError::from(format!(
+                    "unexpected inet_diag socket family {family}"
+                )))
+            }
+        };
+        Ok(SockDiagMessage::Socket(SocketAddr::new(ip, port)))
+    }
+}
+
+impl From<SockDiagMessage> for NetlinkPayload<SockDiagMessage> {
+    fn from(message: SockDiagMessage) -> Self {
+        NetlinkPayload::InnerMessage(message)
+    }
+}
+
+impl Socket<NetlinkSockDiag> {
+    /// get the local addresses of the sockets of the family and protocol which
+    /// are in one of the states, e.g. TCP_LISTEN
+    pub fn dump_socket_addresses(
+        &mut self,
+        family: i32,
+        protocol: i32,
+        states: &[u32],
+    ) -> NetavarkResult<Vec<SocketAddr>> {
+        let msg = SockDiagMessage::Request {
+            family: family as u8,
+            protocol: protocol as u8,
+            states: states.iter().fold(0, |mask, state| mask | 1 << state),
+        };
+        Ok(self
+            .make_netlink_request(msg, NLM_F_DUMP)?
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SockDiagMessage::Socket(addr) => Some(addr),
+                SockDiagMessage::Request { .. } => None,
+            })
+            .collect())
+    }
+}
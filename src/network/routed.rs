@@ -0,0 +1,725 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use log::{debug, error};
+use netlink_packet_route::{link::LinkAttribute, route::RouteType};
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+    firewall::{
+        self,
+        state::{remove_fw_config, write_fw_config},
+    },
+    network::{
+        core_utils::get_default_route_interface,
+        netlink::Socket,
+        netlink_route::{LinkID, NetlinkRoute, Route},
+    },
+};
+
+use super::{
+    bridge::{
+        create_standalone_veth_pair, get_egress_allow_option, get_firewall_conf,
+        get_isolate_option, get_services, render_firewall, setup_services, teardown_services,
+    },
+    constants::{
+        MAX_INTERFACE_NAME_LEN, NO_CONTAINER_INTERFACE_ERROR, OPTION_METRIC, OPTION_MTU,
+        OPTION_NO_DEFAULT_ROUTE, OPTION_OUTBOUND_ADDR4, OPTION_OUTBOUND_ADDR6, ROUTED_GATEWAY_V4,
+        ROUTED_GATEWAY_V6, VALID_ROUTED_OPTS,
+    },
+    core_utils::{self, get_ipam_addresses, parse_option, CoreUtils},
+    driver::{self, DriverInfo},
+    internal_types::{IPAMAddresses, IsolateOption, TearDownNetwork, TeardownPortForward},
+    sysctl,
+    types::{self, NetInterface, StatusBlock},
+};
+
+const NO_INTERFACE_PREFIX_ERROR: &str = "no routed interface prefix given";
+
+/// Length of the container id hash appended to the interface prefix to form
+/// the host veth name.
+const HOST_INTERFACE_HASH_LEN: usize = 8;
+
+/// Longest interface prefix which still results in a valid host veth name,
+/// "<prefix>_<hash>" must fit into MAX_INTERFACE_NAME_LEN.
+pub const MAX_INTERFACE_PREFIX_LEN: usize = MAX_INTERFACE_NAME_LEN - HOST_INTERFACE_HASH_LEN - 1;
+
+struct InternalData {
+    /// interface name of the veth pair inside the container netns
+    container_interface_name: String,
+    /// interface name of the veth pair in the host netns
+    host_interface_name: String,
+    /// prefix shared by all host veths of this network
+    interface_prefix: String,
+    /// static mac address
+    mac_address: Option<Vec<u8>>,
+    /// ip addresses
+    ipam: IPAMAddresses,
+    /// mtu for the network interfaces (0 if default)
+    mtu: u32,
+    /// if this network should be isolated from others
+    isolate: IsolateOption,
+    /// Route metric for any default routes added for the network
+    metric: Option<u32>,
+    /// if set, no default gateway will be added
+    no_default_route: bool,
+    /// outbound IPv4 address for SNAT
+    outbound_addr4: Option<Ipv4Addr>,
+    /// outbound IPv6 address for SNAT
+    outbound_addr6: Option<Ipv6Addr>,
+}
+
+/// The routed driver creates one veth pair per container without any bridge.
+/// The container address is assigned with the full prefix length and the host
+/// routes it via the host veth, the container reaches everything else through
+/// a link local gateway the host veth answers for with proxy arp/ndp.
+pub struct Routed<'a> {
+    info: DriverInfo<'a>,
+    data: Option<InternalData>,
+}
+
+impl<'a> Routed<'a> {
+    pub fn new(info: DriverInfo<'a>) -> Self {
+        Routed { info, data: None }
+    }
+}
+
+impl driver::NetworkDriver for Routed<'_> {
+    fn network_name(&self) -> String {
+        self.info.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        let interface_prefix =
+            get_interface_prefix(self.info.network.network_interface.as_deref())?;
+        if self.info.per_network_opts.interface_name.is_empty() {
+            return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
+        }
+        let ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+        if ipam.dhcp_enabled {
+            return Err(NetavarkError::msg(
+                "dhcp ipam driver is not supported with the routed driver",
+            ));
+        }
+
+        let opts = parse_routed_opts(&self.info.network.options, false)?;
+        // The firewall rules match the host veths by an interface name wildcard,
+        // firewalld does not expand it.
+        if !self.info.network.internal && self.info.firewall.driver_name() == firewall::FIREWALLD {
+            return Err(NetavarkError::msg(
+                "the routed driver is not supported by the firewalld firewall driver",
+            ));
+        }
+        // Parse the egress rules early to catch errors
+        get_egress_allow_option(&self.info.per_network_opts.options)?;
+
+        let static_mac = match &self.info.per_network_opts.static_mac {
+            Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
+            None => None,
+        };
+
+        self.data = Some(InternalData {
+            container_interface_name: self.info.per_network_opts.interface_name.clone(),
+            host_interface_name: get_host_interface_name(&interface_prefix, self.info.container_id),
+            interface_prefix,
+            mac_address: static_mac,
+            ipam,
+            mtu: opts.mtu.unwrap_or(0),
+            isolate: opts.isolate,
+            metric: Some(opts.metric.unwrap_or(100)),
+            no_default_route: opts.no_default_route.unwrap_or(false),
+            outbound_addr4: opts.outbound_addr4,
+            outbound_addr6: opts.outbound_addr6,
+        });
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before setup()")),
+        };
+
+        debug!("Setup network {}", self.info.network.name);
+        debug!(
+            "Container interface name: {} with IP addresses {:?}, host interface name: {}",
+            data.container_interface_name, data.ipam.container_addresses, data.host_interface_name
+        );
+
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        let mac_address = self.create_interfaces(host_sock, netns_sock, data)?;
+
+        let mut response = StatusBlock {
+            dns_server_ips: Some(Vec::<IpAddr>::new()),
+            dns_search_domains: Some(Vec::<String>::new()),
+            interfaces: Some(HashMap::new()),
+            ntp_servers: None,
+            port_mappings: None,
+        };
+        // If --dns-enable=false and --dns was set then return following DNS servers
+        // in status_block so podman can use these and populate resolv.conf
+        if let Some(container_dns_servers) = self.info.container_dns_servers {
+            let _ = response
+                .dns_server_ips
+                .insert(container_dns_servers.clone());
+        }
+
+        let subnets = data
+            .ipam
+            .container_addresses
+            .iter()
+            .map(|addr| types::NetAddress {
+                gateway: Some(get_gateway(addr)),
+                ipnet: host_address(addr),
+            })
+            .collect::<Vec<_>>();
+
+        let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
+        interfaces.insert(
+            data.container_interface_name.clone(),
+            NetInterface {
+                mac_address,
+                subnets: Option::from(subnets),
+            },
+        );
+        let _ = response.interfaces.insert(interfaces);
+
+        // if the network is internal do not setup firewall rules
+        if !self.info.network.internal {
+            self.setup_firewall(data)?;
+            response.port_mappings = self.info.port_mappings.clone().filter(|p| !p.is_empty());
+        }
+
+        Ok((response, None))
+    }
+
+    fn render_firewall(&self) -> NetavarkResult<()> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before render")),
+        };
+
+        if self.info.network.internal {
+            return Ok(());
+        }
+
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
+            &data.ipam.container_addresses,
+            &data.ipam.nameservers,
+            data.isolate.clone(),
+            get_firewall_interface_match(&data.interface_prefix),
+            data.outbound_addr4,
+            data.outbound_addr6,
+        )?;
+        render_firewall(&self.info, sn, spf)
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<()> {
+        let (host_sock, netns_sock) = netlink_sockets;
+        let mut error_list = NetavarkErrorList::new();
+
+        let interface_prefix =
+            get_interface_prefix(self.info.network.network_interface.as_deref())?;
+
+        // Removing the container veth also removes the host veth together
+        // with the host routes and proxy entries on it.
+        if let Err(err) = netns_sock
+            .del_link(LinkID::Name(
+                self.info.per_network_opts.interface_name.to_string(),
+            ))
+            .wrap(format!(
+                "failed to delete container veth {}",
+                self.info.per_network_opts.interface_name
+            ))
+        {
+            error_list.push(err);
+        }
+
+        if !self.info.network.internal {
+            let complete_teardown = match has_host_interfaces(host_sock, &interface_prefix) {
+                Ok(in_use) => !in_use,
+                Err(err) => {
+                    error_list.push(err);
+                    false
+                }
+            };
+
+            if let Err(err) = self.teardown_firewall(complete_teardown, &interface_prefix) {
+                error_list.push(err);
+            }
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+
+        Ok(())
+    }
+}
+
+impl Routed<'_> {
+    /// Create the veth pair and all routes, returns the container veth mac address.
+    fn create_interfaces(
+        &self,
+        host: &mut Socket<NetlinkRoute>,
+        netns: &mut Socket<NetlinkRoute>,
+        data: &InternalData,
+    ) -> NetavarkResult<String> {
+        let mut mtu = data.mtu;
+        if mtu == 0 {
+            // if we have a default route, use its mtu as default
+            if let Ok(link) = get_default_route_interface(host, None) {
+                match core_utils::get_mtu_from_iface_attributes(&link.attributes) {
+                    Ok(iface_mtu) => mtu = iface_mtu,
+                    Err(e) => log::warn!(
+                        "failed to get mtu for default interface {}: {e}, using kernel default",
+                        link.header.index
+                    ),
+                }
+            }
+        }
+
+        let internal = self.info.network.internal;
+        let has_ipv4 = data
+            .ipam
+            .container_addresses
+            .iter()
+            .any(|a| a.addr().is_ipv4());
+        let has_ipv6 = data
+            .ipam
+            .container_addresses
+            .iter()
+            .any(|a| a.addr().is_ipv6());
+
+        let mut sysctls = Vec::with_capacity(6);
+        if internal {
+            // only the host can talk to the container
+            sysctls.push((
+                format!("net/ipv4/conf/{}/forwarding", data.host_interface_name),
+                "0",
+            ));
+            if has_ipv6 {
+                sysctls.push((
+                    format!("net/ipv6/conf/{}/forwarding", data.host_interface_name),
+                    "0",
+                ));
+            }
+        } else {
+            sysctls.push(("net/ipv4/ip_forward".to_string(), "1"));
+            if has_ipv6 {
+                sysctls.push(("net/ipv6/conf/all/forwarding".to_string(), "1"));
+            }
+            sysctls.push((
+                format!("net/ipv4/conf/{}/route_localnet", data.host_interface_name),
+                "1",
+            ));
+        }
+        // answer arp/ndp requests for the link local gateway in the container
+        sysctls.push((
+            format!("net/ipv4/conf/{}/proxy_arp", data.host_interface_name),
+            "1",
+        ));
+        if has_ipv6 {
+            sysctls.push((
+                format!("net/ipv6/conf/{}/proxy_ndp", data.host_interface_name),
+                "1",
+            ));
+        }
+        sysctls.push((
+            format!("net/ipv4/conf/{}/rp_filter", data.host_interface_name),
+            "2",
+        ));
+
+        let mac = create_standalone_veth_pair(
+            host,
+            netns,
+            &data.host_interface_name,
+            &data.container_interface_name,
+            data.mac_address.clone(),
+            data.ipam
+                .container_addresses
+                .iter()
+                .map(host_address)
+                .collect(),
+            data.ipam.ipv6_enabled,
+            self.info.netns_host,
+            self.info.netns_container,
+            mtu,
+        )?;
+
+        // The sysctls must be written after the host veth is created.
+        for (key, value) in sysctls {
+            sysctl::apply_sysctl_value(key, value)?;
+        }
+
+        let host_link = host
+            .get_link(LinkID::Name(data.host_interface_name.clone()))
+            .wrap("get host veth")?;
+        let container_link = netns
+            .get_link(LinkID::Name(data.container_interface_name.clone()))
+            .wrap("get container veth")?;
+
+        for addr in &data.ipam.container_addresses {
+            let route = match host_address(addr) {
+                IpNet::V4(dest) => Route::Ipv4 {
+                    dest,
+                    gw: None,
+                    metric: None,
+                    route_type: RouteType::Unicast,
+                },
+                IpNet::V6(dest) => Route::Ipv6 {
+                    dest,
+                    gw: None,
+                    metric: None,
+                    route_type: RouteType::Unicast,
+                },
+            };
+            host.add_link_route(&route, host_link.header.index)
+                .wrap("add container route on host")?;
+        }
+
+        if has_ipv6 {
+            host.add_neighbour_proxy(host_link.header.index, &IpAddr::V6(ROUTED_GATEWAY_V6))
+                .wrap("add ndp proxy entry for the gateway")?;
+        }
+
+        // The ipv4 gateway is not in any subnet of the container so it needs
+        // an explicit link route, the ipv6 one is link local already.
+        if has_ipv4 {
+            netns
+                .add_link_route(
+                    &Route::Ipv4 {
+                        dest: Ipv4Net::from(ROUTED_GATEWAY_V4),
+                        gw: None,
+                        metric: None,
+                        route_type: RouteType::Unicast,
+                    },
+                    container_link.header.index,
+                )
+                .wrap("add gateway route in container")?;
+        }
+
+        if !internal && !data.no_default_route {
+            if has_ipv4 {
+                netns
+                    .add_link_route(
+                        &Route::Ipv4 {
+                            dest: Ipv4Net::default(),
+                            gw: Some(ROUTED_GATEWAY_V4),
+                            metric: data.metric,
+                            route_type: RouteType::Unicast,
+                        },
+                        container_link.header.index,
+                    )
+                    .wrap("add default route in container")?;
+            }
+            if has_ipv6 {
+                netns
+                    .add_link_route(
+                        &Route::Ipv6 {
+                            dest: Ipv6Net::default(),
+                            gw: Some(ROUTED_GATEWAY_V6),
+                            metric: data.metric,
+                            route_type: RouteType::Unicast,
+                        },
+                        container_link.header.index,
+                    )
+                    .wrap("add default route in container")?;
+            }
+        }
+
+        // All traffic leaves the container through the host, so static routes
+        // always use the link local gateway as next hop.
+        for route in data.ipam.routes.iter() {
+            match route {
+                Route::Ipv4 {
+                    dest,
+                    gw: Some(_),
+                    metric,
+                    route_type,
+                } => netns.add_link_route(
+                    &Route::Ipv4 {
+                        dest: *dest,
+                        gw: Some(ROUTED_GATEWAY_V4),
+                        metric: *metric,
+                        route_type: *route_type,
+                    },
+                    container_link.header.index,
+                )?,
+                Route::Ipv6 {
+                    dest,
+                    gw: Some(_),
+                    metric,
+                    route_type,
+                } => netns.add_link_route(
+                    &Route::Ipv6 {
+                        dest: *dest,
+                        gw: Some(ROUTED_GATEWAY_V6),
+                        metric: *metric,
+                        route_type: *route_type,
+                    },
+                    container_link.header.index,
+                )?,
+                route => netns.add_route(route)?,
+            }
+        }
+
+        Ok(mac)
+    }
+
+    fn setup_firewall(&self, data: &InternalData) -> NetavarkResult<()> {
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
+            &data.ipam.container_addresses,
+            &data.ipam.nameservers,
+            data.isolate.clone(),
+            get_firewall_interface_match(&data.interface_prefix),
+            data.outbound_addr4,
+            data.outbound_addr6,
+        )?;
+
+        if !self.info.rootless {
+            write_fw_config(
+                self.info.config_dir,
+                &self.info.network.id,
+                self.info.container_id,
+                self.info.firewall.driver_name(),
+                &sn,
+                &spf,
+            )?;
+        }
+
+        let services = get_services(&spf);
+
+        let system_dbus = zbus::blocking::Connection::system().ok();
+
+        self.info.firewall.setup_network(sn, &system_dbus)?;
+
+        self.info.firewall.setup_port_forward(spf, &system_dbus)?;
+
+        setup_services(&self.info, services)?;
+        Ok(())
+    }
+
+    fn teardown_firewall(
+        &self,
+        complete_teardown: bool,
+        interface_prefix: &str,
+    ) -> NetavarkResult<()> {
+        // teardown does not call validate() so parse everything again, just
+        // log errors as we still try to do as much as possible for cleanup
+        let opts = parse_routed_opts(&self.info.network.options, false).unwrap_or_else(|e| {
+            error!("failed to parse routed options: {e}");
+            RoutedOptions {
+                mtu: None,
+                isolate: IsolateOption::Never,
+                metric: None,
+                no_default_route: None,
+                outbound_addr4: None,
+                outbound_addr6: None,
+            }
+        });
+        let (container_addresses, nameservers) =
+            match get_ipam_addresses(self.info.per_network_opts, self.info.network) {
+                Ok(i) => (i.container_addresses, i.nameservers),
+                Err(e) => {
+                    error!("failed to parse ipam options: {e}");
+                    (Vec::new(), Vec::new())
+                }
+            };
+
+        let (sn, spf) = get_firewall_conf(
+            &self.info,
+            &container_addresses,
+            &nameservers,
+            opts.isolate,
+            get_firewall_interface_match(interface_prefix),
+            opts.outbound_addr4,
+            opts.outbound_addr6,
+        )?;
+
+        if !self.info.rootless {
+            // IMPORTANT: This must happen before we actually teardown rules.
+            remove_fw_config(
+                self.info.config_dir,
+                &self.info.network.id,
+                self.info.container_id,
+                complete_teardown,
+            )?;
+        }
+
+        if complete_teardown {
+            self.info.firewall.teardown_network(TearDownNetwork {
+                config: sn,
+                complete_teardown,
+            })?;
+        }
+
+        let services = get_services(&spf);
+
+        self.info
+            .firewall
+            .teardown_port_forward(TeardownPortForward {
+                config: spf,
+                complete_teardown,
+            })?;
+
+        teardown_services(&self.info, services)?;
+        Ok(())
+    }
+}
+
+fn get_interface_prefix(name: Option<&str>) -> NetavarkResult<String> {
+    match name {
+        Some(n) if !n.is_empty() => Ok(n.to_string()),
+        _ => Err(NetavarkError::msg(NO_INTERFACE_PREFIX_ERROR)),
+    }
+}
+
+/// The host veth name is derived from the container id so teardown can find
+/// it again without any extra state.
+fn get_host_interface_name(prefix: &str, container_id: &str) -> String {
+    let hash = CoreUtils::create_network_hash(container_id, HOST_INTERFACE_HASH_LEN);
+    format!("{prefix}_{}", hash.to_lowercase())
+}
+
+/// nftables interface name wildcard matching all host veths of the network,
+/// used in place of the bridge name for the isolation rules.
+fn get_firewall_interface_match(prefix: &str) -> String {
+    format!("{prefix}_*")
+}
+
+/// Returns true when any host veth of the network still exists.
+fn has_host_interfaces(host: &mut Socket<NetlinkRoute>, prefix: &str) -> NetavarkResult<bool> {
+    let links = host
+        .dump_links(&mut vec![])
+        .wrap("failed to get host interfaces")?;
+    let prefix = format!("{prefix}_");
+    Ok(links.into_iter().any(|link| {
+        link.attributes.into_iter().any(|nla| match nla {
+            LinkAttribute::IfName(name) => name.starts_with(&prefix),
+            _ => false,
+        })
+    }))
+}
+
+/// Convert the address into a single host address (/32 or /128).
+fn host_address(addr: &IpNet) -> IpNet {
+    match addr {
+        IpNet::V4(v4) => IpNet::V4(Ipv4Net::from(v4.addr())),
+        IpNet::V6(v6) => IpNet::V6(Ipv6Net::from(v6.addr())),
+    }
+}
+
+fn get_gateway(addr: &IpNet) -> IpAddr {
+    match addr {
+        IpNet::V4(_) => IpAddr::V4(ROUTED_GATEWAY_V4),
+        IpNet::V6(_) => IpAddr::V6(ROUTED_GATEWAY_V6),
+    }
+}
+
+pub fn parse_routed_opts(
+    opts: &Option<HashMap<String, String>>,
+    strict: bool,
+) -> NetavarkResult<RoutedOptions> {
+    if strict {
+        if let Some(invalid_key) = opts
+            .as_ref()
+            .and_then(|m| m.keys().find(|k| !VALID_ROUTED_OPTS.contains(&k.as_str())))
+        {
+            return Err(NetavarkError::msg(format!(
+                "unsupported routed network option: {}",
+                invalid_key
+            )));
+        }
+    }
+    let isolate = get_isolate_option(opts)?;
+    // the isolation rules match the host veths by a wildcard, which cannot
+    // be an element of the group set
+    if let IsolateOption::Group(_) = isolate {
+        return Err(NetavarkError::msg(
+            "isolation groups are not supported by the routed driver",
+        ));
+    }
+    Ok(RoutedOptions {
+        mtu: parse_option(opts, OPTION_MTU)?,
+        isolate,
+        metric: parse_option(opts, OPTION_METRIC)?,
+        no_default_route: parse_option(opts, OPTION_NO_DEFAULT_ROUTE)?,
+        outbound_addr4: parse_option(opts, OPTION_OUTBOUND_ADDR4)?,
+        outbound_addr6: parse_option(opts, OPTION_OUTBOUND_ADDR6)?,
+    })
+}
+
+pub struct RoutedOptions {
+    pub mtu: Option<u32>,
+    pub isolate: IsolateOption,
+    pub metric: Option<u32>,
+    pub no_default_route: Option<bool>,
+    pub outbound_addr4: Option<Ipv4Addr>,
+    pub outbound_addr6: Option<Ipv6Addr>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::constants::OPTION_ISOLATE;
+
+    #[test]
+    fn test_host_interface_name() {
+        let name = get_host_interface_name("nvr1", "abc");
+        assert!(name.starts_with("nvr1_"));
+        assert_eq!(name.len(), 4 + 1 + HOST_INTERFACE_HASH_LEN);
+        assert_eq!(name, get_host_interface_name("nvr1", "abc"));
+        assert_ne!(name, get_host_interface_name("nvr1", "abd"));
+
+        let name = get_host_interface_name(
+            &"a".repeat(MAX_INTERFACE_PREFIX_LEN),
+            "c2c8a073252874648259997d53b0a1bffa491e21f04bc1bf8609266359931395",
+        );
+        assert_eq!(name.len(), MAX_INTERFACE_NAME_LEN);
+    }
+
+    #[test]
+    fn test_host_address() {
+        let addr: IpNet = "10.89.0.5/24".parse().unwrap();
+        assert_eq!(
+            host_address(&addr),
+            "10.89.0.5/32".parse::<IpNet>().unwrap()
+        );
+        let addr: IpNet = "fd10::5/64".parse().unwrap();
+        assert_eq!(host_address(&addr), "fd10::5/128".parse::<IpNet>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_routed_opts_strict() {
+        let opts = Some(HashMap::from([(
+            OPTION_ISOLATE.to_string(),
+            "true".to_string(),
+        )]));
+        let parsed = parse_routed_opts(&opts, true).unwrap();
+        assert_eq!(parsed.isolate, IsolateOption::Normal);
+
+        let opts = Some(HashMap::from([("vlan".to_string(), "5".to_string())]));
+        assert!(parse_routed_opts(&opts, true).is_err());
+        assert!(parse_routed_opts(&opts, false).is_ok());
+
+        let opts = Some(HashMap::from([(
+            OPTION_ISOLATE.to_string(),
+            "group:web".to_string(),
+        )]));
+        assert!(parse_routed_opts(&opts, true).is_err());
+    }
+}
@@ -217,6 +217,51 @@ pub struct PortMapping {
     /// 65536.
     #[serde(rename = "range")]
     pub range: u16,
+
+    /// AllowedSources are the subnets clients must connect from, traffic
+    /// from other sources is not forwarded.
+    /// If unset, all sources are allowed.
+    #[serde(rename = "allowed_sources", skip_serializing_if = "Option::is_none")]
+    pub allowed_sources: Option<Vec<IpNet>>,
+
+    /// RateLimit is the maximum number of new connections per second that
+    /// are forwarded, additional connections are dropped.
+    /// If unset, new connections are not limited.
+    #[serde(rename = "rate_limit", skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u32>,
+
+    /// ConnectionLimit is the maximum number of concurrent connections that
+    /// are forwarded, additional connections are dropped.
+    /// If unset, connections are not limited.
+    #[serde(rename = "connection_limit", skip_serializing_if = "Option::is_none")]
+    pub connection_limit: Option<u32>,
+
+    /// Service is the name of a load balanced service. The port mappings of
+    /// all containers with the same service share the host ip and port, new
+    /// connections are distributed across the containers.
+    /// Cannot be combined with allowed_sources, rate_limit or connection_limit.
+    /// Only supported by the nftables firewall driver.
+    #[serde(rename = "service", skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+
+    /// ServiceBalance is how a container of the service is picked for a new
+    /// connection. If unset, round-robin is used.
+    #[serde(rename = "service_balance", skip_serializing_if = "Option::is_none")]
+    pub service_balance: Option<ServiceBalance>,
+}
+
+/// ServiceBalance is the algorithm used to pick the container of a load
+/// balanced service.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceBalance {
+    /// Use the containers in turn.
+    #[default]
+    RoundRobin,
+    /// Pick a random container.
+    Random,
+    /// Hash the source address, a client always gets the same container.
+    SourceHash,
 }
 
 /// StatusBlock contains the network information about a container
@@ -238,6 +283,23 @@ pub struct StatusBlock {
     /// The map key is the interface name.
     #[serde(rename = "interfaces")]
     pub interfaces: Option<HashMap<String, NetInterface>>,
+
+    /// NTP servers sent by the DHCP server, only set with the dhcp ipam driver.
+    #[serde(
+        rename = "ntp_servers",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ntp_servers: Option<Vec<String>>,
+
+    /// Port mappings of the container with the allocated host ports of the
+    /// mappings without a host port, only set by drivers which forward ports.
+    #[serde(
+        rename = "port_mappings",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub port_mappings: Option<Vec<PortMapping>>,
 }
 
 /// NetInterface contains the settings for a given network interface.
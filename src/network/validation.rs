@@ -1,4 +1,5 @@
-use crate::error::NetavarkResult;
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::types::PortMapping;
 use log::debug;
 use std::fs::File;
 
@@ -8,3 +9,67 @@ pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     let _ = File::open(file)?.metadata()?;
     Ok(())
 }
+
+/// Check the port mapping options which are not checked by the firewall drivers.
+pub fn validate_port_mappings(ports: &Option<Vec<PortMapping>>) -> NetavarkResult<()> {
+    for port in ports.iter().flatten() {
+        if port.rate_limit == Some(0) {
+            return Err(NetavarkError::msg(format!(
+                "invalid rate_limit 0 for port {}, must be greater than 0",
+                port.host_port
+            )));
+        }
+        if port.connection_limit == Some(0) {
+            return Err(NetavarkError::msg(format!(
+                "invalid connection_limit 0 for port {}, must be greater than 0",
+                port.host_port
+            )));
+        }
+        match &port.service {
+            Some(name) => {
+                if name.is_empty()
+                    || name.len() > 32
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(NetavarkError::msg(format!(
+                        "invalid service name \"{name}\", must be 1-32 characters of [a-zA-Z0-9_-]"
+                    )));
+                }
+                if port.range > 1 {
+                    return Err(NetavarkError::msg(format!(
+                        "port range is not supported for service {name}"
+                    )));
+                }
+                if port.host_port == 0 {
+                    return Err(NetavarkError::msg(format!(
+                        "a host port is required for service {name}"
+                    )));
+                }
+                // The service rules do not apply these, the port would be
+                // published without the restrictions.
+                for (set, option) in [
+                    (port.allowed_sources.is_some(), "allowed_sources"),
+                    (port.rate_limit.is_some(), "rate_limit"),
+                    (port.connection_limit.is_some(), "connection_limit"),
+                ] {
+                    if set {
+                        return Err(NetavarkError::msg(format!(
+                            "{option} is not supported for service {name}"
+                        )));
+                    }
+                }
+            }
+            None => {
+                if port.service_balance.is_some() {
+                    return Err(NetavarkError::msg(format!(
+                        "service_balance for port {} requires a service",
+                        port.host_port
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
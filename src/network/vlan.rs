@@ -13,7 +13,7 @@ use crate::network::core_utils::get_default_route_interface;
 use crate::network::dhcp::{dhcp_teardown, get_dhcp_lease};
 use crate::{
     dns::aardvark::AardvarkEntry,
-    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
     exec_netns,
     network::core_utils::join_netns,
     network::sysctl::disable_ipv6_autoconf,
@@ -24,9 +24,12 @@ use super::{
         NO_CONTAINER_INTERFACE_ERROR, OPTION_BCLIM, OPTION_METRIC, OPTION_MODE, OPTION_MTU,
         OPTION_NO_DEFAULT_ROUTE, VALID_VLAN_OPTS,
     },
-    core_utils::{self, get_ipam_addresses, get_mac_address, parse_option, CoreUtils},
+    core_utils::{
+        self, get_egress_limit, get_ipam_addresses, get_mac_address, parse_option,
+        remove_egress_limit, set_egress_limit, CoreUtils,
+    },
     driver::{self, DriverInfo},
-    internal_types::IPAMAddresses,
+    internal_types::{EgressLimit, IPAMAddresses},
     types::{NetInterface, StatusBlock},
 };
 
@@ -70,6 +73,8 @@ struct InternalData {
     kind: KindData,
     /// if set, no default gateway will be added
     no_default_route: bool,
+    /// bandwidth limit of the traffic sent by the container
+    egress_limit: Option<EgressLimit>,
     // TODO: add vlan
 }
 
@@ -105,6 +110,10 @@ impl driver::NetworkDriver for Vlan<'_> {
         let metric = opts.metric.unwrap_or(99);
         let no_default_route: bool = opts.no_default_route.unwrap_or(false);
         let bclim = opts.bclim;
+        let egress_limit = get_egress_limit(
+            &self.info.network.options,
+            &self.info.per_network_opts.options,
+        )?;
 
         let mut ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
 
@@ -139,6 +148,7 @@ impl driver::NetworkDriver for Vlan<'_> {
                 other => return Err(NetavarkError::msg(format!("unsupported VLAN type {other}"))),
             },
             no_default_route,
+            egress_limit,
         });
         Ok(())
     }
@@ -176,6 +186,8 @@ impl driver::NetworkDriver for Vlan<'_> {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            ntp_servers: None,
+            port_mappings: None,
         };
 
         // interfaces map, but we only ever expect one, for response
@@ -185,22 +197,24 @@ impl driver::NetworkDriver for Vlan<'_> {
         // a dhcp lease.  it will also perform the IP address assignment
         // to the macvlan interface.
         let subnets = if data.ipam.dhcp_enabled {
-            let (subnets, dns_servers, domain_name) = get_dhcp_lease(
+            let lease = get_dhcp_lease(
                 &data.host_interface_name,
                 &data.container_interface_name,
                 self.info.netns_path,
                 &container_vlan_mac,
                 self.info.container_hostname.as_deref().unwrap_or(""),
                 self.info.container_id,
+                self.info.network,
             )?;
             // do not overwrite dns servers set by dns podman flag
             if !self.info.container_dns_servers.is_some() {
-                response.dns_server_ips = dns_servers;
+                response.dns_server_ips = lease.dns_servers;
             }
-            if domain_name.is_some() {
-                response.dns_search_domains = domain_name;
+            if lease.domain_names.is_some() {
+                response.dns_search_domains = lease.domain_names;
             }
-            subnets
+            response.ntp_servers = lease.ntp_servers;
+            lease.subnets
         } else {
             data.ipam.net_addresses.clone()
         };
@@ -227,9 +241,31 @@ impl driver::NetworkDriver for Vlan<'_> {
             netlink_sockets.1.del_route(route)?;
         }
 
-        netlink_sockets.1.del_link(LinkID::Name(
-            self.info.per_network_opts.interface_name.to_string(),
-        ))?;
+        let mut error_list = NetavarkErrorList::new();
+
+        match get_egress_limit(
+            &self.info.network.options,
+            &self.info.per_network_opts.options,
+        ) {
+            Ok(Some(_)) => remove_egress_limit(
+                netlink_sockets.1,
+                &self.info.per_network_opts.interface_name,
+            )
+            .unwrap_or_else(|err| error_list.push(err)),
+            Ok(None) => {}
+            Err(err) => error_list.push(err),
+        }
+
+        netlink_sockets
+            .1
+            .del_link(LinkID::Name(
+                self.info.per_network_opts.interface_name.to_string(),
+            ))
+            .unwrap_or_else(|err| error_list.push(err));
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
         Ok(())
     }
 }
@@ -344,6 +380,10 @@ fn setup(
         .set_up(LinkID::ID(dev.header.index))
         .wrap(format!("set {kind_data} up"))?;
 
+    if let Some(limit) = &data.egress_limit {
+        set_egress_limit(netns, if_name, limit)?;
+    }
+
     if !data.no_default_route {
         core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
     }
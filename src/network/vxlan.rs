@@ -0,0 +1,464 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use log::{debug, error, warn};
+use netlink_packet_route::link::{InfoData, InfoKind, InfoVxlan, LinkAttribute};
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+    network::{
+        core_utils::get_default_route_interface,
+        netlink::Socket,
+        netlink_route::{CreateLinkOptions, LinkID, NetlinkRoute},
+    },
+};
+
+use super::{
+    bridge::Bridge,
+    constants::{
+        DEFAULT_VXLAN_PORT, OPTION_MODE, OPTION_MTU, OPTION_VNI, OPTION_VXLAN_DEV,
+        OPTION_VXLAN_GROUP, OPTION_VXLAN_LOCAL, OPTION_VXLAN_PORT, OPTION_VXLAN_REMOTE,
+        VALID_VXLAN_OPTS,
+    },
+    core_utils::{self, parse_option},
+    driver::{self, DriverInfo},
+    types::{Network, StatusBlock},
+};
+
+/// Highest vni that fits into the 24 bit vxlan header field.
+const MAX_VNI: u32 = (1 << 24) - 1;
+/// Size of the outer ethernet, ip, udp and vxlan headers.
+const VXLAN_OVERHEAD_V4: u32 = 50;
+const VXLAN_OVERHEAD_V6: u32 = 70;
+/// Mtu of the underlay when it cannot be read from the lower device.
+const DEFAULT_UNDERLAY_MTU: u32 = 1500;
+
+/// The vxlan driver creates a managed bridge exactly like the bridge driver
+/// and attaches a vxlan device to it, so containers on different hosts using
+/// the same vni share one L2 segment.
+pub struct Vxlan<'a> {
+    network: &'a Network,
+    bridge: Bridge<'a>,
+    data: Option<VxlanOptions>,
+    /// mtu of the vxlan interface, the bridge and the veths
+    mtu: u32,
+}
+
+impl<'a> Vxlan<'a> {
+    pub fn new(info: DriverInfo<'a>) -> Self {
+        Vxlan {
+            network: info.network,
+            bridge: Bridge::new(info),
+            data: None,
+            mtu: 0,
+        }
+    }
+}
+
+impl driver::NetworkDriver for Vxlan<'_> {
+    fn network_name(&self) -> String {
+        self.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        if self
+            .network
+            .options
+            .as_ref()
+            .and_then(|o| o.get(OPTION_MODE))
+            .is_some()
+        {
+            return Err(NetavarkError::msg(
+                "vxlan driver only supports managed bridges, the mode option cannot be set",
+            ));
+        }
+        let data = parse_vxlan_opts(&self.network.options, false)?;
+        self.bridge.validate()?;
+
+        // The bridge and the veths must not use a larger mtu than the vxlan
+        // interface, so the default is derived from the underlay here.
+        self.mtu = match parse_option(&self.network.options, OPTION_MTU)? {
+            Some(mtu) => mtu,
+            None => {
+                let mut host = Socket::<NetlinkRoute>::new().wrap("host netlink socket")?;
+                let mtu = get_default_vxlan_mtu(&mut host, &data);
+                self.bridge.set_mtu(mtu);
+                mtu
+            }
+        };
+        self.data = Some(data);
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before setup()")),
+        };
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        let result = self.bridge.setup((host_sock, netns_sock))?;
+
+        // bridge setup already verified that we have a name
+        let bridge_name = self
+            .network
+            .network_interface
+            .as_deref()
+            .unwrap_or_default();
+        create_vxlan_link(host_sock, bridge_name, data, self.mtu)?;
+
+        Ok(result)
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<()> {
+        let (host_sock, netns_sock) = netlink_sockets;
+        let mut error_list = NetavarkErrorList::new();
+
+        match parse_option::<u32>(&self.network.options, OPTION_VNI) {
+            Ok(Some(vni)) => {
+                let bridge_name = self
+                    .network
+                    .network_interface
+                    .as_deref()
+                    .unwrap_or_default();
+                if let Err(err) = maybe_remove_vxlan_link(host_sock, bridge_name, vni) {
+                    error_list.push(err);
+                }
+            }
+            // just log we still try to do as much as possible for cleanup
+            Ok(None) => error!("no {OPTION_VNI} option set for vxlan network"),
+            Err(e) => error!("failed to parse {OPTION_VNI} option: {e}"),
+        }
+
+        if let Err(err) = self.bridge.teardown((host_sock, netns_sock)) {
+            error_list.push(err);
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+        Ok(())
+    }
+}
+
+/// Get the mtu of the lower device minus the vxlan overhead of the underlay
+/// family. The lower device is the dev option or the default route interface.
+fn get_default_vxlan_mtu(host: &mut Socket<NetlinkRoute>, data: &VxlanOptions) -> u32 {
+    let ipv6 = data
+        .local
+        .iter()
+        .chain(data.group.iter())
+        .chain(data.remotes.iter())
+        .next()
+        .is_some_and(|addr| addr.is_ipv6());
+    let overhead = if ipv6 {
+        VXLAN_OVERHEAD_V6
+    } else {
+        VXLAN_OVERHEAD_V4
+    };
+
+    let link = match &data.dev {
+        Some(dev) => host.get_link(LinkID::Name(dev.clone())),
+        None => get_default_route_interface(host, None),
+    };
+    let underlay_mtu = match link
+        .and_then(|link| core_utils::get_mtu_from_iface_attributes(&link.attributes))
+    {
+        Ok(mtu) => mtu,
+        Err(e) => {
+            warn!("failed to get the mtu of the vxlan lower device: {e}, using {DEFAULT_UNDERLAY_MTU}");
+            DEFAULT_UNDERLAY_MTU
+        }
+    };
+    let mtu = underlay_mtu.saturating_sub(overhead);
+    debug!("Using mtu {mtu} for vxlan network derived from underlay mtu {underlay_mtu}");
+    mtu
+}
+
+fn get_vxlan_interface_name(vni: u32) -> String {
+    // max vni has 8 digits so this always fits into the 15 char limit
+    format!("vxlan{vni}")
+}
+
+/// Create the vxlan interface and attach it to the bridge, if it already
+/// exists make sure it is connected to the right bridge.
+fn create_vxlan_link(
+    host: &mut Socket<NetlinkRoute>,
+    bridge_name: &str,
+    data: &VxlanOptions,
+    mtu: u32,
+) -> NetavarkResult<()> {
+    let name = get_vxlan_interface_name(data.vni);
+    let bridge = host
+        .get_link(LinkID::Name(bridge_name.to_string()))
+        .wrap("get bridge interface")?;
+
+    match host.get_link(LinkID::Name(name.clone())) {
+        Ok(link) => {
+            let attached = link.attributes.iter().any(
+                |a| matches!(a, LinkAttribute::Controller(idx) if *idx == bridge.header.index),
+            );
+            if !attached {
+                return Err(NetavarkError::Message(format!(
+                    "vxlan interface {name} already exists but is not attached to bridge {bridge_name}"
+                )));
+            }
+            return Ok(());
+        }
+        Err(err) => match err.unwrap() {
+            NetavarkError::Netlink(e) if -e.raw_code() == libc::ENODEV => {}
+            _ => return Err(err).wrap("get vxlan interface"),
+        },
+    }
+
+    let mut info = vec![
+        InfoVxlan::Id(data.vni),
+        InfoVxlan::Port(data.port.unwrap_or(DEFAULT_VXLAN_PORT)),
+        InfoVxlan::Learning(true),
+    ];
+    match data.local {
+        Some(IpAddr::V4(v4)) => info.push(InfoVxlan::Local(v4)),
+        Some(IpAddr::V6(v6)) => info.push(InfoVxlan::Local6(v6)),
+        None => {}
+    }
+    match data.group {
+        Some(IpAddr::V4(v4)) => info.push(InfoVxlan::Group(v4)),
+        Some(IpAddr::V6(v6)) => info.push(InfoVxlan::Group6(v6)),
+        None => {}
+    }
+
+    // the kernel requires a lower device for multicast groups
+    let dev = match &data.dev {
+        Some(dev) => Some(
+            host.get_link(LinkID::Name(dev.clone()))
+                .wrap(format!("get vxlan lower device {dev}"))?,
+        ),
+        None if data.group.is_some() => Some(get_default_route_interface(host, None)?),
+        None => None,
+    };
+    if let Some(dev) = dev {
+        info.push(InfoVxlan::Link(dev.header.index));
+    }
+
+    let mut opts = CreateLinkOptions::new(name.clone(), InfoKind::Vxlan);
+    opts.mtu = mtu;
+    opts.primary_index = bridge.header.index;
+    opts.info_data = Some(InfoData::Vxlan(info));
+    host.create_link(opts).wrap("create vxlan interface")?;
+
+    let link = host
+        .get_link(LinkID::Name(name))
+        .wrap("get vxlan interface")?;
+
+    for remote in &data.remotes {
+        debug!("adding vxlan remote {remote}");
+        host.add_fdb_entry(link.header.index, remote)
+            .wrap(format!("add vxlan remote {remote}"))?;
+    }
+
+    host.set_up(LinkID::ID(link.header.index))
+        .wrap("set vxlan interface up")?;
+    Ok(())
+}
+
+/// Remove the vxlan interface when the container that is torn down is the
+/// last one attached to the bridge, this allows the bridge driver to remove
+/// the bridge afterwards.
+fn maybe_remove_vxlan_link(
+    host: &mut Socket<NetlinkRoute>,
+    bridge_name: &str,
+    vni: u32,
+) -> NetavarkResult<()> {
+    let name = get_vxlan_interface_name(vni);
+    let bridge = match host.get_link(LinkID::Name(bridge_name.to_string())) {
+        Ok(b) => b,
+        // bridge teardown will report the missing bridge
+        Err(_) => return Ok(()),
+    };
+    let vxlan = match host.get_link(LinkID::Name(name.clone())) {
+        Ok(l) => l,
+        Err(_) => return Ok(()),
+    };
+
+    let links = host
+        .dump_links(&mut vec![LinkAttribute::Controller(bridge.header.index)])
+        .wrap("failed to get connected bridge interfaces")?;
+    let others = links
+        .iter()
+        .filter(|l| l.header.index != vxlan.header.index)
+        .count();
+    // only the veth of the container we tear down is left
+    if others <= 1 {
+        log::info!("removing vxlan interface {name}");
+        host.del_link(LinkID::ID(vxlan.header.index))
+            .wrap(format!("failed to delete vxlan interface {name}"))?;
+    }
+    Ok(())
+}
+
+fn parse_ip_list(value: &str) -> NetavarkResult<Vec<IpAddr>> {
+    value
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.trim().parse::<IpAddr>().map_err(|e| {
+                NetavarkError::msg(format!("invalid {OPTION_VXLAN_REMOTE} address {s}: {e}"))
+            })
+        })
+        .collect()
+}
+
+pub fn parse_vxlan_opts(
+    opts: &Option<HashMap<String, String>>,
+    strict: bool,
+) -> NetavarkResult<VxlanOptions> {
+    if strict {
+        if let Some(invalid_key) = opts
+            .as_ref()
+            .and_then(|m| m.keys().find(|k| !VALID_VXLAN_OPTS.contains(&k.as_str())))
+        {
+            return Err(NetavarkError::msg(format!(
+                "unsupported vxlan network option: {}",
+                invalid_key
+            )));
+        }
+    }
+
+    let vni: u32 = match parse_option(opts, OPTION_VNI)? {
+        Some(vni) => vni,
+        None => {
+            return Err(NetavarkError::msg(format!(
+                "vxlan driver requires the {OPTION_VNI} option"
+            )))
+        }
+    };
+    if vni > MAX_VNI {
+        return Err(NetavarkError::msg(format!(
+            "vni must be between 0 and {MAX_VNI}"
+        )));
+    }
+    let local: Option<IpAddr> = parse_option(opts, OPTION_VXLAN_LOCAL)?;
+    let group: Option<IpAddr> = parse_option(opts, OPTION_VXLAN_GROUP)?;
+    let port = parse_option(opts, OPTION_VXLAN_PORT)?;
+    let dev = parse_option(opts, OPTION_VXLAN_DEV)?;
+    let remotes = match parse_option::<String>(opts, OPTION_VXLAN_REMOTE)? {
+        Some(r) => parse_ip_list(&r)?,
+        None => Vec::new(),
+    };
+
+    if let Some(group) = group {
+        if !remotes.is_empty() {
+            return Err(NetavarkError::msg(format!(
+                "{OPTION_VXLAN_GROUP} and {OPTION_VXLAN_REMOTE} cannot be used together"
+            )));
+        }
+        if !group.is_multicast() {
+            return Err(NetavarkError::msg(format!(
+                "{OPTION_VXLAN_GROUP} {group} is not a multicast address"
+            )));
+        }
+    }
+
+    // the kernel does not allow mixing ipv4 and ipv6 underlay addresses
+    let mut family = None;
+    for addr in local.iter().chain(group.iter()).chain(remotes.iter()) {
+        match family {
+            None => family = Some(addr.is_ipv4()),
+            Some(v4) if v4 != addr.is_ipv4() => {
+                return Err(NetavarkError::msg(
+                    "vxlan underlay addresses must all be of the same ip family",
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(VxlanOptions {
+        vni,
+        local,
+        remotes,
+        group,
+        port,
+        dev,
+    })
+}
+
+pub struct VxlanOptions {
+    pub vni: u32,
+    pub local: Option<IpAddr>,
+    pub remotes: Vec<IpAddr>,
+    pub group: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub dev: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(kv: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(
+            kv.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse_vxlan_opts_remotes() {
+        let o = parse_vxlan_opts(
+            &opts(&[
+                (OPTION_VNI, "42"),
+                (OPTION_VXLAN_LOCAL, "192.168.1.1"),
+                (OPTION_VXLAN_REMOTE, "192.168.1.2,192.168.1.3"),
+            ]),
+            true,
+        )
+        .unwrap();
+        assert_eq!(o.vni, 42);
+        assert_eq!(o.remotes.len(), 2);
+        assert!(o.group.is_none());
+    }
+
+    #[test]
+    fn test_parse_vxlan_opts_invalid() {
+        // missing vni
+        assert!(parse_vxlan_opts(&opts(&[]), true).is_err());
+        // vni too large
+        assert!(parse_vxlan_opts(&opts(&[(OPTION_VNI, "16777216")]), true).is_err());
+        // not a multicast group
+        assert!(parse_vxlan_opts(
+            &opts(&[(OPTION_VNI, "1"), (OPTION_VXLAN_GROUP, "10.0.0.1")]),
+            true
+        )
+        .is_err());
+        // group and remotes
+        assert!(parse_vxlan_opts(
+            &opts(&[
+                (OPTION_VNI, "1"),
+                (OPTION_VXLAN_GROUP, "239.1.1.1"),
+                (OPTION_VXLAN_REMOTE, "10.0.0.1")
+            ]),
+            true
+        )
+        .is_err());
+        // mixed families
+        assert!(parse_vxlan_opts(
+            &opts(&[
+                (OPTION_VNI, "1"),
+                (OPTION_VXLAN_LOCAL, "10.0.0.1"),
+                (OPTION_VXLAN_REMOTE, "fd00::1")
+            ]),
+            true
+        )
+        .is_err());
+        // unknown option
+        assert!(parse_vxlan_opts(&opts(&[(OPTION_VNI, "1"), ("vlan", "2")]), true).is_err());
+    }
+}
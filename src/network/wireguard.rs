@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ipnet::IpNet;
+use log::debug;
+use netlink_packet_route::link::InfoKind;
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    exec_netns,
+    network::{
+        core_utils::join_netns,
+        netlink::Socket,
+        netlink_generic::{NetlinkGeneric, WireguardPeer, WG_KEY_LEN},
+        netlink_route::{CreateLinkOptions, LinkID, NetlinkRoute},
+    },
+    wrap,
+};
+
+use super::{
+    constants::{
+        NO_CONTAINER_INTERFACE_ERROR, OPTION_MTU, OPTION_WG_LISTEN_PORT, OPTION_WG_PEERS,
+        OPTION_WG_PRIVATE_KEY_FILE, VALID_WIREGUARD_OPTS,
+    },
+    core_utils::{get_ipam_addresses, parse_option},
+    driver::{self, DriverInfo},
+    internal_types::IPAMAddresses,
+    types::{NetInterface, StatusBlock},
+};
+
+struct InternalData {
+    /// name of the wireguard interface inside the container
+    container_interface_name: String,
+    /// ip addresses
+    ipam: IPAMAddresses,
+    /// mtu for the interface (0 for the kernel default)
+    mtu: u32,
+    /// decoded private key of the interface
+    private_key: [u8; WG_KEY_LEN],
+    /// udp port to listen on, random if unset
+    listen_port: Option<u16>,
+    /// peers of the interface
+    peers: Vec<WireguardPeer>,
+}
+
+/// The wireguard driver creates a wireguard interface in the container netns.
+/// The interface is created from the host netns so its udp socket stays on the
+/// host, the container only sees the decrypted traffic.
+pub struct Wireguard<'a> {
+    info: DriverInfo<'a>,
+    data: Option<InternalData>,
+}
+
+impl<'a> Wireguard<'a> {
+    pub fn new(info: DriverInfo<'a>) -> Self {
+        Wireguard { info, data: None }
+    }
+}
+
+impl driver::NetworkDriver for Wireguard<'_> {
+    fn network_name(&self) -> String {
+        self.info.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        if self.info.per_network_opts.interface_name.is_empty() {
+            return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
+        }
+        let ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+        if ipam.dhcp_enabled {
+            return Err(NetavarkError::msg(
+                "dhcp ipam driver is not supported with the wireguard driver",
+            ));
+        }
+
+        let opts = parse_wireguard_opts(&self.info.network.options, false)?;
+        let private_key = read_private_key(&opts.private_key_file)?;
+
+        self.data = Some(InternalData {
+            container_interface_name: self.info.per_network_opts.interface_name.clone(),
+            ipam,
+            mtu: opts.mtu.unwrap_or(0),
+            private_key,
+            listen_port: opts.listen_port,
+            peers: opts.peers,
+        });
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before setup()")),
+        };
+
+        debug!("Setup network {}", self.info.network.name);
+        debug!(
+            "Wireguard interface name: {} with IP addresses {:?}",
+            data.container_interface_name, data.ipam.container_addresses
+        );
+
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        // Create the link from the host socket but directly in the container
+        // netns, wireguard binds its udp socket in the netns it was created from.
+        let mut opts =
+            CreateLinkOptions::new(data.container_interface_name.clone(), InfoKind::Wireguard);
+        opts.mtu = data.mtu;
+        opts.netns = Some(self.info.netns_container);
+        host_sock
+            .create_link(opts)
+            .wrap("create wireguard interface")?;
+
+        // The wireguard genetlink api looks up the interface by name in the
+        // netns of the socket so it must be opened in the container netns.
+        let mut genl_sock = exec_netns!(
+            self.info.netns_host,
+            self.info.netns_container,
+            Socket::<NetlinkGeneric>::new().wrap("netns generic netlink socket")
+        )?;
+        genl_sock
+            .set_wireguard_device(
+                &data.container_interface_name,
+                &data.private_key,
+                data.listen_port,
+                &data.peers,
+            )
+            .wrap("configure wireguard interface")?;
+
+        let link = netns_sock
+            .get_link(LinkID::Name(data.container_interface_name.clone()))
+            .wrap("get wireguard interface")?;
+
+        for addr in &data.ipam.container_addresses {
+            netns_sock
+                .add_addr(link.header.index, addr)
+                .wrap("add ip addr to wireguard interface")?;
+        }
+
+        netns_sock
+            .set_up(LinkID::ID(link.header.index))
+            .wrap("set wireguard interface up")?;
+
+        // there is no gateway, all routes go directly over the interface
+        for route in data.ipam.routes.iter() {
+            netns_sock.add_link_route(route, link.header.index)?;
+        }
+
+        let mut response = StatusBlock {
+            dns_server_ips: Some(Vec::<IpAddr>::new()),
+            dns_search_domains: Some(Vec::<String>::new()),
+            interfaces: Some(HashMap::new()),
+            ntp_servers: None,
+            port_mappings: None,
+        };
+        if let Some(container_dns_servers) = self.info.container_dns_servers {
+            let _ = response
+                .dns_server_ips
+                .insert(container_dns_servers.clone());
+        }
+
+        let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
+        interfaces.insert(
+            data.container_interface_name.clone(),
+            NetInterface {
+                // wireguard is a layer 3 interface without mac address
+                mac_address: String::new(),
+                subnets: Option::from(data.ipam.net_addresses.clone()),
+            },
+        );
+        let _ = response.interfaces.insert(interfaces);
+        Ok((response, None))
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut Socket<NetlinkRoute>, &mut Socket<NetlinkRoute>),
+    ) -> NetavarkResult<()> {
+        let (_, netns_sock) = netlink_sockets;
+
+        // routes and addresses are removed together with the interface
+        netns_sock
+            .del_link(LinkID::Name(
+                self.info.per_network_opts.interface_name.to_string(),
+            ))
+            .wrap(format!(
+                "failed to delete wireguard interface {}",
+                self.info.per_network_opts.interface_name
+            ))
+    }
+}
+
+fn read_private_key(path: &str) -> NetavarkResult<[u8; WG_KEY_LEN]> {
+    let content = wrap!(
+        fs::read_to_string(path),
+        format!("read wireguard private key file {path}")
+    )?;
+    decode_key(content.trim()).wrap(format!("invalid private key in {path}"))
+}
+
+/// decode a base64 encoded wireguard key
+fn decode_key(key: &str) -> NetavarkResult<[u8; WG_KEY_LEN]> {
+    let bytes = STANDARD
+        .decode(key)
+        .map_err(|e| NetavarkError::msg(format!("failed to decode key: {e}")))?;
+    bytes
+        .try_into()
+        .map_err(|_| NetavarkError::msg(format!("key must be {WG_KEY_LEN} bytes long")))
+}
+
+/// Parse the peers option. Peers are separated by ";", each peer starts with
+/// its base64 public key followed by optional comma separated key=value pairs:
+/// endpoint=<ip:port>, allowed_ips=<space separated cidrs>, persistent_keepalive=<seconds>.
+fn parse_peers(value: &str) -> NetavarkResult<Vec<WireguardPeer>> {
+    let mut peers = Vec::new();
+    for peer in value.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut fields = peer.split(',').map(str::trim);
+        let public_key = match fields.next() {
+            Some(key) => decode_key(key).wrap(format!("invalid peer public key {key}"))?,
+            None => continue,
+        };
+        let mut wg_peer = WireguardPeer {
+            public_key,
+            endpoint: None,
+            allowed_ips: Vec::new(),
+            persistent_keepalive: None,
+        };
+        for field in fields {
+            let (key, value) = field.split_once('=').ok_or_else(|| {
+                NetavarkError::msg(format!("invalid wireguard peer field \"{field}\""))
+            })?;
+            match key {
+                "endpoint" => {
+                    wg_peer.endpoint = Some(value.parse::<SocketAddr>().map_err(|e| {
+                        NetavarkError::msg(format!("invalid peer endpoint \"{value}\": {e}"))
+                    })?)
+                }
+                "allowed_ips" => {
+                    for ip in value.split_whitespace() {
+                        let net = match ip.parse::<IpNet>() {
+                            Ok(net) => net,
+                            Err(_) => ip.parse::<IpAddr>().map(IpNet::from).map_err(|e| {
+                                NetavarkError::msg(format!("invalid peer allowed ip \"{ip}\": {e}"))
+                            })?,
+                        };
+                        wg_peer.allowed_ips.push(net);
+                    }
+                }
+                "persistent_keepalive" => {
+                    wg_peer.persistent_keepalive = Some(value.parse::<u16>().map_err(|e| {
+                        NetavarkError::msg(format!(
+                            "invalid peer persistent_keepalive \"{value}\": {e}"
+                        ))
+                    })?)
+                }
+                _ => {
+                    return Err(NetavarkError::msg(format!(
+                        "unknown wireguard peer field \"{key}\""
+                    )))
+                }
+            }
+        }
+        peers.push(wg_peer);
+    }
+    Ok(peers)
+}
+
+pub fn parse_wireguard_opts(
+    opts: &Option<HashMap<String, String>>,
+    strict: bool,
+) -> NetavarkResult<WireguardOptions> {
+    if strict {
+        if let Some(invalid_key) = opts.as_ref().and_then(|m| {
+            m.keys()
+                .find(|k| !VALID_WIREGUARD_OPTS.contains(&k.as_str()))
+        }) {
+            return Err(NetavarkError::msg(format!(
+                "unsupported wireguard network option: {}",
+                invalid_key
+            )));
+        }
+    }
+
+    let private_key_file: String = match parse_option(opts, OPTION_WG_PRIVATE_KEY_FILE)? {
+        Some(f) => f,
+        None => {
+            return Err(NetavarkError::msg(format!(
+                "wireguard driver requires the {OPTION_WG_PRIVATE_KEY_FILE} option"
+            )))
+        }
+    };
+    let peers = match parse_option::<String>(opts, OPTION_WG_PEERS)? {
+        Some(p) => parse_peers(&p)?,
+        None => Vec::new(),
+    };
+
+    Ok(WireguardOptions {
+        mtu: parse_option(opts, OPTION_MTU)?,
+        private_key_file,
+        listen_port: parse_option(opts, OPTION_WG_LISTEN_PORT)?,
+        peers,
+    })
+}
+
+pub struct WireguardOptions {
+    pub mtu: Option<u32>,
+    pub private_key_file: String,
+    pub listen_port: Option<u16>,
+    pub peers: Vec<WireguardPeer>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1: &str = "YFuEYJzCtDFIFhEMHvt5bgzq/ugJR1KeM+KSzPjqYU8=";
+    const KEY2: &str = "6tGTnFyZ0Q7NgZNhA+Y13UWAP1MsQzR6n/OVlOwJqWE=";
+
+    #[test]
+    fn test_parse_peers() {
+        let peers = parse_peers(&format!(
+            "{KEY1},endpoint=192.168.1.2:51820,allowed_ips=10.10.0.0/24 10.20.0.1;\
+             {KEY2}, endpoint=[fd00::2]:51821, persistent_keepalive=25"
+        ))
+        .unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].public_key.to_vec(), STANDARD.decode(KEY1).unwrap());
+        assert_eq!(
+            peers[0].endpoint,
+            Some("192.168.1.2:51820".parse().unwrap())
+        );
+        assert_eq!(
+            peers[0].allowed_ips,
+            vec![
+                "10.10.0.0/24".parse::<IpNet>().unwrap(),
+                "10.20.0.1/32".parse::<IpNet>().unwrap()
+            ]
+        );
+        assert_eq!(peers[0].persistent_keepalive, None);
+        assert_eq!(peers[1].endpoint, Some("[fd00::2]:51821".parse().unwrap()));
+        assert!(peers[1].allowed_ips.is_empty());
+        assert_eq!(peers[1].persistent_keepalive, Some(25));
+    }
+
+    #[test]
+    fn test_parse_peers_invalid() {
+        assert!(parse_peers("notakey").is_err());
+        assert!(parse_peers(&format!("{KEY1},endpoint=192.168.1.2")).is_err());
+        assert!(parse_peers(&format!("{KEY1},allowed_ips=10.10.0.0/33")).is_err());
+        assert!(parse_peers(&format!("{KEY1},foo=bar")).is_err());
+        assert!(parse_peers(&format!("{KEY1},endpoint")).is_err());
+    }
+
+    #[test]
+    fn test_parse_wireguard_opts() {
+        let opts = Some(HashMap::from([(
+            OPTION_WG_LISTEN_PORT.to_string(),
+            "51820".to_string(),
+        )]));
+        assert!(parse_wireguard_opts(&opts, false).is_err());
+
+        let opts = Some(HashMap::from([
+            (
+                OPTION_WG_PRIVATE_KEY_FILE.to_string(),
+                "/etc/wireguard/key".to_string(),
+            ),
+            (OPTION_WG_LISTEN_PORT.to_string(), "51820".to_string()),
+            (OPTION_WG_PEERS.to_string(), KEY1.to_string()),
+        ]));
+        let parsed = parse_wireguard_opts(&opts, true).unwrap();
+        assert_eq!(parsed.private_key_file, "/etc/wireguard/key");
+        assert_eq!(parsed.listen_port, Some(51820));
+        assert_eq!(parsed.peers.len(), 1);
+    }
+}
@@ -125,3 +125,41 @@ EOF
         expected_rc=1 run_setup "$input_config"
         assert "$output" =~ "unable to parse mac address 123" "mac address error"
 }
+
+@test "lease options" {
+  gw=$(gateway_from_subnet "$SUBNET_CIDR")
+  add_dhcp_config "dhcp-option=26,1400
+dhcp-option=42,$gw
+dhcp-option=121,192.168.100.0/24,$gw
+dhcp-option=119,example.com,lab.example.com"
+
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "container_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+}
+  \0
+EOF
+
+  run_setup "$input_config"
+  lease="$output"
+  assert "$(jq -r .mtu <<<"$lease")" == "1400" "mtu from the lease"
+  assert "$(jq -r '.ntp_servers[0]' <<<"$lease")" == "$gw" "ntp server from the lease"
+  assert "$(jq -r '.classless_routes[0].destination' <<<"$lease")" == "192.168.100.0/24" "classless route destination"
+  assert "$(jq -r '.classless_routes[0].gateway' <<<"$lease")" == "$gw" "classless route gateway"
+  assert "$(jq -c '.domain_search' <<<"$lease")" == '["example.com","lab.example.com"]' "domain search list"
+
+  run_in_container_netns ip -j link show veth0
+  assert "$(jq -r '.[0].mtu' <<<"$output")" == "1400" "mtu set on the interface"
+  run_in_container_netns ip -j route show 192.168.100.0/24
+  assert "$(jq -r '.[0].gateway' <<<"$output")" == "$gw" "classless route installed"
+  # the router option is ignored when classless routes are sent
+  run_in_container_netns ip -j route show default
+  assert "$output" == "[]" "no default route"
+}
@@ -39,3 +39,68 @@ EOF
 run_in_container_netns kill -s SIGTERM "$PROXY_PID"
 expected_rc=2 run_helper ls -l "$TMP_TESTDIR/socket"
 }
+
+@test "restore leases after restart" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "container_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+}
+  \0
+EOF
+
+  run_setup "$input_config"
+  container_ip=$(jq -r .yiaddr <<<"$output")
+
+  # restart the proxy, the lease cache must survive
+  stop_proxy
+  rm -f "$TMP_TESTDIR/nv-proxy.sock"
+  start_proxy
+  sleep 2
+
+  run_helper jq "has(\"$CONTAINER_MAC\")" "$TMP_TESTDIR/nv-proxy.lease"
+  assert "$output" == "true" "lease kept after restart"
+  run_helper grep "Restored the renewal task for $CONTAINER_MAC" "$TMP_TESTDIR/proxy.log"
+  has_ip "$container_ip" veth0
+
+  # the restored task is released on teardown
+  run_teardown "$input_config"
+  run_helper jq ". | length" "$TMP_TESTDIR/nv-proxy.lease"
+  assert "$output" == 0 "lease removed on teardown"
+}
+
+@test "drop leases of removed containers after restart" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "container_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+}
+  \0
+EOF
+
+  run_setup "$input_config"
+
+  # pretend the container netns was removed while the proxy was down
+  stop_proxy
+  rm -f "$TMP_TESTDIR/nv-proxy.sock"
+  run_helper jq -c ".\"$CONTAINER_MAC\"[0].ns_path = \"/run/netns/does-not-exist\"" "$TMP_TESTDIR/nv-proxy.networks"
+  echo "$output" > "$TMP_TESTDIR/nv-proxy.networks"
+  start_proxy
+  sleep 2
+
+  run_helper jq ". | length" "$TMP_TESTDIR/nv-proxy.lease"
+  assert "$output" == 0 "stale lease dropped"
+  run_helper grep "DHCPRELEASE" "$TMP_TESTDIR/dnsmasq.log"
+}
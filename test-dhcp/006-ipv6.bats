@@ -0,0 +1,88 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# DHCPv6 tests
+#
+
+load helpers
+
+# restart dnsmasq with an additional DHCPv6 range on br0
+function run_dhcp6() {
+  SUBNET6_CIDR=$(random_subnet 6)
+  local prefix=${SUBNET6_CIDR%/64}
+  run_in_container_netns ip addr add ${prefix}1/64 dev br0 nodad
+
+  add_dhcp_config "dhcp-range=${prefix}50,${prefix}59,64,2m
+dhcp-option=option6:dns-server,[${prefix}1]"
+}
+
+@test "ipv6 setup" {
+  run_dhcp6
+
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 1,
+  "ns_path": "$NS_PATH",
+  "container_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+}
+  \0
+EOF
+
+  run_setup "$input_config"
+  assert "$(jq -r .is_v6 <<<"$output")" == "true" "lease is for ipv6"
+  assert "$(jq -r .subnet_mask <<<"$output")" == "" "no subnet mask for ipv6"
+  container_ip=$(jq -r .yiaddr <<<"$output")
+  assert "$container_ip" =~ "^${SUBNET6_CIDR%::/64}::5[0-9]$" "ip from the dhcpv6 range"
+  assert "$(jq -r '.dns_servers[0]' <<<"$output")" == "${SUBNET6_CIDR%/64}1" "dns server from the reply"
+  has_ip "$container_ip" veth0
+
+  run_helper cat "$TMP_TESTDIR/nv-proxy.lease"
+  run_helper jq -r ".\"$CONTAINER_MAC\"[0].is_v6" <<<"$output"
+  assert "$output" == "true" "ipv6 lease cached"
+
+  run_teardown "$input_config"
+  run_helper cat "$TMP_TESTDIR/nv-proxy.lease"
+  run_helper jq ". | length" <<<"$output"
+  assert "$output" == 0 "lease removed on teardown"
+}
+
+@test "ipv6 and ipv4 setup" {
+  run_dhcp6
+
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "container_id": "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+}
+  \0
+EOF
+
+  run_setup "$input_config"
+  ip4=$(jq -r .yiaddr <<<"$output")
+  run_setup "$(jq -c '.version = 1' <<<"$input_config")"
+  ip6=$(jq -r .yiaddr <<<"$output")
+
+  has_ip "$ip4" veth0
+  has_ip "$ip6" veth0
+
+  # both leases are cached under the mac address, ipv4 first
+  run_helper cat "$TMP_TESTDIR/nv-proxy.lease"
+  run_helper jq -c "[.\"$CONTAINER_MAC\"[].is_v6]" <<<"$output"
+  assert "$output" == "[false,true]" "one lease per ip version"
+
+  # a single teardown releases the leases of both families
+  run_teardown "$input_config"
+  run_helper cat "$TMP_TESTDIR/nv-proxy.lease"
+  run_helper jq ". | length" <<<"$output"
+  assert "$output" == 0 "leases removed on teardown"
+}
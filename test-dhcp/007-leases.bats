@@ -0,0 +1,59 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# lease listing tests
+#
+
+load helpers
+
+CONTAINER_ID="0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+
+# run a proxy client command which does not read a network config
+function run_query() {
+  run_helper "$NETAVARK_DHCP_PROXY_CLIENT" --uds "$TMP_TESTDIR/nv-proxy.sock" "$@"
+}
+
+@test "list and get leases" {
+
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "container_id": "$CONTAINER_ID"
+}
+  \0
+EOF
+
+  run_query list-leases
+  assert "$(jq '.leases | length' <<<"$output")" == 0 "no leases before setup"
+
+  run_setup "$input_config"
+  container_ip=$(jq -r .yiaddr <<<"$output")
+
+  run_query list-leases
+  assert "$(jq '.leases | length' <<<"$output")" == 1 "one lease listed"
+  assert "$(jq -r '.leases[0].lease.yiaddr' <<<"$output")" == "$container_ip" "leased address"
+  assert "$(jq -r '.leases[0].container_id' <<<"$output")" == "$CONTAINER_ID" "container id"
+  assert "$(jq -r '.leases[0].renewing' <<<"$output")" == "true" "renewal task running"
+  acquired=$(jq -r '.leases[0].acquired' <<<"$output")
+  t1=$(jq -r '.leases[0].lease.t1' <<<"$output")
+  assert "$(jq -r '.leases[0].next_t1' <<<"$output")" == "$((acquired + t1))" "next renewal time"
+  assert "$(jq -r '.leases[0].last_error' <<<"$output")" == "" "no error"
+
+  run_query get-lease --container-id "$CONTAINER_ID"
+  assert "$(jq -r '.leases[0].lease.mac_address' <<<"$output")" == "$CONTAINER_MAC" "lease by container id"
+
+  run_query get-lease --mac "$CONTAINER_MAC"
+  assert "$(jq -r '.leases[0].container_id' <<<"$output")" == "$CONTAINER_ID" "lease by mac address"
+
+  expected_rc=1 run_query get-lease --container-id "fedcba9876543210"
+  assert "$output" =~ "no lease for container fedcba9876543210" "unknown container"
+
+  run_teardown "$input_config"
+  run_query list-leases
+  assert "$(jq '.leases | length' <<<"$output")" == 0 "lease removed on teardown"
+}
@@ -366,6 +366,16 @@ EOF
 #
 #  stop_dhcp 27231
 #
+#
+# add_dhcp_config <config lines>, restarts dnsmasq with the additional config
+#
+function add_dhcp_config() {
+  stop_dhcp
+  echo "$1" >> "${TMP_TESTDIR}/dnsmasq/test.conf"
+  ip netns exec "${NS_NAME}" dnsmasq --log-debug --log-dhcp --no-daemon --conf-dir "${TMP_TESTDIR}/dnsmasq" &>>"$TMP_TESTDIR/dnsmasq.log" &
+  DNSMASQ_PID=$!
+}
+
 function stop_dhcp() {
   echo "dnsmasq log:"
   cat "${TMP_TESTDIR}/dnsmasq.log"
@@ -149,6 +149,22 @@ function setup() {
     run_in_host_netns ping6 -c 1 fd10:88:a::2
 }
 
+@test "$fw_driver - dual stack bridge without ipv6 nat" {
+    local config=$(jq -c '.network_info.podman1.options = {"ipv6_nat": "false"}' \
+        ${TESTSDIR}/testfiles/dualstack-bridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"10.89.3.0/24\" masquerade" "v4 masquerade rule"
+    assert "$output" !~ "fd10:88:a::/64" "no v6 masquerade rule"
+    expected_rc=1 run_in_host_netns firewall-cmd --policy netavark_policy --query-masquerade
+    assert "$output" == "no" "policy does not masquerade all subnets"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" !~ "10.89.3.0/24" "masquerade rule removed"
+}
+
 @test "$fw_driver - ipv6 bridge with static routes" {
     # add second interface and routes through that interface to test proper teardown
     run_in_container_netns ip link add type dummy
@@ -416,6 +432,147 @@ function setup() {
     test_port_fw hostip="127.0.0.1"
 }
 
+@test "$fw_driver - isolate networks" {
+    # isolate1: 10.89.0.2/24, fd90::2, isolate=true
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json setup $(get_container_netns_path)
+
+    # isolate2: 10.89.1.2/24, fd99::2, isolate=true
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    # isolate3: 10.89.2.2/24, fd92::2, isolate=strict
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate3.json setup $(get_container_netns_path 2)
+
+    run_in_host_netns firewall-cmd --ipset netavark_isolated --get-entries
+    assert "$output" =~ "10.89.0.0/24" "isolate1 subnet in the isolated ipset"
+    assert "$output" =~ "10.89.2.0/24" "isolate3 subnet in the isolated ipset"
+
+    run_in_host_netns firewall-cmd --policy netavark_isolation --list-rich-rules
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"10.89.0.0/24\" destination ipset=\"netavark_isolated\" drop" "normal isolation rule"
+    assert "$output" =~ "rule family=\"ipv6\" source address=\"fd92::/64\" destination ipset=\"netavark_networks6\" drop" "strict isolation rule"
+
+    # the rules must survive a firewalld reload
+    run_netavark_firewalld_reload
+    run_in_host_netns firewall-cmd --reload
+    sleep 1
+
+    # ping our own ip to make sure the ips work and there is no typo
+    run_in_container_netns ping -w 1 -c 1 10.89.0.2
+    run_in_container_netns 1 ping -w 1 -c 1 10.89.1.2
+    run_in_container_netns 2 ping -w 1 -c 1 10.89.2.2
+
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.2.2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.0.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 fd99::2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 fd90::2
+
+    run_netavark --file ${TESTSDIR}/testfiles/isolate3.json teardown $(get_container_netns_path 2)
+    run_in_host_netns firewall-cmd --ipset netavark_isolated --get-entries
+    assert "$output" "!~" "10.89.2.0/24" "isolate3 subnet removed from the isolated ipset"
+    run_in_host_netns firewall-cmd --policy netavark_isolation --list-rich-rules
+    assert "$output" "!~" "fd92::/64" "isolate3 rules removed"
+}
+
+@test "$fw_driver - isolation group" {
+    # isolategrp1: 10.89.4.2/24, fd94::2, isolate=group:web
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group1.json setup $(get_container_netns_path)
+
+    # isolategrp2: 10.89.5.2/24, fd95::2, isolate=group:web
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group2.json setup $(get_container_netns_path 1)
+
+    # isolate1: 10.89.0.2/24, fd90::2, isolate=true
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json setup $(get_container_netns_path 2)
+
+    run_in_host_netns firewall-cmd --ipset netavark_group_web --get-entries
+    assert "$output" =~ "10.89.4.0/24" "isolategrp1 subnet in the group ipset"
+    assert "$output" =~ "10.89.5.0/24" "isolategrp2 subnet in the group ipset"
+
+    run_in_host_netns firewall-cmd --policy netavark_isolation --list-rich-rules
+    assert "$output" =~ "rule priority=\"-1\" family=\"ipv4\" source address=\"10.89.4.0/24\" destination ipset=\"netavark_group_web\" accept" "group accept rule"
+    assert "$output" =~ "rule family=\"ipv6\" source address=\"fd94::/64\" destination ipset=\"netavark_networks6\" drop" "group drop rule"
+
+    run_in_container_netns ping -w 1 -c 1 10.89.5.2
+    run_in_container_netns 1 ping -w 1 -c 1 fd94::2
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.0.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.5.2
+
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group1.json teardown $(get_container_netns_path)
+    run_in_host_netns firewall-cmd --ipset netavark_group_web --get-entries
+    assert "$output" "!~" "10.89.4.0/24" "isolategrp1 subnet removed from the group ipset"
+    run_in_host_netns firewall-cmd --policy netavark_isolation --list-rich-rules
+    assert "$output" "!~" "10.89.4.0/24" "isolategrp1 rules removed"
+}
+
+@test "$fw_driver - egress allow" {
+    # 10.89.3.2, fd10:88:a::2, egress_allow=10.0.0.0/8,tcp:443@1.1.1.0/24,udp@fd00::/8
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-egress-allow.json setup $(get_container_netns_path)
+
+    run_in_host_netns firewall-cmd --policy netavark_egress --list-rich-rules
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"10.89.3.2\" destination address=\"10.0.0.0/8\" accept" "allowed subnet rule"
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"10.89.3.2\" destination address=\"1.1.1.0/24\" port port=\"443\" protocol=\"tcp\" accept" "allowed port rule"
+    assert "$output" =~ "rule family=\"ipv6\" source address=\"fd10:88:a::2\" destination address=\"fd00::/8\" protocol value=\"udp\" accept" "allowed protocol rule"
+    assert "$output" =~ "rule priority=\"1\" family=\"ipv4\" source address=\"10.89.3.2\" drop" "v4 drop rule"
+    assert "$output" =~ "rule priority=\"1\" family=\"ipv6\" source address=\"fd10:88:a::2\" drop" "v6 drop rule"
+
+    # the rules must survive a firewalld reload
+    run_netavark_firewalld_reload
+    run_in_host_netns firewall-cmd --reload
+    sleep 1
+    run_in_host_netns firewall-cmd --policy netavark_egress --list-rich-rules
+    assert "$output" =~ "source address=\"10.89.3.2\" drop" "drop rule after reload"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-egress-allow.json teardown $(get_container_netns_path)
+    run_in_host_netns firewall-cmd --policy netavark_egress --list-rich-rules
+    assert "$output" "!~" "10.89.3.2" "v4 egress rules removed"
+    assert "$output" "!~" "fd10:88:a::2" "v6 egress rules removed"
+}
+
+@test "$fw_driver - port forwarding with allowed sources" {
+    # 10.89.3.2, fd10:88:a::2, 8080 -> 80, allowed_sources=192.168.188.0/24,10.0.0.5/32,fd00::/8
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-allowed-sources.json setup $(get_container_netns_path)
+
+    run_in_host_netns firewall-cmd --policy netavark_portfwd --list-rich-rules
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"192.168.188.0/24\" forward-port port=\"8080\" protocol=\"tcp\" to-port=\"80\" to-addr=\"10.89.3.2\"" "v4 subnet source rule"
+    assert "$output" =~ "rule family=\"ipv4\" source address=\"10.0.0.5/32\" forward-port port=\"8080\" protocol=\"tcp\" to-port=\"80\" to-addr=\"10.89.3.2\"" "v4 address source rule"
+    assert "$output" =~ "rule family=\"ipv6\" source address=\"fd00::/8\" forward-port port=\"8080\" protocol=\"tcp\" to-port=\"80\" to-addr=\"fd10:88:a::2\"" "v6 source rule"
+    run_in_host_netns firewall-cmd --policy netavark_portfwd --list-forward-ports
+    assert "$output" == "" "no unrestricted forward ports"
+    run_in_host_netns firewall-cmd --policy netavark_host_fwd --list-rich-rules
+    assert "$output" == "" "no localhost rule without 127.0.0.1 source"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-allowed-sources.json teardown $(get_container_netns_path)
+    run_in_host_netns firewall-cmd --policy netavark_portfwd --list-rich-rules
+    assert "$output" == "" "source rules removed"
+}
+
+@test "$fw_driver - port forwarding with rate limit" {
+    # 10.88.0.14, 8080 -> 80, rate_limit=10
+    run_netavark --file <(jq 'del(.port_mappings[0].connection_limit)' ${TESTSDIR}/testfiles/bridge-port-limits.json) setup $(get_container_netns_path)
+
+    run_in_host_netns firewall-cmd --policy netavark_zone_acc --list-rich-rules
+    assert "$output" =~ "rule family=\"ipv4\" source NOT ipset=\"netavark_networks\" destination address=\"10.88.0.14\" port port=\"80\" protocol=\"tcp\" accept limit value=\"10/s\"" "rate limit rule"
+    assert "$output" =~ "rule priority=\"1\" family=\"ipv4\" source NOT ipset=\"netavark_networks\" destination address=\"10.88.0.14\" port port=\"80\" protocol=\"tcp\" drop" "rate limit drop rule"
+
+    run_netavark --file <(jq 'del(.port_mappings[0].connection_limit)' ${TESTSDIR}/testfiles/bridge-port-limits.json) teardown $(get_container_netns_path)
+    run_in_host_netns firewall-cmd --policy netavark_zone_acc --list-rich-rules
+    assert "$output" == "" "rate limit rules removed"
+}
+
+@test "$fw_driver - port forwarding with connection limit" {
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/bridge-port-limits.json setup $(get_container_netns_path)
+    assert_json ".error" "connection_limit for port 8080 is not supported by the firewalld driver" "connection_limit error message"
+}
+
+@test "$fw_driver - port forwarding with service" {
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/bridge-port-service.json setup $(get_container_netns_path)
+    assert_json ".error" "service for port 8080 is not supported by the firewalld driver" "service error message"
+}
+
 @test "netavark error - invalid host_ip in port mappings" {
     expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/invalid-port.json setup $(get_container_netns_path)
     assert_json ".error" "invalid host ip \"abcd\" provided for port 8080" "host ip error"
@@ -488,3 +645,22 @@ function strict_port_forwarding_invalid_value_should_warn_and_allow_port_forward
 @test "nftables - strict port forwarding invalid value should warn and allow port forwarding" {
     strict_port_forwarding_invalid_value_should_warn_and_allow_port_forwarding nftables
 }
+
+@test "$fw_driver - firewall render" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json firewall render
+    assert "$output" =~ "org.fedoraproject.FirewallD1.zone.changeZoneOfSource\(\"netavark_zone\", \"10.88.0.0/16\"\)" "zone source rendered"
+    assert "$output" =~ "org.fedoraproject.FirewallD1.policy.setPolicySettings\(\"netavark_portfwd\"" "port forward policy rendered"
+
+    # nothing must have been applied
+    run_in_host_netns firewall-cmd --get-zone-of-source=10.88.0.0/16
+    assert "$output" !~ "netavark_zone" "subnet must not be in the zone"
+
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - routed driver is rejected" {
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman.driver = "routed" | .network_info.podman.network_interface = "nvr1" | del(.network_info.podman.subnets[].gateway)' ${TESTSDIR}/testfiles/simplebridge.json) setup $(get_container_netns_path)
+    assert_json ".error" "the routed driver is not supported by the firewalld firewall driver" "routed driver error"
+    run_in_host_netns ip -o link show
+    assert "$output" !~ "nvr1_" "no host veth created"
+}
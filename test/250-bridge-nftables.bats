@@ -867,6 +867,95 @@ EOF
     assert "${#lines[@]}" = 5 "too many NETAVARK-ISOLATION-3 rules after teardown"
 }
 
+@test "$fw_driver - isolation group" {
+    # isolategrp1: 10.89.4.2/24, fd94::2, isolate=group:web
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group1.json setup $(get_container_netns_path)
+
+    # isolategrp2: 10.89.5.2/24, fd95::2, isolate=group:web
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group2.json setup $(get_container_netns_path 1)
+
+    # isolate1: 10.89.0.2/24, fd90::2, isolate=true
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json setup $(get_container_netns_path 2)
+
+    run_in_host_netns nft list set inet netavark nv_isolation_group_web
+    assert "$output" =~ "type ifname" "group set type"
+    assert "$output" =~ "elements = \{ \"isolategrp1\", \"isolategrp2\" \}" "group set members"
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-1
+    assert "${lines[2]}" =~ "iifname \"isolategrp1\" oifname != @nv_isolation_group_web jump NETAVARK-ISOLATION-3" "isolategrp1 network ISOLATION1 chain"
+    assert "${lines[3]}" =~ "iifname \"isolategrp2\" oifname != @nv_isolation_group_web jump NETAVARK-ISOLATION-3" "isolategrp2 network ISOLATION1 chain"
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-2
+    assert "${lines[2]}" =~ "oifname \"isolategrp1\" drop" "isolategrp1 network ISOLATION2 chain"
+    assert "${lines[3]}" =~ "oifname \"isolategrp2\" drop" "isolategrp2 network ISOLATION2 chain"
+
+    # the networks in the group can reach each other
+    run_in_container_netns ping -w 1 -c 1 10.89.5.2
+    run_in_container_netns 1 ping -w 1 -c 1 10.89.4.2
+    run_in_container_netns ping -w 1 -c 1 fd95::2
+    run_in_container_netns 1 ping -w 1 -c 1 fd94::2
+
+    # but not the network outside of the group and the other way around
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.0.2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 fd90::2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.4.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 fd95::2
+
+    # the set is kept until the last network of the group is removed
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group1.json teardown $(get_container_netns_path)
+    run_in_host_netns nft list set inet netavark nv_isolation_group_web
+    assert "$output" !~ "isolategrp1" "isolategrp1 removed from the group set"
+    assert "$output" =~ "isolategrp2" "isolategrp2 still in the group set"
+
+    run_netavark --file ${TESTSDIR}/testfiles/isolate-group2.json teardown $(get_container_netns_path 1)
+    expected_rc=1 run_in_host_netns nft list set inet netavark nv_isolation_group_web
+
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json teardown $(get_container_netns_path 2)
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-1
+    assert "${#lines[@]}" = 4 "too many NETAVARK-ISOLATION-1 rules after teardown"
+}
+
+@test "$fw_driver - egress allow" {
+    # 10.89.3.2, fd10:88:a::2, egress_allow=10.0.0.0/8,tcp:443@1.1.1.0/24,udp@fd00::/8
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-egress-allow.json setup $(get_container_netns_path)
+
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "$output" =~ "ip saddr 10.89.3.2 jump nv_ec79dd0c_10_89_3_2_egress" "forward chain jumps to v4 egress chain"
+    assert "$output" =~ "ip6 saddr fd10:88:a::2 jump nv_ec79dd0c_fd10-88-a--2_egress" "forward chain jumps to v6 egress chain"
+
+    run_in_host_netns nft list chain inet netavark nv_ec79dd0c_10_89_3_2_egress
+    assert "${lines[2]}" =~ "ct state established,related return" "established return rule"
+    assert "${lines[3]}" =~ "ip daddr 10.0.0.0/8 return" "allowed subnet rule"
+    assert "${lines[4]}" =~ "ip daddr 1.1.1.0/24 tcp dport 443 return" "allowed port rule"
+    assert "${lines[5]}" =~ "drop" "drop rule"
+    assert "${#lines[@]}" = 8 "too many v4 egress rules"
+
+    run_in_host_netns nft list chain inet netavark nv_ec79dd0c_fd10-88-a--2_egress
+    assert "${lines[3]}" =~ "ip6 daddr fd00::/8 meta l4proto udp return" "allowed protocol rule"
+    assert "${lines[4]}" =~ "drop" "drop rule"
+    assert "${#lines[@]}" = 7 "too many v6 egress rules"
+
+    # the rules must be restored from the stored config
+    run_in_host_netns nft flush ruleset
+    run_netavark firewall-reload
+    run_in_host_netns nft list chain inet netavark nv_ec79dd0c_10_89_3_2_egress
+    assert "${#lines[@]}" = 8 "v4 egress rules after firewall-reload"
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "$output" =~ "jump nv_ec79dd0c_10_89_3_2_egress" "forward chain jumps to egress chain after firewall-reload"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-egress-allow.json teardown $(get_container_netns_path)
+
+    expected_rc=1 run_in_host_netns nft list chain inet netavark nv_ec79dd0c_10_89_3_2_egress
+    expected_rc=1 run_in_host_netns nft list chain inet netavark nv_ec79dd0c_fd10-88-a--2_egress
+}
+
+@test "$fw_driver - egress allow invalid" {
+    expected_rc=1 run_netavark --file <(jq '.networks.podman1.options.egress_allow = "icmp@10.0.0.0/8"' ${TESTSDIR}/testfiles/bridge-egress-allow.json) setup $(get_container_netns_path)
+    assert_json ".error" "unable to parse \"egress_allow\": invalid protocol \"icmp\"" "egress_allow error message"
+}
+
 @test "$fw_driver - test read only /proc" {
     if [ -n "$_CONTAINERS_ROOTLESS_UID" ]; then
         skip "test only supported when run as real root"
@@ -1206,6 +1295,31 @@ net/ipv4/conf/podman1/rp_filter = 2"
     run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
 }
 
+@test "$fw_driver - test nftables-watch" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    check_simple_bridge_nftables
+
+    run_netavark_nftables_watch
+    # this run in the background so give it some time to subscribe
+    sleep 1
+
+    # the watcher restores the rules after a flush, once the events stopped
+    run_in_host_netns nft flush ruleset
+    sleep 2
+    check_simple_bridge_nftables
+
+    # and after the table is deleted
+    run_in_host_netns nft delete table inet netavark
+    sleep 2
+    check_simple_bridge_nftables
+
+    # our own teardown must not be undone
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    sleep 1
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "10.88.0.0/16" "subnet rules stay removed after teardown"
+}
+
 function check_simple_bridge_nftables() {
     # check nftables POSTROUTING chain
     run_in_host_netns nft list chain inet netavark POSTROUTING
@@ -1237,20 +1351,147 @@ function check_simple_bridge_nftables() {
 
     local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
     run_in_host_netns nft list chain inet netavark $chain
+    assert "${lines[2]}" =~ "ip saddr 10.88.0.0/16 jump NETAVARK-HOSTPORT-SETMARK" "hairpin mark rule"
+    assert "${lines[3]}" =~ "ip saddr 127.0.0.1 jump NETAVARK-HOSTPORT-SETMARK" "localhost mark rule"
+    assert "${lines[4]}" =~ "dnat ip to meta l4proto . ip daddr . th dport map @${chain}_to_addr : meta l4proto . ip daddr . th dport map @${chain}_to_port" "dnat map rule"
 
-    # extra check so we can be sure that these rules exists before checking later of they are removed
-    assert "$output" =~ "ip saddr 10.88.0.0/16 ip daddr 192.168.188.25 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip saddr 127.0.0.1 ip daddr 192.168.188.25 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip daddr 192.168.188.25 tcp dport 8080 dnat ip to 10.88.0.14:8080"
-    assert "$output" =~ "ip saddr 10.88.0.0/16 ip daddr 192.168.188.25 udp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip saddr 127.0.0.1 ip daddr 192.168.188.25 udp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip daddr 192.168.188.25 udp dport 8080 dnat ip to 10.88.0.14:8080"
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "meta l4proto . ip daddr . th dport @${chain}_ports jump $chain" "port set jump rule"
+
+    # extra check so we can be sure that these elements exists before checking later of they are removed
+    run_in_host_netns nft list set inet netavark ${chain}_ports
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080" "tcp port in port set"
+    assert "$output" =~ "udp . 192.168.188.25 . 8080" "udp port in port set"
+    run_in_host_netns nft list map inet netavark ${chain}_to_addr
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080 : 10.88.0.14" "tcp port in address map"
+    assert "$output" =~ "udp . 192.168.188.25 . 8080 : 10.88.0.14" "udp port in address map"
+    run_in_host_netns nft list map inet netavark ${chain}_to_port
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080 : 8080" "tcp port in port map"
+    assert "$output" =~ "udp . 192.168.188.25 . 8080 : 8080" "udp port in port map"
 
     run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json teardown $(get_container_netns_path)
 
     expected_rc=1 run_in_host_netns nft list chain inet netavark $chain
 }
 
+@test "$fw_driver - port forwarding overlapping host ips" {
+    local config=$(jq -c '.port_mappings = [
+        {"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"},
+        {"host_ip": "127.0.0.1", "container_port": 81, "host_port": 8080, "range": 1, "protocol": "tcp"}]' \
+        ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json)
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "overlapping port mappings for host port 8080/tcp with host ips 0.0.0.0 and 127.0.0.1" "overlap error"
+}
+
+@test "$fw_driver - port forwarding migrates legacy dnat rules" {
+    local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
+
+    # the rules of an older version, a jump and dnat rule per port
+    run_in_host_netns nft add table inet netavark
+    run_in_host_netns nft add chain inet netavark NETAVARK-HOSTPORT-SETMARK
+    run_in_host_netns nft add chain inet netavark NETAVARK-HOSTPORT-DNAT
+    run_in_host_netns nft add chain inet netavark $chain
+    run_in_host_netns nft add rule inet netavark $chain ip saddr 10.88.0.0/16 ip daddr 192.168.188.25 tcp dport 9080 jump NETAVARK-HOSTPORT-SETMARK
+    run_in_host_netns nft add rule inet netavark $chain ip daddr 192.168.188.25 tcp dport 9080 dnat ip to 10.88.0.20:80
+    run_in_host_netns nft add rule inet netavark $chain tcp dport 9090 dnat ip to 10.88.0.21:90
+    run_in_host_netns nft add rule inet netavark NETAVARK-HOSTPORT-DNAT ip daddr 192.168.188.25 tcp dport 9080 jump $chain
+    run_in_host_netns nft add rule inet netavark NETAVARK-HOSTPORT-DNAT tcp dport 9090 jump $chain
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    run_in_host_netns nft list chain inet netavark $chain
+    assert "$output" !~ "dport 9080" "legacy rules removed from the subnet dnat chain"
+    assert "$output" =~ "dnat ip to meta l4proto . ip daddr . th dport map @${chain}_to_addr" "dnat map rule"
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" !~ "tcp dport 9090 jump $chain" "legacy jump rule removed"
+
+    run_in_host_netns nft list set inet netavark ${chain}_ports
+    assert "$output" =~ "tcp . 192.168.188.25 . 9080" "legacy host ip port in port set"
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 9090" "legacy wildcard port in port set"
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080" "container port in port set"
+    run_in_host_netns nft list map inet netavark ${chain}_to_addr
+    assert "$output" =~ "tcp . 192.168.188.25 . 9080 : 10.88.0.20" "legacy port in address map"
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 9090 : 10.88.0.21" "legacy wildcard port in address map"
+    run_in_host_netns nft list map inet netavark ${chain}_to_port
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 9090 : 90" "legacy port in port map"
+}
+
+@test "$fw_driver - port forwarding with allocated host port" {
+    run_in_host_netns sysctl -w net.ipv4.ip_local_port_range="40000 40010"
+
+    local config=$(jq -c '.port_mappings = [
+        {"host_ip": "", "container_port": 80, "host_port": 0, "range": 1, "protocol": "tcp"},
+        {"host_ip": "", "container_port": 8000, "host_port": 0, "range": 3, "protocol": "tcp"},
+        {"host_ip": "", "container_port": 53, "host_port": 0, "range": 1, "protocol": "udp"}]' \
+        ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json)
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.port_mappings[0].host_port" "40000" "first free port allocated"
+    assert_json ".podman.port_mappings[1].host_port" "40001" "port block allocated after it"
+    assert_json ".podman.port_mappings[2].host_port" "40000" "udp port allocated independently"
+
+    local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
+    run_in_host_netns nft list map inet netavark ${chain}_to_port
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 40000 : 80" "allocated tcp port in port map"
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 40003 : 8002" "allocated port block in port map"
+    assert "$output" =~ "udp . 0.0.0.0/0 . 40000 : 53" "allocated udp port in port map"
+
+    # a second container does not get the ports of the first one
+    create_container_ns
+    local config2=$(jq -c '.container_id = "a2" | .networks.podman.static_ips = ["10.88.0.15"]' <<<"$config")
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json ".podman.port_mappings[0].host_port" "40004" "port used by the first container skipped"
+
+    # the range is exhausted now
+    create_container_ns
+    local config3=$(jq -c '.container_id = "a3" | .networks.podman.static_ips = ["10.88.0.16"]' <<<"$config")
+    expected_rc=1 run_netavark setup $(get_container_netns_path 2) <<<"$config3"
+    assert_json ".error" "no free host port for container port 8000/tcp in range 40000-40010" "no free port error"
+
+    # teardown restores the allocated ports from the firewall state
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns nft list map inet netavark ${chain}_to_port
+    assert "$output" !~ "40000 : 80" "allocated tcp port removed from port map"
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 40004 : 80" "second container port kept"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    expected_rc=1 run_in_host_netns nft list chain inet netavark $chain
+}
+
+@test "$fw_driver - port forwarding host port conflict" {
+    # 10.88.0.14, 192.168.188.25:8080 -> 8080/tcp and 192.168.188.25:8080 -> 8080/udp
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    # a second container with the same host port fails before touching the firewall
+    create_container_ns
+    local config2=$(jq -c '.container_id = "a2" | .networks.podman.static_ips = ["10.88.0.15"]' \
+        ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json ".error" "host port 8080/tcp is already used by container $(jq -r .container_id ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json) in network podman" "container conflict error"
+    run_in_host_netns nft list map inet netavark nv_2f259bab_10_88_0_0_nm16_dnat_to_addr
+    assert "$output" !~ "10.88.0.15" "no port forwarding for the second container"
+
+    # a different host ip does not conflict
+    local config3=$(jq -c '.port_mappings[].host_ip = "127.0.0.1"' <<<"$config2")
+    run_netavark setup $(get_container_netns_path 1) <<<"$config3"
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config3"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json teardown $(get_container_netns_path)
+
+    # a socket listening on the host port conflicts as well
+    nsenter -n -t $HOST_NS_PID python3 -c 'import socket, time
+s = socket.socket()
+s.bind(("0.0.0.0", 8080))
+s.listen()
+time.sleep(30)' &
+    local pid=$!
+    sleep 1
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+    assert_json ".error" "host port 8080/tcp is already used by a socket on the host bound to 0.0.0.0:8080" "socket conflict error"
+    kill $pid
+}
+
 # regression test for https://github.com/containers/netavark/issues/1129
 @test "$fw_driver - port firewall rule cleanup host ip" {
     run_netavark --file ${TESTSDIR}/testfiles/bridge-port-hostip.json setup $(get_container_netns_path)
@@ -1258,15 +1499,13 @@ function check_simple_bridge_nftables() {
     local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
     run_in_host_netns nft list chain inet netavark $chain
 
-    run_in_host_netns nft list ruleset
-
-    # extra check so we can be sure that these rules exists before checking later of they are removed
-    assert "$output" =~ "ip saddr 10.88.0.0/16 ip daddr 192.168.188.25 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip saddr 127.0.0.1 ip daddr 192.168.188.25 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip daddr 192.168.188.25 tcp dport 8080 dnat ip to 10.88.0.14:8080"
-    assert "$output" =~ "ip saddr 10.88.0.0/16 ip daddr 192.168.188.24 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip saddr 127.0.0.1 ip daddr 192.168.188.24 tcp dport 8080 jump NETAVARK-HOSTPORT-SETMARK"
-    assert "$output" =~ "ip daddr 192.168.188.24 tcp dport 8080 dnat ip to 10.88.0.14:8080"
+    # extra check so we can be sure that these elements exists before checking later of they are removed
+    run_in_host_netns nft list set inet netavark ${chain}_ports
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080" "first host ip in port set"
+    assert "$output" =~ "tcp . 192.168.188.24 . 8080" "second host ip in port set"
+    run_in_host_netns nft list map inet netavark ${chain}_to_addr
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080 : 10.88.0.14" "first host ip in address map"
+    assert "$output" =~ "tcp . 192.168.188.24 . 8080 : 10.88.0.14" "second host ip in address map"
 
     run_netavark --file ${TESTSDIR}/testfiles/bridge-port-hostip.json teardown $(get_container_netns_path)
 
@@ -1275,6 +1514,125 @@ function check_simple_bridge_nftables() {
     assert "$output" == $'table inet netavark {\n\tchain NETAVARK-HOSTPORT-DNAT {\n\t}\n}' "NETAVARK-HOSTPORT-DNAT chain must be empty"
 }
 
+@test "$fw_driver - port forwarding with allowed sources" {
+    # 10.89.3.2, fd10:88:a::2, 8080 -> 80, allowed_sources=192.168.188.0/24,10.0.0.5/32,fd00::/8
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-allowed-sources.json setup $(get_container_netns_path)
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "ip saddr 192.168.188.0/24 tcp dport 8080 jump nv_ec79dd0c_10_89_3_0_nm24_dnat" "v4 subnet source rule"
+    assert "$output" =~ "ip saddr 10.0.0.5 tcp dport 8080 jump nv_ec79dd0c_10_89_3_0_nm24_dnat" "v4 address source rule"
+    assert "$output" =~ "ip6 saddr fd00::/8 tcp dport 8080 jump nv_ec79dd0c_fd10-88-a--_nm64_dnat" "v6 source rule"
+    assert "$output" "!~" $'\ttcp dport 8080 jump' "no unrestricted jump rule"
+
+    # the port must only be reachable through the source rules
+    run_in_host_netns nft list set inet netavark nv_ec79dd0c_10_89_3_0_nm24_dnat_ports
+    assert "$output" !~ "8080" "no unrestricted port set element"
+    run_in_host_netns nft list map inet netavark nv_ec79dd0c_10_89_3_0_nm24_dnat_to_addr
+    assert "$output" =~ "tcp . 0.0.0.0/0 . 8080 : 10.89.3.2" "wildcard host ip in address map"
+
+    # the sources must be restored from the stored config
+    run_in_host_netns nft flush ruleset
+    run_netavark firewall-reload
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "ip saddr 192.168.188.0/24 tcp dport 8080 jump nv_ec79dd0c_10_89_3_0_nm24_dnat" "v4 source rule after firewall-reload"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-allowed-sources.json teardown $(get_container_netns_path)
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" == $'table inet netavark {\n\tchain NETAVARK-HOSTPORT-DNAT {\n\t}\n}' "NETAVARK-HOSTPORT-DNAT chain must be empty"
+}
+
+@test "$fw_driver - port forwarding with limits" {
+    # 10.88.0.14, 8080 -> 80, rate_limit=10, connection_limit=100
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-limits.json setup $(get_container_netns_path)
+
+    local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
+    run_in_host_netns nft list chain inet netavark $chain
+    assert "${lines[2]}" =~ "tcp dport 8080 limit rate over 10/second burst 10 packets drop" "rate limit rule"
+    assert "${lines[3]}" =~ "tcp dport 8080 ct count over 100 drop" "connection limit rule"
+
+    # the limits must be restored from the stored config
+    run_in_host_netns nft flush ruleset
+    run_netavark firewall-reload
+    run_in_host_netns nft list chain inet netavark $chain
+    assert "${lines[2]}" =~ "tcp dport 8080 limit rate over 10/second burst 10 packets drop" "rate limit rule after firewall-reload"
+
+    # reloading over the existing rules must not add them twice
+    run_netavark firewall-reload
+    run_in_host_netns nft list chain inet netavark $chain
+    assert "$(grep -c 'limit rate over' <<<"$output")" == 1 "rate limit rule added once"
+    assert "$(grep -c 'ct count over' <<<"$output")" == 1 "connection limit rule added once"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-limits.json teardown $(get_container_netns_path)
+
+    expected_rc=1 run_in_host_netns nft list chain inet netavark $chain
+}
+
+@test "netavark error - invalid port limits" {
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].rate_limit = 0' ${TESTSDIR}/testfiles/bridge-port-limits.json) setup $(get_container_netns_path)
+    assert_json ".error" "invalid rate_limit 0 for port 8080, must be greater than 0" "rate_limit error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].connection_limit = 0' ${TESTSDIR}/testfiles/bridge-port-limits.json) setup $(get_container_netns_path)
+    assert_json ".error" "invalid connection_limit 0 for port 8080, must be greater than 0" "connection_limit error message"
+}
+
+@test "$fw_driver - port forwarding with service" {
+    # 10.88.0.14 and 10.88.0.15, 8080 -> 80, service=web
+    local config2=$(jq '.container_id = "aa3c2e19b2ac5f0bb0e2c3a6a0f8d9d79a6e9ac4d02a0f2c5b0f6b8e6b7e1c44" | .networks.podman.static_ips = ["10.88.0.15"]' \
+        ${TESTSDIR}/testfiles/bridge-port-service.json)
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-service.json setup $(get_container_netns_path)
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "tcp dport 8080 jump nv_svc_web" "jump to service chain"
+    run_in_host_netns nft list set inet netavark nv_2f259bab_10_88_0_0_nm16_dnat_ports
+    assert "$output" !~ "8080" "no per container port element"
+
+    run_in_host_netns nft list chain inet netavark nv_svc_web
+    assert "${lines[2]}" =~ "ip saddr 10.88.0.0/16 jump NETAVARK-HOSTPORT-SETMARK" "service hairpin rule"
+    assert "${lines[3]}" =~ "ip saddr 127.0.0.1 jump NETAVARK-HOSTPORT-SETMARK" "service localhost rule"
+    assert "${lines[4]}" =~ "meta nfproto ipv4 dnat ip to numgen inc mod 2 map { 0 : 10.88.0.14, 1 : 10.88.0.15 }:80" "service backend map"
+
+    # the services must be restored from the stored config
+    run_in_host_netns nft flush ruleset
+    run_netavark firewall-reload
+    run_in_host_netns nft list chain inet netavark nv_svc_web
+    assert "${lines[4]}" =~ "meta nfproto ipv4 dnat ip to numgen inc mod 2 map { 0 : 10.88.0.14, 1 : 10.88.0.15 }:80" "service backend map after firewall-reload"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-service.json teardown $(get_container_netns_path)
+
+    run_in_host_netns nft list chain inet netavark nv_svc_web
+    assert "${lines[4]}" =~ "meta nfproto ipv4 dnat ip to numgen inc mod 1 map { 0 : 10.88.0.15 }:80" "service backend map with one backend"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+
+    expected_rc=1 run_in_host_netns nft list chain inet netavark nv_svc_web
+    run_in_host_netns nft list chain inet netavark NETAVARK-HOSTPORT-DNAT
+    assert "$output" !~ "nv_svc_web" "jump to service chain removed"
+}
+
+@test "netavark error - invalid port service" {
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].service = "web service"' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" 'invalid service name "web service", must be 1-32 characters of [a-zA-Z0-9_-]' "service name error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].range = 2' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" "port range is not supported for service web" "service range error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].service_balance = "random" | del(.port_mappings[0].service)' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" "service_balance for port 8080 requires a service" "service_balance error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].allowed_sources = ["10.0.0.0/8"]' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" "allowed_sources is not supported for service web" "service allowed_sources error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].rate_limit = 10' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" "rate_limit is not supported for service web" "service rate_limit error message"
+
+    expected_rc=1 run_netavark --file <(jq '.port_mappings[0].connection_limit = 10' ${TESTSDIR}/testfiles/bridge-port-service.json) setup $(get_container_netns_path)
+    assert_json ".error" "connection_limit is not supported for service web" "service connection_limit error message"
+}
+
 @test "$fw_driver - bridge with outbound addr4" {
     run_netavark --file ${TESTSDIR}/testfiles/bridge-outbound-addr4.json setup $(get_container_netns_path)
 
@@ -1301,6 +1659,39 @@ function check_simple_bridge_nftables() {
     expected_rc=1 run_in_host_netns nft list chain inet netavark nv_2f259bab_fd10-88-a--_nm64
 }
 
+@test "$fw_driver - bridge without ipv6 nat" {
+    run_in_host_netns ip link add uplink0 type dummy
+    run_in_host_netns ip link set uplink0 up
+
+    local config=$(jq -c '.network_info.podman1.options = {"ipv6_nat": "false", "ipv6_ndp_proxy": "uplink0"}' \
+        ${TESTSDIR}/testfiles/ipv6-bridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # the subnet chain only accepts the traffic to the subnet
+    run_in_host_netns nft list chain inet netavark nv_ec79dd0c_fd10-88-a--_nm64
+    assert "${lines[2]}" =~ "ip6 daddr fd10:88:a::/64 accept" "accept rule"
+    assert "$output" !~ "masquerade" "no masquerade rule"
+
+    # forwarding rules still exist
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "$output" =~ "ip6 saddr fd10:88:a::/64 accept" "forward rule"
+
+    run_in_host_netns ip -6 neigh show proxy dev uplink0
+    assert "$output" =~ "fd10:88:a::2" "ndp proxy entry for the container"
+    run_in_host_netns cat /proc/sys/net/ipv6/conf/uplink0/proxy_ndp
+    assert "$output" == "1" "proxy_ndp enabled on the uplink"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -6 neigh show proxy dev uplink0
+    assert "$output" !~ "fd10:88:a::2" "ndp proxy entry removed"
+}
+
+@test "netavark error - ipv6_nat=false with outbound_addr6" {
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman1.options.ipv6_nat = "false"' ${TESTSDIR}/testfiles/bridge-outbound-addr6.json) setup $(get_container_netns_path)
+    assert_json ".error" "outbound_addr6 cannot be used with ipv6_nat=false" "ipv6_nat error message"
+}
+
 @test "$fw_driver - aardvark-dns error cleanup" {
     expected_rc=1 run_netavark -a /usr/bin/false --file ${TESTSDIR}/testfiles/dualstack-bridge-custom-dns-server.json setup $(get_container_netns_path)
     assert_json ".error" "error while applying dns entries: aardvark-dns exited unexpectedly without error message" "aardvark-dns error"
@@ -1344,3 +1735,203 @@ EOF
     PATH="$NETAVARK_TMPDIR:$PATH" expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
     assert_json ".error" 'nftables error: "nft" did not return successfully while getting the current ruleset: nft custom error message' "error message from nft is included"
 }
+
+@test "$fw_driver - host-local ipam allocation" {
+    # drop the static ips so netavark has to allocate them
+    local config=$(jq -c 'del(.networks.podman.static_ips)' ${TESTSDIR}/testfiles/simplebridge.json)
+    local state_file="$NETAVARK_TMPDIR/config/ipam/53ce4390f2adb1681eb1a90ec8b48c49c015e0a8d336c197637e7f65e365fa9e.json"
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.2/16" "first free address allocated"
+
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" '.[].addr_info[] | select(.family=="inet") | .local' == "10.88.0.2" "container address"
+
+    run_helper jq -r '.allocations."10.88.0.2"' "$state_file"
+    assert "$output" == "6ce776ea58b5" "allocation stored"
+
+    # a second container gets the next address
+    create_container_ns
+    local config2=$(jq -c '.container_id = "1234567890ab"' <<<"$config")
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.3/16" "second address allocated"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_helper jq -r '.allocations | keys | join(",")' "$state_file"
+    assert "$output" == "10.88.0.3" "first address released"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    expected_rc=2 run_helper ls "$state_file"
+}
+
+@test "$fw_driver - host-local ipam lease range" {
+    local config=$(jq -c 'del(.networks.podman.static_ips) |
+        .network_info.podman.subnets[0].lease_range = {"start_ip": "10.88.0.100", "end_ip": "10.88.0.100"}' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.100/16" "address from the lease range"
+
+    create_container_ns
+    local config2=$(jq -c '.container_id = "1234567890ab"' <<<"$config")
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json ".error" "no free ip addresses left in subnet 10.88.0.0/16" "lease range exhausted"
+
+    # the failed setup must not leak an allocation
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.100/16" "address reused"
+}
+
+@test "$fw_driver - firewall render" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json firewall render
+    local rendered="$output"
+
+    # every line is a ruleset in the nftables json format
+    run_helper jq -s 'length' <<<"$rendered"
+    assert "$output" == "2" "network and port forward rulesets"
+
+    local chain="nv_2f259bab_10_88_0_0_nm16_dnat"
+    assert "$rendered" =~ "\"add\":\{\"chain\":\{\"family\":\"inet\",\"table\":\"netavark\",\"name\":\"$chain\"" "dnat chain rendered"
+    assert "$rendered" =~ "\"name\":\"${chain}_to_addr\",\"elem\":" "port element rendered"
+    assert "$rendered" =~ "10.88.0.14" "container ip rendered"
+
+    # nothing must have been applied
+    run_in_host_netns nft list tables
+    assert "$output" !~ "netavark" "netavark table must not exist"
+
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - firewall check" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    run_netavark firewall check
+    assert "$output" == "" "no differences after setup"
+
+    local net_id="2f259bab93aaaaa2542ba43ef33eb990d0999ee1b9924b557b7be53c0b7a1bb9"
+    local chain="nv_2f259bab_10_88_0_0_nm16"
+
+    # change the rules behind netavark's back
+    run_in_host_netns nft flush chain inet netavark $chain
+    run_in_host_netns nft delete element inet netavark ${chain}_dnat_to_addr \{ tcp . 192.168.188.25 . 8080 \}
+    run_in_host_netns nft add rule inet netavark FORWARD ip saddr 10.99.0.0/16 accept
+
+    expected_rc=1 run_netavark firewall check
+    assert "$output" =~ "missing for network $net_id: \{\"rule\":\{\"family\":\"inet\",\"table\":\"netavark\",\"chain\":\"$chain\"" "missing subnet rule reported"
+    assert "$output" =~ "missing for container f922ffdda5718b26ea585a500d5ad05191da5461b06d6f62e4d1f66ca901a253 in network $net_id: \{\"element\":\{\"family\":\"inet\",\"table\":\"netavark\",\"name\":\"${chain}_dnat_to_addr\"" "missing port element reported"
+    assert "$output" =~ "extra: \{\"rule\":\{\"family\":\"inet\",\"table\":\"netavark\",\"chain\":\"FORWARD\"" "extra rule reported"
+    assert "$output" =~ "10.99.0.0" "extra rule subnet"
+
+    run_netavark firewall check --repair
+
+    run_in_host_netns nft list chain inet netavark $chain
+    assert "$output" =~ "ip daddr 10.88.0.0/16 accept" "subnet rule repaired"
+    run_in_host_netns nft list map inet netavark ${chain}_dnat_to_addr
+    assert "$output" =~ "tcp . 192.168.188.25 . 8080 : 10.88.0.14" "port element repaired"
+
+    # extra rules are only reported, never removed
+    expected_rc=1 run_netavark firewall check
+    assert "$output" !~ "missing for" "nothing missing after repair"
+    assert "$output" =~ "extra: " "extra rule still reported"
+
+    # a rule before the drop of invalid connections
+    run_in_host_netns nft insert rule inet netavark FORWARD jump NETAVARK-ISOLATION-1
+    expected_rc=1 run_netavark firewall check
+    assert "$output" =~ "wrong order: chain FORWARD" "misordered chain reported"
+
+    run_netavark firewall check --repair
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "${lines[3]}" =~ "ct state invalid drop" "chain rebuilt in order"
+    assert "$output" =~ "ip saddr 10.99.0.0/16 accept" "extra rule kept"
+
+    expected_rc=1 run_netavark firewall check
+    assert "$output" !~ "wrong order" "order repaired"
+}
+
+@test "$fw_driver - flush conntrack entries on port forward teardown" {
+    command -v conntrack >/dev/null || skip "test requires the conntrack tool"
+
+    local config=$(jq -c '.port_mappings = [{"host_ip": "", "container_port": 8080, "host_port": 8080, "range": 1, "protocol": "udp"}]' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_connection_test "0" udp 8080 10.88.0.1 8080
+
+    run_in_host_netns conntrack -L -p udp --orig-port-dst 8080 --reply-src 10.88.0.2
+    assert "$output" =~ "dport=8080" "conntrack entry of the forwarded flow"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns conntrack -L -p udp --orig-port-dst 8080 --reply-src 10.88.0.2
+    assert "$output" !~ "dport=8080" "conntrack entry must be removed"
+}
+
+@test "$fw_driver - bridge with offload" {
+    # the flowtable needs an uplink device with a default route
+    run_in_host_netns ip link add uplink0 type dummy
+    run_in_host_netns ip link set uplink0 up
+    run_in_host_netns ip addr add 192.0.2.1/24 dev uplink0
+    run_in_host_netns ip route add default via 192.0.2.254
+
+    local config=$(jq -c '.network_info.podman.options.offload = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns nft list flowtable inet netavark NETAVARK-FLOWTABLE
+    assert "$output" =~ "hook ingress priority filter" "flowtable hook"
+    assert "$output" =~ "podman0" "bridge in flowtable devices"
+    assert "$output" =~ "uplink0" "uplink in flowtable devices"
+
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "$output" =~ "jump NETAVARK-OFFLOAD" "jump to offload chain"
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-OFFLOAD
+    assert "${lines[2]}" =~ 'ip saddr 10.88.0.0/16 iifname "podman0" oifname "uplink0" ct state established flow add @NETAVARK-FLOWTABLE' "offload rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_in_host_netns nft list flowtable inet netavark NETAVARK-FLOWTABLE
+    run_in_host_netns nft list chain inet netavark FORWARD
+    assert "$output" !~ "NETAVARK-OFFLOAD" "jump to offload chain removed"
+}
+
+@test "netavark error - invalid isolation group" {
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman.options.isolate = "group:this-name-is-too-long"' ${TESTSDIR}/testfiles/simplebridge.json) setup $(get_container_netns_path)
+    assert_json ".error" "invalid isolation group \"this-name-is-too-long\", must be 1-15 characters of [a-zA-Z0-9_-]" "invalid isolation group"
+}
+
+@test "netavark error - invalid offload option" {
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman.options.offload = "yes"' ${TESTSDIR}/testfiles/simplebridge.json) setup $(get_container_netns_path)
+    assert_json ".error" "unable to parse \"offload\": provided string was not \`true\` or \`false\`" "invalid offload option"
+}
+
+@test "$fw_driver - bridge with egress limit" {
+    local config=$(jq -c '.network_info.podman.options.egress_rate = "10mbit"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns tc filter show dev veth0 ingress
+    assert "$output" =~ "matchall" "matchall filter on host veth"
+    assert "$output" =~ "police .* rate 10Mbit burst .* action drop" "police action on host veth"
+    run_in_container_netns tc qdisc show dev eth0
+    assert "$output" !~ "tbf" "no qdisc in the container netns"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns tc filter show dev veth0 ingress
+
+    # the per container options take precedence
+    config=$(jq -c '.networks.podman.options = {"egress_rate": "1mbit", "egress_burst": "32kb"}' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns tc filter show dev veth0 ingress
+    assert "$output" =~ "police .* rate 1Mbit burst 32Kb" "police action with container options"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
+@test "netavark error - invalid egress limit" {
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman.options.egress_rate = "10mbps"' ${TESTSDIR}/testfiles/simplebridge.json) setup $(get_container_netns_path)
+    assert_json ".error" "unable to parse \"egress_rate\": invalid rate unit \"mbps\"" "invalid egress_rate"
+
+    expected_rc=1 run_netavark --file <(jq '.network_info.podman.options.egress_burst = "64kb"' ${TESTSDIR}/testfiles/simplebridge.json) setup $(get_container_netns_path)
+    assert_json ".error" "egress_burst requires egress_rate" "egress_burst without egress_rate"
+}
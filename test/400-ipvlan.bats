@@ -127,6 +127,17 @@ function setup() {
     assert_json "$result" ".podman.interfaces.eth0.subnets[0].ipnet" "==" "$ipaddr/16" "Result contains correct IP address"
 }
 
+@test "ipvlan setup with egress limit" {
+    local config=$(jq -c '.network_info.podman.options.egress_rate = "10mbit"' ${TESTSDIR}/testfiles/ipvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns tc qdisc show dev eth0
+    assert "$output" =~ "qdisc tbf .* root .* rate 10Mbit burst 64Kb lat 25ms" "tbf qdisc on container interface"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
 @test "ipvlan modes" {
     for mode in l2 l3 l3s; do
         # echo here so we know which test failed
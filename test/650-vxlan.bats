@@ -0,0 +1,112 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# vxlan driver tests
+#
+
+load helpers
+
+# accepts the remote peers (comma separated) as first arg
+function createVxlanConfig() {
+    local remote=$1
+
+    read -r -d '\0' config <<EOF
+{
+  "container_id": "6ce776ea58b5",
+  "container_name": "testcontainer",
+  "networks": {
+    "podman1": {
+      "static_ips": [
+        "10.88.0.2"
+      ],
+      "interface_name": "eth0"
+    }
+  },
+  "network_info": {
+    "podman1": {
+      "name": "podman1",
+      "id": "ed82e3a703682a9c09629d3cf45c1f1e7da5b32aeff3faf82837ef4d005356e6",
+      "driver": "vxlan",
+      "network_interface": "podman1",
+      "subnets": [
+        {
+          "gateway": "10.88.0.1",
+          "subnet": "10.88.0.0/16"
+        }
+      ],
+      "ipv6_enabled": false,
+      "internal": false,
+      "dns_enabled": false,
+      "ipam_options": {
+        "driver": "host-local"
+      },
+      "options": {
+        "vni": "42",
+        "mtu": "1450",
+        "vxlan_remote": "$remote"
+      }
+    }
+  }
+}\0
+EOF
+
+echo "$config"
+}
+
+@test "vxlan - setup and teardown" {
+    local config=$(createVxlanConfig "192.168.100.2,192.168.100.3")
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j --details link show vxlan42
+    link_info="$output"
+    assert_json "$link_info" '.[].flags[] | select(.=="UP")' == "UP" "vxlan interface is up"
+    assert_json "$link_info" '.[].linkinfo.info_data.id' == "42" "vni is set"
+    assert_json "$link_info" '.[].linkinfo.info_data.port' == "4789" "default port is used"
+    assert_json "$link_info" '.[].master' == "podman1" "vxlan is attached to the bridge"
+    assert_json "$link_info" '.[].mtu' == "1450" "vxlan mtu"
+
+    run_in_host_netns bridge -j fdb show dev vxlan42
+    assert_json "$output" '[.[] | select(.mac=="00:00:00:00:00:00") | .dst] | sort' == '[
+  "192.168.100.2",
+  "192.168.100.3"
+]' "remote peers configured"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_in_host_netns ip link show vxlan42
+    expected_rc=1 run_in_host_netns ip link show podman1
+}
+
+@test "vxlan - missing vni" {
+    local config=$(createVxlanConfig "192.168.100.2")
+    config=$(jq -c 'del(.network_info.podman1.options.vni)' <<<"$config")
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "vxlan driver requires the vni option" "vni required"
+}
+
+@test "vxlan - default mtu from the lower device" {
+    run_in_host_netns ip link add dummy0 mtu 9000 type dummy
+    run_in_host_netns ip link set dummy0 up
+
+    local config=$(createVxlanConfig "192.168.100.2")
+    config=$(jq -c 'del(.network_info.podman1.options.mtu) | .network_info.podman1.options.vxlan_dev = "dummy0"' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j link show vxlan42
+    assert_json "$output" '.[].mtu' == "8950" "vxlan mtu with ipv4 underlay"
+    run_in_host_netns ip -j link show podman1
+    assert_json "$output" '.[].mtu' == "8950" "bridge mtu matches the vxlan mtu"
+    run_in_container_netns ip -j link show eth0
+    assert_json "$output" '.[].mtu' == "8950" "container mtu matches the vxlan mtu"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq -c '.network_info.podman1.options.vxlan_remote = "fd00::2"' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j link show vxlan42
+    assert_json "$output" '.[].mtu' == "8930" "vxlan mtu with ipv6 underlay"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
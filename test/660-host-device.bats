@@ -0,0 +1,95 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# host-device driver tests
+#
+
+load helpers
+
+function setup() {
+    basic_setup
+
+    # create a extra interface which we can move into the container
+    run_in_host_netns ip link add dummy0 mtu 1400 type dummy
+}
+
+function createHostDeviceConfig() {
+    local mtu=$1
+
+    read -r -d '\0' config <<EOF
+{
+  "container_id": "6ce776ea58b5",
+  "container_name": "testcontainer",
+  "networks": {
+    "podman1": {
+      "static_ips": [
+        "10.88.0.2"
+      ],
+      "interface_name": "eth0"
+    }
+  },
+  "network_info": {
+    "podman1": {
+      "name": "podman1",
+      "id": "ed82e3a703682a9c09629d3cf45c1f1e7da5b32aeff3faf82837ef4d005356e6",
+      "driver": "host-device",
+      "network_interface": "dummy0",
+      "subnets": [
+        {
+          "gateway": "10.88.0.1",
+          "subnet": "10.88.0.0/16"
+        }
+      ],
+      "ipv6_enabled": false,
+      "internal": false,
+      "dns_enabled": false,
+      "ipam_options": {
+        "driver": "host-local"
+      },
+      "options": {
+        "mtu": "$mtu"
+      }
+    }
+  }
+}\0
+EOF
+
+echo "$config"
+}
+
+@test "host-device - setup and teardown" {
+    local config=$(createHostDeviceConfig 1300)
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+
+    expected_rc=1 run_in_host_netns ip link show dummy0
+
+    mac=$(jq -r '.podman1.interfaces.eth0.mac_address' <<< "$result" )
+    run_in_container_netns ip -j --details link show eth0
+    link_info="$output"
+    assert_json "$link_info" ".[].address" "==" "$mac" "MAC matches container mac"
+    assert_json "$link_info" '.[].flags[] | select(.=="UP")' "==" "UP" "Container interface is up"
+    assert_json "$link_info" ".[].linkinfo.info_kind" "==" "dummy" "Container interface is the host device"
+    assert_json "$link_info" ".[].mtu" "==" "1300" "Container interface mtu"
+
+    run_in_container_netns ip addr show eth0
+    assert "$output" "=~" "10.88.0.2/16" "IP address matches container address"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_in_container_netns ip link show eth0
+    run_in_host_netns ip -j link show dummy0
+    assert_json "$output" ".[].mtu" "==" "1400" "original mtu restored"
+    assert_json "$output" '.[].flags | index("UP")' "==" "null" "device left down as before setup"
+}
+
+@test "host-device - teardown restores up state" {
+    run_in_host_netns ip link set dummy0 up
+    local config=$(createHostDeviceConfig 1300)
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j link show dummy0
+    assert_json "$output" '.[].flags[] | select(.=="UP")' "==" "UP" "device up as before setup"
+}
@@ -0,0 +1,148 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# routed driver tests
+#
+
+load helpers
+
+export NETAVARK_FW=nftables
+
+function createRoutedConfig() {
+    read -r -d '\0' config <<EOF
+{
+  "container_id": "6ce776ea58b5",
+  "container_name": "testcontainer",
+  "networks": {
+    "podman1": {
+      "static_ips": [
+        "10.88.0.2",
+        "fd10:88:a::2"
+      ],
+      "interface_name": "eth0"
+    }
+  },
+  "network_info": {
+    "podman1": {
+      "name": "podman1",
+      "id": "ed82e3a703682a9c09629d3cf45c1f1e7da5b32aeff3faf82837ef4d005356e6",
+      "driver": "routed",
+      "network_interface": "nvr1",
+      "subnets": [
+        {
+          "subnet": "10.88.0.0/16"
+        },
+        {
+          "subnet": "fd10:88:a::/64"
+        }
+      ],
+      "ipv6_enabled": true,
+      "internal": false,
+      "dns_enabled": false,
+      "ipam_options": {
+        "driver": "host-local"
+      }
+    }
+  }
+}\0
+EOF
+
+echo "$config"
+}
+
+@test "routed - setup and teardown" {
+    local config=$(createRoutedConfig)
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" ".podman1.interfaces.eth0.subnets[0].ipnet" == "10.88.0.2/32" "host address reported"
+    assert_json "$result" ".podman1.interfaces.eth0.subnets[0].gateway" == "169.254.1.1" "link local gateway reported"
+
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" '.[].addr_info[] | select(.family=="inet") | .prefixlen' == "32" "container ipv4 has a host prefix"
+    assert_json "$output" '.[].addr_info[] | select(.local=="fd10:88:a::2") | .prefixlen' == "128" "container ipv6 has a host prefix"
+
+    run_in_container_netns ip -j route show default
+    assert_json "$output" '.[0].gateway' == "169.254.1.1" "ipv4 default route via link local gateway"
+    run_in_container_netns ip -j -6 route show default
+    assert_json "$output" '.[0].gateway' == "fe80::1" "ipv6 default route via link local gateway"
+
+    # no bridge, only the host side of the veth pair
+    run_in_host_netns ip -j link show type bridge
+    assert "$output" == "[]" "no bridge created"
+    run_in_host_netns ip -j link show type veth
+    host_veth=$(jq -r '.[].ifname' <<<"$output")
+    assert "$host_veth" =~ "^nvr1_[0-9a-f]{8}$" "host veth name"
+
+    run_in_host_netns ip -j route show 10.88.0.2
+    assert_json "$output" '.[0].dev' == "$host_veth" "host route to the container"
+    run_in_host_netns ip -j -6 route show fd10:88:a::2
+    assert_json "$output" '.[0].dev' == "$host_veth" "host ipv6 route to the container"
+    run_in_host_netns ip -6 neigh show proxy dev $host_veth
+    assert "$output" =~ "fe80::1" "ndp proxy entry for the gateway"
+
+    run_in_host_netns cat /proc/sys/net/ipv4/conf/$host_veth/proxy_arp
+    assert "$output" == "1" "proxy arp enabled"
+
+    run_in_host_netns ping -c 1 10.88.0.2
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-3
+    assert "$output" =~ 'oifname "nvr1_\*" drop' "isolation rule matches all host veths"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_in_container_netns ip link show eth0
+    expected_rc=1 run_in_host_netns ip link show $host_veth
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-3
+    assert "$output" "!~" 'nvr1_' "isolation rule removed"
+}
+
+@test "routed - dhcp is not supported" {
+    local config=$(createRoutedConfig)
+    config=$(jq -c '.network_info.podman1.ipam_options.driver = "dhcp"' <<<"$config")
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "dhcp ipam driver is not supported with the routed driver" "dhcp rejected"
+}
+
+@test "routed - create" {
+    run_netavark create <<<'{
+  "network": {
+    "name": "routed1",
+    "id": "abc123def4567890123456789012345678901234567890123456789012345678",
+    "driver": "routed",
+    "dns_enabled": true,
+    "internal": false,
+    "ipv6_enabled": false
+  },
+  "used": {"interfaces": ["nvr1"], "names": {}, "subnets": []},
+  "options": {
+    "subnet_pools": [{"base": "10.89.0.0/16", "size": 24}],
+    "default_interface_name": "podman",
+    "check_used_subnets": false
+  }
+}'
+    result="$output"
+    assert_json "$result" ".network_interface" == "nvr2" "interface prefix"
+    assert_json "$result" ".dns_enabled" == "false" "dns is disabled"
+    assert_json "$result" ".ipam_options.driver" == "host-local" "ipam driver"
+    assert_json "$result" ".subnets[0].subnet" =~ "^10\\.89\\.[0-9]+\\.0/24$" "subnet allocated"
+
+    expected_rc=1 run_netavark create <<<'{
+  "network": {
+    "name": "routed1",
+    "id": "abc123def4567890123456789012345678901234567890123456789012345678",
+    "driver": "routed",
+    "network_interface": "toolongname",
+    "dns_enabled": false,
+    "internal": false,
+    "ipv6_enabled": false
+  },
+  "used": {"interfaces": [], "names": {}, "subnets": []},
+  "options": {
+    "subnet_pools": [{"base": "10.89.0.0/16", "size": 24}],
+    "default_interface_name": "podman",
+    "check_used_subnets": false
+  }
+}'
+    assert_json ".error" "routed interface prefix toolongname is longer than 6 characters" "prefix length"
+}
@@ -0,0 +1,121 @@
+#!/usr/bin/env bats   -*- bats -*-
+#
+# wireguard driver tests
+#
+
+load helpers
+
+KEY1_PRIVATE="+EBM/fZJX2E6HS8CCUNw3DqagwOK/n5nxBz0wvl5hlM="
+KEY1_PUBLIC="8AsLfaHjGIpbRh8ZdfSLsDYyTbYlsFrf3YXCh+9ixgM="
+KEY2_PRIVATE="gMOlOG2jISTHljLErwGzFLzCy3FoQh5rhDckgUDpH2c="
+KEY2_PUBLIC="fic/h2YuunV7fmcf4s9UR+gaIGco/iTmvwG6CdpxzBM="
+
+function setup() {
+    basic_setup
+    modprobe wireguard || skip "wireguard kernel module not available"
+}
+
+# create a wireguard config, args are: ip, private key file, listen port, peers
+function createWireguardConfig() {
+    read -r -d '\0' config <<EOF
+{
+  "container_id": "6ce776ea58b5",
+  "container_name": "testcontainer",
+  "networks": {
+    "wg1": {
+      "static_ips": [
+        "$1"
+      ],
+      "interface_name": "wg0"
+    }
+  },
+  "network_info": {
+    "wg1": {
+      "name": "wg1",
+      "id": "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b",
+      "driver": "wireguard",
+      "subnets": [
+        {
+          "subnet": "10.99.0.0/24"
+        }
+      ],
+      "routes": [
+        {
+          "destination": "10.100.0.0/24"
+        }
+      ],
+      "ipv6_enabled": false,
+      "internal": false,
+      "dns_enabled": false,
+      "options": {
+        "wg_private_key_file": "$2",
+        "wg_listen_port": "$3",
+        "wg_peers": "$4"
+      },
+      "ipam_options": {
+        "driver": "host-local"
+      }
+    }
+  }
+}\0
+EOF
+
+echo "$config"
+}
+
+@test "wireguard - two containers" {
+    create_container_ns
+
+    echo "$KEY1_PRIVATE" > "$NETAVARK_TMPDIR/key1"
+    echo "$KEY2_PRIVATE" > "$NETAVARK_TMPDIR/key2"
+
+    local config1=$(createWireguardConfig 10.99.0.2 "$NETAVARK_TMPDIR/key1" 51821 \
+        "$KEY2_PUBLIC,endpoint=127.0.0.1:51822,allowed_ips=10.99.0.3/32 10.100.0.0/24")
+    local config2=$(createWireguardConfig 10.99.0.3 "$NETAVARK_TMPDIR/key2" 51822 \
+        "$KEY1_PUBLIC,endpoint=127.0.0.1:51821,allowed_ips=10.99.0.2/32")
+
+    run_netavark setup $(get_container_netns_path) <<<"$config1"
+    result="$output"
+    assert_json "$result" ".wg1.interfaces.wg0.subnets[0].ipnet" == "10.99.0.2/24" "container address reported"
+    assert_json "$result" ".wg1.interfaces.wg0.mac_address" == "" "no mac address"
+
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+
+    run_in_container_netns ip -j -d link show wg0
+    assert_json "$output" ".[].linkinfo.info_kind" == "wireguard" "wireguard interface created"
+
+    run_in_container_netns ip -j route show 10.100.0.0/24
+    assert_json "$output" ".[0].dev" == "wg0" "network route over the wireguard interface"
+
+    # the udp sockets are bound in the host netns
+    run_in_host_netns ss -Hlun
+    assert "$output" =~ ":51821" "listen port of the first interface on the host"
+    assert "$output" =~ ":51822" "listen port of the second interface on the host"
+
+    run_in_container_netns ping -c 1 -W 5 10.99.0.3
+    run_in_container_netns 1 ping -c 1 -W 5 10.99.0.2
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+
+    expected_rc=1 run_in_container_netns ip link show wg0
+    expected_rc=1 run_in_container_netns 1 ip link show wg0
+    run_in_host_netns ss -Hlun
+    assert "$output" "!~" ":5182[12]" "udp sockets closed"
+}
+
+@test "wireguard - missing private key option" {
+    local config=$(createWireguardConfig 10.99.0.2 "" 51821 "")
+    config=$(jq -c 'del(.network_info.wg1.options.wg_private_key_file)' <<<"$config")
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "wireguard driver requires the wg_private_key_file option" "missing key rejected"
+}
+
+@test "wireguard - invalid private key" {
+    echo "notakey" > "$NETAVARK_TMPDIR/key1"
+    local config=$(createWireguardConfig 10.99.0.2 "$NETAVARK_TMPDIR/key1" 51821 "")
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert "$output" =~ "invalid private key in $NETAVARK_TMPDIR/key1" "invalid key rejected"
+}
@@ -49,6 +49,9 @@ function basic_setup() {
 
 function basic_teardown() {
     teardown_firewalld
+    if [ -n "${NETAVARK_NFTABLES_WATCH_PID}" ]; then
+        kill -9 $NETAVARK_NFTABLES_WATCH_PID
+    fi
     kill -9 $HOST_NS_PID
     for i in "${!CONTAINER_NS_PIDS[@]}"; do
         kill -9 "${CONTAINER_NS_PIDS[$i]}"
@@ -154,6 +157,12 @@ function run_netavark_firewalld_reload() {
     NETAVARK_FIREWALLD_RELOAD_PID=$!
 }
 
+function run_netavark_nftables_watch() {
+    # need to use nsetner as this will be run in the background
+    nsenter -n -t $HOST_NS_PID $NETAVARK --config "$NETAVARK_TMPDIR/config" nftables-watch &
+    NETAVARK_NFTABLES_WATCH_PID=$!
+}
+
 
 ################
 #  run_in_container_netns  #  Run args in container netns